@@ -0,0 +1,38 @@
+/* C API for the pim conversion core. Build with `cargo build --release --features ffi`. */
+#ifndef PIM_H
+#define PIM_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PIM_FORMAT_JSON 0
+#define PIM_FORMAT_YAML 1
+#define PIM_FORMAT_JSON5 2
+#define PIM_FORMAT_HCL 3
+/* Requires the xlsx feature. */
+#define PIM_FORMAT_XLSX 4
+/* Output only. */
+#define PIM_FORMAT_NAGIOS 5
+
+/* Validate source content. Returns 0 on success or a pim exit code. On failure the error message
+ * is written to error_out, if not NULL, and must be freed with pim_string_free. */
+int pim_validate(const uint8_t *input, size_t input_len, int input_format, char **error_out);
+
+/* Convert source content. On success output_out receives a JSON object mapping each job name to
+ * the serialized content of its target file. Strings must be freed with pim_string_free. */
+int pim_convert(const uint8_t *input, size_t input_len, int input_format, int output_format,
+                bool pretty, char **output_out, char **error_out);
+
+/* Free a string returned by pim. */
+void pim_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PIM_H */
@@ -0,0 +1,70 @@
+use crate::app::source::label_name;
+use std::path::PathBuf;
+
+/// The receiver scraped targets are forwarded to when none is given.
+pub const DEFAULT_FORWARD_TO: &str = "prometheus.remote_write.default.receiver";
+
+/// Render a Grafana Alloy config snippet with a `discovery.file` and `prometheus.scrape`
+/// component per job, reading the job's target file and forwarding samples to the receiver.
+/// Component labels are the job names turned into valid identifiers.
+pub fn config(files: &[(String, PathBuf)], forward_to: &str) -> String {
+    let mut config = String::from("// Generated by pim. Changes are overwritten.\n");
+    for (job, path) in files {
+        let name = label_name(job);
+        config.push_str(&format!(
+            "\ndiscovery.file \"{name}\" {{\n  files = [{path}]\n}}\n\n\
+             prometheus.scrape \"{name}\" {{\n  targets    = discovery.file.{name}.targets\n  \
+             forward_to = [{forward_to}]\n  job_name   = {job}\n}}\n",
+            name = name,
+            path = quote(&path.to_string_lossy()),
+            forward_to = forward_to,
+            job = quote(job),
+        ));
+    }
+    config
+}
+
+// Quote a string for Alloy, which uses Go string syntax.
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let files = vec![(
+            "node-exporter".to_string(),
+            PathBuf::from("/etc/alloy/targets/node-exporter_targets.json"),
+        )];
+        assert_eq!(
+            config(&files, DEFAULT_FORWARD_TO),
+            r#"// Generated by pim. Changes are overwritten.
+
+discovery.file "node_exporter" {
+  files = ["/etc/alloy/targets/node-exporter_targets.json"]
+}
+
+prometheus.scrape "node_exporter" {
+  targets    = discovery.file.node_exporter.targets
+  forward_to = [prometheus.remote_write.default.receiver]
+  job_name   = "node-exporter"
+}
+"#
+        );
+        assert_eq!(quote(r#"C:\a "b""#), r#""C:\\a \"b\"""#);
+    }
+}
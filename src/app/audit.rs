@@ -0,0 +1,52 @@
+use crate::app::diff::{Diff, DiffSummary, JobDiff};
+use crate::core::error::*;
+use crate::core::io::{hostname, username};
+use crate::core::time::{format_rfc3339, unix_now};
+use log::debug;
+use serde::Serialize;
+use std::{collections::BTreeMap, fs::OpenOptions, io::Write, path::Path};
+
+/// A record of the changes made to the target files by a single run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditRecord {
+    pub timestamp: String,
+    pub user: String,
+    pub host: String,
+    pub output: String,
+    pub summary: DiffSummary,
+    pub jobs: BTreeMap<String, JobDiff>,
+}
+
+impl AuditRecord {
+    pub fn new(diff: &Diff, output: &str) -> Self {
+        AuditRecord {
+            timestamp: format_rfc3339(unix_now()),
+            user: username(),
+            host: hostname(),
+            output: output.to_string(),
+            summary: diff.summary(),
+            jobs: diff.jobs.clone(),
+        }
+    }
+
+    /// Append the record to the audit log as a single JSON line.
+    pub fn append(&self, path: &Path) -> Result<()> {
+        debug!("Appending audit record to {}", path.display());
+        let mut line = serde_json::to_string(self).map_err(|e| {
+            Error::new(SourceError::SerdeJson(e))
+                .set_context("Failed to serialize audit record")
+                .set_kind(ErrorKind::Internal)
+        })?;
+        line.push('\n');
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| {
+                Error::new(SourceError::Io(e))
+                    .set_context(&format!("Failed to write audit log: {}", path.display()))
+            })
+    }
+}
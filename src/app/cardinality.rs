@@ -0,0 +1,121 @@
+use crate::app::target::TargetFiles;
+use crate::core::error::*;
+use log::debug;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The number of values shown when a label has too many.
+const SHOWN_VALUES: usize = 5;
+
+/// The most distinct values a label may have across all target files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelLimit {
+    pub label: String,
+    pub max: usize,
+}
+
+/// Parse a label limit from `label=n`.
+pub fn parse_label_limit(s: &str) -> std::result::Result<LabelLimit, String> {
+    let invalid = || format!("'{}' is not a label limit, such as instance_id=100", s);
+    let (label, max) = s.split_once('=').ok_or_else(invalid)?;
+    let max = max.trim().parse().map_err(|_| invalid())?;
+    match label.trim() {
+        "" => Err(invalid()),
+        label => Ok(LabelLimit {
+            label: label.to_string(),
+            max,
+        }),
+    }
+}
+
+/// Fail if any limited label has more distinct values across all target files than its limit.
+/// Every label over its limit is reported.
+pub fn check(target_files: &TargetFiles, limits: &[LabelLimit]) -> Result<()> {
+    if limits.is_empty() {
+        return Ok(());
+    }
+
+    let mut values: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (_, target_file) in target_files {
+        for group in target_file.targets() {
+            for limit in limits {
+                if let Some(value) = group.labels().get(&limit.label) {
+                    values.entry(&limit.label).or_default().insert(value);
+                }
+            }
+        }
+    }
+
+    let mut over = Vec::new();
+    for limit in limits {
+        let values = values.get(limit.label.as_str());
+        let count = values.map_or(0, |v| v.len());
+        debug!(
+            "Label '{}' has {} of at most {} values",
+            limit.label, count, limit.max
+        );
+        if let Some(values) = values.filter(|_| count > limit.max) {
+            let mut shown: Vec<&str> = values.iter().take(SHOWN_VALUES).copied().collect();
+            if count > SHOWN_VALUES {
+                shown.push("...");
+            }
+            over.push(format!(
+                "label '{}' has {} values, more than {}: {}",
+                limit.label,
+                count,
+                limit.max,
+                shown.join(", ")
+            ));
+        }
+    }
+
+    match over.is_empty() {
+        true => Ok(()),
+        false => Err(Error::new(SourceError::Msg(over.join("\n")))
+            .set_context("Label cardinality is over its limit")
+            .set_kind(ErrorKind::Validation)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::app::target::{TargetFile, TargetGroup};
+    use crate::core::{Output, OutputFormat};
+
+    #[test]
+    fn test_parse_label_limit() {
+        assert_eq!(
+            parse_label_limit("env=3"),
+            Ok(LabelLimit {
+                label: "env".to_string(),
+                max: 3
+            })
+        );
+        assert!(parse_label_limit("env").is_err());
+        assert!(parse_label_limit("=3").is_err());
+        assert!(parse_label_limit("env=many").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let output = Output::from_writer(std::io::sink(), OutputFormat::Json);
+        let mut file = TargetFile::new("node", &output, &OutputFormat::Json).unwrap();
+        for (env, host) in [("prod", "web1"), ("prod", "web2"), ("dev", "dev1")] {
+            let labels = BTreeMap::from([
+                ("env".to_string(), env.to_string()),
+                ("host".to_string(), host.to_string()),
+            ]);
+            file.add_target(TargetGroup::new("node", labels, vec![host.to_string()]));
+        }
+        let mut target_files = TargetFiles::default();
+        target_files.insert("node".to_string(), file);
+
+        let limit = |s| parse_label_limit(s).unwrap();
+        assert!(check(&target_files, &[limit("env=2"), limit("team=0")]).is_ok());
+        let err = check(&target_files, &[limit("env=1"), limit("host=2")]).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("label 'env' has 2 values, more than 1: dev, prod"));
+        assert!(msg.contains("label 'host' has 3 values, more than 2: dev1, web1, web2"));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::app::target::TargetFiles;
+use crate::core::error::*;
+use log::debug;
+use serde_json::Value;
+
+/// A contract the serialized target files are checked against before they are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum Compliance {
+    /// The Prometheus file_sd format: a list of objects with a `targets` list of strings and an
+    /// optional `labels` map of strings, and no other keys.
+    Prometheus,
+}
+
+impl Compliance {
+    /// Check the serialized target files against the contract. This checks what pim actually
+    /// writes rather than its types, so changes to serialization can't silently break consumers.
+    pub fn check(&self, target_files: &TargetFiles) -> Result<()> {
+        for (job, target_file) in target_files {
+            debug!("Checking {:?} compliance of job '{}'", self, job);
+            let value = serde_json::to_value(target_file.targets()).map_err(|e| {
+                Error::new(SourceError::SerdeJson(e))
+                    .set_context("Failed to serialize target file")
+                    .set_kind(ErrorKind::Internal)
+            })?;
+
+            let res = match self {
+                Compliance::Prometheus => check_file_sd(&value),
+            };
+            if let Err(msg) = res {
+                return Err(Error::new(SourceError::Msg(format!(
+                    "Target file for job '{}' is not Prometheus file_sd compliant: {}",
+                    job, msg
+                )))
+                .set_kind(ErrorKind::Validation));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Check a value against the file_sd contract, returning where it breaks it.
+fn check_file_sd(value: &Value) -> std::result::Result<(), String> {
+    let Value::Array(groups) = value else {
+        return Err("the file is not a list of target groups".to_string());
+    };
+
+    for (i, group) in groups.iter().enumerate() {
+        let Value::Object(group) = group else {
+            return Err(format!("[{}] is not an object", i));
+        };
+        if let Some(key) = group.keys().find(|k| *k != "targets" && *k != "labels") {
+            return Err(format!("[{}] has the unknown key '{}'", i, key));
+        }
+
+        match group.get("targets") {
+            Some(Value::Array(targets)) => {
+                if let Some(j) = targets.iter().position(|t| !t.is_string()) {
+                    return Err(format!("[{}].targets[{}] is not a string", i, j));
+                }
+            }
+            Some(_) => return Err(format!("[{}].targets is not a list", i)),
+            None => return Err(format!("[{}] has no targets", i)),
+        }
+
+        match group.get("labels") {
+            Some(Value::Object(labels)) => {
+                if let Some((name, _)) = labels.iter().find(|(_, v)| !v.is_string()) {
+                    return Err(format!("[{}].labels.{} is not a string", i, name));
+                }
+            }
+            Some(_) => return Err(format!("[{}].labels is not a map", i)),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_check_file_sd() {
+        assert!(check_file_sd(&json!([])).is_ok());
+        assert!(
+            check_file_sd(&json!([
+                {"targets": ["a:1"], "labels": {"job": "node"}},
+                {"targets": []}
+            ]))
+            .is_ok()
+        );
+        assert!(check_file_sd(&json!({"targets": []})).is_err());
+        assert!(check_file_sd(&json!(["a:1"])).is_err());
+        assert!(check_file_sd(&json!([{"labels": {}}])).is_err());
+        assert!(check_file_sd(&json!([{"targets": "a:1"}])).is_err());
+        assert!(check_file_sd(&json!([{"targets": [1]}])).is_err());
+        assert!(check_file_sd(&json!([{"targets": [], "labels": {"a": 1}}])).is_err());
+        assert_eq!(
+            check_file_sd(&json!([{"targets": [], "job": "node"}])),
+            Err("[0] has the unknown key 'job'".to_string())
+        );
+    }
+}
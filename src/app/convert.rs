@@ -0,0 +1,93 @@
+use crate::app::source::{Source, SourceFile};
+use crate::app::target::TargetFiles;
+use crate::core::error::*;
+use crate::core::input::{Input, InputFormat};
+use crate::core::io::SharedBuffer;
+use crate::core::output::{Output, OutputFormat};
+use log::debug;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+// In-memory conversion helpers. Nothing here touches the filesystem or terminal so these work
+// without the `fs` feature, such as in a wasm32 web playground.
+
+/// Parse and validate source content. Returns the parsed sources.
+pub fn validate(content: &[u8], input_format: InputFormat) -> Result<Vec<Source>> {
+    debug!("Validating {} bytes of source content", content.len());
+    let sources = parse(content, input_format)?;
+    for source in &sources {
+        source.validate()?;
+    }
+
+    Ok(sources)
+}
+
+/// Convert source content into serialized target file content. Returns a map of job names to the
+/// content of the target file for that job.
+pub fn convert(
+    content: &[u8],
+    input_format: InputFormat,
+    output_format: OutputFormat,
+    pretty: bool,
+) -> Result<BTreeMap<String, String>> {
+    debug!("Converting {} bytes of source content", content.len());
+    let mut source_file = SourceFile::new(vec![]);
+    source_file.sources = parse(content, input_format)?;
+
+    // The shared output is only used to create the target files. Each job is serialized into its
+    // own buffer below.
+    let output = Output::from_writer(SharedBuffer::new(), output_format.clone());
+    let mut target_files = TargetFiles::default();
+    source_file.into_targets(&output, &output_format, &mut target_files)?;
+
+    let mut converted = BTreeMap::new();
+    for (job, target_file) in &target_files {
+        let buffer = SharedBuffer::new();
+        let mut job_output = Output::from_writer(buffer.clone(), output_format.clone());
+        job_output.set_pretty(pretty);
+        target_file.write_to(&mut job_output)?;
+        converted.insert(job.clone(), buffer.to_string_lossy());
+    }
+
+    Ok(converted)
+}
+
+fn parse(content: &[u8], input_format: InputFormat) -> Result<Vec<Source>> {
+    let input = Input::from_reader(Cursor::new(content.to_vec()), input_format)?;
+    let mut source_file = SourceFile::new(vec![input]);
+    source_file.read_sources()?;
+    Ok(source_file.sources)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    const SOURCE: &str = "- jobs: [node, ssh]\n  labels:\n    env: dev\n  targets: [host]\n";
+
+    #[test]
+    fn test_validate() {
+        let sources = validate(SOURCE.as_bytes(), InputFormat::Yaml).unwrap();
+        assert_eq!(sources.len(), 1);
+
+        let invalid = "- jobs: []\n  labels: {}\n  targets: [host]\n";
+        assert!(validate(invalid.as_bytes(), InputFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_convert() {
+        let converted = convert(
+            SOURCE.as_bytes(),
+            InputFormat::Yaml,
+            OutputFormat::Json,
+            false,
+        )
+        .unwrap();
+        assert_eq!(converted.len(), 2);
+        assert_eq!(
+            converted["ssh"],
+            r#"[{"labels":{"job":"ssh","env":"dev"},"targets":["host"]}]"#
+        );
+    }
+}
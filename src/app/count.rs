@@ -0,0 +1,90 @@
+use crate::app::target::TargetFiles;
+use std::collections::BTreeSet;
+
+/// A label a group must have with a value, such as `env=prod`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelSelector {
+    pub label: String,
+    pub value: String,
+}
+
+/// Parse a label selector from `label=value`.
+pub fn parse_label_selector(s: &str) -> std::result::Result<LabelSelector, String> {
+    let invalid = || format!("'{}' is not a label selector, such as env=prod", s);
+    let (label, value) = s.split_once('=').ok_or_else(invalid)?;
+    match label.trim() {
+        "" => Err(invalid()),
+        label => Ok(LabelSelector {
+            label: label.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+/// Count the distinct targets of the groups in the jobs with every selected label. No jobs
+/// selects every job.
+pub fn count(target_files: &TargetFiles, jobs: &[String], selectors: &[LabelSelector]) -> usize {
+    let mut unique = BTreeSet::new();
+    for (job, target_file) in target_files {
+        if !jobs.is_empty() && !jobs.contains(job) {
+            continue;
+        }
+        for group in target_file.targets() {
+            let selected = selectors
+                .iter()
+                .all(|s| group.labels().get(&s.label) == Some(&s.value));
+            if selected {
+                unique.extend(group.targets());
+            }
+        }
+    }
+    unique.len()
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::app::target::{TargetFile, TargetGroup};
+    use crate::core::{Output, OutputFormat};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_parse_label_selector() {
+        assert_eq!(
+            parse_label_selector("env=prod"),
+            Ok(LabelSelector {
+                label: "env".to_string(),
+                value: "prod".to_string()
+            })
+        );
+        assert!(parse_label_selector("env").is_err());
+        assert!(parse_label_selector("=prod").is_err());
+    }
+
+    #[test]
+    fn test_count() {
+        let output = Output::from_writer(std::io::sink(), OutputFormat::Json);
+        let group = |job, env: &str, hosts: &[&str]| {
+            let labels = BTreeMap::from([("env".to_string(), env.to_string())]);
+            TargetGroup::new(job, labels, hosts.iter().map(|h| h.to_string()).collect())
+        };
+        let mut node = TargetFile::new("node", &output, &OutputFormat::Json).unwrap();
+        node.add_target(group("node", "prod", &["web1", "web2"]));
+        node.add_target(group("node", "dev", &["web1"]));
+        let mut ssh = TargetFile::new("ssh", &output, &OutputFormat::Json).unwrap();
+        ssh.add_target(group("ssh", "prod", &["web1"]));
+        let mut target_files = TargetFiles::default();
+        target_files.insert("node".to_string(), node);
+        target_files.insert("ssh".to_string(), ssh);
+
+        let select = |s| parse_label_selector(s).unwrap();
+        assert_eq!(count(&target_files, &[], &[]), 2);
+        assert_eq!(count(&target_files, &["node".to_string()], &[]), 2);
+        assert_eq!(count(&target_files, &[], &[select("env=dev")]), 1);
+        assert_eq!(
+            count(&target_files, &["ssh".to_string()], &[select("env=dev")]),
+            0
+        );
+    }
+}
@@ -0,0 +1,380 @@
+use crate::app::target::{TargetFiles, TargetGroup};
+#[cfg(feature = "fs")]
+use crate::core::error::*;
+#[cfg(feature = "fs")]
+use crate::core::output::{Output, OutputFormat, OutputKind};
+use log::debug;
+#[cfg(feature = "fs")]
+use log::warn;
+use serde::Serialize;
+use std::collections::BTreeMap;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// Target groups per job.
+pub type JobGroups = BTreeMap<String, Vec<TargetGroup>>;
+
+/// The differences between two generations of target files. Groups are matched by their ids, or
+/// by their labels when either has no id.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Diff {
+    pub jobs: BTreeMap<String, JobDiff>,
+}
+
+/// The differences for a single job.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct JobDiff {
+    pub added: Vec<TargetGroup>,
+    pub removed: Vec<TargetGroup>,
+    pub changed: Vec<GroupChange>,
+}
+
+/// A group that exists in both generations with different targets or, for a group matched by
+/// its id, different labels.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct GroupChange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    /// The labels in the previous generation, when they changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_labels: Option<BTreeMap<String, String>>,
+    pub added_targets: Vec<String>,
+    pub removed_targets: Vec<String>,
+}
+
+/// Counts of the differences across all jobs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DiffSummary {
+    pub groups_added: usize,
+    pub groups_removed: usize,
+    pub groups_changed: usize,
+    pub targets_added: usize,
+    pub targets_removed: usize,
+}
+
+impl JobDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn summary(&self) -> DiffSummary {
+        let mut summary = DiffSummary {
+            groups_added: self.added.len(),
+            groups_removed: self.removed.len(),
+            groups_changed: self.changed.len(),
+            targets_added: self.added.iter().map(|g| g.targets().len()).sum(),
+            targets_removed: self.removed.iter().map(|g| g.targets().len()).sum(),
+        };
+        for change in &self.changed {
+            summary.targets_added += change.added_targets.len();
+            summary.targets_removed += change.removed_targets.len();
+        }
+
+        summary
+    }
+}
+
+impl DiffSummary {
+    /// Describe the counts that aren't zero, such as `1 group added, 2 targets added`.
+    pub fn describe(&self) -> String {
+        let counts = [
+            (self.groups_added, "group", "added"),
+            (self.groups_removed, "group", "removed"),
+            (self.groups_changed, "group", "changed"),
+            (self.targets_added, "target", "added"),
+            (self.targets_removed, "target", "removed"),
+        ];
+        counts
+            .iter()
+            .filter(|(count, _, _)| *count > 0)
+            .map(|(count, noun, verb)| match count {
+                1 => format!("1 {} {}", noun, verb),
+                n => format!("{} {}s {}", n, noun, verb),
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+impl Diff {
+    /// Compare the previous and current target groups per job. Jobs without changes are left
+    /// out.
+    pub fn between(previous: &JobGroups, current: &JobGroups) -> Self {
+        debug!("Computing diff between target generations");
+        let empty = Vec::new();
+        let mut jobs = BTreeMap::new();
+        let names: std::collections::BTreeSet<&String> =
+            previous.keys().chain(current.keys()).collect();
+
+        for job in names {
+            let old = previous.get(job).unwrap_or(&empty);
+            let new = current.get(job).unwrap_or(&empty);
+            let job_diff = diff_groups(old, new);
+            if !job_diff.is_empty() {
+                jobs.insert(job.clone(), job_diff);
+            }
+        }
+
+        Diff { jobs }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    pub fn summary(&self) -> DiffSummary {
+        let mut summary = DiffSummary::default();
+        for job in self.jobs.values() {
+            let job = job.summary();
+            summary.groups_added += job.groups_added;
+            summary.groups_removed += job.groups_removed;
+            summary.groups_changed += job.groups_changed;
+            summary.targets_added += job.targets_added;
+            summary.targets_removed += job.targets_removed;
+        }
+
+        summary
+    }
+}
+
+// Find the group in the other generation, by id when both have one and by labels otherwise. A
+// group with the same id, or without an id like this one, is preferred, so a group that just got
+// an id still matches its previous generation.
+fn find_group<'a>(groups: &'a [TargetGroup], group: &TargetGroup) -> Option<&'a TargetGroup> {
+    let same = |g: &&TargetGroup| match (g.id(), group.id()) {
+        (Some(a), Some(b)) => a == b,
+        _ => g.labels() == group.labels(),
+    };
+    groups
+        .iter()
+        .filter(same)
+        .find(|g| g.id() == group.id())
+        .or_else(|| groups.iter().find(same))
+}
+
+// Match groups and compare their targets and labels.
+fn diff_groups(old: &[TargetGroup], new: &[TargetGroup]) -> JobDiff {
+    let mut job_diff = JobDiff::default();
+    for group in new {
+        match find_group(old, group) {
+            None => job_diff.added.push(group.clone()),
+            Some(previous) => {
+                let added_targets: Vec<String> = group
+                    .targets()
+                    .iter()
+                    .filter(|t| !previous.targets().contains(t))
+                    .cloned()
+                    .collect();
+                let removed_targets: Vec<String> = previous
+                    .targets()
+                    .iter()
+                    .filter(|t| !group.targets().contains(t))
+                    .cloned()
+                    .collect();
+                let previous_labels =
+                    Some(previous.labels()).filter(|labels| *labels != group.labels());
+                if !added_targets.is_empty()
+                    || !removed_targets.is_empty()
+                    || previous_labels.is_some()
+                {
+                    job_diff.changed.push(GroupChange {
+                        id: group.id().map(String::from),
+                        labels: group.labels().clone(),
+                        previous_labels: previous_labels.cloned(),
+                        added_targets,
+                        removed_targets,
+                    });
+                }
+            }
+        }
+    }
+
+    for group in old {
+        if find_group(new, group).is_none() {
+            job_diff.removed.push(group.clone());
+        }
+    }
+
+    job_diff
+}
+
+/// The target groups per job about to be written.
+pub fn current_groups(target_files: &TargetFiles) -> JobGroups {
+    target_files
+        .iter()
+        .map(|(job, file)| (job.clone(), file.targets().clone()))
+        .collect()
+}
+
+/// Read the target groups per job from the existing files that the target files will replace.
+/// For directory outputs, target files of jobs that are no longer generated are read too. Files
+/// that don't exist or can't be parsed are treated as empty.
+#[cfg(feature = "fs")]
+pub fn read_previous(output: &Output, target_files: &TargetFiles) -> Result<JobGroups> {
+    debug!("Reading previous target files");
+    let mut previous = JobGroups::new();
+    for (job, file) in target_files {
+        if matches!(file.output().kind(), OutputKind::File(_)) {
+            let groups = read_groups(file.output().path(), file.output().format());
+            previous.insert(job.clone(), groups);
+        }
+    }
+
+    if let OutputKind::Directory(dir) = output.kind() {
+        let suffix = format!("_targets.{}", output.format().extension());
+        let entries = std::fs::read_dir(dir).map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(&format!("reading directory: {}", dir.display()))
+        })?;
+        // Job names may have been changed to make valid file names, so files are matched to the
+        // target files by path rather than by the job name in the file name.
+        let claimed: Vec<&Path> = target_files
+            .iter()
+            .map(|(_, file)| file.output().path().as_path())
+            .collect();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(job) = name.strip_suffix(&suffix) else {
+                continue;
+            };
+            if !claimed.contains(&entry.path().as_path()) && !previous.contains_key(job) {
+                let groups = read_groups(&entry.path(), output.format());
+                previous.insert(job.to_string(), groups);
+            }
+        }
+    }
+
+    Ok(previous)
+}
+
+/// Read the target groups from an existing target file. Files that don't exist or can't be
+/// parsed are treated as empty.
+#[cfg(feature = "fs")]
+pub fn read_groups(path: &Path, format: &OutputFormat) -> Vec<TargetGroup> {
+    match try_read_groups(path, format) {
+        Ok(groups) => groups,
+        Err(e) => {
+            warn!(
+                "Could not parse existing target file {}: {}",
+                path.display(),
+                e
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// Read the target groups from an existing target file, failing if it can't be parsed. A file
+/// that doesn't exist or is empty has no groups.
+#[cfg(feature = "fs")]
+pub fn try_read_groups(path: &Path, format: &OutputFormat) -> Result<Vec<TargetGroup>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(Error::new(SourceError::Io(e))
+                .set_context(&format!("reading target file: {}", path.display())));
+        }
+    };
+    if content.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let context = format!("parsing target file: {}", path.display());
+    match format {
+        OutputFormat::Json => serde_json::from_str(&content)
+            .map_err(|e| Error::new(SourceError::SerdeJson(e)).set_context(&context)),
+        OutputFormat::Yaml => serde_yaml::from_str(&content)
+            .map_err(|e| Error::new(SourceError::SerdeYaml(e)).set_context(&context)),
+        OutputFormat::Nagios => Err(Error::new(SourceError::Msg(
+            "Nagios object definitions can't be read back".to_string(),
+        ))
+        .set_context(&context)
+        .set_kind(ErrorKind::Config)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn group(job: &str, env: &str, targets: &[&str]) -> TargetGroup {
+        let labels = BTreeMap::from([("env".to_string(), env.to_string())]);
+        TargetGroup::new(job, labels, targets.iter().map(|t| t.to_string()).collect())
+    }
+
+    #[test]
+    fn test_diff_between() {
+        let previous = JobGroups::from([
+            (
+                "node".to_string(),
+                vec![
+                    group("node", "dev", &["a", "b"]),
+                    group("node", "prd", &["c"]),
+                ],
+            ),
+            ("old".to_string(), vec![group("old", "dev", &["x"])]),
+        ]);
+        let current = JobGroups::from([(
+            "node".to_string(),
+            vec![
+                group("node", "dev", &["a", "d"]),
+                group("node", "stg", &["e"]),
+            ],
+        )]);
+
+        let diff = Diff::between(&previous, &current);
+        let node = &diff.jobs["node"];
+        assert_eq!(node.added.len(), 1);
+        assert_eq!(node.removed.len(), 1);
+        assert_eq!(node.changed[0].added_targets, vec!["d".to_string()]);
+        assert_eq!(node.changed[0].removed_targets, vec!["b".to_string()]);
+        assert_eq!(diff.jobs["old"].removed.len(), 1);
+
+        let summary = diff.summary();
+        assert_eq!(summary.groups_added, 1);
+        assert_eq!(summary.groups_removed, 2);
+        assert_eq!(summary.targets_added, 2);
+        assert_eq!(summary.targets_removed, 3);
+    }
+
+    #[test]
+    fn test_diff_by_id() {
+        let id = |group: TargetGroup| group.with_id(Some("web".to_string()));
+        let previous = JobGroups::from([(
+            "node".to_string(),
+            vec![
+                id(group("node", "dev", &["a"])),
+                group("node", "dev", &["x"]),
+            ],
+        )]);
+        let current = JobGroups::from([(
+            "node".to_string(),
+            vec![
+                id(group("node", "stg", &["a"])),
+                group("node", "dev", &["x"]),
+            ],
+        )]);
+
+        let diff = Diff::between(&previous, &current);
+        let node = &diff.jobs["node"];
+        assert!(node.added.is_empty() && node.removed.is_empty());
+        assert_eq!(node.changed.len(), 1);
+        assert_eq!(node.changed[0].id.as_deref(), Some("web"));
+        assert_eq!(node.changed[0].labels["env"], "stg");
+        assert_eq!(
+            node.changed[0].previous_labels.as_ref().unwrap()["env"],
+            "dev"
+        );
+        assert!(node.changed[0].added_targets.is_empty());
+        assert_eq!(diff.summary().describe(), "1 group changed");
+    }
+
+    #[test]
+    fn test_diff_unchanged() {
+        let groups = JobGroups::from([("node".to_string(), vec![group("node", "dev", &["a"])])]);
+        assert!(Diff::between(&groups, &groups).is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::app::source::Source;
+use crate::core::table;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A target that is in more than one job or group, so it is scraped more than once.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Duplicate {
+    pub target: String,
+    pub jobs: BTreeSet<String>,
+    /// The number of distinct groups, by job, labels, and id, the target is in.
+    pub groups: usize,
+    /// Where the sources adding the target came from, such as file paths or provider names.
+    pub sources: BTreeSet<String>,
+}
+
+#[derive(Default)]
+struct Seen<'a> {
+    groups: BTreeSet<(&'a str, &'a BTreeMap<String, String>, Option<&'a str>)>,
+    sources: BTreeSet<&'a str>,
+}
+
+/// Find the targets in more than one job or group. Each source comes with where it came from.
+/// Duplicates are sorted by target.
+pub fn find(sources: &[(String, Source)]) -> Vec<Duplicate> {
+    let mut seen: BTreeMap<&str, Seen> = BTreeMap::new();
+    for (origin, source) in sources {
+        for target in source.targets() {
+            let entry = seen.entry(target.as_str()).or_default();
+            entry.sources.insert(origin);
+            for job in source.jobs() {
+                entry.groups.insert((job, source.labels(), source.id()));
+            }
+        }
+    }
+
+    seen.into_iter()
+        .filter(|(_, seen)| seen.groups.len() > 1)
+        .map(|(target, seen)| Duplicate {
+            target: target.to_string(),
+            jobs: seen
+                .groups
+                .iter()
+                .map(|(job, ..)| job.to_string())
+                .collect(),
+            groups: seen.groups.len(),
+            sources: seen.sources.iter().map(|s| s.to_string()).collect(),
+        })
+        .collect()
+}
+
+/// Render the duplicates as a table.
+pub fn table(duplicates: &[Duplicate]) -> String {
+    let join = |set: &BTreeSet<String>| set.iter().cloned().collect::<Vec<_>>().join(", ");
+    let rows: Vec<Vec<String>> = duplicates
+        .iter()
+        .map(|d| {
+            vec![
+                d.target.clone(),
+                join(&d.jobs),
+                d.groups.to_string(),
+                join(&d.sources),
+            ]
+        })
+        .collect();
+    table::render(&["TARGET", "JOBS", "GROUPS", "SOURCES"], &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn source(jobs: &[&str], env: &str, targets: &[&str]) -> Source {
+        let mut builder = Source::builder().label("env", env);
+        for job in jobs {
+            builder = builder.job(job);
+        }
+        for target in targets {
+            builder = builder.target(target);
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_find() {
+        let sources = vec![
+            (
+                "a.yml".to_string(),
+                source(&["node"], "prod", &["web1:9100", "web2:9100"]),
+            ),
+            // The same group again only merges into it.
+            (
+                "b.yml".to_string(),
+                source(&["node"], "prod", &["web2:9100"]),
+            ),
+            // Another job or other labels scrape the target again.
+            (
+                "c.yml".to_string(),
+                source(&["team_node"], "prod", &["web1:9100"]),
+            ),
+            (
+                "d.yml".to_string(),
+                source(&["ssh", "icmp"], "prod", &["db1"]),
+            ),
+        ];
+        let duplicates = find(&sources);
+        assert_eq!(duplicates.len(), 2);
+        assert_eq!(duplicates[0].target, "db1");
+        assert_eq!(duplicates[0].groups, 2);
+        assert_eq!(duplicates[1].target, "web1:9100");
+        assert_eq!(
+            duplicates[1].jobs,
+            BTreeSet::from(["node".to_string(), "team_node".to_string()])
+        );
+        assert_eq!(
+            duplicates[1].sources,
+            BTreeSet::from(["a.yml".to_string(), "c.yml".to_string()])
+        );
+        assert_eq!(
+            table(&duplicates[1..]),
+            "TARGET     JOBS             GROUPS  SOURCES\n\
+             web1:9100  node, team_node  2       a.yml, c.yml\n"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use crate::app::target::TargetFiles;
+use log::warn;
+use std::collections::BTreeSet;
+
+/// Labels vmagent reads from targets to tune scraping, which Prometheus doesn't know.
+const VICTORIAMETRICS_LABELS: &[&str] = &[
+    "__sample_limit__",
+    "__series_limit__",
+    "__stream_parse__",
+    "__scrape_align_interval__",
+    "__scrape_offset__",
+];
+
+/// The scraper the target files are written for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum Flavor {
+    /// Any scraper. Every label is written as it is.
+    #[default]
+    Generic,
+    /// Prometheus. Labels only vmagent understands are dropped.
+    Prometheus,
+    /// vmagent and VictoriaMetrics. `__vm_*` labels and vmagent's scrape tuning labels, such as
+    /// `__series_limit__`, are kept.
+    #[cfg_attr(
+        feature = "application",
+        value(name = "victoriametrics", alias = "vmagent")
+    )]
+    VictoriaMetrics,
+}
+
+impl Flavor {
+    /// Check if a label is written for this flavor.
+    pub fn keeps_label(&self, name: &str) -> bool {
+        match self {
+            Flavor::Prometheus => {
+                !name.starts_with("__vm_") && !VICTORIAMETRICS_LABELS.contains(&name)
+            }
+            Flavor::Generic | Flavor::VictoriaMetrics => true,
+        }
+    }
+
+    /// Drop the labels this flavor doesn't keep from the target files. Each dropped label is
+    /// warned about once.
+    pub fn apply(&self, target_files: &mut TargetFiles) {
+        let mut dropped = BTreeSet::new();
+        for (_job, target_file) in target_files.iter_mut() {
+            for group in target_file.mut_targets() {
+                group.mut_labels().retain(|name, _| {
+                    let keep = self.keeps_label(name);
+                    if !keep {
+                        dropped.insert(name.clone());
+                    }
+                    keep
+                });
+            }
+        }
+
+        for name in dropped {
+            warn!(
+                "Dropping label '{}', which only vmagent uses, use --flavor victoriametrics to keep it",
+                name
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_keeps_label() {
+        assert!(Flavor::default().keeps_label("__vm_tenant"));
+        assert!(Flavor::default().keeps_label("__series_limit__"));
+        assert!(Flavor::Prometheus.keeps_label("env"));
+        assert!(Flavor::Prometheus.keeps_label("__metrics_path__"));
+        assert!(!Flavor::Prometheus.keeps_label("__vm_tenant"));
+        assert!(!Flavor::Prometheus.keeps_label("__series_limit__"));
+        assert!(Flavor::VictoriaMetrics.keeps_label("__vm_tenant"));
+        assert!(Flavor::VictoriaMetrics.keeps_label("__series_limit__"));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::app::migrate::{self, FIELDS};
+use crate::core::decrypt;
+use crate::core::error::*;
+use crate::core::input::InputFormat;
+use crate::core::yaml::BlockList;
+
+// Source files are formatted by editing their lines, like migrations, so comments, anchors, and
+// aliases are kept. Every formatted document is read back and checked to hold the same sources
+// as the original.
+
+/// Format a YAML source document: the fields of each source are ordered as pim writes them,
+/// with merge keys first, the sources of a list are indented the same, and trailing whitespace,
+/// leading blank lines, and repeated blank lines are removed. Returns None when the document is
+/// already formatted.
+pub fn format(text: &str, format: &InputFormat) -> Result<Option<String>> {
+    if decrypt::is_age(text.as_bytes()) || decrypt::is_sops(text, format) {
+        return Err(Error::new(SourceError::Msg(
+            "Encrypted sources can't be formatted, decrypt them first".to_string(),
+        ))
+        .set_kind(ErrorKind::Validation));
+    }
+    if *format != InputFormat::Yaml {
+        return Err(Error::new(SourceError::UnsupportedInputFormat(
+            format.as_str().to_string(),
+        ))
+        .set_context("Only YAML sources can be formatted")
+        .set_kind(ErrorKind::Config));
+    }
+
+    let document = migrate::read(text, format)?;
+    let mut formatted = trim_lines(text);
+    if let Some(mut list) = BlockList::parse_sources(&formatted) {
+        // A versioned document keeps its sources under `sources`, indented below it.
+        let col = match BlockList::parse(&formatted) {
+            Some(_) => 0,
+            None => 2,
+        };
+        let order: Vec<&str> = std::iter::once("<<")
+            .chain(FIELDS.iter().copied())
+            .collect();
+        list.sort_fields(&order);
+        list.indent(col);
+        formatted = trim_lines(&list.to_string());
+    }
+
+    if formatted == text {
+        return Ok(None);
+    }
+    if migrate::read(&formatted, format)? != document {
+        return Err(Error::new(SourceError::Msg(
+            "Formatting would change the sources of the document".to_string(),
+        ))
+        .set_kind(ErrorKind::Internal));
+    }
+    Ok(Some(formatted))
+}
+
+// Remove trailing whitespace, blank lines at the start and end, and repeated blank lines, and
+// end the text with a newline.
+fn trim_lines(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut blank = false;
+    for line in text.lines().map(str::trim_end) {
+        if line.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let yaml = "\n# hosts  \n- &web\n    targets: [web1]  # primary\n    # the exporter\n    \
+                    jobs: [node]\n\n\n    labels: &labels {env: prod}\n# same as web\n- *web\n\
+                    - targets: [db1]\n  # database\n  jobs: [pg]\n  labels: *labels\n\
+                    - <<: *web\n  targets: [web2]\n-   targets: [app1]\n    jobs: [node]\n    labels: {}\n";
+        let formatted = format(yaml, &InputFormat::Yaml).unwrap().unwrap();
+        assert_eq!(
+            formatted,
+            "# hosts\n- &web\n  # the exporter\n  jobs: [node]\n\n  \
+             labels: &labels {env: prod}\n  targets: [web1]  # primary\n# same as web\n- *web\n\
+             # database\n- jobs: [pg]\n  labels: *labels\n  targets: [db1]\n\
+             - <<: *web\n  targets: [web2]\n- jobs: [node]\n  labels: {}\n  targets: [app1]\n"
+        );
+        assert_eq!(format(&formatted, &InputFormat::Yaml).unwrap(), None);
+    }
+
+    #[test]
+    fn test_format_versioned() {
+        let yaml = "apiVersion: pim/v1\nx-defaults: &defaults\n  jobs: [node]\n  labels: {team: web}\n\
+                    sources:\n    # web servers\n    - targets: [web1]\n      <<: *defaults\n\
+                    \n    - <<: *defaults\n      targets: [web2]  \nx-notes: kept\n";
+        let formatted = format(yaml, &InputFormat::Yaml).unwrap().unwrap();
+        assert_eq!(
+            formatted,
+            "apiVersion: pim/v1\nx-defaults: &defaults\n  jobs: [node]\n  labels: {team: web}\n\
+             sources:\n  # web servers\n  - <<: *defaults\n    targets: [web1]\n\n\
+             \u{20} - <<: *defaults\n    targets: [web2]\nx-notes: kept\n"
+        );
+        assert_eq!(format(&formatted, &InputFormat::Yaml).unwrap(), None);
+
+        assert!(format("[{\"jobs\": [\"node\"]}]", &InputFormat::Json).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use crate::app::diff::Diff;
+use crate::core::error::*;
+use log::{debug, info};
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+// Commits are made with the git CLI so the work tree's own configuration, hooks, and credentials
+// apply, the same as a commit made by hand.
+
+/// Stage the files and commit them in the git work tree holding the directory. Only the given
+/// files are committed, so other staged changes are left alone. Returns false without committing
+/// when the files have no changes.
+pub fn commit(dir: &Path, files: &[PathBuf], message: &str) -> Result<bool> {
+    if files.is_empty() {
+        return Ok(false);
+    }
+
+    let mut add = Command::new("git");
+    add.arg("add").arg("--").args(files);
+    run(add, dir, "add")?;
+
+    // `git diff --quiet` exits with 1 when there are differences.
+    let mut diff = Command::new("git");
+    diff.args(["diff", "--cached", "--quiet", "--"]).args(files);
+    if output(diff, dir, "diff")?.status.success() {
+        debug!("No changes to commit in {}", dir.display());
+        return Ok(false);
+    }
+
+    let mut commit = Command::new("git");
+    commit
+        .args(["commit", "--quiet", "--message", message, "--"])
+        .args(files);
+    run(commit, dir, "commit")?;
+    info!("Committed target files in {}", dir.display());
+    Ok(true)
+}
+
+/// Push the current branch of the git work tree holding the directory to its upstream.
+pub fn push(dir: &Path) -> Result<()> {
+    let mut push = Command::new("git");
+    push.args(["push", "--quiet"]);
+    run(push, dir, "push")?;
+    info!("Pushed target files in {}", dir.display());
+    Ok(())
+}
+
+/// A commit message summarizing the changes, with a line per changed job.
+pub fn commit_message(diff: &Diff) -> String {
+    if diff.is_empty() {
+        return "Update scrape targets".to_string();
+    }
+
+    let summary = diff.summary();
+    let mut message = format!(
+        "Update scrape targets: {} added, {} removed",
+        summary.targets_added, summary.targets_removed
+    );
+    message.push_str("\n\n");
+    for (job, job_diff) in &diff.jobs {
+        message.push_str(&format!("{}: {}\n", job, job_diff.summary().describe()));
+    }
+    message
+}
+
+fn output(mut command: Command, dir: &Path, action: &str) -> Result<Output> {
+    debug!("Running git {} in {}", action, dir.display());
+    command
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| Error::new(SourceError::Io(e)).set_context(&format!("running git {}", action)))
+}
+
+fn run(command: Command, dir: &Path, action: &str) -> Result<()> {
+    let output = output(command, dir, action)?;
+    if !output.status.success() {
+        return Err(Error::new(SourceError::Msg(format!(
+            "git {} exited with {}: {}",
+            action,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+        .set_context(&format!("Failed to git {} target files", action))
+        .set_kind(ErrorKind::Io));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::app::diff::JobGroups;
+    use crate::app::target::TargetGroup;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_commit_message() {
+        assert_eq!(commit_message(&Diff::default()), "Update scrape targets");
+
+        let group = |env: &str, targets: &[&str]| {
+            TargetGroup::new(
+                "node",
+                BTreeMap::from([("env".to_string(), env.to_string())]),
+                targets.iter().map(|t| t.to_string()).collect(),
+            )
+        };
+        let previous = JobGroups::from([("node".to_string(), vec![group("prod", &["a:1"])])]);
+        let current = JobGroups::from([
+            ("node".to_string(), vec![group("prod", &["a:1", "b:1"])]),
+            ("web".to_string(), vec![group("dev", &["c:1", "d:1"])]),
+        ]);
+        assert_eq!(
+            commit_message(&Diff::between(&previous, &current)),
+            "Update scrape targets: 3 added, 0 removed\n\n\
+             node: 1 group changed, 1 target added\n\
+             web: 1 group added, 2 targets added\n"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use crate::core::error::*;
+use log::{debug, info};
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Hooks are run through the shell so they can be given as one option value with their own
+// arguments, such as `--pre-write 'promtool check sd-config'`. The job and file are appended as
+// the last two arguments.
+
+/// Run a hook for a target file. The hook's output is logged, and a hook exiting with an error
+/// fails with its stderr.
+pub fn run(hook: &str, job: &str, path: &Path) -> Result<()> {
+    debug!("Running hook '{}' for {}", hook, path.display());
+    let output = command(hook, job, path)?
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| {
+            Error::new(SourceError::Io(e)).set_context(&format!("running hook '{}'", hook))
+        })?;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        info!("{}: {}", hook, line);
+    }
+    if !output.status.success() {
+        return Err(Error::new(SourceError::Msg(format!(
+            "'{}' exited with {} for {}: {}",
+            hook,
+            output.status,
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+        .set_context(&format!("Hook failed for job '{}'", job))
+        .set_kind(ErrorKind::Io));
+    }
+    Ok(())
+}
+
+// Run the command with the job and path appended as its arguments.
+fn command(hook: &str, job: &str, path: &Path) -> Result<Command> {
+    shell(hook, &[job.as_ref(), path.as_os_str()])
+}
+
+/// A command run through the shell, with the arguments appended to it.
+#[cfg(not(windows))]
+pub(crate) fn shell(line: &str, args: &[&OsStr]) -> Result<Command> {
+    // `$0` is the name sh reports errors with, and `"$@"` passes the arguments on unchanged.
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(format!("{} \"$@\"", line))
+        .arg("pim")
+        .args(args);
+    Ok(command)
+}
+
+/// A command run through the shell, with the arguments appended to it.
+#[cfg(windows)]
+pub(crate) fn shell(line: &str, args: &[&OsStr]) -> Result<Command> {
+    use std::os::windows::process::CommandExt;
+
+    // cmd parses its command line itself, so it is passed as is. `/S` strips the outer quotes.
+    let mut command = Command::new("cmd");
+    command
+        .args(["/D", "/S", "/C"])
+        .raw_arg(format!("\"{}\"", cmd_line(line, args)?));
+    Ok(command)
+}
+
+// Append the arguments to the line, each in double quotes so cmd doesn't split them or act on
+// `&`, `|`, `<`, `>`, or `^`. cmd still expands `%` and `!` variables inside quotes and can't
+// escape a quote, so arguments with those characters are rejected.
+#[cfg(windows)]
+fn cmd_line(line: &str, args: &[&OsStr]) -> Result<String> {
+    let mut cmd_line = line.to_string();
+    for arg in args {
+        let quoted = arg
+            .to_str()
+            .filter(|arg| !arg.contains(['"', '%', '!', '\r', '\n']))
+            .ok_or_else(|| {
+                Error::new(SourceError::Msg(format!(
+                    "'{}' contains characters cmd would interpret",
+                    arg.to_string_lossy()
+                )))
+                .set_context("Can not pass the argument to a hook")
+                .set_kind(ErrorKind::Validation)
+            })?;
+        cmd_line.push_str(&format!(" \"{}\"", quoted));
+    }
+    Ok(cmd_line)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run() {
+        let path = Path::new("targets/node_targets.json");
+        let check = "check() { test \"$1:$2\" = node:targets/node_targets.json; }; check";
+        assert!(run(check, "node", path).is_ok());
+        let err = run("echo bad >&2; false", "node", path).unwrap_err();
+        assert!(err.to_string().contains("bad"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_cmd_line() {
+        let path = Path::new(r"targets\a&b_targets.json");
+        assert_eq!(
+            cmd_line("promtool check", &["a&b".as_ref(), path.as_os_str()]).unwrap(),
+            r#"promtool check "a&b" "targets\a&b_targets.json""#
+        );
+        for arg in ["a\"b", "%PATH%", "!PATH!", "a\r\nb"] {
+            assert!(cmd_line("echo", &[arg.as_ref()]).is_err());
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_run() {
+        let path = Path::new(r"targets\node_targets.json");
+        assert!(run("echo", "a & exit 1", path).is_ok());
+        assert!(run("exit 1 & rem", "node", path).is_err());
+    }
+}
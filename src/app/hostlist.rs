@@ -0,0 +1,80 @@
+use crate::app::target::{TargetFiles, TargetGroup, check_file_names, safe_file_name};
+use crate::core::error::*;
+use crate::core::host::split_target;
+use crate::core::io::Writer;
+use log::info;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// The extension of per job host lists.
+pub const EXTENSION: &str = "hosts";
+
+/// The sorted, unique hosts of the target groups without ports, one per line, as read by
+/// parallel-ssh tools such as `pssh`, `pdsh -w ^file`, and `clush --hostfile`.
+pub fn render<'a>(groups: impl IntoIterator<Item = &'a TargetGroup>) -> String {
+    let hosts: BTreeSet<&str> = groups
+        .into_iter()
+        .flat_map(|group| group.targets())
+        .map(|target| split_target(target).0)
+        .filter(|host| !host.is_empty())
+        .collect();
+    hosts
+        .into_iter()
+        .map(|host| format!("{}\n", host))
+        .collect()
+}
+
+/// The host lists of the target files by path. A directory gets a `{job}.hosts` file per job, and
+/// any other path a single list with the hosts of every job.
+pub fn lists(target_files: &TargetFiles, path: &Path) -> Vec<(PathBuf, String)> {
+    let mut lists = Vec::new();
+    if path.is_dir() {
+        for (job, target_file) in target_files {
+            let file = path.join(format!("{}.{}", safe_file_name(job), EXTENSION));
+            lists.push((file, render(target_file.targets())));
+        }
+    } else {
+        let groups = target_files.iter().flat_map(|(_, file)| file.targets());
+        lists.push((path.to_path_buf(), render(groups)));
+    }
+    lists
+}
+
+/// Write the host lists of the target files and return the written paths.
+pub fn write(target_files: &TargetFiles, path: &Path, mode: u32) -> Result<Vec<PathBuf>> {
+    if path.is_dir() {
+        check_file_names(target_files.jobs())?;
+    }
+    let mut written = Vec::new();
+    for (file, hosts) in lists(target_files, path) {
+        info!("Writing host list to {}", file.display());
+        Writer::Pending(file.clone(), mode).write_all(hosts.as_bytes())?;
+        written.push(file);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_render() {
+        let groups = [
+            TargetGroup::new(
+                "node",
+                BTreeMap::new(),
+                vec!["web2:9100".to_string(), "web1:9100".to_string()],
+            ),
+            TargetGroup::new(
+                "node",
+                BTreeMap::new(),
+                vec!["web1:9101".to_string(), "[::1]:9100".to_string()],
+            ),
+        ];
+        assert_eq!(render(&groups), "::1\nweb1\nweb2\n");
+        assert_eq!(render(&[]), "");
+    }
+}
@@ -0,0 +1,86 @@
+use crate::app::target::TargetFiles;
+use crate::core::table;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// A job that would be generated and where its targets are written.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Job {
+    pub job: String,
+    /// The target file of the job. Jobs written to stdout or a single file share a path.
+    pub path: PathBuf,
+    pub groups: usize,
+    /// Every target of every group, so a target in several groups is counted once per group.
+    pub targets: usize,
+}
+
+/// List the jobs of the target files, sorted by job.
+pub fn list(target_files: &TargetFiles) -> Vec<Job> {
+    target_files
+        .into_iter()
+        .map(|(job, target_file)| Job {
+            job: job.clone(),
+            path: target_file.output().path().clone(),
+            groups: target_file.targets().len(),
+            targets: target_file
+                .targets()
+                .iter()
+                .map(|g| g.targets().len())
+                .sum(),
+        })
+        .collect()
+}
+
+/// Render the jobs as a table.
+pub fn table(jobs: &[Job]) -> String {
+    let rows: Vec<Vec<String>> = jobs
+        .iter()
+        .map(|j| {
+            vec![
+                j.job.clone(),
+                j.path.display().to_string(),
+                j.groups.to_string(),
+                j.targets.to_string(),
+            ]
+        })
+        .collect();
+    table::render(&["JOB", "PATH", "GROUPS", "TARGETS"], &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::app::target::{TargetFile, TargetGroup};
+    use crate::core::{Output, OutputFormat};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_list() {
+        let output = Output::from_writer(std::io::sink(), OutputFormat::Json);
+        let mut target_files = TargetFiles::default();
+        for (job, groups) in [
+            (
+                "node",
+                vec![vec!["web1:9100", "web2:9100"], vec!["dev1:9100"]],
+            ),
+            ("blackbox", vec![vec!["web1:9100"]]),
+        ] {
+            let mut file = TargetFile::new(job, &output, &OutputFormat::Json).unwrap();
+            for (i, targets) in groups.into_iter().enumerate() {
+                let labels = BTreeMap::from([("group".to_string(), i.to_string())]);
+                let targets = targets.into_iter().map(String::from).collect();
+                file.add_target(TargetGroup::new(job, labels, targets));
+            }
+            target_files.insert(job.to_string(), file);
+        }
+
+        let jobs = list(&target_files);
+        assert_eq!(
+            table(&jobs),
+            "JOB       PATH      GROUPS  TARGETS\n\
+             blackbox  <writer>  1       1\n\
+             node      <writer>  2       3\n"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::core::error::*;
+use log::debug;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// A mapping is compiled into one jq expression and applied like `--transform`, so every field
+// of a mapping file is a jq expression with the full jq language behind it.
+
+/// How the documents of another schema map to sources. Each field is a jq expression evaluated
+/// for each item of the document.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mapping {
+    /// The items of the document that become sources. Defaults to each element of a list, or
+    /// the document itself.
+    pub items: Option<String>,
+    /// The jobs of an item's source.
+    pub jobs: String,
+    /// The targets of an item's source.
+    pub targets: String,
+    /// The value of each label of an item's source. Labels without a value are left out.
+    pub labels: BTreeMap<String, String>,
+}
+
+impl Mapping {
+    /// Read a mapping file, a YAML mapping of `items`, `job`, `targets`, and `labels.<name>` to
+    /// jq expressions. Labels can also be given as a `labels` mapping.
+    pub fn read(path: &Path) -> Result<Self> {
+        debug!("Reading mapping file: {}", path.display());
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(&format!("reading mapping file: {}", path.display()))
+                .set_kind(ErrorKind::Config)
+        })?;
+        Self::parse(&text).map_err(|e| {
+            e.set_context(&format!("Invalid mapping file: {}", path.display()))
+                .set_kind(ErrorKind::Config)
+        })
+    }
+
+    /// Parse the YAML of a mapping file.
+    pub fn parse(text: &str) -> Result<Self> {
+        let fields: BTreeMap<String, serde_yaml::Value> = serde_yaml::from_str(text)
+            .map_err(|e| Error::new(SourceError::SerdeYaml(e)).set_kind(ErrorKind::Config))?;
+
+        let mut mapping = Mapping::default();
+        let (mut jobs, mut targets) = (None, None);
+        for (key, value) in fields {
+            match (key.as_str(), value) {
+                ("labels", serde_yaml::Value::Mapping(labels)) => {
+                    for (name, expr) in labels {
+                        let (Some(name), Some(expr)) = (name.as_str(), expr.as_str()) else {
+                            return Err(invalid("labels must map label names to expressions"));
+                        };
+                        mapping.labels.insert(name.to_string(), expr.to_string());
+                    }
+                }
+                (key, serde_yaml::Value::String(expr)) => match key {
+                    "items" => mapping.items = Some(expr),
+                    "job" | "jobs" => jobs = Some(expr),
+                    "targets" => targets = Some(expr),
+                    key => match key.strip_prefix("labels.") {
+                        Some(name) if !name.is_empty() => {
+                            mapping.labels.insert(name.to_string(), expr);
+                        }
+                        _ => return Err(invalid(&format!("unknown field '{}'", key))),
+                    },
+                },
+                (key, _) => return Err(invalid(&format!("'{}' must be a jq expression", key))),
+            }
+        }
+
+        mapping.jobs = jobs.ok_or_else(|| invalid("the job expression is missing"))?;
+        mapping.targets = targets.ok_or_else(|| invalid("the targets expression is missing"))?;
+        Ok(mapping)
+    }
+
+    /// The jq expression turning a document into a list of sources.
+    pub fn to_jq(&self) -> String {
+        let items = self
+            .items
+            .as_deref()
+            .unwrap_or("if type == \"array\" then .[] else . end");
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|(name, expr)| {
+                format!(
+                    "{}: ([{}][0])",
+                    serde_json::Value::from(name.as_str()),
+                    expr
+                )
+            })
+            .collect();
+        // Labels without a value are dropped, and other values are made strings.
+        let labels = format!(
+            "{{{}}} | with_entries(select(.value != null) | .value |= tostring)",
+            labels.join(", ")
+        );
+        format!(
+            "[{} | {{jobs: [{}], targets: [{}], labels: ({})}}]",
+            items, self.jobs, self.targets, labels
+        )
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(SourceError::Msg(msg.to_string())).set_kind(ErrorKind::Config)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mapping = Mapping::parse(
+            "items: .services[]\njob: .service\ntargets: '.hosts[] | . + \":9100\"'\n\
+             labels.env: .environment\nlabels:\n  team: .owner\n",
+        )
+        .unwrap();
+        assert_eq!(
+            mapping,
+            Mapping {
+                items: Some(".services[]".to_string()),
+                jobs: ".service".to_string(),
+                targets: ".hosts[] | . + \":9100\"".to_string(),
+                labels: BTreeMap::from([
+                    ("env".to_string(), ".environment".to_string()),
+                    ("team".to_string(), ".owner".to_string()),
+                ]),
+            }
+        );
+        assert_eq!(
+            mapping.to_jq(),
+            "[.services[] | {jobs: [.service], targets: [.hosts[] | . + \":9100\"], \
+             labels: ({\"env\": ([.environment][0]), \"team\": ([.owner][0])} \
+             | with_entries(select(.value != null) | .value |= tostring))}]"
+        );
+
+        assert!(Mapping::parse("targets: .hosts[]\n").is_err());
+        assert!(Mapping::parse("job: .service\ntargets: .hosts[]\nport: 9100\n").is_err());
+        assert!(Mapping::parse("job: .service\ntargets: .hosts[]\nlabel.env: .env\n").is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::app::target::TargetFiles;
+use crate::core::error::*;
+use crate::core::http;
+use log::{debug, info};
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Metrics about pim's own generation runs, rendered in the Prometheus text exposition format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    inner: Mutex<MetricsData>,
+}
+
+/// A snapshot of the metric values.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MetricsData {
+    pub runs_total: u64,
+    pub parse_errors_total: u64,
+    pub write_failures_total: u64,
+    pub sources_parsed: u64,
+    pub targets_generated: BTreeMap<String, u64>,
+    pub last_success_timestamp_seconds: f64,
+    pub last_run_duration_seconds: f64,
+    pub last_run_success: bool,
+}
+
+impl MetricsData {
+    /// Seconds since the last successful run, or None if there has not been one.
+    pub fn last_success_age(&self) -> Option<f64> {
+        if self.last_success_timestamp_seconds <= 0.0 {
+            return None;
+        }
+
+        Some((unix_now() - self.last_success_timestamp_seconds).max(0.0))
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Get a copy of the current metric values.
+    pub fn snapshot(&self) -> MetricsData {
+        self.with(|data| data.clone())
+    }
+
+    /// Record the start of a run.
+    pub fn record_run(&self) {
+        self.with(|data| {
+            data.runs_total += 1;
+            data.last_run_success = false;
+        });
+    }
+
+    pub fn record_parse_error(&self) {
+        self.with(|data| data.parse_errors_total += 1);
+    }
+
+    pub fn record_write_failure(&self) {
+        self.with(|data| data.write_failures_total += 1);
+    }
+
+    pub fn record_duration(&self, duration: Duration) {
+        self.with(|data| data.last_run_duration_seconds = duration.as_secs_f64());
+    }
+
+    /// Record a successful run with the number of sources parsed and the generated targets.
+    pub fn record_success(&self, sources: usize, target_files: &TargetFiles) {
+        debug!("Recording successful run in metrics");
+        let targets: BTreeMap<String, u64> = target_files
+            .iter()
+            .map(|(job, file)| {
+                let count: usize = file.targets().iter().map(|tg| tg.targets().len()).sum();
+                (job.clone(), count as u64)
+            })
+            .collect();
+        let now = unix_now();
+
+        self.with(|data| {
+            data.sources_parsed = sources as u64;
+            data.targets_generated = targets;
+            data.last_success_timestamp_seconds = now;
+            data.last_run_success = true;
+        });
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let data = self.snapshot();
+        let mut out = String::new();
+        metric(
+            &mut out,
+            "pim_runs_total",
+            "counter",
+            "Total number of generation runs.",
+            &[(String::new(), data.runs_total as f64)],
+        );
+        metric(
+            &mut out,
+            "pim_parse_errors_total",
+            "counter",
+            "Total number of runs that failed to parse sources.",
+            &[(String::new(), data.parse_errors_total as f64)],
+        );
+        metric(
+            &mut out,
+            "pim_write_failures_total",
+            "counter",
+            "Total number of runs that failed to write target files.",
+            &[(String::new(), data.write_failures_total as f64)],
+        );
+        metric(
+            &mut out,
+            "pim_sources_parsed",
+            "gauge",
+            "Number of sources parsed in the last successful run.",
+            &[(String::new(), data.sources_parsed as f64)],
+        );
+        let targets: Vec<(String, f64)> = data
+            .targets_generated
+            .iter()
+            .map(|(job, count)| (format!("{{job=\"{}\"}}", escape_label(job)), *count as f64))
+            .collect();
+        metric(
+            &mut out,
+            "pim_targets_generated",
+            "gauge",
+            "Number of targets generated per job in the last successful run.",
+            &targets,
+        );
+        metric(
+            &mut out,
+            "pim_last_success_timestamp_seconds",
+            "gauge",
+            "Unix timestamp of the last successful run.",
+            &[(String::new(), data.last_success_timestamp_seconds)],
+        );
+        metric(
+            &mut out,
+            "pim_last_run_success",
+            "gauge",
+            "Whether the last run succeeded (1) or failed (0).",
+            &[(String::new(), if data.last_run_success { 1.0 } else { 0.0 })],
+        );
+        metric(
+            &mut out,
+            "pim_last_run_duration_seconds",
+            "gauge",
+            "Duration of the last run in seconds.",
+            &[(String::new(), data.last_run_duration_seconds)],
+        );
+
+        out
+    }
+
+    /// Push the metrics to a Prometheus Pushgateway. The grouping labels are added to the URL,
+    /// base64 encoded so values may contain slashes.
+    pub fn push(&self, url: &str, job: &str, grouping: &[(&str, &str)]) -> Result<()> {
+        let mut push_url = format!(
+            "{}/metrics/job@base64/{}",
+            url.trim_end_matches('/'),
+            base64_url(job)
+        );
+        for (name, value) in grouping {
+            push_url.push_str(&format!("/{}@base64/{}", name, base64_url(value)));
+        }
+
+        info!("Pushing metrics to {}", push_url);
+        let response = http::send(
+            "PUT",
+            &push_url,
+            &[("Content-Type", "text/plain; version=0.0.4")],
+            self.render().as_bytes(),
+        )?;
+        if !(200..300).contains(&response.status) {
+            return Err(Error::new(SourceError::Msg(format!(
+                "Pushgateway returned status {}: {}",
+                response.status,
+                String::from_utf8_lossy(&response.body).trim()
+            )))
+            .set_context("Failed to push metrics")
+            .set_kind(ErrorKind::Network));
+        }
+
+        Ok(())
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut MetricsData) -> T) -> T {
+        match self.inner.lock() {
+            Ok(mut data) => f(&mut data),
+            Err(poisoned) => f(&mut poisoned.into_inner()),
+        }
+    }
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+// Write a single metric family. Each sample is a label set, including braces, and a value.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, f64)]) {
+    out.push_str(&format!(
+        "# HELP {} {}\n# TYPE {} {}\n",
+        name, help, name, kind
+    ));
+    for (labels, value) in samples {
+        out.push_str(&format!("{}{} {}\n", name, labels, value));
+    }
+}
+
+// Encode a grouping label value with the URL safe base64 alphabet as expected by the Pushgateway.
+// An empty value is encoded as `=` since the Pushgateway can't take empty path segments.
+fn base64_url(value: &str) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    if value.is_empty() {
+        return "=".to_string();
+    }
+
+    let mut out = String::new();
+    for chunk in value.as_bytes().chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        out.push(ALPHABET[(n >> 18) as usize & 63] as char);
+        out.push(ALPHABET[(n >> 12) as usize & 63] as char);
+        if chunk.len() > 1 {
+            out.push(ALPHABET[(n >> 6) as usize & 63] as char);
+        }
+        if chunk.len() > 2 {
+            out.push(ALPHABET[n as usize & 63] as char);
+        }
+    }
+
+    out
+}
+
+/// Escape a label value for the Prometheus text exposition format.
+pub fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_metrics_render() {
+        let metrics = Metrics::new();
+        metrics.record_run();
+        metrics.record_parse_error();
+        let out = metrics.render();
+        assert!(out.contains("# TYPE pim_runs_total counter\npim_runs_total 1\n"));
+        assert!(out.contains("pim_parse_errors_total 1\n"));
+        assert!(out.contains("pim_write_failures_total 0\n"));
+    }
+
+    #[test]
+    fn test_base64_url() {
+        assert_eq!(base64_url("/etc/prometheus"), "L2V0Yy9wcm9tZXRoZXVz");
+        assert_eq!(base64_url("ab"), "YWI");
+        assert_eq!(base64_url(""), "=");
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("a\"b\\c\n"), "a\\\"b\\\\c\\n");
+    }
+}
@@ -0,0 +1,507 @@
+use crate::app::source::{
+    SOURCE_API_VERSION, SOURCE_VERSIONS, Source, SourceDocument, parse_version,
+};
+use crate::core::decrypt;
+use crate::core::error::*;
+use crate::core::hcl;
+use crate::core::input::InputFormat;
+use crate::core::output::Style;
+use crate::core::yaml::{self, BlockList, Item};
+use log::warn;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+// Source documents are migrated by editing their text instead of serializing the parsed sources
+// again, so comments, ordering, and formatting are kept. Documents whose sources need changes
+// that can't be made to the text are written again from their sources. Every migration is
+// checked by reading the migrated document back and comparing its sources with the upgraded
+// ones.
+
+/// The changes made to each source to upgrade it to a version of the source format.
+struct Step {
+    /// The version the sources are upgraded to.
+    version: u32,
+    /// Fields renamed, from their old name to their new one.
+    renames: &'static [(&'static str, &'static str)],
+    /// Fields holding a list, where a single value becomes a list of one.
+    lists: &'static [&'static str],
+    /// Fields added to sources without them, with their default value as YAML flow.
+    defaults: &'static [(&'static str, &'static str)],
+}
+
+/// The steps upgrading sources, oldest first. Documents without a version are upgraded by every
+/// step. Files written before the format had a version often give a single `job` or `target`,
+/// and leave out `labels`, which the first version requires.
+const STEPS: &[Step] = &[Step {
+    version: 1,
+    renames: &[("job", "jobs"), ("target", "targets")],
+    lists: &["jobs", "targets"],
+    defaults: &[("labels", "{}")],
+}];
+
+/// The fields of a source in the order they are written.
+pub(crate) const FIELDS: &[&str] = &["jobs", "labels", "targets", "id"];
+
+/// A versioned source document as it is written.
+#[derive(Serialize)]
+struct Document<'a> {
+    #[serde(rename = "apiVersion")]
+    api_version: &'a str,
+    sources: &'a [Source],
+}
+
+/// Upgrade a source document to the current version of the source format. Returns None when the
+/// document is already at the current version.
+pub fn migrate(text: &str, format: &InputFormat) -> Result<Option<String>> {
+    if decrypt::is_age(text.as_bytes()) || decrypt::is_sops(text, format) {
+        return Err(Error::new(SourceError::Msg(
+            "Encrypted sources can't be migrated, decrypt them first".to_string(),
+        ))
+        .set_kind(ErrorKind::Validation));
+    }
+
+    let (version, mut items) = read_items(text, format)?;
+    let current = SOURCE_VERSIONS.last().copied().unwrap_or(1);
+    let from = match version {
+        Some(version) if version == current => return Ok(None),
+        Some(version) => version,
+        None => 0,
+    };
+    let steps: Vec<&Step> = STEPS.iter().filter(|s| s.version > from).collect();
+    let mut changed = false;
+    for item in &mut items {
+        for step in &steps {
+            changed |= step.apply(item);
+        }
+    }
+    let upgrade_error = |e: String| {
+        Error::new(SourceError::Msg(e))
+            .set_context("The sources can't be upgraded to the current source format")
+            .set_kind(ErrorKind::Validation)
+    };
+    let sources: Vec<Source> = match format {
+        // YAML is read back as YAML, so scalars such as `9100` are read as strings.
+        InputFormat::Yaml => serde_yaml::to_string(&items)
+            .and_then(|text| serde_yaml::from_str(&text))
+            .map_err(|e| upgrade_error(e.to_string()))?,
+        _ => {
+            serde_json::from_value(Value::Array(items)).map_err(|e| upgrade_error(e.to_string()))?
+        }
+    };
+
+    // Documents without a version are lists of sources, which only need the version added once
+    // their sources are upgraded.
+    let edited = match (version, format) {
+        (Some(_), _) => None,
+        (None, InputFormat::Yaml) if changed => {
+            edit_yaml(text, &steps, &sources).map(|t| wrap_yaml(&t))
+        }
+        (None, _) if changed => None,
+        (None, format) => Some(wrap(text, format)?),
+    };
+    if let Some(edited) = edited {
+        if read(&edited, format).is_ok_and(|d| d.sources == sources) {
+            return Ok(Some(edited));
+        }
+    }
+
+    warn!("Writing the upgraded sources again, their comments and formatting are not kept");
+    let rendered = render(&sources, format)?;
+    if read(&rendered, format)?.sources != sources {
+        return Err(Error::new(SourceError::Msg(
+            "The migrated document has other sources than the original".to_string(),
+        ))
+        .set_kind(ErrorKind::Internal));
+    }
+    Ok(Some(rendered))
+}
+
+impl Step {
+    // Upgrade a source, returning whether it changed.
+    fn apply(&self, item: &mut Value) -> bool {
+        let Some(fields) = item.as_object_mut() else {
+            return false;
+        };
+        let mut changed = false;
+        for (old, new) in self.renames {
+            if fields.contains_key(*new) {
+                continue;
+            }
+            if let Some(value) = fields.remove(*old) {
+                fields.insert(new.to_string(), value);
+                changed = true;
+            }
+        }
+        for name in self.lists {
+            if let Some(value) = fields.get_mut(*name) {
+                if !value.is_array() && !value.is_null() {
+                    *value = Value::Array(vec![value.take()]);
+                    changed = true;
+                }
+            }
+        }
+        for (name, default) in self.defaults {
+            if !fields.contains_key(*name) {
+                let default = serde_yaml::from_str(default).unwrap_or(Value::Null);
+                fields.insert(name.to_string(), default);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+// Read the version of a document and its sources without reading them as sources, since sources
+// of older versions may not be valid sources anymore.
+fn read_items(text: &str, format: &InputFormat) -> Result<(Option<u32>, Vec<Value>)> {
+    let parse_error = |e: String| {
+        Error::new(SourceError::Msg(e))
+            .set_context("Failed to read source document")
+            .set_kind(ErrorKind::Parse)
+    };
+    let document: Value = match format {
+        InputFormat::Json => serde_json::from_str(text).map_err(|e| parse_error(e.to_string()))?,
+        InputFormat::Json5 => json5::from_str(text).map_err(|e| parse_error(e.to_string()))?,
+        InputFormat::Yaml => yaml::from_str(text).map_err(|e| parse_error(e.to_string()))?,
+        InputFormat::Hcl => {
+            let mut body = hcl::to_value(text)?;
+            let blocks = body
+                .get_mut("source")
+                .map(Value::take)
+                .unwrap_or_else(|| Value::Array(Vec::new()));
+            match body.get_mut("apiVersion") {
+                Some(version) => {
+                    let mut document = Map::new();
+                    document.insert("apiVersion".to_string(), version.take());
+                    document.insert("sources".to_string(), blocks);
+                    Value::Object(document)
+                }
+                None => blocks,
+            }
+        }
+        format => return Err(unsupported(format)),
+    };
+
+    match document {
+        Value::Array(items) => Ok((None, items)),
+        Value::Object(mut document) => {
+            let version = document
+                .get("apiVersion")
+                .or_else(|| document.get("version"))
+                .ok_or_else(|| parse_error("the document has no apiVersion".to_string()))?;
+            let version = parse_version(version).map_err(parse_error)?;
+            let items = match document.remove("sources") {
+                Some(Value::Array(items)) => items,
+                None | Some(Value::Null) => Vec::new(),
+                Some(_) => return Err(parse_error("sources must be a list".to_string())),
+            };
+            Ok((Some(version), items))
+        }
+        _ => Err(parse_error(
+            "expected a list of sources or a mapping with apiVersion and sources".to_string(),
+        )),
+    }
+}
+
+// Read the version and sources of a document.
+pub(crate) fn read(text: &str, format: &InputFormat) -> Result<SourceDocument> {
+    let parse_error = |e: String| {
+        Error::new(SourceError::Msg(e))
+            .set_context("Failed to read source document")
+            .set_kind(ErrorKind::Parse)
+    };
+    match format {
+        InputFormat::Json => serde_json::from_str(text).map_err(|e| parse_error(e.to_string())),
+        InputFormat::Json5 => json5::from_str(text).map_err(|e| parse_error(e.to_string())),
+        InputFormat::Yaml => yaml::from_str(text).map_err(|e| parse_error(e.to_string())),
+        InputFormat::Hcl => {
+            let mut body = hcl::to_value(text)?;
+            let version = match body.get("apiVersion") {
+                Some(version) => Some(parse_version(version).map_err(parse_error)?),
+                None => None,
+            };
+            let blocks = body
+                .get_mut("source")
+                .map(Value::take)
+                .unwrap_or_else(|| Value::Array(Vec::new()));
+            let sources: Vec<Source> =
+                serde_json::from_value(blocks).map_err(|e| parse_error(e.to_string()))?;
+            Ok(SourceDocument { version, sources })
+        }
+        format => Err(unsupported(format)),
+    }
+}
+
+// Add the current version to a list of sources.
+fn wrap(text: &str, format: &InputFormat) -> Result<String> {
+    Ok(match format {
+        InputFormat::Json => format!(
+            "{{\n  \"apiVersion\": \"{}\",\n  \"sources\": {}\n}}\n",
+            SOURCE_API_VERSION,
+            indent(text.trim(), "  ").trim_start()
+        ),
+        // JSON5 strings can continue on the next line, so its lines are left as they are.
+        InputFormat::Json5 => format!(
+            "{{\n  apiVersion: \"{}\",\n  sources: {}\n}}\n",
+            SOURCE_API_VERSION,
+            text.trim()
+        ),
+        InputFormat::Hcl => format!("apiVersion = \"{}\"\n\n{}", SOURCE_API_VERSION, text),
+        InputFormat::Yaml => wrap_yaml(text),
+        format => return Err(unsupported(format)),
+    })
+}
+
+// Nest a YAML list under `sources`. Comments, blank lines, and the document start before the
+// list stay at the top.
+fn wrap_yaml(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let start = lines
+        .iter()
+        .position(|l| {
+            let l = l.trim();
+            !l.is_empty() && !l.starts_with('#') && l != "---" && !l.starts_with('%')
+        })
+        .unwrap_or(lines.len());
+    let mut out = String::new();
+    for line in &lines[..start] {
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push_str(&format!("apiVersion: {}\nsources:\n", SOURCE_API_VERSION));
+    out.push_str(&indent(&lines[start..].join("\n"), "  "));
+    out.push('\n');
+    out
+}
+
+// Make the changes of the steps to each source of a YAML list, keeping the comments, anchors,
+// and formatting of its lines. Sources that can't be edited, such as sources in flow style, are
+// written again on their own. Returns None for documents that aren't block lists.
+fn edit_yaml(text: &str, steps: &[&Step], sources: &[Source]) -> Option<String> {
+    let mut list = BlockList::parse(text)?;
+    if list.items().len() != sources.len() {
+        return None;
+    }
+    for (item, source) in list.items_mut().iter_mut().zip(sources) {
+        // Aliases change with the source they point at.
+        if item.is_alias() {
+            continue;
+        }
+        let mut edited = item.clone();
+        match steps
+            .iter()
+            .try_for_each(|step| edit_item(&mut edited, step))
+        {
+            Some(()) => *item = edited,
+            None => {
+                warn!("Writing a source in flow style again, comments inside it are not kept");
+                item.replace(&yaml::to_string(source, &Style::default()).ok()?);
+            }
+        }
+    }
+    Some(list.to_string())
+}
+
+// Make the changes of a step to the lines of one source.
+fn edit_item(item: &mut Item, step: &Step) -> Option<()> {
+    for (old, new) in step.renames {
+        item.rename(old, new)?;
+    }
+    for name in step.lists {
+        item.to_list(name)?;
+    }
+    for (name, default) in step.defaults {
+        // Sources with merge keys can get the field from the mapping they merge.
+        if item
+            .fields()?
+            .iter()
+            .any(|f| f.name == *name || f.name == "<<")
+        {
+            continue;
+        }
+        // Added fields go before the first field that is written after them.
+        let later = match FIELDS.iter().position(|f| f == name) {
+            Some(pos) => &FIELDS[pos + 1..],
+            None => &[][..],
+        };
+        item.insert(name, default, later)?;
+    }
+    Some(())
+}
+
+// Indent every line that isn't blank.
+fn indent(text: &str, prefix: &str) -> String {
+    text.lines()
+        .map(|l| match l.trim().is_empty() {
+            true => String::new(),
+            false => format!("{}{}", prefix, l),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Write the sources as a document of the current version.
+fn render(sources: &[Source], format: &InputFormat) -> Result<String> {
+    let document = Document {
+        api_version: SOURCE_API_VERSION,
+        sources,
+    };
+    let serialize_error = |e: String| {
+        Error::new(SourceError::Msg(e))
+            .set_context("Failed to write migrated sources")
+            .set_kind(ErrorKind::Internal)
+    };
+    match format {
+        // JSON is also JSON5.
+        InputFormat::Json | InputFormat::Json5 => serde_json::to_string_pretty(&document)
+            .map(|s| s + "\n")
+            .map_err(|e| serialize_error(e.to_string())),
+        InputFormat::Yaml => {
+            serde_yaml::to_string(&document).map_err(|e| serialize_error(e.to_string()))
+        }
+        InputFormat::Hcl => Ok(render_hcl(sources)),
+        format => Err(unsupported(format)),
+    }
+}
+
+fn render_hcl(sources: &[Source]) -> String {
+    let list = |values: &[String]| {
+        let values: Vec<String> = values.iter().map(|v| hcl_string(v)).collect();
+        format!("[{}]", values.join(", "))
+    };
+    let mut out = format!("apiVersion = \"{}\"\n", SOURCE_API_VERSION);
+    for source in sources {
+        let labels: Vec<String> = source
+            .labels()
+            .iter()
+            .map(|(name, value)| format!("{} = {}", hcl_string(name), hcl_string(value)))
+            .collect();
+        let labels = match labels.is_empty() {
+            true => "{}".to_string(),
+            false => format!("{{ {} }}", labels.join(", ")),
+        };
+        out.push_str(&format!(
+            "\nsource {{\n  jobs    = {}\n  labels  = {}\n  targets = {}\n}}\n",
+            list(source.jobs()),
+            labels,
+            list(source.targets())
+        ));
+    }
+    out
+}
+
+// A quoted HCL string. JSON escapes are HCL escapes, and template sequences are escaped.
+fn hcl_string(value: &str) -> String {
+    Value::from(value)
+        .to_string()
+        .replace("${", "$${")
+        .replace("%{", "%%{")
+}
+
+fn unsupported(format: &InputFormat) -> Error {
+    Error::new(SourceError::UnsupportedInputFormat(
+        format.as_str().to_string(),
+    ))
+    .set_context("Only JSON, JSON5, YAML, and HCL sources can be migrated")
+    .set_kind(ErrorKind::Config)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_migrate() {
+        let yaml = "# inventory\n---\n- jobs: [node]\n  labels: {}\n  targets:\n    # web\n    - web1:9100\n";
+        let migrated = migrate(yaml, &InputFormat::Yaml).unwrap().unwrap();
+        assert_eq!(
+            migrated,
+            "# inventory\n---\napiVersion: pim/v1\nsources:\n  - jobs: [node]\n    labels: {}\n    \
+             targets:\n      # web\n      - web1:9100\n"
+        );
+        assert_eq!(migrate(&migrated, &InputFormat::Yaml).unwrap(), None);
+
+        let json = "[\n  {\"jobs\": [\"node\"], \"labels\": {}, \"targets\": [\"web1\"]}\n]\n";
+        let migrated = migrate(json, &InputFormat::Json).unwrap().unwrap();
+        assert_eq!(
+            migrated,
+            "{\n  \"apiVersion\": \"pim/v1\",\n  \"sources\": [\n    \
+             {\"jobs\": [\"node\"], \"labels\": {}, \"targets\": [\"web1\"]}\n  ]\n}\n"
+        );
+
+        let hcl = "source {\n  jobs = [\"node\"]\n  labels = {}\n  targets = [\"web1\"]\n}\n";
+        let migrated = migrate(hcl, &InputFormat::Hcl).unwrap().unwrap();
+        assert!(migrated.starts_with("apiVersion = \"pim/v1\"\n\nsource {"));
+        assert_eq!(migrate(&migrated, &InputFormat::Hcl).unwrap(), None);
+
+        assert!(migrate("apiVersion: v9\nsources: []\n", &InputFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_migrate_upgrades_sources() {
+        let yaml = "# hosts\n- job: node  # exporter\n  # web servers\n  targets:\n    - web1:9100\n\
+                    - targets: [db1]\n  jobs: [pg]\n  labels: {port: 9100}\n";
+        let migrated = migrate(yaml, &InputFormat::Yaml).unwrap().unwrap();
+        assert_eq!(
+            migrated,
+            "# hosts\napiVersion: pim/v1\nsources:\n  - jobs: [node]  # exporter\n    labels: {}\n    \
+             # web servers\n    targets:\n      - web1:9100\n  - targets: [db1]\n    \
+             jobs: [pg]\n    labels: {port: 9100}\n"
+        );
+
+        let expected = "apiVersion: pim/v1\nsources:\n  - jobs: [node]\n    labels: {}\n    \
+                        targets: [\"web1:9100\"]\n";
+        let expected = read(expected, &InputFormat::Yaml).unwrap().sources;
+        // Sources in flow style and other formats are written again.
+        for (text, format) in [
+            ("[{job: node, target: \"web1:9100\"}]", InputFormat::Yaml),
+            (
+                "[{\"job\": \"node\", \"targets\": [\"web1:9100\"]}]",
+                InputFormat::Json,
+            ),
+            ("[{job: 'node', target: 'web1:9100'}]", InputFormat::Json5),
+            (
+                "source \"web\" {\n  job = \"node\"\n  target = \"web1:9100\"\n}\n",
+                InputFormat::Hcl,
+            ),
+        ] {
+            let migrated = migrate(text, &format).unwrap().unwrap();
+            let document = read(&migrated, &format).unwrap();
+            assert_eq!(document.version, Some(1));
+            assert_eq!(document.sources, expected);
+        }
+
+        // Anchors and the comments around sources written again are kept.
+        let yaml = "# hosts\n- &web\n  job: node\n  targets: [web1]\n# same as web\n- *web\n\
+                    # db\n- {job: pg, target: db1}\n";
+        let migrated = migrate(yaml, &InputFormat::Yaml).unwrap().unwrap();
+        assert_eq!(
+            migrated,
+            "# hosts\napiVersion: pim/v1\nsources:\n  - &web\n    jobs: [node]\n    labels: {}\n    \
+             targets: [web1]\n  # same as web\n  - *web\n  # db\n  - jobs:\n    - pg\n    \
+             labels: {}\n    targets:\n    - db1\n"
+        );
+
+        assert!(migrate("- jobs: [node]\n", &InputFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_migrate_keeps_comments_and_anchors() {
+        let yaml = "# inventory\n- &web  # web servers\n  job: node  # exporter\n  \
+                    labels: &labels {env: prod}\n  target: web1\n# dev copy\n- <<: *web\n  \
+                    # dev hosts\n  target: dev1\n- jobs: [pg]\n  labels: *labels\n  targets: [db1]\n";
+        let migrated = migrate(yaml, &InputFormat::Yaml).unwrap().unwrap();
+        assert_eq!(
+            migrated,
+            "# inventory\napiVersion: pim/v1\nsources:\n  - &web  # web servers\n    \
+             jobs: [node]  # exporter\n    labels: &labels {env: prod}\n    targets: [web1]\n  \
+             # dev copy\n  - <<: *web\n    # dev hosts\n    targets: [dev1]\n  - jobs: [pg]\n    \
+             labels: *labels\n    targets: [db1]\n"
+        );
+        let sources = read(&migrated, &InputFormat::Yaml).unwrap().sources;
+        assert_eq!(sources[1].jobs(), &vec!["node".to_string()]);
+        assert_eq!(sources[2].labels()["env"], "prod");
+        assert_eq!(migrate(&migrated, &InputFormat::Yaml).unwrap(), None);
+    }
+}
@@ -1,5 +1,48 @@
-pub use self::source::{Source, SourceFile};
-pub use self::target::{TargetFile, TargetFiles, TargetGroup};
+pub use self::source::{Source, SourceBuilder, SourceFile};
+pub use self::target::{TargetFile, TargetFiles, TargetGroup, TargetSort};
 
+pub mod alloy;
+#[cfg(feature = "fs")]
+pub mod audit;
+pub mod cardinality;
+pub mod compliance;
+pub mod convert;
+pub mod count;
+pub mod diff;
+pub mod duplicates;
+pub mod flavor;
+#[cfg(feature = "fs")]
+pub mod fmt;
+#[cfg(feature = "fs")]
+pub mod git;
+#[cfg(feature = "fs")]
+pub mod hooks;
+#[cfg(feature = "fs")]
+pub mod hostlist;
+pub mod jobs;
+#[cfg(feature = "fs")]
+pub mod mapping;
+#[cfg(feature = "server")]
+pub mod metrics;
+#[cfg(feature = "fs")]
+pub mod migrate;
+pub mod otel;
+pub mod owners;
+pub mod params;
+#[cfg(feature = "plugin")]
+pub mod plugin;
+#[cfg(all(feature = "fs", feature = "server"))]
+pub mod probe;
+#[cfg(feature = "provider")]
+pub mod provider;
 pub mod source;
+#[cfg(feature = "fs")]
+pub mod space;
+pub mod stats;
 pub mod target;
+#[cfg(feature = "fs")]
+pub mod transform;
+#[cfg(feature = "fs")]
+pub mod versions;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
@@ -0,0 +1,97 @@
+use crate::core::error::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The name of the receiver when none is given.
+pub const DEFAULT_RECEIVER: &str = "prometheus/pim";
+
+#[derive(Serialize)]
+struct Config {
+    receivers: BTreeMap<String, Receiver>,
+}
+
+#[derive(Serialize)]
+struct Receiver {
+    config: PrometheusConfig,
+}
+
+#[derive(Serialize)]
+struct PrometheusConfig {
+    scrape_configs: Vec<ScrapeConfig>,
+}
+
+#[derive(Serialize)]
+struct ScrapeConfig {
+    job_name: String,
+    file_sd_configs: Vec<FileSdConfig>,
+}
+
+#[derive(Serialize)]
+struct FileSdConfig {
+    files: Vec<String>,
+}
+
+/// Render an OpenTelemetry Collector config snippet with a `prometheus` receiver holding a
+/// scrape config per job, reading the job's target file with `file_sd_configs`. Add the receiver
+/// to a metrics pipeline to use it.
+pub fn config(files: &[(String, PathBuf)], receiver_name: &str) -> Result<String> {
+    let scrape_configs = files
+        .iter()
+        .map(|(job, path)| ScrapeConfig {
+            job_name: escape(job),
+            file_sd_configs: vec![FileSdConfig {
+                files: vec![escape(&path.to_string_lossy())],
+            }],
+        })
+        .collect();
+    let receiver = Receiver {
+        config: PrometheusConfig { scrape_configs },
+    };
+    let config = Config {
+        receivers: BTreeMap::from([(receiver_name.to_string(), receiver)]),
+    };
+
+    let yaml = serde_yaml::to_string(&config).map_err(|e| {
+        Error::new(SourceError::SerdeYaml(e))
+            .set_context("Failed to serialize OpenTelemetry Collector config")
+            .set_kind(ErrorKind::Internal)
+    })?;
+    Ok(format!(
+        "# Generated by pim. Changes are overwritten.\n{}",
+        yaml
+    ))
+}
+
+// The collector expands `$` in config values as environment variables.
+fn escape(s: &str) -> String {
+    s.replace('$', "$$")
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let files = vec![(
+            "node-exporter".to_string(),
+            PathBuf::from("/etc/otelcol/targets/node-exporter_targets.json"),
+        )];
+        assert_eq!(
+            config(&files, DEFAULT_RECEIVER).unwrap(),
+            r#"# Generated by pim. Changes are overwritten.
+receivers:
+  prometheus/pim:
+    config:
+      scrape_configs:
+      - job_name: node-exporter
+        file_sd_configs:
+        - files:
+          - /etc/otelcol/targets/node-exporter_targets.json
+"#
+        );
+        assert_eq!(escape("a$b"), "a$$b");
+    }
+}
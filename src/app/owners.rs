@@ -0,0 +1,115 @@
+use crate::app::target::TargetFiles;
+use crate::core::table;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// The targets of target files grouped by the value of an ownership label, such as `team`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Owners {
+    pub label: String,
+    /// Targets sorted by owner, job, and target, with the unowned targets first.
+    pub targets: Vec<OwnedTarget>,
+    /// The number of groups without the label or with an empty value.
+    pub unowned_groups: usize,
+}
+
+/// A target of a job with the value of the ownership label, if its group has one.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct OwnedTarget {
+    pub owner: Option<String>,
+    pub job: String,
+    pub target: String,
+}
+
+impl Owners {
+    /// Group the targets of the target files by the value of `label`.
+    pub fn new(target_files: &TargetFiles, label: &str) -> Self {
+        let mut targets = BTreeSet::new();
+        let mut unowned_groups = 0;
+        for (job, target_file) in target_files {
+            for group in target_file.targets() {
+                let owner = group.labels().get(label).filter(|v| !v.is_empty());
+                if owner.is_none() {
+                    unowned_groups += 1;
+                }
+                for target in group.targets() {
+                    targets.insert(OwnedTarget {
+                        owner: owner.cloned(),
+                        job: job.clone(),
+                        target: target.clone(),
+                    });
+                }
+            }
+        }
+
+        Owners {
+            label: label.to_string(),
+            targets: targets.into_iter().collect(),
+            unowned_groups,
+        }
+    }
+
+    /// The targets without an owner.
+    pub fn unowned(&self) -> impl Iterator<Item = &OwnedTarget> {
+        self.targets.iter().filter(|t| t.owner.is_none())
+    }
+
+    /// Render the targets as CSV with the label, job, and target of each. Unowned targets have
+    /// an empty owner.
+    pub fn csv(&self) -> String {
+        let rows: Vec<Vec<String>> = self
+            .targets
+            .iter()
+            .map(|t| {
+                vec![
+                    t.owner.clone().unwrap_or_default(),
+                    t.job.clone(),
+                    t.target.clone(),
+                ]
+            })
+            .collect();
+        table::csv(&[&self.label, "job", "target"], &rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::app::target::{TargetFile, TargetGroup};
+    use crate::core::{Output, OutputFormat};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_owners() {
+        let output = Output::from_writer(std::io::sink(), OutputFormat::Json);
+        let mut file = TargetFile::new("node", &output, &OutputFormat::Json).unwrap();
+        for (team, targets) in [
+            (Some("db"), vec!["db1:9100"]),
+            (Some("web"), vec!["web1:9100"]),
+            (Some(""), vec!["tmp1:9100"]),
+            (None, vec!["old1:9100"]),
+        ] {
+            let mut labels = BTreeMap::new();
+            if let Some(team) = team {
+                labels.insert("team".to_string(), team.to_string());
+            }
+            let targets = targets.into_iter().map(String::from).collect();
+            file.add_target(TargetGroup::new("node", labels, targets));
+        }
+        let mut target_files = TargetFiles::default();
+        target_files.insert("node".to_string(), file);
+
+        let owners = Owners::new(&target_files, "team");
+        assert_eq!(owners.unowned_groups, 2);
+        assert_eq!(owners.unowned().count(), 2);
+        assert_eq!(
+            owners.csv(),
+            "team,job,target\n\
+             ,node,old1:9100\n\
+             ,node,tmp1:9100\n\
+             db,node,db1:9100\n\
+             web,node,web1:9100\n"
+        );
+    }
+}
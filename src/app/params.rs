@@ -0,0 +1,136 @@
+use crate::app::target::TargetFiles;
+use log::{debug, warn};
+use std::collections::BTreeSet;
+
+/// The prefix of labels Prometheus sends as URL parameters when scraping a target.
+pub const PARAM_PREFIX: &str = "__param_";
+
+/// Labels of a job sent as URL parameters, for exporters that take query parameters such as the
+/// module to use or the address to probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlParams {
+    pub job: String,
+    pub labels: Vec<String>,
+}
+
+/// Parse the URL parameters of a job from `job=label,label`.
+pub fn parse_url_params(s: &str) -> std::result::Result<UrlParams, String> {
+    let invalid = || {
+        format!(
+            "'{}' is not a job and its labels, such as snmp=module,auth",
+            s
+        )
+    };
+    let (job, labels) = s.split_once('=').ok_or_else(invalid)?;
+    let labels: Vec<String> = labels
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(str::to_string)
+        .collect();
+    match job.trim() {
+        "" => Err(invalid()),
+        _ if labels.is_empty() => Err(invalid()),
+        job => Ok(UrlParams {
+            job: job.to_string(),
+            labels,
+        }),
+    }
+}
+
+/// Copy the listed labels of each job's groups to `__param_<label>` labels, and remove the
+/// original labels when `drop` is set. Groups without a listed label are left without its
+/// parameter. Jobs that aren't generated are warned about.
+pub fn apply(target_files: &mut TargetFiles, params: &[UrlParams], drop: bool) {
+    let mut missing = BTreeSet::new();
+    for param in params {
+        let Some(target_file) = target_files.target_file_mut(&param.job) else {
+            missing.insert(param.job.as_str());
+            continue;
+        };
+
+        debug!(
+            "Sending labels {:?} of job '{}' as URL parameters",
+            param.labels, param.job
+        );
+        for group in target_file.mut_targets() {
+            let labels = group.mut_labels();
+            for label in &param.labels {
+                let value = match drop {
+                    true => labels.remove(label),
+                    false => labels.get(label).cloned(),
+                };
+                if let Some(value) = value {
+                    labels.insert(format!("{}{}", PARAM_PREFIX, label), value);
+                }
+            }
+        }
+    }
+
+    for job in missing {
+        warn!("No targets for job '{}' to send URL parameters for", job);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::app::target::{TargetFile, TargetGroup};
+    use crate::core::output::{Output, OutputFormat};
+    use std::collections::BTreeMap;
+
+    fn target_files() -> TargetFiles {
+        let output = Output::from_writer(std::io::sink(), OutputFormat::Json);
+        let mut file = TargetFile::new("snmp", &output, &OutputFormat::Json).unwrap();
+        let labels = BTreeMap::from([
+            ("module".to_string(), "if_mib".to_string()),
+            ("env".to_string(), "prod".to_string()),
+        ]);
+        file.add_target(TargetGroup::new(
+            "snmp",
+            labels,
+            vec!["switch1".to_string()],
+        ));
+        let mut files = TargetFiles::default();
+        files.insert("snmp".to_string(), file);
+        files
+    }
+
+    fn labels(files: &TargetFiles) -> Vec<String> {
+        let group = &files.get("snmp").unwrap().targets()[0];
+        group.labels().keys().cloned().collect()
+    }
+
+    #[test]
+    fn test_parse_url_params() {
+        let params = parse_url_params("snmp = module, auth").unwrap();
+        assert_eq!(params.job, "snmp");
+        assert_eq!(params.labels, vec!["module", "auth"]);
+        assert!(parse_url_params("snmp").is_err());
+        assert!(parse_url_params("=module").is_err());
+        assert!(parse_url_params("snmp=").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let params = [parse_url_params("snmp=module,auth").unwrap()];
+        let mut files = target_files();
+        apply(&mut files, &params, false);
+        assert_eq!(labels(&files), ["__param_module", "env", "job", "module"]);
+        let group = &files.get("snmp").unwrap().targets()[0];
+        assert_eq!(group.labels()["__param_module"], "if_mib");
+
+        let mut files = target_files();
+        apply(&mut files, &params, true);
+        assert_eq!(labels(&files), ["__param_module", "env", "job"]);
+
+        let mut files = target_files();
+        apply(
+            &mut files,
+            &[parse_url_params("node=module").unwrap()],
+            true,
+        );
+        assert_eq!(labels(&files), ["env", "job", "module"]);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::app::target::{TargetFiles, TargetGroup};
+use crate::core::error::*;
+use log::{debug, info};
+use mlua::{Function, Lua, LuaOptions, LuaSerdeExt, StdLib, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// A plugin is a Lua script run in process. It defines a `transform` function that is called
+// with every target group as a table, `{job = "node", labels = {...}, targets = {...}}`, and
+// returns the group to keep, changed or not, or nil to drop it. Scripts run in a sandbox with
+// only the base, string, table, math, and utf8 libraries, so they can't read files, run
+// commands, or write to stdout.
+
+/// The memory a plugin can use, so a runaway script fails the run instead of the host.
+pub const MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+
+/// A target group as the plugin reads it.
+#[derive(Debug, Serialize)]
+struct PluginInput<'a> {
+    job: &'a str,
+    labels: &'a BTreeMap<String, String>,
+    targets: &'a [String],
+}
+
+/// A target group as the plugin returns it. The job of a group can't be changed.
+#[derive(Debug, Deserialize)]
+struct PluginOutput {
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    targets: Vec<String>,
+}
+
+/// Pass every target group through the `transform` function of the Lua script at `plugin`,
+/// replacing each with the group it returns or dropping it.
+pub fn transform(plugin: &Path, target_files: &mut TargetFiles) -> Result<()> {
+    let script = std::fs::read_to_string(plugin).map_err(|e| {
+        Error::new(SourceError::Io(e))
+            .set_context(&format!("reading plugin: {}", plugin.display()))
+            .set_kind(ErrorKind::Config)
+    })?;
+    transform_script(&plugin.display().to_string(), &script, target_files)
+}
+
+fn transform_script(name: &str, script: &str, target_files: &mut TargetFiles) -> Result<()> {
+    let lua = sandbox(name).map_err(|e| plugin_error(name, e.to_string()))?;
+    lua.load(script)
+        .set_name(name)
+        .exec()
+        .map_err(|e| plugin_error(name, e.to_string()))?;
+    let function: Function = match lua.globals().get("transform") {
+        Ok(Value::Function(function)) => function,
+        _ => {
+            return Err(plugin_error(
+                name,
+                "does not define a transform function".to_string(),
+            ));
+        }
+    };
+
+    let mut passed = 0;
+    let mut dropped = 0;
+    for (job, target_file) in target_files.iter_mut() {
+        let groups = std::mem::take(target_file.mut_targets());
+        for group in groups {
+            let input = lua
+                .to_value(&PluginInput {
+                    job,
+                    labels: group.labels(),
+                    targets: group.targets(),
+                })
+                .map_err(|e| plugin_error(name, e.to_string()))?;
+            let output: Value = function
+                .call(input)
+                .map_err(|e| plugin_error(name, e.to_string()))?;
+            let changed: Option<PluginOutput> = match output {
+                Value::Nil => None,
+                Value::Table(_) => Some(lua.from_value(output).map_err(|e| {
+                    plugin_error(name, format!("returned an invalid group: {}", e))
+                })?),
+                other => {
+                    return Err(plugin_error(
+                        name,
+                        format!("returned a {} instead of a group or nil", other.type_name()),
+                    ));
+                }
+            };
+            passed += 1;
+            match changed {
+                Some(changed) => target_file.add_target(
+                    TargetGroup::new(job, changed.labels, changed.targets)
+                        .with_id(group.id().map(String::from)),
+                ),
+                None => dropped += 1,
+            }
+        }
+    }
+    debug!("Passed {} target groups to plugin '{}'", passed, name);
+    info!("Plugin '{}' dropped {} target groups", name, dropped);
+    Ok(())
+}
+
+// A Lua state without the io, os, package, and debug libraries. `print` writes to the log
+// instead of stdout, which may be an output.
+fn sandbox(name: &str) -> mlua::Result<Lua> {
+    let libs = StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8;
+    let lua = Lua::new_with(libs, LuaOptions::new())?;
+    lua.set_memory_limit(MEMORY_LIMIT)?;
+
+    let globals = lua.globals();
+    for unsafe_function in ["dofile", "loadfile"] {
+        globals.set(unsafe_function, Value::Nil)?;
+    }
+    let name = name.to_string();
+    let print = lua.create_function(move |_, args: mlua::Variadic<Value>| {
+        let text: Vec<String> = args
+            .iter()
+            .map(|v| v.to_string().unwrap_or_else(|_| v.type_name().to_string()))
+            .collect();
+        info!("{}: {}", name, text.join("\t"));
+        Ok(())
+    })?;
+    globals.set("print", print)?;
+    drop(globals);
+    Ok(lua)
+}
+
+fn plugin_error(plugin: &str, msg: String) -> Error {
+    Error::new(SourceError::Msg(format!("'{}' {}", plugin, msg)))
+        .set_context("Transform plugin failed")
+        .set_kind(ErrorKind::Config)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::app::target::TargetFile;
+    use crate::core::{Output, OutputFormat};
+
+    fn target_files() -> TargetFiles {
+        let output = Output::from_writer(std::io::sink(), OutputFormat::Json);
+        let mut file = TargetFile::new("node", &output, &OutputFormat::Json).unwrap();
+        for (env, target) in [("prod", "web1:9100"), ("dev", "dev1:9100")] {
+            let labels = BTreeMap::from([("env".to_string(), env.to_string())]);
+            file.add_target(TargetGroup::new("node", labels, vec![target.to_string()]));
+        }
+        let mut target_files = TargetFiles::default();
+        target_files.insert("node".to_string(), file);
+        target_files
+    }
+
+    #[test]
+    fn test_transform() {
+        let script = r#"
+            function transform(group)
+                if group.targets[1] == "dev1:9100" then
+                    return nil
+                end
+                group.labels.env = string.gsub(group.labels.env, "prod", "production")
+                group.labels.site = group.job .. "-dc1"
+                table.insert(group.targets, "web2:9100")
+                return group
+            end
+        "#;
+        let mut files = target_files();
+        transform_script("test.lua", script, &mut files).unwrap();
+        let groups = files.get("node").unwrap().targets();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].labels()["env"], "production");
+        assert_eq!(groups[0].labels()["site"], "node-dc1");
+        assert_eq!(groups[0].labels()["job"], "node");
+        assert_eq!(groups[0].targets(), &["web1:9100", "web2:9100"]);
+    }
+
+    #[test]
+    fn test_transform_errors() {
+        let fails = |script: &str| {
+            transform_script("test.lua", script, &mut target_files())
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(fails("x = 1").contains("does not define a transform function"));
+        assert!(fails("function transform(g) error('boom') end").contains("boom"));
+        assert!(fails("function transform(g) return 42 end").contains("returned a integer"));
+        assert!(fails("function transform(g) return {targets = 1} end").contains("invalid group"));
+        assert!(fails("function transform(g) os.execute('true') return g end").contains("os"));
+        assert!(fails("function transform(g) io.write('x') return g end").contains("io"));
+        assert!(
+            fails("function transform(g) dofile('/etc/passwd') return g end").contains("dofile")
+        );
+        assert!(fails("local s = 'x' while true do s = s .. s end").contains("memory"));
+    }
+}
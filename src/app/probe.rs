@@ -0,0 +1,530 @@
+use crate::app::target::{TargetFiles, TargetGroup};
+use crate::core::error::*;
+use crate::core::host::split_target;
+use crate::core::http::{self, Response};
+use crate::core::table;
+use log::{debug, info};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// The label added to unreachable targets when annotating.
+pub const UNREACHABLE_LABEL: &str = "pim_unreachable";
+
+/// The scheme of HTTP probes when neither the options nor the `__scheme__` label give one.
+pub const DEFAULT_SCHEME: &str = "http";
+
+/// The path of HTTP probes when neither the options nor the `__metrics_path__` label give one.
+pub const DEFAULT_METRICS_PATH: &str = "/metrics";
+
+/// How targets are probed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum Probe {
+    /// Open a TCP connection to the target's port. Targets without a port or a URL scheme to
+    /// take one from are skipped.
+    #[default]
+    Tcp,
+    /// Request the target's metrics endpoint and check for a 200 with Prometheus exposition
+    /// content. The `__scheme__` and `__metrics_path__` labels of the target's group override
+    /// the scheme and path. https uses the `curl` CLI.
+    Http,
+}
+
+/// Options shared by every probe of a run.
+#[derive(Debug, Clone)]
+pub struct ProbeOptions {
+    /// Time to wait for each probe.
+    pub timeout: Duration,
+    /// Number of probes run at the same time.
+    pub concurrency: usize,
+    /// Scheme of HTTP probes.
+    pub scheme: String,
+    /// Path of HTTP probes.
+    pub metrics_path: String,
+}
+
+impl Default for ProbeOptions {
+    fn default() -> Self {
+        ProbeOptions {
+            timeout: Duration::from_secs(2),
+            concurrency: 32,
+            scheme: DEFAULT_SCHEME.to_string(),
+            metrics_path: DEFAULT_METRICS_PATH.to_string(),
+        }
+    }
+}
+
+/// The outcome of probing a target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down,
+    /// The target couldn't be probed, such as a target without a port for a TCP probe. Skipped
+    /// targets count as reachable.
+    Skipped,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Status::Up => "up",
+            Status::Down => "down",
+            Status::Skipped => "skipped",
+        }
+    }
+}
+
+/// The result of probing a target of a job.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProbeResult {
+    pub job: String,
+    pub target: String,
+    pub status: Status,
+    /// What was found, such as the error or the number of samples.
+    pub detail: String,
+}
+
+// A target of a job and what to probe for it.
+struct Endpoint<'a> {
+    job: &'a str,
+    target: &'a str,
+    scheme: &'a str,
+    path: &'a str,
+}
+
+impl Probe {
+    /// Probe each target of each job, with up to `concurrency` probes at a time. Results are
+    /// sorted by job and target.
+    pub fn run(&self, target_files: &TargetFiles, options: &ProbeOptions) -> Vec<ProbeResult> {
+        let mut seen = BTreeSet::new();
+        let mut endpoints = Vec::new();
+        for (job, target_file) in target_files {
+            for group in target_file.targets() {
+                let scheme = group
+                    .labels()
+                    .get("__scheme__")
+                    .map_or(options.scheme.as_str(), |s| s.as_str());
+                let path = group
+                    .labels()
+                    .get("__metrics_path__")
+                    .map_or(options.metrics_path.as_str(), |s| s.as_str());
+                for target in group.targets() {
+                    if seen.insert((job.as_str(), target.as_str())) {
+                        endpoints.push(Endpoint {
+                            job,
+                            target,
+                            scheme,
+                            path,
+                        });
+                    }
+                }
+            }
+        }
+
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::new());
+        let workers = options.concurrency.clamp(1, endpoints.len().max(1));
+        info!(
+            "Probing {} targets with {} workers",
+            endpoints.len(),
+            workers
+        );
+
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    while let Some(endpoint) = endpoints.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let (status, detail) = match self {
+                            Probe::Tcp => tcp(endpoint.target, options.timeout),
+                            Probe::Http => http(endpoint, options.timeout),
+                        };
+                        debug!(
+                            "Probed '{}': {} {}",
+                            endpoint.target,
+                            status.as_str(),
+                            detail
+                        );
+                        let result = ProbeResult {
+                            job: endpoint.job.to_string(),
+                            target: endpoint.target.to_string(),
+                            status,
+                            detail,
+                        };
+                        results
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .push(result);
+                    }
+                });
+            }
+        });
+
+        let mut results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+        results.sort_by(|a, b| (&a.job, &a.target).cmp(&(&b.job, &b.target)));
+        results
+    }
+}
+
+/// The job and target pairs that are down.
+pub fn unreachable(results: &[ProbeResult]) -> BTreeSet<(String, String)> {
+    results
+        .iter()
+        .filter(|result| result.status == Status::Down)
+        .map(|result| (result.job.clone(), result.target.clone()))
+        .collect()
+}
+
+/// Render the results as a table.
+pub fn table(results: &[ProbeResult]) -> String {
+    let rows: Vec<Vec<String>> = results
+        .iter()
+        .map(|r| {
+            vec![
+                r.job.clone(),
+                r.target.clone(),
+                r.status.as_str().to_string(),
+                r.detail.clone(),
+            ]
+        })
+        .collect();
+    table::render(&["JOB", "TARGET", "STATUS", "DETAIL"], &rows)
+}
+
+/// Move the unreachable targets of each group into a copy of the group labeled
+/// `pim_unreachable="true"`.
+pub fn annotate(target_files: &mut TargetFiles, unreachable: &BTreeSet<(String, String)>) {
+    for (job, target_file) in target_files.iter_mut() {
+        let mut groups = Vec::new();
+        for mut group in std::mem::take(target_file.mut_targets()) {
+            let (dead, alive): (Vec<String>, Vec<String>) = group
+                .targets()
+                .iter()
+                .cloned()
+                .partition(|target| unreachable.contains(&(job.clone(), target.clone())));
+            if dead.is_empty() {
+                groups.push(group);
+                continue;
+            }
+
+            let mut labels = group.labels().clone();
+            labels.insert(UNREACHABLE_LABEL.to_string(), "true".to_string());
+            if !alive.is_empty() {
+                *group.mut_targets() = alive;
+                groups.push(group);
+            }
+            groups.push(TargetGroup::new(job, labels, dead));
+        }
+        *target_file.mut_targets() = groups;
+    }
+}
+
+/// Drop the unreachable targets, and the groups they leave without targets.
+pub fn prune(target_files: &mut TargetFiles, unreachable: &BTreeSet<(String, String)>) {
+    for (job, target_file) in target_files.iter_mut() {
+        target_file.mut_targets().retain_mut(|group| {
+            let before = group.targets().len();
+            group
+                .mut_targets()
+                .retain(|target| !unreachable.contains(&(job.clone(), target.clone())));
+            group.targets().len() == before || !group.targets().is_empty()
+        });
+    }
+}
+
+// Connect to the target.
+fn tcp(target: &str, timeout: Duration) -> (Status, String) {
+    let Some(address) = address(target, None) else {
+        return (Status::Skipped, "no port".to_string());
+    };
+
+    let addrs = match address.to_socket_addrs() {
+        Ok(addrs) => addrs,
+        Err(e) => return (Status::Down, format!("failed to resolve: {}", e)),
+    };
+    let mut error = "no addresses".to_string();
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => return (Status::Up, format!("connected to {}", addr)),
+            Err(e) => error = format!("{}: {}", addr, e),
+        }
+    }
+    (Status::Down, error)
+}
+
+// Request the metrics endpoint of the target and check the response.
+fn http(endpoint: &Endpoint, timeout: Duration) -> (Status, String) {
+    if endpoint.target.contains("://") {
+        return (Status::Skipped, "not a host:port target".to_string());
+    }
+    let Some(address) = address(endpoint.target, Some(endpoint.scheme)) else {
+        return (
+            Status::Skipped,
+            format!("unsupported scheme '{}'", endpoint.scheme),
+        );
+    };
+
+    let url = format!("{}://{}{}", endpoint.scheme, address, endpoint.path);
+    let res = match endpoint.scheme {
+        "https" => curl(&url, timeout),
+        _ => http::send_with_timeout("GET", &url, &[("Accept", "text/plain")], &[], timeout),
+    };
+    let response = match res {
+        Ok(response) => response,
+        // Errors with context span lines, which would break the table.
+        Err(e) => return (Status::Down, e.to_string().replace('\n', ": ")),
+    };
+
+    if response.status != 200 {
+        return (
+            Status::Down,
+            format!("{} returned {}", url, response.status),
+        );
+    }
+    match check_exposition(&response) {
+        Ok(samples) => (Status::Up, format!("{} samples", samples)),
+        Err(e) => (Status::Down, format!("{}: {}", url, e)),
+    }
+}
+
+// Request an https URL with curl, since pim doesn't implement TLS. HTTP/1.1 is forced so the
+// response can be read like any other.
+fn curl(url: &str, timeout: Duration) -> Result<Response> {
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--include", "--http1.1"])
+        .arg("--max-time")
+        .arg(format!("{:.3}", timeout.as_secs_f64()))
+        .arg("--")
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| Error::new(SourceError::Io(e)).set_context("running curl"))?;
+
+    if !output.status.success() {
+        return Err(Error::new(SourceError::Msg(format!(
+            "curl exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+        .set_kind(ErrorKind::Io));
+    }
+    http::read_response(&mut output.stdout.as_slice())
+}
+
+// Check that a response holds Prometheus or OpenMetrics exposition content, returning the number
+// of samples.
+fn check_exposition(response: &Response) -> std::result::Result<usize, String> {
+    let content_type = response
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        .map(|(_, value)| value.to_lowercase())
+        .unwrap_or_default();
+    if !content_type.starts_with("text/plain")
+        && !content_type.starts_with("application/openmetrics-text")
+    {
+        return Err(format!("unexpected content type '{}'", content_type));
+    }
+
+    let body = String::from_utf8_lossy(&response.body);
+    let mut samples = 0;
+    for (i, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !is_sample(line) {
+            return Err(format!("line {} is not a sample", i + 1));
+        }
+        samples += 1;
+    }
+    match samples {
+        0 => Err("no samples".to_string()),
+        samples => Ok(samples),
+    }
+}
+
+// Check a line is a sample: a metric name, optional labels, and a value.
+fn is_sample(line: &str) -> bool {
+    let name_end = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or(line.len());
+    let (name, mut rest) = line.split_at(name_end);
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        return false;
+    }
+
+    if rest.starts_with('{') {
+        // Skip to the closing brace, ignoring braces in quoted label values.
+        let mut quoted = false;
+        let mut escaped = false;
+        let mut end = None;
+        for (i, c) in rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                '}' if !quoted => {
+                    end = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        match end {
+            Some(end) => rest = &rest[end + 1..],
+            None => return false,
+        }
+    }
+
+    rest.split_whitespace()
+        .next()
+        .is_some_and(|value| value.parse::<f64>().is_ok())
+}
+
+// The address to connect to for a target. Targets without a port take it from their URL scheme
+// or, for HTTP probes, the probe scheme.
+fn address(target: &str, scheme: Option<&str>) -> Option<String> {
+    let (host, port) = split_target(target);
+    let scheme = target
+        .split_once("://")
+        .map(|(scheme, _)| scheme)
+        .or(scheme);
+    if scheme.is_some_and(|scheme| scheme != "http" && scheme != "https") {
+        return None;
+    }
+    let port = match (port, scheme) {
+        (Some(port), _) => port,
+        (None, Some("http")) => "80",
+        (None, Some("https")) => "443",
+        _ => return None,
+    };
+    match host.contains(':') {
+        true => Some(format!("[{}]:{}", host, port)),
+        false => Some(format!("{}:{}", host, port)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::app::target::TargetFile;
+    use crate::core::io::SharedBuffer;
+    use crate::core::output::{Output, OutputFormat};
+    use std::collections::BTreeMap;
+    use std::net::TcpListener;
+
+    fn target_files(groups: &[(&str, &[&str])]) -> TargetFiles {
+        let output = Output::from_writer(SharedBuffer::new(), OutputFormat::Json);
+        let mut file = TargetFile::new("node", &output, &OutputFormat::Json).unwrap();
+        for (env, targets) in groups {
+            let labels = BTreeMap::from([("env".to_string(), env.to_string())]);
+            let targets = targets.iter().map(|t| t.to_string()).collect();
+            file.add_target(TargetGroup::new("node", labels, targets));
+        }
+        let mut target_files = TargetFiles::default();
+        target_files.insert("node".to_string(), file);
+        target_files
+    }
+
+    #[test]
+    fn test_address() {
+        assert_eq!(address("web1:9100", None), Some("web1:9100".to_string()));
+        assert_eq!(address("[::1]:9100", None), Some("[::1]:9100".to_string()));
+        assert_eq!(
+            address("https://example.com/health", None),
+            Some("example.com:443".to_string())
+        );
+        assert_eq!(address("web1", None), None);
+        assert_eq!(address("web1", Some("http")), Some("web1:80".to_string()));
+        assert_eq!(address("web1:9100", Some("ftp")), None);
+    }
+
+    #[test]
+    fn test_run_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let alive = listener.local_addr().unwrap().to_string();
+        // Bind and drop a listener for a port nothing listens on.
+        let dead = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let files = target_files(&[("prod", &[alive.as_str(), dead.as_str(), "web1"])]);
+        let options = ProbeOptions {
+            concurrency: 2,
+            ..ProbeOptions::default()
+        };
+        let results = Probe::Tcp.run(&files, &options);
+        let statuses: Vec<(&str, Status)> = results
+            .iter()
+            .map(|r| (r.target.as_str(), r.status))
+            .collect();
+        let mut expected = vec![
+            (alive.as_str(), Status::Up),
+            (dead.as_str(), Status::Down),
+            ("web1", Status::Skipped),
+        ];
+        expected.sort();
+        assert_eq!(statuses, expected);
+        assert_eq!(
+            unreachable(&results),
+            BTreeSet::from([("node".to_string(), dead.clone())])
+        );
+        assert!(table(&results).starts_with("JOB   TARGET"));
+    }
+
+    #[test]
+    fn test_check_exposition() {
+        let response = |content_type: &str, body: &str| {
+            Response::new(200)
+                .with_header("Content-Type", content_type)
+                .with_body(body.as_bytes().to_vec())
+        };
+        let body = "# HELP up Up.\n# TYPE up gauge\nup 1\n\
+                    http_requests_total{code=\"200\",path=\"/a}\"} 3 1700000000\n\
+                    nan_metric NaN\n";
+        assert_eq!(
+            check_exposition(&response("text/plain; version=0.0.4", body)),
+            Ok(3)
+        );
+        assert!(check_exposition(&response("text/html", body)).is_err());
+        assert!(check_exposition(&response("text/plain", "<html></html>")).is_err());
+        assert!(check_exposition(&response("text/plain", "# only comments\n")).is_err());
+        assert!(!is_sample("up"));
+        assert!(!is_sample("1up 1"));
+        assert!(!is_sample("up{a=\"b\" 1"));
+    }
+
+    #[test]
+    fn test_annotate_and_prune() {
+        let groups: &[(&str, &[&str])] = &[("prod", &["a:1", "b:1"]), ("dev", &["c:1"])];
+        let unreachable = BTreeSet::from([
+            ("node".to_string(), "b:1".to_string()),
+            ("node".to_string(), "c:1".to_string()),
+        ]);
+
+        let mut annotated = target_files(groups);
+        annotate(&mut annotated, &unreachable);
+        let groups_out = annotated.get("node").unwrap().targets();
+        assert_eq!(groups_out.len(), 3);
+        assert_eq!(groups_out[0].targets(), &vec!["a:1".to_string()]);
+        assert_eq!(groups_out[1].targets(), &vec!["b:1".to_string()]);
+        assert_eq!(groups_out[1].labels()[UNREACHABLE_LABEL], "true");
+        assert_eq!(groups_out[1].labels()["env"], "prod");
+        assert_eq!(groups_out[2].labels()["env"], "dev");
+        assert_eq!(groups_out[2].labels()[UNREACHABLE_LABEL], "true");
+
+        let mut pruned = target_files(groups);
+        prune(&mut pruned, &unreachable);
+        let groups_out = pruned.get("node").unwrap().targets();
+        assert_eq!(groups_out.len(), 1);
+        assert_eq!(groups_out[0].targets(), &vec!["a:1".to_string()]);
+    }
+}
@@ -0,0 +1,456 @@
+use crate::app::provider::{Provider, host_port, label_name};
+use crate::app::source::Source;
+use crate::core::error::*;
+use log::{debug, info};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::process::Command;
+
+/// The cloud platform the cloud provider lists instances from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum CloudPlatform {
+    /// Amazon EC2 instances, listed with the `aws` CLI.
+    Ec2,
+    /// Google Compute Engine instances, listed with the `gcloud` CLI.
+    Gce,
+    /// Azure virtual machines, listed with the `az` CLI.
+    Azure,
+}
+
+impl CloudPlatform {
+    /// The label prefix, such as `ec2`.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            CloudPlatform::Ec2 => "ec2",
+            CloudPlatform::Gce => "gce",
+            CloudPlatform::Azure => "azure",
+        }
+    }
+}
+
+/// Discover running cloud instances with matching tags. Each instance with a private IP becomes
+/// a source with the private IP and a port as the target, and its tags as labels.
+///
+/// Instances are listed with the platform's CLI, which handles credentials and TLS, so it must
+/// be installed and logged in where pim runs. GCE labels are used as the instance tags.
+#[derive(Debug, Clone)]
+pub struct Cloud {
+    platform: CloudPlatform,
+    job: String,
+    port: u16,
+    scope: Option<String>,
+    tags: BTreeMap<String, String>,
+}
+
+// Amazon EC2 `describe-instances` output.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Ec2Output {
+    #[serde(default)]
+    reservations: Vec<Ec2Reservation>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Ec2Reservation {
+    #[serde(default)]
+    instances: Vec<Ec2Instance>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Ec2Instance {
+    instance_id: String,
+    #[serde(default)]
+    instance_type: String,
+    #[serde(default)]
+    private_ip_address: Option<String>,
+    #[serde(default)]
+    placement: Ec2Placement,
+    #[serde(default)]
+    state: Ec2State,
+    #[serde(default)]
+    tags: Vec<Ec2Tag>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Ec2Placement {
+    #[serde(default)]
+    availability_zone: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Ec2State {
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Ec2Tag {
+    key: String,
+    #[serde(default)]
+    value: String,
+}
+
+// Google Compute Engine `instances list` output.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GceInstance {
+    name: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    zone: String,
+    #[serde(default)]
+    machine_type: String,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    network_interfaces: Vec<GceInterface>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GceInterface {
+    #[serde(default, rename = "networkIP")]
+    network_ip: String,
+}
+
+// Azure `vm list --show-details` output.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureVm {
+    name: String,
+    #[serde(default)]
+    location: String,
+    #[serde(default)]
+    resource_group: String,
+    #[serde(default)]
+    power_state: Option<String>,
+    #[serde(default)]
+    private_ips: Option<String>,
+    #[serde(default)]
+    tags: Option<BTreeMap<String, String>>,
+}
+
+// The parts of an instance that are turned into a source.
+#[derive(Debug)]
+struct Instance {
+    ip: String,
+    tags: BTreeMap<String, String>,
+    labels: BTreeMap<String, String>,
+}
+
+impl Cloud {
+    /// Create a provider for `platform`. Targets use `job` as the job and `port` as the port.
+    pub fn new(platform: CloudPlatform, job: &str, port: u16) -> Self {
+        Cloud {
+            platform,
+            job: job.to_string(),
+            port,
+            scope: None,
+            tags: BTreeMap::new(),
+        }
+    }
+
+    /// Where to list instances: the EC2 region, GCE project, or Azure resource group. The CLI's
+    /// configured default is used when not set.
+    pub fn scope(mut self, scope: &str) -> Self {
+        self.scope = Some(scope.to_string());
+        self
+    }
+
+    /// Only discover instances with the tag `key` set to `value`.
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    // The command that lists instances as JSON.
+    fn command(&self) -> (&'static str, Vec<String>) {
+        let args: &[&str] = match self.platform {
+            CloudPlatform::Ec2 => &["ec2", "describe-instances", "--output", "json"],
+            CloudPlatform::Gce => &["compute", "instances", "list", "--format", "json"],
+            CloudPlatform::Azure => &["vm", "list", "--show-details", "--output", "json"],
+        };
+        let mut args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+
+        if let Some(scope) = &self.scope {
+            let flag = match self.platform {
+                CloudPlatform::Ec2 => "--region",
+                CloudPlatform::Gce => "--project",
+                CloudPlatform::Azure => "--resource-group",
+            };
+            args.extend([flag.to_string(), scope.clone()]);
+        }
+
+        let program = match self.platform {
+            CloudPlatform::Ec2 => "aws",
+            CloudPlatform::Gce => "gcloud",
+            CloudPlatform::Azure => "az",
+        };
+        (program, args)
+    }
+
+    // Parse the command output into running instances with a private IP.
+    fn instances(&self, output: &[u8]) -> Result<Vec<Instance>> {
+        let instances = match self.platform {
+            CloudPlatform::Ec2 => {
+                let output: Ec2Output = parse(output, self.platform)?;
+                output
+                    .reservations
+                    .into_iter()
+                    .flat_map(|r| r.instances)
+                    .filter(|i| i.state.name == "running")
+                    .filter_map(ec2_instance)
+                    .collect()
+            }
+            CloudPlatform::Gce => {
+                let output: Vec<GceInstance> = parse(output, self.platform)?;
+                output
+                    .into_iter()
+                    .filter(|i| i.status == "RUNNING")
+                    .filter_map(gce_instance)
+                    .collect()
+            }
+            CloudPlatform::Azure => {
+                let output: Vec<AzureVm> = parse(output, self.platform)?;
+                output
+                    .into_iter()
+                    .filter(|vm| vm.power_state.as_deref() == Some("VM running"))
+                    .filter_map(azure_instance)
+                    .collect()
+            }
+        };
+
+        Ok(instances)
+    }
+
+    // Convert instances with all of the tag filters into sources.
+    fn instances_to_sources(&self, instances: Vec<Instance>) -> Result<Vec<Source>> {
+        let prefix = self.platform.prefix();
+        let mut sources = Vec::new();
+        for instance in instances {
+            if !self
+                .tags
+                .iter()
+                .all(|(key, value)| instance.tags.get(key) == Some(value))
+            {
+                continue;
+            }
+
+            let mut labels = instance.labels;
+            for (key, value) in &instance.tags {
+                labels.insert(format!("{}_tag_{}", prefix, label_name(key)), value.clone());
+            }
+
+            sources.push(
+                Source::builder()
+                    .job(&self.job)
+                    .labels(labels)
+                    .target(&host_port(&instance.ip, self.port))
+                    .build()?,
+            );
+        }
+
+        Ok(sources)
+    }
+}
+
+impl Provider for Cloud {
+    fn name(&self) -> &str {
+        self.platform.prefix()
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        let (program, args) = self.command();
+        debug!("Running {} {}", program, args.join(" "));
+        let output = Command::new(program).args(&args).output().map_err(|e| {
+            Error::new(SourceError::Io(e)).set_context(&format!("running {}", program))
+        })?;
+        if !output.status.success() {
+            return Err(Error::new(SourceError::Msg(format!(
+                "{} exited with {}: {}",
+                program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+            .set_kind(ErrorKind::Io));
+        }
+
+        let sources = self.instances_to_sources(self.instances(&output.stdout)?)?;
+        info!(
+            "Discovered {} sources from {}",
+            sources.len(),
+            self.platform.prefix()
+        );
+        Ok(sources)
+    }
+}
+
+fn parse<T: DeserializeOwned>(output: &[u8], platform: CloudPlatform) -> Result<T> {
+    serde_json::from_slice(output).map_err(|e| {
+        Error::new(SourceError::SerdeJson(e))
+            .set_context(&format!("Failed to parse {} instances", platform.prefix()))
+    })
+}
+
+fn ec2_instance(instance: Ec2Instance) -> Option<Instance> {
+    let Some(ip) = instance.private_ip_address else {
+        debug!("Skipping {} without a private IP", instance.instance_id);
+        return None;
+    };
+
+    Some(Instance {
+        ip,
+        tags: instance
+            .tags
+            .into_iter()
+            .map(|t| (t.key, t.value))
+            .collect(),
+        labels: BTreeMap::from([
+            ("ec2_instance_id".to_string(), instance.instance_id),
+            ("ec2_instance_type".to_string(), instance.instance_type),
+            (
+                "ec2_availability_zone".to_string(),
+                instance.placement.availability_zone,
+            ),
+        ]),
+    })
+}
+
+fn gce_instance(instance: GceInstance) -> Option<Instance> {
+    let Some(ip) = instance
+        .network_interfaces
+        .into_iter()
+        .map(|i| i.network_ip)
+        .find(|ip| !ip.is_empty())
+    else {
+        debug!("Skipping {} without a private IP", instance.name);
+        return None;
+    };
+
+    // The zone and machine type are URLs ending in their names.
+    let last = |url: &str| url.rsplit('/').next().unwrap_or_default().to_string();
+    Some(Instance {
+        ip,
+        tags: instance.labels,
+        labels: BTreeMap::from([
+            ("gce_name".to_string(), instance.name),
+            ("gce_zone".to_string(), last(&instance.zone)),
+            ("gce_machine_type".to_string(), last(&instance.machine_type)),
+        ]),
+    })
+}
+
+fn azure_instance(vm: AzureVm) -> Option<Instance> {
+    // Private IPs are a comma separated list.
+    let Some(ip) = vm
+        .private_ips
+        .as_deref()
+        .and_then(|ips| ips.split(',').map(str::trim).find(|ip| !ip.is_empty()))
+        .map(String::from)
+    else {
+        debug!("Skipping {} without a private IP", vm.name);
+        return None;
+    };
+
+    Some(Instance {
+        ip,
+        tags: vm.tags.unwrap_or_default(),
+        labels: BTreeMap::from([
+            ("azure_name".to_string(), vm.name),
+            ("azure_location".to_string(), vm.location),
+            ("azure_resource_group".to_string(), vm.resource_group),
+        ]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        let cloud = Cloud::new(CloudPlatform::Ec2, "node", 9100).scope("us-east-1");
+        let (program, args) = cloud.command();
+        assert_eq!(program, "aws");
+        assert_eq!(args[args.len() - 2..], ["--region", "us-east-1"]);
+
+        let (program, args) = Cloud::new(CloudPlatform::Azure, "node", 9100).command();
+        assert_eq!(program, "az");
+        assert!(!args.contains(&"--resource-group".to_string()));
+    }
+
+    #[test]
+    fn test_ec2_sources() {
+        let json = r#"{"Reservations": [{"Instances": [
+            {"InstanceId": "i-1", "InstanceType": "t3.micro", "PrivateIpAddress": "10.0.0.1",
+             "Placement": {"AvailabilityZone": "us-east-1a"}, "State": {"Name": "running"},
+             "Tags": [{"Key": "env", "Value": "prod"}, {"Key": "aws:role", "Value": "web"}]},
+            {"InstanceId": "i-2", "PrivateIpAddress": "10.0.0.2", "State": {"Name": "stopped"},
+             "Tags": [{"Key": "env", "Value": "prod"}]},
+            {"InstanceId": "i-3", "PrivateIpAddress": "10.0.0.3", "State": {"Name": "running"},
+             "Tags": [{"Key": "env", "Value": "dev"}]}
+        ]}]}"#;
+        let cloud = Cloud::new(CloudPlatform::Ec2, "node", 9100).tag("env", "prod");
+        let instances = cloud.instances(json.as_bytes()).unwrap();
+        assert_eq!(instances.len(), 2);
+
+        let sources = cloud.instances_to_sources(instances).unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].targets(), &vec!["10.0.0.1:9100".to_string()]);
+        assert_eq!(sources[0].labels()["ec2_instance_id"], "i-1");
+        assert_eq!(sources[0].labels()["ec2_availability_zone"], "us-east-1a");
+        assert_eq!(sources[0].labels()["ec2_tag_aws_role"], "web");
+    }
+
+    #[test]
+    fn test_gce_sources() {
+        let json = r#"[
+            {"name": "web-1", "status": "RUNNING",
+             "zone": "https://www.googleapis.com/compute/v1/projects/p/zones/us-central1-a",
+             "machineType": "https://www.googleapis.com/compute/v1/projects/p/zones/us-central1-a/machineTypes/e2-small",
+             "labels": {"env": "prod"}, "networkInterfaces": [{"networkIP": "10.1.0.1"}]},
+            {"name": "web-2", "status": "RUNNING", "networkInterfaces": []}
+        ]"#;
+        let cloud = Cloud::new(CloudPlatform::Gce, "node", 9100);
+        let sources = cloud
+            .instances_to_sources(cloud.instances(json.as_bytes()).unwrap())
+            .unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].targets(), &vec!["10.1.0.1:9100".to_string()]);
+        assert_eq!(sources[0].labels()["gce_zone"], "us-central1-a");
+        assert_eq!(sources[0].labels()["gce_machine_type"], "e2-small");
+        assert_eq!(sources[0].labels()["gce_tag_env"], "prod");
+    }
+
+    #[test]
+    fn test_azure_sources() {
+        let json = r#"[
+            {"name": "vm1", "location": "eastus", "resourceGroup": "rg", "powerState": "VM running",
+             "privateIps": "10.2.0.4,10.2.0.5", "tags": {"env": "prod"}},
+            {"name": "vm2", "powerState": "VM deallocated", "privateIps": "10.2.0.6", "tags": null}
+        ]"#;
+        let cloud = Cloud::new(CloudPlatform::Azure, "node", 9100).tag("env", "prod");
+        let sources = cloud
+            .instances_to_sources(cloud.instances(json.as_bytes()).unwrap())
+            .unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].targets(), &vec!["10.2.0.4:9100".to_string()]);
+        assert_eq!(sources[0].labels()["azure_resource_group"], "rg");
+        assert_eq!(sources[0].labels()["azure_tag_env"], "prod");
+    }
+
+    #[test]
+    fn test_invalid_output() {
+        let cloud = Cloud::new(CloudPlatform::Ec2, "node", 9100);
+        assert!(cloud.instances(b"not json").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use crate::app::provider::{Provider, get_json, host_port, label_name, url_encode};
+use crate::app::source::Source;
+use crate::core::error::*;
+use log::{debug, info};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Discover services from a Consul catalog. Each service instance becomes a source with the
+/// service name as the job and `address:port` as the target.
+///
+/// Instances get `consul_datacenter`, `consul_node`, and `consul_service` labels. Tags of the
+/// form `key=value` become labels and other tags are joined into a `consul_tags` label.
+#[derive(Debug, Clone)]
+pub struct Consul {
+    address: String,
+    datacenter: Option<String>,
+    services: Vec<String>,
+    token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CatalogService {
+    #[serde(default)]
+    node: String,
+    #[serde(default)]
+    address: String,
+    #[serde(default)]
+    datacenter: String,
+    #[serde(default)]
+    service_name: String,
+    #[serde(default)]
+    service_address: String,
+    #[serde(default)]
+    service_port: u16,
+    #[serde(default)]
+    service_tags: Option<Vec<String>>,
+}
+
+impl Consul {
+    /// Create a provider for the Consul agent at `address`, such as `http://127.0.0.1:8500`.
+    pub fn new(address: &str) -> Self {
+        Consul {
+            address: address.trim_end_matches('/').to_string(),
+            datacenter: None,
+            services: Vec::new(),
+            token: None,
+        }
+    }
+
+    /// Query this datacenter instead of the agent's own.
+    pub fn datacenter(mut self, datacenter: &str) -> Self {
+        self.datacenter = Some(datacenter.to_string());
+        self
+    }
+
+    /// Only discover these services. All services are discovered when none are given.
+    pub fn services(mut self, services: Vec<String>) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// The ACL token sent as `X-Consul-Token`.
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let mut url = format!("{}{}", self.address, path);
+        if let Some(dc) = &self.datacenter {
+            url.push_str(&format!("?dc={}", url_encode(dc)));
+        }
+
+        match &self.token {
+            Some(token) => get_json(&url, &[("X-Consul-Token", token)]),
+            None => get_json(&url, &[]),
+        }
+    }
+}
+
+impl Provider for Consul {
+    fn name(&self) -> &str {
+        "consul"
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        let services = if self.services.is_empty() {
+            let catalog: BTreeMap<String, serde_json::Value> = self.get("/v1/catalog/services")?;
+            catalog.into_keys().collect()
+        } else {
+            self.services.clone()
+        };
+        debug!("Consul services to discover: {:?}", services);
+
+        let mut sources = Vec::new();
+        for service in &services {
+            let instances: Vec<CatalogService> =
+                self.get(&format!("/v1/catalog/service/{}", url_encode(service)))?;
+            sources.extend(instances_to_sources(instances)?);
+        }
+
+        info!("Discovered {} sources from Consul", sources.len());
+        Ok(sources)
+    }
+}
+
+// Convert catalog service instances into sources.
+fn instances_to_sources(instances: Vec<CatalogService>) -> Result<Vec<Source>> {
+    let mut sources = Vec::new();
+    for instance in instances {
+        let host = if instance.service_address.is_empty() {
+            &instance.address
+        } else {
+            &instance.service_address
+        };
+        if host.is_empty() || instance.service_port == 0 {
+            debug!(
+                "Skipping {} on {} without an address and port",
+                instance.service_name, instance.node
+            );
+            continue;
+        }
+
+        let mut labels = BTreeMap::new();
+        labels.insert("consul_datacenter".to_string(), instance.datacenter);
+        labels.insert("consul_node".to_string(), instance.node);
+        labels.insert("consul_service".to_string(), instance.service_name.clone());
+
+        let mut plain_tags = Vec::new();
+        for tag in instance.service_tags.unwrap_or_default() {
+            match tag.split_once('=') {
+                Some((key, value)) if !key.is_empty() => {
+                    labels.insert(label_name(key), value.to_string());
+                }
+                _ => plain_tags.push(tag),
+            }
+        }
+        if !plain_tags.is_empty() {
+            plain_tags.sort();
+            labels.insert("consul_tags".to_string(), plain_tags.join(","));
+        }
+
+        sources.push(
+            Source::builder()
+                .job(&instance.service_name)
+                .labels(labels)
+                .target(&host_port(host, instance.service_port))
+                .build()?,
+        );
+    }
+
+    Ok(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_instances_to_sources() {
+        let json = r#"[
+            {"Node": "web1", "Address": "10.0.0.1", "Datacenter": "dc1", "ServiceName": "web",
+             "ServiceAddress": "", "ServicePort": 8080, "ServiceTags": ["env=prod", "primary"]},
+            {"Node": "web2", "Address": "10.0.0.2", "Datacenter": "dc1", "ServiceName": "web",
+             "ServiceAddress": "10.1.0.2", "ServicePort": 8080, "ServiceTags": null},
+            {"Node": "web3", "Address": "10.0.0.3", "Datacenter": "dc1", "ServiceName": "web",
+             "ServicePort": 0}
+        ]"#;
+        let instances: Vec<CatalogService> = serde_json::from_str(json).unwrap();
+        let sources = instances_to_sources(instances).unwrap();
+        assert_eq!(sources.len(), 2);
+
+        assert_eq!(sources[0].jobs(), &vec!["web".to_string()]);
+        assert_eq!(sources[0].targets(), &vec!["10.0.0.1:8080".to_string()]);
+        assert_eq!(sources[0].labels()["env"], "prod");
+        assert_eq!(sources[0].labels()["consul_tags"], "primary");
+        assert_eq!(sources[0].labels()["consul_node"], "web1");
+
+        assert_eq!(sources[1].targets(), &vec!["10.1.0.2:8080".to_string()]);
+        assert!(!sources[1].labels().contains_key("consul_tags"));
+    }
+}
@@ -0,0 +1,486 @@
+#[cfg(unix)]
+use crate::app::provider::json_response;
+use crate::app::provider::{Provider, get_json, host_port};
+use crate::app::source::Source;
+use crate::core::error::*;
+#[cfg(unix)]
+use crate::core::http;
+use log::{debug, info};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The default Docker daemon address.
+pub const DEFAULT_DOCKER_HOST: &str = "unix:///var/run/docker.sock";
+
+/// The container label holding the metrics port.
+pub const DEFAULT_PORT_LABEL: &str = "prometheus.io/port";
+
+/// Discover containers that expose a metrics port, from a Docker daemon or a Compose file.
+///
+/// A container's metrics port is read from its port label, or is the default port when the
+/// container exposes it. Targets are the container address and metrics port, or the published
+/// port on the host when a host is set. For Compose files the container address is the service
+/// name. The job is the Compose service name or the container name.
+#[derive(Debug, Clone)]
+pub struct Docker {
+    location: DockerLocation,
+    label: String,
+    port: Option<u16>,
+    host: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DockerLocation {
+    Daemon(String),
+    Compose(PathBuf),
+}
+
+// A container from the daemon or a Compose service.
+#[derive(Debug, Default)]
+struct Container {
+    name: String,
+    address: Option<String>,
+    image: String,
+    labels: BTreeMap<String, String>,
+    // Private ports and the host ports they are published on.
+    ports: Vec<(u16, Option<u16>)>,
+}
+
+// A container in the daemon's `/containers/json` response.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiContainer {
+    #[serde(default)]
+    names: Vec<String>,
+    #[serde(default)]
+    image: String,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    ports: Vec<ApiPort>,
+    #[serde(default)]
+    network_settings: ApiNetworkSettings,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiPort {
+    private_port: u16,
+    #[serde(default)]
+    public_port: Option<u16>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiNetworkSettings {
+    #[serde(default)]
+    networks: BTreeMap<String, ApiNetwork>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiNetwork {
+    #[serde(default, rename = "IPAddress")]
+    ip_address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComposeFile {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    services: BTreeMap<String, ComposeService>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ComposeService {
+    #[serde(default)]
+    image: Option<String>,
+    #[serde(default)]
+    container_name: Option<String>,
+    #[serde(default)]
+    labels: ComposeLabels,
+    #[serde(default)]
+    ports: Vec<ComposePort>,
+    #[serde(default)]
+    expose: Vec<serde_yaml::Value>,
+}
+
+// Compose labels are a map or a list of `key=value` strings.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ComposeLabels {
+    Map(BTreeMap<String, serde_yaml::Value>),
+    List(Vec<String>),
+}
+
+impl Default for ComposeLabels {
+    fn default() -> Self {
+        ComposeLabels::List(Vec::new())
+    }
+}
+
+// Compose ports are `[ip:][published:]target[/protocol]` strings or long syntax maps.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ComposePort {
+    Long {
+        target: u16,
+        #[serde(default)]
+        published: Option<serde_yaml::Value>,
+    },
+    Short(serde_yaml::Value),
+}
+
+impl Docker {
+    /// Discover running containers from the daemon at `address`, a `unix://` socket or an
+    /// http:// URL such as `http://127.0.0.1:2375`.
+    pub fn daemon(address: &str) -> Self {
+        Docker::with_location(DockerLocation::Daemon(address.to_string()))
+    }
+
+    /// Discover services from the Compose file at `path`.
+    pub fn compose(path: &Path) -> Self {
+        Docker::with_location(DockerLocation::Compose(path.to_path_buf()))
+    }
+
+    fn with_location(location: DockerLocation) -> Self {
+        Docker {
+            location,
+            label: DEFAULT_PORT_LABEL.to_string(),
+            port: None,
+            host: None,
+        }
+    }
+
+    /// The container label holding the metrics port. Defaults to [`DEFAULT_PORT_LABEL`].
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = label.to_string();
+        self
+    }
+
+    /// Use this port for containers that expose it and have no port label.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Target the published port on this host instead of the container address, for a
+    /// Prometheus that can not reach container networks.
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_string());
+        self
+    }
+
+    fn containers(&self) -> Result<Vec<Container>> {
+        match &self.location {
+            DockerLocation::Daemon(address) => {
+                let containers: Vec<ApiContainer> = match address.strip_prefix("unix://") {
+                    #[cfg(unix)]
+                    Some(socket) => {
+                        let path = "/containers/json";
+                        let response = http::send_unix(
+                            Path::new(socket),
+                            "GET",
+                            path,
+                            &[("Accept", "application/json")],
+                            &[],
+                        )?;
+                        json_response(&format!("{}{}", address, path), response)?
+                    }
+                    #[cfg(not(unix))]
+                    Some(_) => {
+                        return Err(Error::new(SourceError::InvalidInputSource(format!(
+                            "Docker address '{}' is not supported on this platform",
+                            address
+                        )))
+                        .set_kind(ErrorKind::Config));
+                    }
+                    None => get_json(
+                        &format!("{}/containers/json", address.trim_end_matches('/')),
+                        &[],
+                    )?,
+                };
+                Ok(containers.into_iter().map(api_container).collect())
+            }
+            DockerLocation::Compose(path) => {
+                debug!("Reading Compose file: {}", path.display());
+                let content = std::fs::read_to_string(path).map_err(|e| {
+                    Error::new(SourceError::Io(e))
+                        .set_context(&format!("reading Compose file: {}", path.display()))
+                })?;
+                compose_containers(&content, path)
+            }
+        }
+    }
+
+    // Convert containers with a metrics port into sources.
+    fn containers_to_sources(&self, containers: Vec<Container>) -> Result<Vec<Source>> {
+        let mut sources = Vec::new();
+        for container in containers {
+            let port = match container.labels.get(&self.label) {
+                Some(port) => port.parse::<u16>().ok(),
+                None => self
+                    .port
+                    .filter(|p| container.ports.iter().any(|(private, _)| private == p)),
+            };
+            let Some(port) = port else {
+                debug!(
+                    "Skipping container {} without a metrics port",
+                    container.name
+                );
+                continue;
+            };
+
+            let target = match &self.host {
+                Some(host) => container
+                    .ports
+                    .iter()
+                    .find(|(private, _)| *private == port)
+                    .and_then(|(_, public)| *public)
+                    .map(|public| host_port(host, public)),
+                None => container.address.as_ref().map(|a| host_port(a, port)),
+            };
+            let Some(target) = target else {
+                debug!("Skipping container {} without an address", container.name);
+                continue;
+            };
+
+            let service = container.labels.get("com.docker.compose.service");
+            let mut labels = BTreeMap::from([
+                ("docker_container".to_string(), container.name.clone()),
+                ("docker_image".to_string(), container.image.clone()),
+            ]);
+            if let Some(project) = container.labels.get("com.docker.compose.project") {
+                labels.insert("docker_compose_project".to_string(), project.clone());
+            }
+            if let Some(service) = service {
+                labels.insert("docker_compose_service".to_string(), service.clone());
+            }
+
+            sources.push(
+                Source::builder()
+                    .job(service.unwrap_or(&container.name))
+                    .labels(labels)
+                    .target(&target)
+                    .build()?,
+            );
+        }
+
+        Ok(sources)
+    }
+}
+
+impl Provider for Docker {
+    fn name(&self) -> &str {
+        "docker"
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        let sources = self.containers_to_sources(self.containers()?)?;
+        info!("Discovered {} sources from Docker", sources.len());
+        Ok(sources)
+    }
+}
+
+fn api_container(container: ApiContainer) -> Container {
+    Container {
+        name: container
+            .names
+            .first()
+            .map(|n| n.trim_start_matches('/').to_string())
+            .unwrap_or_default(),
+        address: container
+            .network_settings
+            .networks
+            .into_values()
+            .map(|n| n.ip_address)
+            .find(|ip| !ip.is_empty()),
+        image: container.image,
+        labels: container.labels,
+        ports: container
+            .ports
+            .into_iter()
+            .map(|p| (p.private_port, p.public_port))
+            .collect(),
+    }
+}
+
+// Read the services in a Compose file as containers. Services are addressed by name, and the
+// project and service are added as the labels Compose gives containers.
+fn compose_containers(content: &str, path: &Path) -> Result<Vec<Container>> {
+    let compose: ComposeFile = serde_yaml::from_str(content).map_err(|e| {
+        Error::new(SourceError::SerdeYaml(e))
+            .set_context(&format!("parsing Compose file: {}", path.display()))
+    })?;
+
+    // Like Compose, default the project name to the directory name.
+    let project = compose.name.unwrap_or_else(|| {
+        std::fs::canonicalize(path)
+            .unwrap_or_else(|_| path.to_path_buf())
+            .parent()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    });
+
+    let mut containers = Vec::new();
+    for (name, service) in compose.services {
+        let mut labels: BTreeMap<String, String> = match service.labels {
+            ComposeLabels::Map(map) => map.into_iter().map(|(k, v)| (k, yaml_string(&v))).collect(),
+            ComposeLabels::List(list) => list
+                .iter()
+                .map(|l| match l.split_once('=') {
+                    Some((k, v)) => (k.to_string(), v.to_string()),
+                    None => (l.clone(), String::new()),
+                })
+                .collect(),
+        };
+        labels.insert("com.docker.compose.project".to_string(), project.clone());
+        labels.insert("com.docker.compose.service".to_string(), name.clone());
+
+        let mut ports: Vec<(u16, Option<u16>)> =
+            service.ports.iter().filter_map(compose_port).collect();
+        ports.extend(
+            service
+                .expose
+                .iter()
+                .filter_map(|p| yaml_string(p).split('/').next()?.parse().ok())
+                .map(|p| (p, None)),
+        );
+
+        containers.push(Container {
+            name: service.container_name.unwrap_or_else(|| name.clone()),
+            address: Some(name),
+            image: service.image.unwrap_or_default(),
+            labels,
+            ports,
+        });
+    }
+
+    Ok(containers)
+}
+
+// Parse a Compose port into the container port and published port.
+fn compose_port(port: &ComposePort) -> Option<(u16, Option<u16>)> {
+    match port {
+        ComposePort::Long { target, published } => Some((
+            *target,
+            published.as_ref().and_then(|p| yaml_string(p).parse().ok()),
+        )),
+        ComposePort::Short(value) => {
+            let value = yaml_string(value);
+            let value = value.split('/').next()?;
+            let mut parts = value.rsplit(':');
+            let target = parts.next()?.parse().ok()?;
+            let published = parts.next().and_then(|p| p.parse().ok());
+            Some((target, published))
+        }
+    }
+}
+
+fn yaml_string(value: &serde_yaml::Value) -> String {
+    match value {
+        serde_yaml::Value::String(s) => s.clone(),
+        serde_yaml::Value::Number(n) => n.to_string(),
+        serde_yaml::Value::Bool(b) => b.to_string(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn api_containers(json: &str) -> Vec<Container> {
+        let containers: Vec<ApiContainer> = serde_json::from_str(json).unwrap();
+        containers.into_iter().map(api_container).collect()
+    }
+
+    #[test]
+    fn test_api_containers_to_sources() {
+        let json = r#"[
+            {"Names": ["/app-web-1"], "Image": "nginx",
+             "Labels": {"prometheus.io/port": "9113", "com.docker.compose.project": "app",
+                        "com.docker.compose.service": "web"},
+             "Ports": [{"PrivatePort": 9113, "PublicPort": 19113, "Type": "tcp"}],
+             "NetworkSettings": {"Networks": {"app_default": {"IPAddress": "172.18.0.2"}}}},
+            {"Names": ["/node-exporter"], "Image": "prom/node-exporter",
+             "Ports": [{"PrivatePort": 9100, "Type": "tcp"}],
+             "NetworkSettings": {"Networks": {"bridge": {"IPAddress": "172.17.0.3"}}}},
+            {"Names": ["/redis"], "Image": "redis", "Ports": [{"PrivatePort": 6379}],
+             "NetworkSettings": {"Networks": {"bridge": {"IPAddress": "172.17.0.4"}}}}
+        ]"#;
+        let docker = Docker::daemon(DEFAULT_DOCKER_HOST).port(9100);
+        let sources = docker.containers_to_sources(api_containers(json)).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].jobs(), &vec!["web".to_string()]);
+        assert_eq!(sources[0].targets(), &vec!["172.18.0.2:9113".to_string()]);
+        assert_eq!(sources[0].labels()["docker_compose_project"], "app");
+        assert_eq!(sources[1].jobs(), &vec!["node-exporter".to_string()]);
+        assert_eq!(sources[1].targets(), &vec!["172.17.0.3:9100".to_string()]);
+
+        let sources = docker
+            .host("docker1.example.com")
+            .containers_to_sources(api_containers(json))
+            .unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(
+            sources[0].targets(),
+            &vec!["docker1.example.com:19113".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_compose_containers() {
+        let yaml = r#"
+name: shop
+services:
+  web:
+    image: nginx
+    labels:
+      prometheus.io/port: 9113
+    ports:
+      - "8080:80"
+      - "127.0.0.1:19113:9113/tcp"
+  node:
+    image: prom/node-exporter
+    container_name: node-exporter
+    labels: ["tier=infra"]
+    expose: ["9100"]
+  worker:
+    image: worker
+    ports:
+      - target: 9100
+        published: "19100"
+"#;
+        let path = Path::new("compose.yml");
+        let containers = compose_containers(yaml, path).unwrap();
+        assert_eq!(containers.len(), 3);
+        assert_eq!(
+            containers[1].ports,
+            vec![(80, Some(8080)), (9113, Some(19113))]
+        );
+        assert_eq!(containers[2].ports, vec![(9100, Some(19100))]);
+
+        let docker = Docker::compose(path).port(9100);
+        let sources = docker.containers_to_sources(containers).unwrap();
+        assert_eq!(sources.len(), 3);
+        assert_eq!(sources[0].jobs(), &vec!["node".to_string()]);
+        assert_eq!(sources[0].targets(), &vec!["node:9100".to_string()]);
+        assert_eq!(sources[0].labels()["docker_container"], "node-exporter");
+        assert_eq!(sources[1].targets(), &vec!["web:9113".to_string()]);
+        assert_eq!(sources[1].labels()["docker_compose_project"], "shop");
+        assert_eq!(sources[2].targets(), &vec!["worker:9100".to_string()]);
+
+        let sources = docker
+            .host("localhost")
+            .containers_to_sources(compose_containers(yaml, path).unwrap())
+            .unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].targets(), &vec!["localhost:19113".to_string()]);
+        assert_eq!(sources[1].targets(), &vec!["localhost:19100".to_string()]);
+    }
+}
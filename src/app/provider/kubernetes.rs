@@ -0,0 +1,371 @@
+use crate::app::provider::{Provider, block_on, host_port, label_name};
+use crate::app::source::Source;
+use crate::core::error::*;
+use k8s_openapi::NamespaceResourceScope;
+use k8s_openapi::api::core::v1::{Endpoints, Pod};
+use kube::api::{Api, ListParams};
+use kube::config::{KubeConfigOptions, Kubeconfig};
+use kube::{Client, Config};
+use log::{debug, info};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The annotation that selects pods in the pods role.
+pub const DEFAULT_SCRAPE_ANNOTATION: &str = "prometheus.io/scrape";
+
+/// What the Kubernetes provider discovers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum KubernetesRole {
+    /// One group per service port with the ready endpoint addresses as targets.
+    Endpoints,
+    /// One group per annotated pod with the pod IP and port as the target.
+    Pods,
+}
+
+/// Discover scrape targets from the Kubernetes API.
+///
+/// The API server and credentials are read from a kubeconfig context with kube, so client
+/// certificates, tokens, and exec plugins such as cloud login helpers all work.
+#[derive(Debug, Clone)]
+pub struct Kubernetes {
+    config: Config,
+    namespace: Option<String>,
+    role: KubernetesRole,
+    annotation: String,
+}
+
+impl Kubernetes {
+    /// Create a provider for the API server at `server` without credentials, such as
+    /// `http://127.0.0.1:8001` for a `kubectl proxy`.
+    pub fn new(server: &str, role: KubernetesRole) -> Result<Self> {
+        let uri = server.parse().map_err(|e| {
+            Error::new(SourceError::InvalidInputSource(format!(
+                "Invalid Kubernetes API server '{}': {}",
+                server, e
+            )))
+            .set_kind(ErrorKind::Config)
+        })?;
+        Ok(Kubernetes {
+            config: Config::new(uri),
+            namespace: None,
+            role,
+            annotation: DEFAULT_SCRAPE_ANNOTATION.to_string(),
+        })
+    }
+
+    /// Create a provider from a kubeconfig file, or from KUBECONFIG or `~/.kube/config` when
+    /// `path` is None. The current context is used when `context` is None, and the context's
+    /// namespace is used when set.
+    pub fn from_kubeconfig(
+        path: Option<&Path>,
+        context: Option<&str>,
+        role: KubernetesRole,
+    ) -> Result<Self> {
+        let kubeconfig = match path {
+            Some(path) => {
+                debug!("Reading kubeconfig: {}", path.display());
+                Kubeconfig::read_from(path)
+            }
+            None => Kubeconfig::read(),
+        }
+        .map_err(kubeconfig_error)?;
+        Self::from_kubeconfig_value(kubeconfig, context, role)
+    }
+
+    fn from_kubeconfig_value(
+        kubeconfig: Kubeconfig,
+        context: Option<&str>,
+        role: KubernetesRole,
+    ) -> Result<Self> {
+        // kube falls back to the `default` namespace, but a context without one lists all.
+        let name = context.or(kubeconfig.current_context.as_deref());
+        let namespace = kubeconfig
+            .contexts
+            .iter()
+            .find(|c| Some(c.name.as_str()) == name)
+            .and_then(|c| c.context.as_ref()?.namespace.clone());
+
+        let options = KubeConfigOptions {
+            context: context.map(String::from),
+            ..Default::default()
+        };
+        let config = block_on(Config::from_custom_kubeconfig(kubeconfig, &options))?
+            .map_err(kubeconfig_error)?;
+        Ok(Kubernetes {
+            config,
+            namespace,
+            role,
+            annotation: DEFAULT_SCRAPE_ANNOTATION.to_string(),
+        })
+    }
+
+    /// Only discover targets in this namespace. All namespaces are used when not set.
+    pub fn namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    /// The annotation that must be `"true"` for a pod to be discovered in the pods role. The
+    /// port is read from the `port` annotation with the same prefix, such as `prometheus.io/port`.
+    pub fn annotation(mut self, annotation: &str) -> Self {
+        self.annotation = annotation.to_string();
+        self
+    }
+
+    async fn list<K>(&self, client: Client) -> Result<Vec<K>>
+    where
+        K: kube::Resource<Scope = NamespaceResourceScope, DynamicType = ()>
+            + Clone
+            + DeserializeOwned
+            + std::fmt::Debug,
+    {
+        let api: Api<K> = match &self.namespace {
+            Some(ns) => Api::namespaced(client, ns),
+            None => Api::all(client),
+        };
+        let list = api.list(&ListParams::default()).await.map_err(|e| {
+            Error::new(SourceError::Msg(e.to_string()))
+                .set_context(&format!(
+                    "listing {} from {}",
+                    K::plural(&()),
+                    self.config.cluster_url
+                ))
+                .set_kind(ErrorKind::Network)
+        })?;
+        Ok(list.items)
+    }
+}
+
+impl Provider for Kubernetes {
+    fn name(&self) -> &str {
+        "kubernetes"
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        let sources = block_on(async {
+            let client = Client::try_from(self.config.clone()).map_err(|e| {
+                Error::new(SourceError::Msg(e.to_string()))
+                    .set_context("creating the Kubernetes client")
+                    .set_kind(ErrorKind::Config)
+            })?;
+            match self.role {
+                KubernetesRole::Endpoints => endpoints_to_sources(self.list(client).await?),
+                KubernetesRole::Pods => pods_to_sources(self.list(client).await?, &self.annotation),
+            }
+        })??;
+
+        info!("Discovered {} sources from Kubernetes", sources.len());
+        Ok(sources)
+    }
+}
+
+// An error reading a kubeconfig.
+fn kubeconfig_error(e: kube::config::KubeconfigError) -> Error {
+    Error::new(SourceError::Msg(e.to_string()))
+        .set_context("reading kubeconfig")
+        .set_kind(ErrorKind::Config)
+}
+
+// Convert endpoints into one source per service port, with the service name as the job.
+fn endpoints_to_sources(endpoints: Vec<Endpoints>) -> Result<Vec<Source>> {
+    let mut sources = Vec::new();
+    for endpoint in endpoints {
+        let name = endpoint.metadata.name.unwrap_or_default();
+        let namespace = endpoint.metadata.namespace.unwrap_or_default();
+        for subset in endpoint.subsets.iter().flatten() {
+            let addresses = subset.addresses.as_deref().unwrap_or_default();
+            if addresses.is_empty() {
+                continue;
+            }
+
+            for port in subset.ports.iter().flatten() {
+                let Ok(number) = u16::try_from(port.port) else {
+                    continue;
+                };
+                let mut labels = BTreeMap::from([
+                    ("kubernetes_namespace".to_string(), namespace.clone()),
+                    ("kubernetes_service".to_string(), name.clone()),
+                ]);
+                if let Some(port_name) = &port.name {
+                    labels.insert("kubernetes_port_name".to_string(), port_name.clone());
+                }
+
+                sources.push(
+                    Source::builder()
+                        .job(&name)
+                        .labels(labels)
+                        .targets(addresses.iter().map(|a| host_port(&a.ip, number)))
+                        .build()?,
+                );
+            }
+        }
+    }
+
+    Ok(sources)
+}
+
+// Convert running pods with the scrape annotation set to `"true"` into sources. The job is the
+// pod's `app.kubernetes.io/name` or `app` label, falling back to the pod name.
+fn pods_to_sources(pods: Vec<Pod>, annotation: &str) -> Result<Vec<Source>> {
+    let port_annotation = match annotation.rsplit_once('/') {
+        Some((prefix, _)) => format!("{}/port", prefix),
+        None => "port".to_string(),
+    };
+
+    let mut sources = Vec::new();
+    for pod in pods {
+        let meta = &pod.metadata;
+        let name = meta.name.clone().unwrap_or_default();
+        let namespace = meta.namespace.clone().unwrap_or_default();
+        let annotations = meta.annotations.clone().unwrap_or_default();
+        let pod_labels = meta.labels.clone().unwrap_or_default();
+        let status = pod.status.clone().unwrap_or_default();
+        let pod_ip = status.pod_ip.unwrap_or_default();
+        if annotations.get(annotation).map(String::as_str) != Some("true")
+            || status.phase.as_deref() != Some("Running")
+            || pod_ip.is_empty()
+        {
+            continue;
+        }
+
+        let spec = pod.spec.unwrap_or_default();
+        let port = match annotations.get(&port_annotation) {
+            Some(port) => port.parse::<u16>().ok(),
+            None => spec
+                .containers
+                .iter()
+                .flat_map(|c| c.ports.iter().flatten().next())
+                .find_map(|p| u16::try_from(p.container_port).ok()),
+        };
+        let Some(port) = port else {
+            debug!("Skipping pod {}/{} without a port", namespace, name);
+            continue;
+        };
+
+        let job = pod_labels
+            .get("app.kubernetes.io/name")
+            .or_else(|| pod_labels.get("app"))
+            .unwrap_or(&name);
+
+        let mut labels = BTreeMap::from([
+            ("kubernetes_namespace".to_string(), namespace.clone()),
+            ("kubernetes_pod_name".to_string(), name.clone()),
+            (
+                "kubernetes_node".to_string(),
+                spec.node_name.clone().unwrap_or_default(),
+            ),
+        ]);
+        for (key, value) in &pod_labels {
+            labels.insert(
+                format!("kubernetes_pod_label_{}", label_name(key)),
+                value.clone(),
+            );
+        }
+
+        sources.push(
+            Source::builder()
+                .job(job)
+                .labels(labels)
+                .target(&host_port(&pod_ip, port))
+                .build()?,
+        );
+    }
+
+    Ok(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_from_kubeconfig() {
+        let config = r#"
+apiVersion: v1
+kind: Config
+current-context: dev
+contexts:
+  - name: dev
+    context: {cluster: proxy, user: admin, namespace: monitoring}
+  - name: prod
+    context: {cluster: prod, user: admin}
+clusters:
+  - name: proxy
+    cluster: {server: "http://127.0.0.1:8001/"}
+  - name: prod
+    cluster: {server: "https://k8s.example.com:6443"}
+users:
+  - name: admin
+    user: {token: secret}
+"#;
+        let kubeconfig = || Kubeconfig::from_yaml(config).unwrap();
+        let k =
+            Kubernetes::from_kubeconfig_value(kubeconfig(), None, KubernetesRole::Pods).unwrap();
+        assert_eq!(k.config.cluster_url.to_string(), "http://127.0.0.1:8001/");
+        assert_eq!(k.namespace.as_deref(), Some("monitoring"));
+        assert!(k.config.auth_info.token.is_some());
+
+        let k = Kubernetes::from_kubeconfig_value(kubeconfig(), Some("prod"), KubernetesRole::Pods)
+            .unwrap();
+        assert_eq!(k.config.cluster_url.host(), Some("k8s.example.com"));
+        assert_eq!(k.namespace, None);
+
+        let err =
+            Kubernetes::from_kubeconfig_value(kubeconfig(), Some("nope"), KubernetesRole::Pods)
+                .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Config);
+    }
+
+    #[test]
+    fn test_endpoints_to_sources() {
+        let json = r#"[{
+            "metadata": {"name": "node-exporter", "namespace": "monitoring"},
+            "subsets": [{
+                "addresses": [{"ip": "10.1.0.5"}, {"ip": "10.1.0.6"}],
+                "ports": [{"name": "metrics", "port": 9100}]
+            }]
+        }, {
+            "metadata": {"name": "idle", "namespace": "default"},
+            "subsets": [{"ports": [{"port": 80}]}]
+        }]"#;
+        let endpoints: Vec<Endpoints> = serde_json::from_str(json).unwrap();
+        let sources = endpoints_to_sources(endpoints).unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].jobs(), &vec!["node-exporter".to_string()]);
+        assert_eq!(
+            sources[0].targets(),
+            &vec!["10.1.0.5:9100".to_string(), "10.1.0.6:9100".to_string()]
+        );
+        assert_eq!(sources[0].labels()["kubernetes_port_name"], "metrics");
+    }
+
+    #[test]
+    fn test_pods_to_sources() {
+        let json = r#"[{
+            "metadata": {"name": "api-1", "namespace": "web", "labels": {"app": "api"},
+                         "annotations": {"prometheus.io/scrape": "true", "prometheus.io/port": "8080"}},
+            "spec": {"nodeName": "node1", "containers": [{"name": "api", "ports": [{"containerPort": 80}]}]},
+            "status": {"podIP": "10.2.0.1", "phase": "Running"}
+        }, {
+            "metadata": {"name": "worker-1", "namespace": "web",
+                         "annotations": {"prometheus.io/scrape": "true"}},
+            "spec": {"containers": [{"name": "worker", "ports": [{"containerPort": 9000}]}]},
+            "status": {"podIP": "10.2.0.2", "phase": "Running"}
+        }, {
+            "metadata": {"name": "quiet", "namespace": "web"},
+            "spec": {"containers": [{"name": "quiet", "ports": [{"containerPort": 80}]}]},
+            "status": {"podIP": "10.2.0.3", "phase": "Running"}
+        }]"#;
+        let pods: Vec<Pod> = serde_json::from_str(json).unwrap();
+        let sources = pods_to_sources(pods, DEFAULT_SCRAPE_ANNOTATION).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].jobs(), &vec!["api".to_string()]);
+        assert_eq!(sources[0].targets(), &vec!["10.2.0.1:8080".to_string()]);
+        assert_eq!(sources[0].labels()["kubernetes_pod_label_app"], "api");
+        assert_eq!(sources[1].jobs(), &vec!["worker-1".to_string()]);
+        assert_eq!(sources[1].targets(), &vec!["10.2.0.2:9000".to_string()]);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::app::provider::{Provider, host_port};
+use crate::app::source::Source;
+use crate::core::error::*;
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The LDAP filter used when none is given. It matches Active Directory computer objects.
+pub const DEFAULT_FILTER: &str = "(objectClass=computer)";
+
+/// Discover computer objects from an LDAP directory such as Active Directory. Each entry with a
+/// `dNSHostName` becomes a source with the host name and a port as the target.
+///
+/// Entries are searched with `ldapsearch`, which handles TLS and must be installed where pim
+/// runs. Sources get `ldap_name`, `ldap_ou`, and `ldap_os` labels, where `ldap_ou` is the OU path
+/// from the entry's DN, such as `Servers/Web`. Other attributes are mapped to labels with
+/// [`Ldap::attribute`].
+#[derive(Debug, Clone)]
+pub struct Ldap {
+    uri: String,
+    base: String,
+    filter: String,
+    bind_dn: Option<String>,
+    password_file: Option<PathBuf>,
+    job: String,
+    port: u16,
+    attributes: BTreeMap<String, String>,
+}
+
+// A directory entry. Attribute names are lowercase.
+#[derive(Debug, Default)]
+struct Entry {
+    dn: String,
+    attributes: BTreeMap<String, Vec<String>>,
+}
+
+impl Entry {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .get(&name.to_lowercase())
+            .and_then(|v| v.first())
+            .map(String::as_str)
+    }
+}
+
+impl Ldap {
+    /// Create a provider that searches `base`, such as `DC=corp,DC=example,DC=com`, on the
+    /// directory at `uri`, such as `ldaps://dc1.corp.example.com`. Targets use `job` as the job
+    /// and `port` as the port.
+    pub fn new(uri: &str, base: &str, job: &str, port: u16) -> Self {
+        Ldap {
+            uri: uri.to_string(),
+            base: base.to_string(),
+            filter: DEFAULT_FILTER.to_string(),
+            bind_dn: None,
+            password_file: None,
+            job: job.to_string(),
+            port,
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    /// The LDAP search filter, such as `(&(objectClass=computer)(operatingSystem=*Server*))`.
+    pub fn filter(mut self, filter: &str) -> Self {
+        self.filter = filter.to_string();
+        self
+    }
+
+    /// Bind as this DN with the password in `password_file`. Searches are anonymous otherwise.
+    pub fn bind(mut self, dn: &str, password_file: &Path) -> Self {
+        self.bind_dn = Some(dn.to_string());
+        self.password_file = Some(password_file.to_path_buf());
+        self
+    }
+
+    /// Add the value of the `attribute` attribute to each source as the `label` label.
+    pub fn attribute(mut self, label: &str, attribute: &str) -> Self {
+        self.attributes
+            .insert(label.to_string(), attribute.to_string());
+        self
+    }
+
+    // The ldapsearch arguments. Results are paged so Active Directory returns more than its
+    // size limit.
+    fn args(&self) -> Vec<String> {
+        let mut args: Vec<String> = ["-LLL", "-x", "-o", "ldif-wrap=no", "-E", "pr=500/noprompt"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        args.extend(["-H".to_string(), self.uri.clone()]);
+        args.extend(["-b".to_string(), self.base.clone()]);
+        if let (Some(dn), Some(file)) = (&self.bind_dn, &self.password_file) {
+            args.extend(["-D".to_string(), dn.clone()]);
+            args.extend(["-y".to_string(), file.display().to_string()]);
+        }
+
+        args.push(self.filter.clone());
+        args.extend(
+            ["dNSHostName", "name", "operatingSystem"]
+                .iter()
+                .map(|a| a.to_string()),
+        );
+        args.extend(self.attributes.values().cloned());
+        args
+    }
+
+    // Convert entries into sources.
+    fn entries_to_sources(&self, entries: Vec<Entry>) -> Result<Vec<Source>> {
+        let mut sources = Vec::new();
+        for entry in entries {
+            let Some(host) = entry.get("dNSHostName") else {
+                debug!("Skipping {} without a dNSHostName", entry.dn);
+                continue;
+            };
+
+            let mut labels = BTreeMap::from([("ldap_ou".to_string(), ou_path(&entry.dn))]);
+            if let Some(name) = entry.get("name") {
+                labels.insert("ldap_name".to_string(), name.to_string());
+            }
+            if let Some(os) = entry.get("operatingSystem") {
+                labels.insert("ldap_os".to_string(), os.to_string());
+            }
+            for (label, attribute) in &self.attributes {
+                if let Some(values) = entry.attributes.get(&attribute.to_lowercase()) {
+                    labels.insert(label.clone(), values.join(","));
+                }
+            }
+
+            sources.push(
+                Source::builder()
+                    .job(&self.job)
+                    .labels(labels)
+                    .target(&host_port(&host.to_lowercase(), self.port))
+                    .build()?,
+            );
+        }
+
+        Ok(sources)
+    }
+}
+
+impl Provider for Ldap {
+    fn name(&self) -> &str {
+        "ldap"
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        let args = self.args();
+        debug!("Running ldapsearch {}", args.join(" "));
+        let output = Command::new("ldapsearch")
+            .args(&args)
+            .output()
+            .map_err(|e| Error::new(SourceError::Io(e)).set_context("running ldapsearch"))?;
+        if !output.status.success() {
+            return Err(Error::new(SourceError::Msg(format!(
+                "ldapsearch exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+            .set_kind(ErrorKind::Io));
+        }
+
+        let entries = parse_ldif(&String::from_utf8_lossy(&output.stdout));
+        let sources = self.entries_to_sources(entries)?;
+        info!("Discovered {} sources from LDAP", sources.len());
+        Ok(sources)
+    }
+}
+
+// Parse LDIF search results. Folded lines are joined and base64 values are decoded.
+fn parse_ldif(ldif: &str) -> Vec<Entry> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ldif.lines() {
+        match (line.strip_prefix(' '), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ if !line.starts_with('#') => lines.push(line.to_string()),
+            _ => {}
+        }
+    }
+
+    let mut entries = Vec::new();
+    let mut entry: Option<Entry> = None;
+    for line in lines {
+        if line.trim().is_empty() {
+            entries.extend(entry.take());
+            continue;
+        }
+
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = match value.strip_prefix(':') {
+            Some(encoded) => match base64_decode(encoded.trim()) {
+                Some(value) => value,
+                None => continue,
+            },
+            None => value.trim().to_string(),
+        };
+
+        if name.eq_ignore_ascii_case("dn") {
+            entries.extend(entry.take());
+            entry = Some(Entry {
+                dn: value,
+                ..Default::default()
+            });
+        } else if let Some(entry) = entry.as_mut() {
+            entry
+                .attributes
+                .entry(name.to_lowercase())
+                .or_default()
+                .push(value);
+        }
+    }
+
+    entries.extend(entry);
+    entries
+}
+
+// Decode a standard base64 value as UTF-8.
+fn base64_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in value.bytes().filter(|&c| c != b'=') {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+// Build the OU path of a DN from the top down, such as `Servers/Web` for
+// `CN=WEB1,OU=Web,OU=Servers,DC=corp,DC=com`.
+fn ou_path(dn: &str) -> String {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+    for c in dn.chars() {
+        match c {
+            _ if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            '\\' => escaped = true,
+            ',' => parts.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    parts.push(current);
+
+    let ous: Vec<&str> = parts
+        .iter()
+        .filter_map(|rdn| {
+            let (key, value) = rdn.split_once('=')?;
+            key.trim().eq_ignore_ascii_case("ou").then(|| value.trim())
+        })
+        .collect();
+    ous.into_iter().rev().collect::<Vec<_>>().join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_parse_ldif() {
+        let ldif = "dn: CN=WEB1,OU=Web,OU=Servers,DC=corp,DC=com\n\
+                    dNSHostName: WEB1.corp.com\n\
+                    name: WEB1\n\
+                    operatingSystem: Windows Ser\n \
+                    ver 2022 Standard\n\
+                    \n\
+                    # refldap://corp.com/CN=Configuration,DC=corp,DC=com\n\
+                    dn:: Q049REIxLE9VPURCLERDPWNvcnAsREM9Y29t\n\
+                    name: DB1\n\
+                    memberOf: CN=A\n\
+                    memberOf: CN=B\n";
+        let entries = parse_ldif(ldif);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].get("dnshostname"), Some("WEB1.corp.com"));
+        assert_eq!(
+            entries[0].get("operatingSystem"),
+            Some("Windows Server 2022 Standard")
+        );
+        assert_eq!(entries[1].dn, "CN=DB1,OU=DB,DC=corp,DC=com");
+        assert_eq!(entries[1].attributes["memberof"], vec!["CN=A", "CN=B"]);
+    }
+
+    #[test]
+    fn test_ou_path() {
+        assert_eq!(
+            ou_path("CN=WEB1,OU=Web,OU=Servers,DC=corp,DC=com"),
+            "Servers/Web"
+        );
+        assert_eq!(ou_path("CN=A\\, B,OU=X\\,Y,DC=corp"), "X,Y");
+        assert_eq!(ou_path("CN=WEB1,CN=Computers,DC=corp,DC=com"), "");
+    }
+
+    #[test]
+    fn test_entries_to_sources() {
+        let ldif = "dn: CN=WEB1,OU=Web,OU=Servers,DC=corp,DC=com\n\
+                    dNSHostName: WEB1.corp.com\n\
+                    name: WEB1\n\
+                    description: IIS\n\
+                    \n\
+                    dn: CN=OLD,OU=Servers,DC=corp,DC=com\n\
+                    name: OLD\n";
+        let ldap = Ldap::new("ldap://dc1", "DC=corp,DC=com", "windows", 9182)
+            .attribute("description", "description");
+        let sources = ldap.entries_to_sources(parse_ldif(ldif)).unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(
+            sources[0].targets(),
+            &vec!["web1.corp.com:9182".to_string()]
+        );
+        assert_eq!(sources[0].labels()["ldap_ou"], "Servers/Web");
+        assert_eq!(sources[0].labels()["ldap_name"], "WEB1");
+        assert_eq!(sources[0].labels()["description"], "IIS");
+        assert!(ldap.args().contains(&"description".to_string()));
+    }
+}
@@ -0,0 +1,130 @@
+#[cfg(feature = "cloud")]
+pub use self::cloud::{Cloud, CloudPlatform};
+pub use self::consul::Consul;
+pub use self::docker::Docker;
+#[cfg(feature = "kubernetes")]
+pub use self::kubernetes::{Kubernetes, KubernetesRole};
+pub use self::ldap::Ldap;
+pub use self::netbox::{NetBox, NetBoxKind};
+pub use self::nmap::Nmap;
+pub use self::puppetdb::PuppetDb;
+#[cfg(feature = "sql")]
+pub use self::sql::{Sql, SqlEngine};
+pub use self::srv::Srv;
+pub use self::terraform::Terraform;
+pub use self::zone::Zone;
+
+#[cfg(feature = "cloud")]
+pub mod cloud;
+pub mod consul;
+pub mod docker;
+#[cfg(feature = "kubernetes")]
+pub mod kubernetes;
+pub mod ldap;
+pub mod netbox;
+pub mod nmap;
+pub mod puppetdb;
+#[cfg(feature = "sql")]
+pub mod sql;
+pub mod srv;
+pub mod terraform;
+pub mod zone;
+
+pub use crate::app::source::label_name;
+
+use crate::app::source::Source;
+use crate::core::error::*;
+use crate::core::http;
+use log::debug;
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+
+/// A provider discovers sources from an external system, such as a service catalog, to be merged
+/// with the sources read from files.
+pub trait Provider: Debug + Send + Sync {
+    /// A short name for logs and errors, such as `consul`.
+    fn name(&self) -> &str;
+
+    /// Query the external system and return the discovered sources.
+    fn sources(&self) -> Result<Vec<Source>>;
+}
+
+/// Run a future to completion on a runtime of its own, for providers built on async clients. The
+/// queries are the only async work, so pim has no runtime of its own.
+#[cfg(any(feature = "kubernetes", feature = "sql"))]
+pub fn block_on<F: std::future::Future>(future: F) -> Result<F::Output> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context("starting the async runtime")
+                .set_kind(ErrorKind::Internal)
+        })?;
+    Ok(runtime.block_on(future))
+}
+
+/// Send a GET request and deserialize the JSON response body. Non-2xx responses are errors.
+pub fn get_json<T: DeserializeOwned>(url: &str, headers: &[(&str, &str)]) -> Result<T> {
+    debug!("Fetching {}", url);
+    let mut all_headers = vec![("Accept", "application/json")];
+    all_headers.extend_from_slice(headers);
+    let response = http::send("GET", url, &all_headers, &[])?;
+    json_response(url, response)
+}
+
+// Deserialize the JSON body of a response to `url`. Non-2xx responses are errors.
+fn json_response<T: DeserializeOwned>(url: &str, response: http::Response) -> Result<T> {
+    if !(200..300).contains(&response.status) {
+        return Err(Error::new(SourceError::Msg(format!(
+            "GET {} returned {}: {}",
+            url,
+            response.status,
+            String::from_utf8_lossy(&response.body).trim()
+        )))
+        .set_kind(ErrorKind::Network));
+    }
+
+    serde_json::from_slice(&response.body).map_err(|e| {
+        Error::new(SourceError::SerdeJson(e))
+            .set_context(&format!("Failed to parse response from {}", url))
+    })
+}
+
+/// Percent-encode a URL path segment or query value.
+pub fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Join a host and port into a target, adding brackets around IPv6 addresses.
+pub fn host_port(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_helpers() {
+        assert_eq!(url_encode("web api/v1"), "web%20api%2Fv1");
+        assert_eq!(label_name("team"), "team");
+        assert_eq!(label_name("k8s.io/app-name"), "k8s_io_app_name");
+        assert_eq!(label_name("1st"), "_1st");
+        assert_eq!(host_port("10.0.0.1", 9100), "10.0.0.1:9100");
+        assert_eq!(host_port("fd00::1", 9100), "[fd00::1]:9100");
+    }
+}
@@ -0,0 +1,249 @@
+use crate::app::provider::{Provider, get_json, host_port};
+use crate::app::source::Source;
+use crate::core::error::*;
+use log::{debug, info};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+// The number of objects requested per page.
+const PAGE_SIZE: usize = 500;
+
+/// The NetBox objects to discover.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum NetBoxKind {
+    Devices,
+    VirtualMachines,
+    #[default]
+    All,
+}
+
+/// Discover devices and virtual machines from NetBox. Each object with a primary IP becomes a
+/// source with `ip:port` as the target.
+///
+/// Objects get `netbox_name`, `netbox_site`, `netbox_role`, `netbox_tenant`, `netbox_platform`,
+/// and `netbox_cluster` labels when set, using slugs where NetBox has them. Tags are joined into
+/// a `netbox_tags` label.
+#[derive(Debug, Clone)]
+pub struct NetBox {
+    address: String,
+    token: Option<String>,
+    filter: Option<String>,
+    kind: NetBoxKind,
+    job: String,
+    port: u16,
+}
+
+#[derive(Debug, Deserialize)]
+struct Page {
+    #[serde(default)]
+    count: usize,
+    #[serde(default)]
+    results: Vec<Object>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Object {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    site: Option<Nested>,
+    // NetBox 4 renamed `device_role` to `role`.
+    #[serde(default, alias = "device_role")]
+    role: Option<Nested>,
+    #[serde(default)]
+    tenant: Option<Nested>,
+    #[serde(default)]
+    platform: Option<Nested>,
+    #[serde(default)]
+    cluster: Option<Nested>,
+    #[serde(default)]
+    tags: Vec<Nested>,
+    #[serde(default)]
+    primary_ip: Option<IpAddress>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Nested {
+    #[serde(default)]
+    slug: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IpAddress {
+    address: String,
+}
+
+impl Nested {
+    fn value(&self) -> Option<&str> {
+        self.slug.as_deref().or(self.name.as_deref())
+    }
+}
+
+impl NetBox {
+    /// Create a provider for the NetBox instance at `address`, such as `http://netbox.local`.
+    /// Targets use `job` as the job and `port` as the port.
+    pub fn new(address: &str, job: &str, port: u16) -> Self {
+        NetBox {
+            address: address.trim_end_matches('/').to_string(),
+            token: None,
+            filter: None,
+            kind: NetBoxKind::default(),
+            job: job.to_string(),
+            port,
+        }
+    }
+
+    /// The API token sent as `Authorization: Token <token>`.
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// A NetBox filter query added to every request, such as `status=active&tag=prometheus`.
+    pub fn filter(mut self, filter: &str) -> Self {
+        self.filter = Some(filter.trim_start_matches('?').to_string());
+        self
+    }
+
+    /// The objects to discover.
+    pub fn kind(mut self, kind: NetBoxKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    // Fetch every page of objects from an API list endpoint.
+    fn list(&self, path: &str) -> Result<Vec<Object>> {
+        let auth = self.token.as_ref().map(|t| format!("Token {}", t));
+        let mut objects = Vec::new();
+        loop {
+            let mut url = format!(
+                "{}{}?limit={}&offset={}",
+                self.address,
+                path,
+                PAGE_SIZE,
+                objects.len()
+            );
+            if let Some(filter) = &self.filter {
+                url.push('&');
+                url.push_str(filter);
+            }
+
+            let page: Page = match &auth {
+                Some(auth) => get_json(&url, &[("Authorization", auth)])?,
+                None => get_json(&url, &[])?,
+            };
+            let done = page.results.len() < PAGE_SIZE;
+            objects.extend(page.results);
+            if done || objects.len() >= page.count {
+                break;
+            }
+        }
+
+        debug!("Fetched {} objects from {}", objects.len(), path);
+        Ok(objects)
+    }
+}
+
+impl Provider for NetBox {
+    fn name(&self) -> &str {
+        "netbox"
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        let mut objects = Vec::new();
+        if self.kind != NetBoxKind::VirtualMachines {
+            objects.extend(self.list("/api/dcim/devices/")?);
+        }
+        if self.kind != NetBoxKind::Devices {
+            objects.extend(self.list("/api/virtualization/virtual-machines/")?);
+        }
+
+        let sources = objects_to_sources(objects, &self.job, self.port)?;
+        info!("Discovered {} sources from NetBox", sources.len());
+        Ok(sources)
+    }
+}
+
+// Convert NetBox objects with a primary IP into sources.
+fn objects_to_sources(objects: Vec<Object>, job: &str, port: u16) -> Result<Vec<Source>> {
+    let mut sources = Vec::new();
+    for object in objects {
+        let Some(ip) = &object.primary_ip else {
+            debug!("Skipping {:?} without a primary IP", object.name);
+            continue;
+        };
+        // Addresses include the prefix length, such as 10.0.0.1/24.
+        let host = ip.address.split('/').next().unwrap_or_default();
+
+        let mut labels = BTreeMap::new();
+        if let Some(name) = &object.name {
+            labels.insert("netbox_name".to_string(), name.clone());
+        }
+        for (label, nested) in [
+            ("netbox_site", &object.site),
+            ("netbox_role", &object.role),
+            ("netbox_tenant", &object.tenant),
+            ("netbox_platform", &object.platform),
+            ("netbox_cluster", &object.cluster),
+        ] {
+            if let Some(value) = nested.as_ref().and_then(Nested::value) {
+                labels.insert(label.to_string(), value.to_string());
+            }
+        }
+
+        let mut tags: Vec<&str> = object.tags.iter().filter_map(Nested::value).collect();
+        if !tags.is_empty() {
+            tags.sort();
+            labels.insert("netbox_tags".to_string(), tags.join(","));
+        }
+
+        sources.push(
+            Source::builder()
+                .job(job)
+                .labels(labels)
+                .target(&host_port(host, port))
+                .build()?,
+        );
+    }
+
+    Ok(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_objects_to_sources() {
+        let json = r#"{"count": 3, "results": [
+            {"name": "web1", "site": {"slug": "ams1", "name": "Amsterdam 1"},
+             "device_role": {"slug": "web-server"}, "tenant": null,
+             "tags": [{"slug": "prometheus"}, {"slug": "dmz"}],
+             "primary_ip": {"address": "10.0.0.1/24"}},
+            {"name": "vm1", "site": null, "role": {"name": "Database"},
+             "cluster": {"name": "prod"}, "primary_ip": {"address": "2001:db8::5/64"}},
+            {"name": "spare", "primary_ip": null}
+        ]}"#;
+        let page: Page = serde_json::from_str(json).unwrap();
+        let sources = objects_to_sources(page.results, "node", 9100).unwrap();
+        assert_eq!(sources.len(), 2);
+
+        assert_eq!(sources[0].jobs(), &vec!["node".to_string()]);
+        assert_eq!(sources[0].targets(), &vec!["10.0.0.1:9100".to_string()]);
+        assert_eq!(sources[0].labels()["netbox_site"], "ams1");
+        assert_eq!(sources[0].labels()["netbox_role"], "web-server");
+        assert_eq!(sources[0].labels()["netbox_tags"], "dmz,prometheus");
+        assert!(!sources[0].labels().contains_key("netbox_tenant"));
+
+        assert_eq!(
+            sources[1].targets(),
+            &vec!["[2001:db8::5]:9100".to_string()]
+        );
+        assert_eq!(sources[1].labels()["netbox_role"], "Database");
+        assert_eq!(sources[1].labels()["netbox_cluster"], "prod");
+    }
+}
@@ -0,0 +1,304 @@
+use crate::app::provider::{Provider, host_port};
+use crate::app::source::Source;
+use crate::core::error::*;
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Import hosts from an nmap XML scan, such as the output of `nmap -oX scan.xml -O`. Each host
+/// that is up with at least one of the given ports open becomes a source with the host's address
+/// and open ports as the targets.
+///
+/// Sources get an `nmap_hostname` label with the first host name and an `nmap_os` label with the
+/// best OS guess when the scan has them.
+#[derive(Debug, Clone)]
+pub struct Nmap {
+    path: PathBuf,
+    job: String,
+    ports: Vec<u16>,
+}
+
+// A host from the scan.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Host {
+    up: bool,
+    address: Option<String>,
+    hostname: Option<String>,
+    os: Option<(u8, String)>,
+    open_ports: Vec<u16>,
+}
+
+// An XML tag. Closing tags have a `/` prefix on the name.
+#[derive(Debug, PartialEq, Eq)]
+struct Tag {
+    name: String,
+    attributes: BTreeMap<String, String>,
+}
+
+impl Nmap {
+    /// Create a provider for the scan at `path`. Targets use `job` as the job and are built from
+    /// the open `ports`.
+    pub fn new(path: &Path, job: &str, ports: Vec<u16>) -> Self {
+        Nmap {
+            path: path.to_path_buf(),
+            job: job.to_string(),
+            ports,
+        }
+    }
+
+    // Convert scanned hosts into sources.
+    fn hosts_to_sources(&self, hosts: Vec<Host>) -> Result<Vec<Source>> {
+        let mut sources = Vec::new();
+        for host in hosts {
+            let Some(address) = host.address.filter(|_| host.up) else {
+                continue;
+            };
+            let targets: Vec<String> = self
+                .ports
+                .iter()
+                .filter(|p| host.open_ports.contains(p))
+                .map(|p| host_port(&address, *p))
+                .collect();
+            if targets.is_empty() {
+                debug!("Skipping {} without an open port", address);
+                continue;
+            }
+
+            let mut labels = BTreeMap::new();
+            if let Some(hostname) = host.hostname {
+                labels.insert("nmap_hostname".to_string(), hostname);
+            }
+            if let Some((_, os)) = host.os {
+                labels.insert("nmap_os".to_string(), os);
+            }
+
+            sources.push(
+                Source::builder()
+                    .job(&self.job)
+                    .labels(labels)
+                    .targets(targets)
+                    .build()?,
+            );
+        }
+
+        Ok(sources)
+    }
+}
+
+impl Provider for Nmap {
+    fn name(&self) -> &str {
+        "nmap"
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        debug!("Reading nmap scan: {}", self.path.display());
+        let xml = std::fs::read_to_string(&self.path).map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(&format!("reading nmap scan: {}", self.path.display()))
+        })?;
+
+        let sources = self.hosts_to_sources(parse_hosts(&xml)?)?;
+        info!("Imported {} sources from nmap", sources.len());
+        Ok(sources)
+    }
+}
+
+// Collect the hosts from nmap XML. Only the elements pim uses are read.
+fn parse_hosts(xml: &str) -> Result<Vec<Host>> {
+    let mut hosts = Vec::new();
+    let mut host: Option<Host> = None;
+    let mut port: Option<u16> = None;
+    for tag in tags(xml)? {
+        let attr = |name: &str| tag.attributes.get(name).cloned();
+        match tag.name.as_str() {
+            "host" => host = Some(Host::default()),
+            "/host" => hosts.extend(host.take()),
+            _ => {}
+        }
+        let Some(host) = host.as_mut() else {
+            continue;
+        };
+
+        match tag.name.as_str() {
+            "status" => host.up = attr("state").as_deref() == Some("up"),
+            // Prefer IPv4 addresses and skip MAC addresses.
+            "address" => match attr("addrtype").as_deref() {
+                Some("ipv4") => host.address = attr("addr"),
+                Some("ipv6") if host.address.is_none() => host.address = attr("addr"),
+                _ => {}
+            },
+            "hostname" if host.hostname.is_none() => host.hostname = attr("name"),
+            "port" if attr("protocol").as_deref() == Some("tcp") => {
+                port = attr("portid").and_then(|p| p.parse().ok());
+            }
+            "/port" => port = None,
+            "state" => {
+                if let Some(p) = port {
+                    if attr("state").as_deref() == Some("open") {
+                        host.open_ports.push(p);
+                    }
+                }
+            }
+            "osmatch" => {
+                let accuracy = attr("accuracy").and_then(|a| a.parse().ok()).unwrap_or(0);
+                if let Some(name) = attr("name") {
+                    if host.os.as_ref().is_none_or(|(best, _)| accuracy > *best) {
+                        host.os = Some((accuracy, name));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(hosts)
+}
+
+// Split XML into its tags. Self-closing tags are returned as an opening and a closing tag, and
+// text, comments, and declarations are skipped.
+fn tags(xml: &str) -> Result<Vec<Tag>> {
+    let invalid = |msg: &str| {
+        Error::new(SourceError::InvalidInputSource(format!(
+            "Invalid nmap XML: {}",
+            msg
+        )))
+        .set_kind(ErrorKind::Parse)
+    };
+
+    let mut tags = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            let end = comment
+                .find("-->")
+                .ok_or_else(|| invalid("unclosed comment"))?;
+            rest = &comment[end + 3..];
+            continue;
+        }
+
+        let end = tag_end(rest).ok_or_else(|| invalid("unclosed tag"))?;
+        let body = &rest[1..end];
+        rest = &rest[end + 1..];
+        if body.starts_with('?') || body.starts_with('!') {
+            continue;
+        }
+
+        let (body, self_closing) = match body.strip_suffix('/') {
+            Some(body) => (body, true),
+            None => (body, false),
+        };
+        let (name, attributes) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+        tags.push(Tag {
+            name: name.to_string(),
+            attributes: parse_attributes(attributes),
+        });
+        if self_closing {
+            tags.push(Tag {
+                name: format!("/{}", name),
+                attributes: BTreeMap::new(),
+            });
+        }
+    }
+
+    Ok(tags)
+}
+
+// Find the `>` closing the tag at the start of `xml`, skipping quoted attribute values.
+fn tag_end(xml: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in xml.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('>', None) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_attributes(mut attributes: &str) -> BTreeMap<String, String> {
+    let mut map = BTreeMap::new();
+    while let Some((name, rest)) = attributes.split_once('=') {
+        let rest = rest.trim_start();
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            break;
+        };
+        let Some(end) = rest[1..].find(quote) else {
+            break;
+        };
+        map.insert(name.trim().to_string(), unescape(&rest[1..end + 1]));
+        attributes = &rest[end + 2..];
+    }
+    map
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    const SCAN: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE nmaprun>
+<!-- Nmap 7.94 scan initiated as: nmap -O -oX scan.xml 10.0.0.0/24 -->
+<nmaprun scanner="nmap" args="nmap -O -oX scan.xml 10.0.0.0/24">
+<host starttime="1" endtime="2"><status state="up" reason="arp-response"/>
+<address addr="10.0.0.5" addrtype="ipv4"/>
+<address addr="00:11:22:33:44:55" addrtype="mac" vendor="Dell &amp; Co"/>
+<hostnames><hostname name="web1.example.com" type="PTR"/></hostnames>
+<ports><port protocol="tcp" portid="22"><state state="open" reason="syn-ack"/><service name="ssh"/></port>
+<port protocol="tcp" portid="9100"><state state="open" reason="syn-ack"/></port>
+<port protocol="tcp" portid="9182"><state state="filtered" reason="no-response"/></port>
+</ports>
+<os><osmatch name="Linux 4.15 - 5.8" accuracy="96"/><osmatch name="Linux 5.4" accuracy="98"/></os>
+</host>
+<host><status state="up"/><address addr="10.0.0.6" addrtype="ipv4"/>
+<ports><port protocol="tcp" portid="22"><state state="open"/></port></ports>
+</host>
+<host><status state="down"/><address addr="10.0.0.7" addrtype="ipv4"/>
+<ports><port protocol="tcp" portid="9100"><state state="open"/></port></ports>
+</host>
+</nmaprun>
+"#;
+
+    #[test]
+    fn test_parse_hosts() {
+        let hosts = parse_hosts(SCAN).unwrap();
+        assert_eq!(hosts.len(), 3);
+        assert_eq!(hosts[0].address.as_deref(), Some("10.0.0.5"));
+        assert_eq!(hosts[0].hostname.as_deref(), Some("web1.example.com"));
+        assert_eq!(hosts[0].os, Some((98, "Linux 5.4".to_string())));
+        assert_eq!(hosts[0].open_ports, vec![22, 9100]);
+        assert!(!hosts[2].up);
+
+        assert!(parse_hosts("<host><status state=\"up\"").is_err());
+    }
+
+    #[test]
+    fn test_hosts_to_sources() {
+        let nmap = Nmap::new(Path::new("scan.xml"), "node", vec![9100, 9182]);
+        let sources = nmap.hosts_to_sources(parse_hosts(SCAN).unwrap()).unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].targets(), &vec!["10.0.0.5:9100".to_string()]);
+        assert_eq!(sources[0].labels()["nmap_hostname"], "web1.example.com");
+        assert_eq!(sources[0].labels()["nmap_os"], "Linux 5.4");
+    }
+
+    #[test]
+    fn test_parse_attributes() {
+        let attributes = parse_attributes(r#"name="a &lt;b&gt;" type='PTR' x = "1""#);
+        assert_eq!(attributes["name"], "a <b>");
+        assert_eq!(attributes["type"], "PTR");
+        assert_eq!(attributes["x"], "1");
+    }
+}
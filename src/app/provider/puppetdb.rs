@@ -0,0 +1,191 @@
+use crate::app::provider::{Provider, get_json, host_port, url_encode};
+use crate::app::source::Source;
+use crate::core::error::*;
+use log::{debug, info};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// The PQL query used when none is given. It returns the facts of every active node.
+pub const DEFAULT_QUERY: &str = r#"inventory[certname, facts] { certname ~ "." }"#;
+
+/// Discover nodes from a PuppetDB PQL query. Each result with a certname becomes a source with
+/// the certname, or the value of an address fact, plus a port as the target.
+///
+/// Facts are mapped to labels with [`PuppetDb::fact`]. Fact paths are dotted, such as
+/// `os.family`, and are looked up in the result's `facts` and then in the result itself.
+#[derive(Debug, Clone)]
+pub struct PuppetDb {
+    address: String,
+    query: String,
+    token: Option<String>,
+    job: String,
+    port: u16,
+    address_fact: Option<String>,
+    facts: BTreeMap<String, String>,
+}
+
+impl PuppetDb {
+    /// Create a provider for the PuppetDB at `address`, such as `http://puppetdb:8080`. Targets
+    /// use `job` as the job and `port` as the port.
+    pub fn new(address: &str, job: &str, port: u16) -> Self {
+        PuppetDb {
+            address: address.trim_end_matches('/').to_string(),
+            query: DEFAULT_QUERY.to_string(),
+            token: None,
+            job: job.to_string(),
+            port,
+            address_fact: None,
+            facts: BTreeMap::new(),
+        }
+    }
+
+    /// The PQL query to run, such as `inventory[certname, facts] { facts.kernel = "Linux" }`.
+    pub fn query(mut self, query: &str) -> Self {
+        self.query = query.to_string();
+        self
+    }
+
+    /// The RBAC token sent as `X-Authentication`, as used by Puppet Enterprise.
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Use the value of this fact as the target host instead of the certname, such as
+    /// `networking.ip`.
+    pub fn address_fact(mut self, fact: &str) -> Self {
+        self.address_fact = Some(fact.to_string());
+        self
+    }
+
+    /// Add the value of `fact` to each source as the `label` label.
+    pub fn fact(mut self, label: &str, fact: &str) -> Self {
+        self.facts.insert(label.to_string(), fact.to_string());
+        self
+    }
+}
+
+impl Provider for PuppetDb {
+    fn name(&self) -> &str {
+        "puppetdb"
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        let url = format!(
+            "{}/pdb/query/v4?query={}",
+            self.address,
+            url_encode(&self.query)
+        );
+        let results: Vec<Value> = match &self.token {
+            Some(token) => get_json(&url, &[("X-Authentication", token)])?,
+            None => get_json(&url, &[])?,
+        };
+
+        let sources = self.results_to_sources(results)?;
+        info!("Discovered {} sources from PuppetDB", sources.len());
+        Ok(sources)
+    }
+}
+
+impl PuppetDb {
+    // Convert query results into sources.
+    fn results_to_sources(&self, results: Vec<Value>) -> Result<Vec<Source>> {
+        let mut sources = Vec::new();
+        for result in results {
+            let Some(certname) = result.get("certname").and_then(Value::as_str) else {
+                debug!("Skipping PuppetDB result without a certname");
+                continue;
+            };
+
+            let host = match &self.address_fact {
+                Some(fact) => match lookup(&result, fact).and_then(label_value) {
+                    Some(host) => host,
+                    None => {
+                        debug!("Skipping {} without the {} fact", certname, fact);
+                        continue;
+                    }
+                },
+                None => certname.to_string(),
+            };
+
+            let mut labels =
+                BTreeMap::from([("puppetdb_certname".to_string(), certname.to_string())]);
+            for (label, fact) in &self.facts {
+                if let Some(value) = lookup(&result, fact).and_then(label_value) {
+                    labels.insert(label.clone(), value);
+                }
+            }
+
+            sources.push(
+                Source::builder()
+                    .job(&self.job)
+                    .labels(labels)
+                    .target(&host_port(&host, self.port))
+                    .build()?,
+            );
+        }
+
+        Ok(sources)
+    }
+}
+
+// Look up a dotted fact path in the result's facts, then in the result itself.
+fn lookup<'a>(result: &'a Value, path: &str) -> Option<&'a Value> {
+    let find = |root: &'a Value| {
+        path.split('.')
+            .try_fold(root, |value, key| value.get(key))
+            .filter(|v| !v.is_null())
+    };
+
+    result.get("facts").and_then(find).or_else(|| find(result))
+}
+
+// Convert a fact value into a label value. Strings are used as is and other values as JSON.
+fn label_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        v => Some(v.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_results_to_sources() {
+        let json = r#"[
+            {"certname": "web1.example.com",
+             "facts": {"os": {"family": "Debian"}, "networking": {"ip": "10.0.0.1"},
+                       "is_virtual": true, "role": "web"}},
+            {"certname": "db1.example.com", "facts": {"os": {"family": "RedHat"}}},
+            {"name": "no-certname"}
+        ]"#;
+        let results: Vec<Value> = serde_json::from_str(json).unwrap();
+        let puppetdb = PuppetDb::new("http://puppetdb:8080", "node", 9100)
+            .fact("os_family", "os.family")
+            .fact("virtual", "is_virtual")
+            .fact("role", "role");
+
+        let sources = puppetdb.results_to_sources(results.clone()).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(
+            sources[0].targets(),
+            &vec!["web1.example.com:9100".to_string()]
+        );
+        assert_eq!(sources[0].labels()["os_family"], "Debian");
+        assert_eq!(sources[0].labels()["virtual"], "true");
+        assert_eq!(sources[0].labels()["role"], "web");
+        assert_eq!(sources[1].labels()["puppetdb_certname"], "db1.example.com");
+        assert!(!sources[1].labels().contains_key("role"));
+
+        let sources = puppetdb
+            .address_fact("networking.ip")
+            .results_to_sources(results)
+            .unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].targets(), &vec!["10.0.0.1:9100".to_string()]);
+    }
+}
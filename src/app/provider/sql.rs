@@ -0,0 +1,277 @@
+use crate::app::provider::{Provider, block_on, label_name};
+use crate::app::source::Source;
+use crate::core::error::*;
+use log::{debug, info};
+use sqlx::mysql::{MySqlConnectOptions, MySqlConnection};
+use sqlx::postgres::{PgConnectOptions, PgConnection};
+use sqlx::{Column, Connection, Row, TypeInfo};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// The database the SQL provider queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum SqlEngine {
+    /// PostgreSQL.
+    Postgres,
+    /// MySQL or MariaDB.
+    Mysql,
+}
+
+impl SqlEngine {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SqlEngine::Postgres => "postgres",
+            SqlEngine::Mysql => "mysql",
+        }
+    }
+}
+
+/// Discover sources with a SQL query. Each row becomes a source with the job column as the job,
+/// the target column as the target, and every other column as a label.
+///
+/// Queries are run with sqlx. Text, integer, float, and boolean columns are read, and columns of
+/// other types must be cast to text in the query. Rows without a job or target are skipped, as
+/// are empty and NULL label values. Columns are renamed to valid label names, so alias them in
+/// the query to pick the label.
+#[derive(Debug, Clone)]
+pub struct Sql {
+    engine: SqlEngine,
+    url: String,
+    query: String,
+    job_column: String,
+    target_column: String,
+}
+
+impl Sql {
+    /// Create a provider that runs `query` on the database at `url`, such as
+    /// `postgres://pim@db.example.com/cmdb`.
+    pub fn new(engine: SqlEngine, url: &str, query: &str) -> Self {
+        Sql {
+            engine,
+            url: url.to_string(),
+            query: query.to_string(),
+            job_column: "job".to_string(),
+            target_column: "target".to_string(),
+        }
+    }
+
+    /// The column holding the job. Defaults to `job`.
+    pub fn job_column(mut self, column: &str) -> Self {
+        self.job_column = column.to_string();
+        self
+    }
+
+    /// The column holding the target, such as `host:9100`. Defaults to `target`.
+    pub fn target_column(mut self, column: &str) -> Self {
+        self.target_column = column.to_string();
+        self
+    }
+
+    // Convert rows into sources.
+    fn rows_to_sources(&self, rows: Vec<BTreeMap<String, Option<String>>>) -> Result<Vec<Source>> {
+        let mut sources = Vec::new();
+        for row in rows {
+            let value = |column: &str| row.get(column).cloned().flatten().filter(|v| !v.is_empty());
+            let (Some(job), Some(target)) = (value(&self.job_column), value(&self.target_column))
+            else {
+                debug!("Skipping row without a job or target: {:?}", row);
+                continue;
+            };
+
+            let labels: BTreeMap<String, String> = row
+                .iter()
+                .filter(|(column, _)| **column != self.job_column && **column != self.target_column)
+                .filter_map(|(column, value)| {
+                    let value = value.as_ref().filter(|v| !v.is_empty())?;
+                    Some((label_name(column), value.clone()))
+                })
+                .collect();
+
+            sources.push(
+                Source::builder()
+                    .job(&job)
+                    .labels(labels)
+                    .target(&target)
+                    .build()?,
+            );
+        }
+
+        Ok(sources)
+    }
+}
+
+impl Provider for Sql {
+    fn name(&self) -> &str {
+        "sql"
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        debug!("Running {} query: {}", self.engine.as_str(), self.query);
+        let rows = block_on(self.rows())??;
+        let sources = self.rows_to_sources(rows)?;
+        info!("Discovered {} sources from SQL", sources.len());
+        Ok(sources)
+    }
+}
+
+// Read a column as text with the first type of the list that can decode it. NULL values are
+// None.
+macro_rules! column_text {
+    ($row:expr, $i:expr, $($ty:ty),+) => {{
+        let row = $row;
+        let i = $i;
+        let mut text = None;
+        $(
+            if text.is_none() {
+                if let Ok(value) = row.try_get::<Option<$ty>, _>(i) {
+                    text = Some(value.map(|v| v.to_string()));
+                }
+            }
+        )+
+        text
+    }};
+}
+
+impl Sql {
+    // Connect to the database and run the query. Rows are maps of column names to values.
+    async fn rows(&self) -> Result<Vec<BTreeMap<String, Option<String>>>> {
+        match self.engine {
+            SqlEngine::Postgres => {
+                let options = PgConnectOptions::from_str(&self.url).map_err(|e| self.error(e))?;
+                let mut connection = PgConnection::connect_with(&options)
+                    .await
+                    .map_err(|e| self.error(e))?;
+                let rows = sqlx::query(&self.query)
+                    .fetch_all(&mut connection)
+                    .await
+                    .map_err(|e| self.error(e))?;
+                let _ = connection.close().await;
+                rows.iter()
+                    .map(|row| {
+                        self.row(row, |i| {
+                            column_text!(row, i, String, i64, i32, i16, f64, f32, bool)
+                        })
+                    })
+                    .collect()
+            }
+            SqlEngine::Mysql => {
+                // sqlx only knows the mysql scheme, which MariaDB speaks too.
+                let url = match self.url.strip_prefix("mariadb://") {
+                    Some(rest) => format!("mysql://{}", rest),
+                    None => self.url.clone(),
+                };
+                let options = MySqlConnectOptions::from_str(&url).map_err(|e| self.error(e))?;
+                let mut connection = MySqlConnection::connect_with(&options)
+                    .await
+                    .map_err(|e| self.error(e))?;
+                let rows = sqlx::query(&self.query)
+                    .fetch_all(&mut connection)
+                    .await
+                    .map_err(|e| self.error(e))?;
+                let _ = connection.close().await;
+                rows.iter()
+                    .map(|row| self.row(row, |i| column_text!(row, i, String, i64, u64, f64, bool)))
+                    .collect()
+            }
+        }
+    }
+
+    // The columns of a row by name, read with `text`. Columns of other types, such as dates, are
+    // errors so the query can cast them.
+    fn row<R: Row>(
+        &self,
+        row: &R,
+        text: impl Fn(usize) -> Option<Option<String>>,
+    ) -> Result<BTreeMap<String, Option<String>>> {
+        row.columns()
+            .iter()
+            .map(|column| {
+                let name = column.name().to_string();
+                match text(column.ordinal()) {
+                    Some(value) => Ok((name, value)),
+                    None => Err(Error::new(SourceError::Msg(format!(
+                        "Column '{}' of type {} can't be read as text, cast it in the query",
+                        name,
+                        column.type_info().name()
+                    )))
+                    .set_kind(ErrorKind::Config)),
+                }
+            })
+            .collect()
+    }
+
+    // An error from the database, with the query for context.
+    fn error(&self, e: sqlx::Error) -> Error {
+        let kind = match e {
+            sqlx::Error::Configuration(_) | sqlx::Error::Database(_) => ErrorKind::Config,
+            _ => ErrorKind::Network,
+        };
+        Error::new(SourceError::Msg(e.to_string()))
+            .set_context(&format!(
+                "running {} query: {}",
+                self.engine.as_str(),
+                self.query
+            ))
+            .set_kind(kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_rows_to_sources() {
+        let row = |values: [Option<&str>; 4]| -> BTreeMap<String, Option<String>> {
+            ["exporter", "address", "env", "Data Center"]
+                .into_iter()
+                .map(String::from)
+                .zip(values.map(|v| v.map(String::from)))
+                .collect()
+        };
+        let rows = vec![
+            row([Some("node"), Some("web1:9100"), Some("prod"), Some("dc1")]),
+            row([Some("node"), None, Some("prod"), Some("dc1")]),
+            row([Some("mysqld"), Some("db1:9104"), Some(""), Some("dc2")]),
+        ];
+        let sql = Sql::new(SqlEngine::Postgres, "postgres://db/cmdb", "SELECT 1")
+            .job_column("exporter")
+            .target_column("address");
+        let sources = sql.rows_to_sources(rows).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].jobs(), &vec!["node".to_string()]);
+        assert_eq!(sources[0].targets(), &vec!["web1:9100".to_string()]);
+        assert_eq!(sources[0].labels()["env"], "prod");
+        assert_eq!(sources[0].labels()["Data_Center"], "dc1");
+        assert!(!sources[1].labels().contains_key("env"));
+    }
+
+    #[test]
+    fn test_error() {
+        let sql = Sql::new(SqlEngine::Mysql, "mysql://db/cmdb", "SELECT 1");
+        let err = sql.error(sqlx::Error::Configuration("bad URL".into()));
+        assert_eq!(err.kind(), ErrorKind::Config);
+        let err = sql.error(sqlx::Error::PoolTimedOut);
+        assert_eq!(err.kind(), ErrorKind::Network);
+    }
+
+    #[test]
+    fn test_unreachable_database() {
+        // Nothing listens on port 1, so connecting fails without a database.
+        let sql = Sql::new(
+            SqlEngine::Postgres,
+            "postgres://pim@127.0.0.1:1/cmdb",
+            "SELECT 1",
+        );
+        let err = sql.sources().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Network);
+        let sql = Sql::new(
+            SqlEngine::Mysql,
+            "mariadb://pim@127.0.0.1:1/cmdb",
+            "SELECT 1",
+        );
+        assert_eq!(sql.sources().unwrap_err().kind(), ErrorKind::Network);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::app::provider::{Provider, host_port};
+use crate::app::source::Source;
+use crate::core::dns::{self, SrvRecord};
+use crate::core::error::*;
+use log::info;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+
+/// Discover targets from DNS SRV records, such as `_node._tcp.example.com`. Each name becomes a
+/// source with the record targets and ports as the targets.
+///
+/// Sources get a `srv_name` label with the name and any labels added with [`Srv::label`]. The
+/// records are looked up on every run, so serve mode follows changes to them.
+#[derive(Debug, Clone)]
+pub struct Srv {
+    names: Vec<String>,
+    job: String,
+    labels: BTreeMap<String, String>,
+    nameserver: Option<SocketAddr>,
+}
+
+impl Srv {
+    /// Create a provider for the SRV records of `names`. Targets use `job` as the job.
+    pub fn new(names: Vec<String>, job: &str) -> Self {
+        Srv {
+            names,
+            job: job.to_string(),
+            labels: BTreeMap::new(),
+            nameserver: None,
+        }
+    }
+
+    /// Add a label to every source.
+    pub fn label(mut self, name: &str, value: &str) -> Self {
+        self.labels.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// The name server to query. Defaults to the first one in /etc/resolv.conf, and is required
+    /// without one.
+    pub fn nameserver(mut self, nameserver: SocketAddr) -> Self {
+        self.nameserver = Some(nameserver);
+        self
+    }
+
+    // Convert the records for `name` into a source.
+    fn records_to_sources(&self, name: &str, records: Vec<SrvRecord>) -> Result<Vec<Source>> {
+        let targets: BTreeSet<String> = records
+            .iter()
+            .map(|r| host_port(&r.target, r.port))
+            .collect();
+        if targets.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(vec![
+            Source::builder()
+                .job(&self.job)
+                .labels(self.labels.clone())
+                .label("srv_name", name.trim_end_matches('.'))
+                .targets(targets)
+                .build()?,
+        ])
+    }
+}
+
+impl Provider for Srv {
+    fn name(&self) -> &str {
+        "srv"
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        let nameserver = match self.nameserver {
+            Some(nameserver) => nameserver,
+            None => dns::nameserver()?,
+        };
+        let mut sources = Vec::new();
+        for name in &self.names {
+            let records = dns::resolve_srv(name, nameserver)?;
+            info!("Resolved {} SRV records for {}", records.len(), name);
+            sources.append(&mut self.records_to_sources(name, records)?);
+        }
+        Ok(sources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_records_to_sources() {
+        let record = |target: &str, port| SrvRecord {
+            priority: 10,
+            weight: 0,
+            port,
+            target: target.to_string(),
+        };
+        let srv =
+            Srv::new(vec!["_node._tcp.example.com.".to_string()], "node").label("env", "prod");
+        let sources = srv
+            .records_to_sources(
+                &srv.names[0],
+                vec![
+                    record("web2.example.com", 9100),
+                    record("web1.example.com", 9100),
+                    record("web1.example.com", 9100),
+                ],
+            )
+            .unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].jobs(), &vec!["node".to_string()]);
+        assert_eq!(
+            sources[0].targets(),
+            &vec![
+                "web1.example.com:9100".to_string(),
+                "web2.example.com:9100".to_string()
+            ]
+        );
+        assert_eq!(sources[0].labels()["srv_name"], "_node._tcp.example.com");
+        assert_eq!(sources[0].labels()["env"], "prod");
+        assert!(srv.records_to_sources("x", Vec::new()).unwrap().is_empty());
+    }
+}
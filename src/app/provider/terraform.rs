@@ -0,0 +1,270 @@
+use crate::app::provider::{Provider, get_json, host_port, label_name};
+use crate::app::source::Source;
+use crate::core::error::*;
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// The resource attributes used as targets when none are given, as `type.attribute` paths.
+pub const DEFAULT_TARGETS: &[&str] = &[
+    "aws_instance.private_ip",
+    "google_compute_instance.network_interface.0.network_ip",
+    "azurerm_linux_virtual_machine.private_ip_address",
+    "azurerm_windows_virtual_machine.private_ip_address",
+    "openstack_compute_instance_v2.access_ip_v4",
+    "vsphere_virtual_machine.default_ip_address",
+];
+
+/// Discover targets from a Terraform state file. Each instance of a mapped resource type with
+/// the mapped attribute set becomes a source with the attribute value and a port as the target.
+///
+/// The state is read from a local file or an http:// or https:// URL, such as a Terraform HTTP backend.
+/// Sources get a `terraform_resource` label with the resource address, and the resource's `tags`
+/// or `labels` attribute as `terraform_tag_<key>` labels.
+#[derive(Debug, Clone)]
+pub struct Terraform {
+    location: String,
+    job: String,
+    port: u16,
+    targets: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct State {
+    #[serde(default)]
+    resources: Vec<Resource>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Resource {
+    #[serde(default)]
+    module: Option<String>,
+    #[serde(default)]
+    mode: String,
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+    #[serde(default)]
+    instances: Vec<Instance>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Instance {
+    #[serde(default)]
+    index_key: Option<Value>,
+    #[serde(default)]
+    attributes: Value,
+}
+
+impl Terraform {
+    /// Create a provider for the state at `location`, a file path or an http:// or https:// URL. Targets use
+    /// `job` as the job and `port` as the port.
+    pub fn new(location: &str, job: &str, port: u16) -> Self {
+        Terraform {
+            location: location.to_string(),
+            job: job.to_string(),
+            port,
+            targets: BTreeMap::new(),
+        }
+    }
+
+    /// Use `attribute` of `kind` resources as the target host, such as `private_ip` of
+    /// `aws_instance`. Nested attributes are dotted, such as `network_interface.0.network_ip`.
+    /// [`DEFAULT_TARGETS`] is used when no targets are added.
+    pub fn target(mut self, kind: &str, attribute: &str) -> Self {
+        self.targets
+            .entry(kind.to_string())
+            .or_default()
+            .push(attribute.to_string());
+        self
+    }
+
+    // The attributes to use as targets by resource type.
+    fn target_map(&self) -> BTreeMap<String, Vec<String>> {
+        if !self.targets.is_empty() {
+            return self.targets.clone();
+        }
+
+        let mut targets: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for target in DEFAULT_TARGETS {
+            if let Some((kind, attribute)) = target.split_once('.') {
+                targets
+                    .entry(kind.to_string())
+                    .or_default()
+                    .push(attribute.to_string());
+            }
+        }
+        targets
+    }
+
+    fn read_state(&self) -> Result<State> {
+        if self.location.contains("://") {
+            return get_json(&self.location, &[]);
+        }
+
+        debug!("Reading Terraform state: {}", self.location);
+        let content = std::fs::read_to_string(&self.location).map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(&format!("reading Terraform state: {}", self.location))
+        })?;
+        serde_json::from_str(&content).map_err(|e| {
+            Error::new(SourceError::SerdeJson(e))
+                .set_context(&format!("parsing Terraform state: {}", self.location))
+        })
+    }
+
+    // Convert the managed resources in the state into sources.
+    fn state_to_sources(&self, state: State) -> Result<Vec<Source>> {
+        let targets = self.target_map();
+        let mut sources = Vec::new();
+        for resource in state.resources {
+            let Some(attributes) = targets.get(&resource.kind) else {
+                continue;
+            };
+            if resource.mode != "managed" {
+                continue;
+            }
+
+            for instance in &resource.instances {
+                let address = resource_address(&resource, instance);
+                let hosts: Vec<String> = attributes
+                    .iter()
+                    .filter_map(|a| lookup(&instance.attributes, a))
+                    .flat_map(hosts)
+                    .collect();
+                if hosts.is_empty() {
+                    debug!("Skipping {} without a target attribute", address);
+                    continue;
+                }
+
+                let mut labels = BTreeMap::from([("terraform_resource".to_string(), address)]);
+                let tags = ["tags", "labels"]
+                    .iter()
+                    .find_map(|key| instance.attributes.get(key)?.as_object());
+                for (key, value) in tags.into_iter().flatten() {
+                    if let Some(value) = value.as_str() {
+                        labels.insert(
+                            format!("terraform_tag_{}", label_name(key)),
+                            value.to_string(),
+                        );
+                    }
+                }
+
+                sources.push(
+                    Source::builder()
+                        .job(&self.job)
+                        .labels(labels)
+                        .targets(hosts.iter().map(|h| host_port(h, self.port)))
+                        .build()?,
+                );
+            }
+        }
+
+        Ok(sources)
+    }
+}
+
+impl Provider for Terraform {
+    fn name(&self) -> &str {
+        "terraform"
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        let sources = self.state_to_sources(self.read_state()?)?;
+        info!("Discovered {} sources from Terraform state", sources.len());
+        Ok(sources)
+    }
+}
+
+// The resource address as shown by `terraform state list`, such as
+// `module.web.aws_instance.app[0]`.
+fn resource_address(resource: &Resource, instance: &Instance) -> String {
+    let mut address = String::new();
+    if let Some(module) = &resource.module {
+        address.push_str(module);
+        address.push('.');
+    }
+    address.push_str(&format!("{}.{}", resource.kind, resource.name));
+    match &instance.index_key {
+        Some(Value::Number(n)) => address.push_str(&format!("[{}]", n)),
+        Some(Value::String(s)) => address.push_str(&format!("[\"{}\"]", s)),
+        _ => {}
+    }
+    address
+}
+
+// Look up a dotted attribute path. Numeric parts index into lists.
+fn lookup<'a>(attributes: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(attributes, |value, key| match value {
+            Value::Array(list) => list.get(key.parse::<usize>().ok()?),
+            value => value.get(key),
+        })
+}
+
+// The non-empty host strings in an attribute value, which may be a list.
+fn hosts(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) if !s.is_empty() => vec![s.clone()],
+        Value::Array(list) => list.iter().flat_map(hosts).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    const STATE: &str = r#"{"version": 4, "resources": [
+        {"mode": "managed", "type": "aws_instance", "name": "web", "module": "module.app",
+         "instances": [
+            {"index_key": 0, "attributes": {"private_ip": "10.0.0.1",
+             "tags": {"Name": "web-0", "env": "prod"}}},
+            {"index_key": 1, "attributes": {"private_ip": ""}}
+         ]},
+        {"mode": "managed", "type": "google_compute_instance", "name": "db",
+         "instances": [{"index_key": "a", "attributes": {"labels": {"role": "db"},
+            "network_interface": [{"network_ip": "10.1.0.1"}]}}]},
+        {"mode": "data", "type": "aws_instance", "name": "existing",
+         "instances": [{"attributes": {"private_ip": "10.0.0.9"}}]},
+        {"mode": "managed", "type": "aws_lb", "name": "front",
+         "instances": [{"attributes": {"dns_name": "front.elb.amazonaws.com"}}]}
+    ]}"#;
+
+    #[test]
+    fn test_state_to_sources() {
+        let state: State = serde_json::from_str(STATE).unwrap();
+        let sources = Terraform::new("terraform.tfstate", "node", 9100)
+            .state_to_sources(state)
+            .unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].targets(), &vec!["10.0.0.1:9100".to_string()]);
+        assert_eq!(
+            sources[0].labels()["terraform_resource"],
+            "module.app.aws_instance.web[0]"
+        );
+        assert_eq!(sources[0].labels()["terraform_tag_Name"], "web-0");
+        assert_eq!(sources[1].targets(), &vec!["10.1.0.1:9100".to_string()]);
+        assert_eq!(
+            sources[1].labels()["terraform_resource"],
+            "google_compute_instance.db[\"a\"]"
+        );
+        assert_eq!(sources[1].labels()["terraform_tag_role"], "db");
+    }
+
+    #[test]
+    fn test_state_to_sources_with_targets() {
+        let state: State = serde_json::from_str(STATE).unwrap();
+        let sources = Terraform::new("terraform.tfstate", "blackbox", 443)
+            .target("aws_lb", "dns_name")
+            .state_to_sources(state)
+            .unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(
+            sources[0].targets(),
+            &vec!["front.elb.amazonaws.com:443".to_string()]
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use crate::app::provider::{Provider, host_port};
+use crate::app::source::Source;
+use crate::core::error::*;
+use crate::core::ignore::glob_match;
+use log::{debug, info, warn};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Discover hosts from the A and AAAA records in a BIND zone file. The matching names become a
+/// single source with the names, or their addresses, and a port as the targets.
+///
+/// Relative names use the zone's `$ORIGIN`, the origin set with [`Zone::origin`], or the file
+/// name without a `db.` prefix or `.zone` or `.db` suffix. The source gets a `dns_zone` label with
+/// the origin. `$INCLUDE` directives are not followed.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    path: PathBuf,
+    job: String,
+    port: u16,
+    origin: Option<String>,
+    patterns: Vec<String>,
+    addresses: bool,
+}
+
+// An A or AAAA record. Names are lowercase and fully qualified without the trailing dot.
+#[derive(Debug, PartialEq, Eq)]
+struct Record {
+    name: String,
+    address: String,
+}
+
+impl Zone {
+    /// Create a provider for the zone file at `path`. Targets use `job` as the job and `port` as
+    /// the port.
+    pub fn new(path: &Path, job: &str, port: u16) -> Self {
+        Zone {
+            path: path.to_path_buf(),
+            job: job.to_string(),
+            port,
+            origin: None,
+            patterns: Vec::new(),
+            addresses: false,
+        }
+    }
+
+    /// The origin for files without an `$ORIGIN` directive, such as `example.com`.
+    pub fn origin(mut self, origin: &str) -> Self {
+        self.origin = Some(origin.trim_end_matches('.').to_lowercase());
+        self
+    }
+
+    /// Only discover names matching this glob, such as `web*.example.com`. All names are
+    /// discovered when no patterns are added.
+    pub fn pattern(mut self, pattern: &str) -> Self {
+        self.patterns.push(pattern.to_lowercase());
+        self
+    }
+
+    /// Target the record addresses instead of the names.
+    pub fn addresses(mut self, addresses: bool) -> Self {
+        self.addresses = addresses;
+        self
+    }
+
+    // The origin used before any `$ORIGIN` directive.
+    fn default_origin(&self) -> String {
+        if let Some(origin) = &self.origin {
+            return origin.clone();
+        }
+
+        let name = self
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let name = name.strip_prefix("db.").unwrap_or(&name);
+        let name = name
+            .strip_suffix(".zone")
+            .or_else(|| name.strip_suffix(".db"))
+            .unwrap_or(name);
+        name.to_string()
+    }
+
+    // Convert the matching records into a source.
+    fn records_to_sources(&self, origin: &str, records: Vec<Record>) -> Result<Vec<Source>> {
+        let targets: BTreeSet<String> = records
+            .into_iter()
+            .filter(|r| {
+                self.patterns.is_empty()
+                    || self
+                        .patterns
+                        .iter()
+                        .any(|p| glob_match(p.as_bytes(), r.name.as_bytes()))
+            })
+            .map(|r| match self.addresses {
+                true => host_port(&r.address, self.port),
+                false => host_port(&r.name, self.port),
+            })
+            .collect();
+        if targets.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(vec![
+            Source::builder()
+                .job(&self.job)
+                .label("dns_zone", origin)
+                .targets(targets)
+                .build()?,
+        ])
+    }
+}
+
+impl Provider for Zone {
+    fn name(&self) -> &str {
+        "zone"
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        debug!("Reading zone file: {}", self.path.display());
+        let content = std::fs::read_to_string(&self.path).map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(&format!("reading zone file: {}", self.path.display()))
+        })?;
+
+        let default_origin = self.default_origin();
+        let (origin, records) = parse_zone(&content, &default_origin);
+        let sources = self.records_to_sources(&origin, records)?;
+        info!(
+            "Discovered {} targets from zone {}",
+            sources.first().map_or(0, |s| s.targets().len()),
+            origin
+        );
+        Ok(sources)
+    }
+}
+
+// Parse the A and AAAA records in a zone file. Returns the first origin the records were read
+// with and the records.
+fn parse_zone(content: &str, default_origin: &str) -> (String, Vec<Record>) {
+    let mut origin = default_origin.to_string();
+    let mut zone: Option<String> = None;
+    let mut last_name = origin.clone();
+    let mut records = Vec::new();
+    for entry in entries(content) {
+        let mut fields = entry.fields.iter().map(String::as_str).peekable();
+        match fields.peek().copied() {
+            Some("$ORIGIN") => {
+                if let Some(value) = fields.nth(1) {
+                    origin = qualify(value, &origin);
+                }
+                continue;
+            }
+            Some(directive) if directive.starts_with('$') => {
+                if directive.eq_ignore_ascii_case("$INCLUDE") {
+                    warn!("Skipping $INCLUDE in zone {}", origin);
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let name = match entry.continued {
+            true => last_name.clone(),
+            false => match fields.next() {
+                Some(name) => qualify(name, &origin),
+                None => continue,
+            },
+        };
+        last_name = name.clone();
+        zone.get_or_insert_with(|| origin.clone());
+
+        // Skip the optional TTL and class before the type.
+        let kind = fields.find(|f| !is_ttl(f) && !is_class(f));
+        let Some(address) = fields.next() else {
+            continue;
+        };
+        if kind
+            .is_some_and(|kind| kind.eq_ignore_ascii_case("A") || kind.eq_ignore_ascii_case("AAAA"))
+        {
+            records.push(Record {
+                name: name.clone(),
+                address: address.to_string(),
+            });
+        }
+    }
+
+    (zone.unwrap_or(origin), records)
+}
+
+// A record or directive with its fields. Continued entries start with whitespace and use the
+// previous name.
+#[derive(Debug)]
+struct Entry {
+    continued: bool,
+    fields: Vec<String>,
+}
+
+// Split a zone file into entries, removing comments and joining parenthesized lines.
+fn entries(content: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut current: Option<Entry> = None;
+    let mut depth = 0;
+    for line in content.lines() {
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut fields = Vec::new();
+        for c in line.chars() {
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    field.push(c);
+                }
+                ';' if !quoted => break,
+                '(' if !quoted => depth += 1,
+                ')' if !quoted => depth -= 1,
+                c if c.is_whitespace() && !quoted => {
+                    if !field.is_empty() {
+                        fields.push(std::mem::take(&mut field));
+                    }
+                }
+                c => field.push(c),
+            }
+        }
+        if !field.is_empty() {
+            fields.push(field);
+        }
+
+        match current.as_mut() {
+            Some(entry) => entry.fields.extend(fields),
+            None if fields.is_empty() => continue,
+            None => {
+                current = Some(Entry {
+                    continued: line.starts_with(char::is_whitespace),
+                    fields,
+                });
+            }
+        }
+        if depth <= 0 {
+            depth = 0;
+            entries.extend(current.take());
+        }
+    }
+
+    entries.extend(current);
+    entries
+}
+
+// Make a name fully qualified, lowercase, and without the trailing dot.
+fn qualify(name: &str, origin: &str) -> String {
+    let name = name.to_lowercase();
+    if name == "@" {
+        origin.to_string()
+    } else if let Some(name) = name.strip_suffix('.') {
+        name.to_string()
+    } else if origin.is_empty() {
+        name
+    } else {
+        format!("{}.{}", name, origin)
+    }
+}
+
+// TTLs are numbers with optional BIND unit suffixes, such as `3600` or `1h30m`.
+fn is_ttl(field: &str) -> bool {
+    field.starts_with(|c: char| c.is_ascii_digit())
+        && field
+            .chars()
+            .all(|c| c.is_ascii_digit() || "smhdwSMHDW".contains(c))
+}
+
+fn is_class(field: &str) -> bool {
+    ["IN", "CH", "HS", "CS"]
+        .iter()
+        .any(|c| field.eq_ignore_ascii_case(c))
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    const ZONE: &str = r#"$TTL 1h
+$ORIGIN example.com.
+@   IN  SOA ns1 hostmaster (
+        2024010101 ; serial
+        3600 900 604800 300 )
+    IN  NS  ns1
+ns1     IN  A   192.0.2.1
+web1    300 IN  A   192.0.2.10
+        IN  AAAA 2001:db8::10
+WEB2    A   192.0.2.11 ; second web server
+mail    IN  MX  10 mx.example.net.
+txt     IN  TXT "a ; b"
+db.internal.example.com. IN A 10.0.0.5
+"#;
+
+    #[test]
+    fn test_parse_zone() {
+        let (origin, records) = parse_zone(ZONE, "ignored");
+        assert_eq!(origin, "example.com");
+        let names: Vec<&str> = records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "ns1.example.com",
+                "web1.example.com",
+                "web1.example.com",
+                "web2.example.com",
+                "db.internal.example.com"
+            ]
+        );
+        assert_eq!(records[2].address, "2001:db8::10");
+    }
+
+    #[test]
+    fn test_records_to_sources() {
+        let (origin, records) = parse_zone(ZONE, "example.com");
+        let zone = Zone::new(Path::new("db.example.com"), "node", 9100).pattern("web*");
+        assert_eq!(zone.default_origin(), "example.com");
+        let sources = zone.records_to_sources(&origin, records).unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(
+            sources[0].targets(),
+            &vec![
+                "web1.example.com:9100".to_string(),
+                "web2.example.com:9100".to_string()
+            ]
+        );
+        assert_eq!(sources[0].labels()["dns_zone"], "example.com");
+
+        let (origin, records) = parse_zone(ZONE, "example.com");
+        let sources = zone
+            .addresses(true)
+            .records_to_sources(&origin, records)
+            .unwrap();
+        assert_eq!(
+            sources[0].targets(),
+            &vec![
+                "192.0.2.10:9100".to_string(),
+                "192.0.2.11:9100".to_string(),
+                "[2001:db8::10]:9100".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_ttl() {
+        assert!(is_ttl("3600"));
+        assert!(is_ttl("1h30m"));
+        assert!(!is_ttl("A"));
+        assert!(!is_ttl("h1"));
+    }
+}
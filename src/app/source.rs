@@ -1,19 +1,37 @@
-use crate::app::target::{TargetFile, TargetFiles, TargetGroup};
+use crate::app::target::{MergeKey, TargetFile, TargetFiles, TargetGroup, check_file_names};
+#[cfg(feature = "fs")]
+use crate::app::transform;
+#[cfg(feature = "xlsx")]
+use crate::app::xlsx;
 use crate::core::error::*;
+use crate::core::hcl;
 use crate::core::input::{Input, InputFormat};
-use crate::core::output::{Output, OutputFormat};
+use crate::core::output::{Output, OutputFormat, OutputKind};
+use crate::core::yaml;
 use log::{debug, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use tracing::info_span;
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+/// A single source entry describing the jobs, labels, and targets for a group of targets.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct Source {
     jobs: Vec<String>,
     labels: BTreeMap<String, String>,
     targets: Vec<String>,
+    /// Identifies the group of this source across runs, and keeps it apart from groups with the
+    /// same job and labels but another id. Also read as `group_id`.
+    #[serde(default, alias = "group_id", skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
 }
 
 impl Source {
+    /// Create a new builder for constructing a validated Source.
+    pub fn builder() -> SourceBuilder {
+        SourceBuilder::default()
+    }
+
     pub fn jobs(&self) -> &Vec<String> {
         &self.jobs
     }
@@ -37,6 +55,91 @@ impl Source {
         &mut self.targets
     }
 
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn set_id(&mut self, id: Option<String>) {
+        self.id = id;
+    }
+
+    /// Validate the source. A valid source has at least one job, no empty jobs or targets, and
+    /// only label names allowed by Prometheus.
+    pub fn validate(&self) -> Result<()> {
+        debug!("Validating source");
+        if self.jobs.is_empty() {
+            return Err(Error::new(SourceError::InvalidInputSource(
+                "Source must have at least one job".to_string(),
+            ))
+            .set_kind(ErrorKind::Validation));
+        }
+
+        if self.jobs.iter().any(|job| job.is_empty()) {
+            return Err(Error::new(SourceError::InvalidInputSource(
+                "Jobs in source cannot be empty".to_string(),
+            ))
+            .set_kind(ErrorKind::Validation));
+        }
+
+        if self.targets.iter().any(|target| target.is_empty()) {
+            return Err(Error::new(SourceError::InvalidInputSource(
+                "Targets in source cannot be empty".to_string(),
+            ))
+            .set_kind(ErrorKind::Validation));
+        }
+
+        for name in self.labels.keys() {
+            if !is_valid_label_name(name) {
+                return Err(Error::new(SourceError::InvalidInputSource(format!(
+                    "Invalid label name '{}'",
+                    name
+                )))
+                .set_kind(ErrorKind::Validation));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merge another source into this one. Jobs and targets are added if not already present and
+    /// labels from `other` override labels with the same name.
+    pub fn merge(&mut self, other: Source) {
+        debug!("Merging source into source");
+        for job in other.jobs {
+            if !self.jobs.contains(&job) {
+                self.jobs.push(job);
+            }
+        }
+
+        self.labels.extend(other.labels);
+        if other.id.is_some() {
+            self.id = other.id;
+        }
+
+        for target in other.targets {
+            if !self.targets.contains(&target) {
+                self.targets.push(target);
+            }
+        }
+    }
+
+    /// Normalize the source by trimming whitespace and removing empty and duplicate jobs and
+    /// targets, and an empty id. The order of first occurrence is kept.
+    pub fn normalize(&mut self) {
+        debug!("Normalizing source");
+        normalize_list(&mut self.jobs);
+        normalize_list(&mut self.targets);
+        self.labels = std::mem::take(&mut self.labels)
+            .into_iter()
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect();
+        self.id = self
+            .id
+            .take()
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty());
+    }
+
     pub fn into_targets(
         &self,
         output: &Output,
@@ -44,22 +147,11 @@ impl Source {
         target_files: &mut TargetFiles,
     ) -> Result<()> {
         debug!("Converting source into target files");
-        if self.jobs.is_empty() {
-            return Err(Error::new(SourceError::InvalidInputSource(
-                "Source must have at least one job".to_string(),
-            ))
-            .set_code(CODE_RUNTIME_ERROR));
-        }
+        self.validate()?;
 
         debug!("Converting jobs into target groups");
         for job in &self.jobs {
-            if job.is_empty() {
-                return Err(Error::new(SourceError::InvalidInputSource(
-                    "Jobs in source cannot be empty".to_string(),
-                ))
-                .set_code(CODE_RUNTIME_ERROR));
-            }
-
+            let _span = info_span!("convert job", job = %job).entered();
             debug!("Processing job: {}", job);
             if !target_files.has_job(job) {
                 let target_file = TargetFile::new(job, output, format)?;
@@ -69,11 +161,10 @@ impl Source {
             debug!("Adding target group to target file for job: {}", job);
             match target_files.target_file_mut(job) {
                 Some(tf) => {
-                    tf.add_target(TargetGroup::new(
-                        job,
-                        self.labels.clone(),
-                        self.targets.clone(),
-                    ));
+                    tf.add_target(
+                        TargetGroup::new(job, self.labels.clone(), self.targets.clone())
+                            .with_id(self.id.clone()),
+                    );
                 }
                 None => {
                     // No need to return an error but we do need to warn the user. that there could
@@ -88,10 +179,317 @@ impl Source {
     }
 }
 
+/// Builder for constructing a validated Source.
+#[derive(Debug, Default, Clone)]
+pub struct SourceBuilder {
+    source: Source,
+}
+
+impl SourceBuilder {
+    pub fn job(mut self, job: &str) -> Self {
+        self.source.jobs.push(job.to_string());
+        self
+    }
+
+    pub fn jobs<I, S>(mut self, jobs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.source.jobs.extend(jobs.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn label(mut self, name: &str, value: &str) -> Self {
+        self.source
+            .labels
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn labels<I, K, V>(mut self, labels: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.source
+            .labels
+            .extend(labels.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    pub fn id(mut self, id: &str) -> Self {
+        self.source.id = Some(id.to_string());
+        self
+    }
+
+    pub fn target(mut self, target: &str) -> Self {
+        self.source.targets.push(target.to_string());
+        self
+    }
+
+    pub fn targets<I, S>(mut self, targets: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.source
+            .targets
+            .extend(targets.into_iter().map(Into::into));
+        self
+    }
+
+    /// Normalize and validate the source before returning it.
+    pub fn build(self) -> Result<Source> {
+        let mut source = self.source;
+        source.normalize();
+        source.validate()?;
+        Ok(source)
+    }
+}
+
+/// Check if a label name is valid for Prometheus ([a-zA-Z_][a-zA-Z0-9_]*).
+pub fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Turn an arbitrary name, such as a tag key, into a valid Prometheus label name by replacing
+/// invalid characters with `_`.
+pub fn label_name(name: &str) -> String {
+    let mut label: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !label.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        label.insert(0, '_');
+    }
+    label
+}
+
+/// The version of the source format, given as the `apiVersion` of a source document.
+pub const SOURCE_API_VERSION: &str = "pim/v1";
+
+/// The source format versions this pim reads.
+pub const SOURCE_VERSIONS: &[u32] = &[1];
+
+/// A source document, either a list of sources or a mapping with the `apiVersion` of the source
+/// format and its `sources`. A list has no version and is read as the first version, so
+/// documents written before the format was versioned keep working. Fields of the mapping
+/// starting with `x-` are ignored.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SourceDocument {
+    pub version: Option<u32>,
+    pub sources: Vec<Source>,
+}
+
+impl<'de> Deserialize<'de> for SourceDocument {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(DocumentVisitor)
+    }
+}
+
+struct DocumentVisitor;
+
+impl<'de> serde::de::Visitor<'de> for DocumentVisitor {
+    type Value = SourceDocument;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a list of sources or a mapping with apiVersion and sources")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+        self,
+        seq: A,
+    ) -> std::result::Result<Self::Value, A::Error> {
+        let sources = Vec::deserialize(serde::de::value::SeqAccessDeserializer::new(seq))?;
+        Ok(SourceDocument {
+            version: None,
+            sources,
+        })
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> std::result::Result<Self::Value, A::Error> {
+        use serde::de::Error as _;
+        // Each version has its own deserializer, so older documents can still be read once the
+        // format changes. Sources after the version are read like a list of sources, and sources
+        // before it are kept as a value until the version is known.
+        let (mut version, mut sources, mut pending) = (None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "apiVersion" | "version" => {
+                    let value = map.next_value::<Value>()?;
+                    version = Some(parse_version(&value).map_err(A::Error::custom)?);
+                }
+                "sources" => match version {
+                    Some(1) => sources = Some(map.next_value::<Vec<Source>>()?),
+                    Some(version) => return Err(no_reader(version)),
+                    None => pending = Some(map.next_value::<Value>()?),
+                },
+                // Extension fields hold anchors shared by the sources, such as `&defaults`.
+                key if key.starts_with("x-") => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+                key => {
+                    return Err(A::Error::unknown_field(
+                        key,
+                        &["apiVersion", "version", "sources"],
+                    ));
+                }
+            }
+        }
+
+        let version = version.ok_or_else(|| A::Error::missing_field("apiVersion"))?;
+        let sources = match (sources, pending, version) {
+            (Some(sources), _, _) => sources,
+            (None, Some(pending), 1) => {
+                serde_json::from_value(pending).map_err(A::Error::custom)?
+            }
+            (None, Some(_), version) => return Err(no_reader(version)),
+            (None, None, _) => Vec::new(),
+        };
+        Ok(SourceDocument {
+            version: Some(version),
+            sources,
+        })
+    }
+}
+
+fn no_reader<E: serde::de::Error>(version: u32) -> E {
+    E::custom(format!("no reader for source format version {}", version))
+}
+
+/// Read a source format version, such as `pim/v1`, `v1`, or `1`, and check that it is supported.
+pub fn parse_version(value: &Value) -> std::result::Result<u32, String> {
+    let version = match value {
+        Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+        Value::String(s) => {
+            let s = s.strip_prefix("pim/").unwrap_or(s);
+            s.strip_prefix('v').unwrap_or(s).parse().ok()
+        }
+        _ => None,
+    };
+    match version {
+        Some(version) if SOURCE_VERSIONS.contains(&version) => Ok(version),
+        _ => Err(format!(
+            "unsupported source format version {}, this pim reads {}",
+            value, SOURCE_API_VERSION
+        )),
+    }
+}
+
+// Trim all items, then drop empty and duplicate items while keeping the first occurrence.
+fn normalize_list(list: &mut Vec<String>) {
+    let mut seen: Vec<String> = Vec::new();
+    for item in list.drain(..) {
+        let item = item.trim().to_string();
+        if !item.is_empty() && !seen.contains(&item) {
+            seen.push(item);
+        }
+    }
+    *list = seen;
+}
+
+// HCL sources are written as a `source` block per entry.
+fn from_hcl(text: &str) -> Result<Vec<Source>> {
+    let mut body = hcl::to_value(text)?;
+    if let Some(version) = body.get("apiVersion") {
+        parse_version(version).map_err(|e| {
+            Error::new(SourceError::Msg(e))
+                .set_context("Failed to read HCL source")
+                .set_kind(ErrorKind::Parse)
+        })?;
+    }
+    if let Some(name) = body
+        .as_object()
+        .and_then(|b| b.keys().find(|k| *k != "source" && *k != "apiVersion"))
+    {
+        return Err(Error::new(SourceError::Msg(format!(
+            "Unexpected '{}' in HCL source, expected source blocks",
+            name
+        )))
+        .set_kind(ErrorKind::Parse));
+    }
+
+    let blocks = body
+        .get_mut("source")
+        .map(Value::take)
+        .unwrap_or_else(|| Value::Array(Vec::new()));
+    serde_json::from_value(blocks).map_err(|e| {
+        Error::new(SourceError::SerdeJson(e)).set_context("Failed to deserialize source from HCL")
+    })
+}
+
+// Parse a source document without reading it as sources, so it can be transformed first.
+#[cfg(feature = "fs")]
+fn document(input: &mut Input) -> Result<Value> {
+    let name = input.kind().name();
+    match input.format() {
+        InputFormat::Json => {
+            let text = input.read_text()?;
+            serde_json::from_str(&text).map_err(|e| {
+                Error::new(SourceError::SerdeJson(e))
+                    .set_context("Failed to parse JSON source")
+                    .locate(&name, &text)
+            })
+        }
+        InputFormat::Json5 => {
+            let text = input.read_text()?;
+            json5::from_str(&text).map_err(|e| {
+                Error::new(SourceError::Json5(e))
+                    .set_context("Failed to parse JSON5 source")
+                    .locate(&name, &text)
+            })
+        }
+        InputFormat::Hcl => hcl::to_value(&input.read_text()?),
+        #[cfg(feature = "xlsx")]
+        InputFormat::Xlsx => {
+            let sheet = input.sheet().map(str::to_string);
+            let sources = xlsx::read_sources(input.read_bytes()?, sheet.as_deref())?;
+            serde_json::to_value(sources).map_err(|e| {
+                Error::new(SourceError::SerdeJson(e))
+                    .set_context("Failed to serialize XLSX sources")
+                    .set_kind(ErrorKind::Internal)
+            })
+        }
+        InputFormat::Yaml => {
+            let text = input.read_text()?;
+            yaml::from_str_limited(&text, input.max_yaml_nodes()).map_err(|e| {
+                Error::new(SourceError::SerdeYaml(e))
+                    .set_context("Failed to parse YAML source")
+                    .locate(&name, &text)
+            })
+        }
+        _ => Err(Error::new(SourceError::UnsupportedInputFormat(
+            input.format().as_str().to_string(),
+        ))
+        .set_context("Unsupported input format for source")
+        .set_kind(ErrorKind::Config)),
+    }
+}
+
 #[derive(Debug)]
 pub struct SourceFile {
     pub inputs: Vec<Input>,
     pub sources: Vec<Source>,
+    // Sources dropped since an identical one came first.
+    duplicates: usize,
+    // Whether the group ids of sources keep their groups apart.
+    merge_key: MergeKey,
+    // A jq expression applied to each parsed document before it is read as sources.
+    #[cfg(feature = "fs")]
+    transform: Option<String>,
 }
 
 impl SourceFile {
@@ -99,9 +497,24 @@ impl SourceFile {
         SourceFile {
             inputs,
             sources: Vec::new(),
+            duplicates: 0,
+            merge_key: MergeKey::default(),
+            #[cfg(feature = "fs")]
+            transform: None,
         }
     }
 
+    /// Apply a jq expression to each parsed source document before it is read as sources, to
+    /// reshape documents that don't quite match the source format. Requires `jq`.
+    #[cfg(feature = "fs")]
+    pub fn set_transform(&mut self, expr: Option<String>) {
+        self.transform = expr;
+    }
+
+    pub fn set_merge_key(&mut self, merge_key: MergeKey) {
+        self.merge_key = merge_key;
+    }
+
     pub fn add_input(&mut self, input: Input) {
         self.inputs.push(input);
     }
@@ -113,41 +526,68 @@ impl SourceFile {
     pub fn read_sources(&mut self) -> Result<()> {
         debug!("Reading sources from inputs");
         for input in &mut self.inputs {
+            let name = input.kind().name();
+            let _span = info_span!("parse source file", path = %name).entered();
             debug!("Reading source from input: {:?}", input);
-            /*
-            input.read_content()?;
-            let content = &input.content();
-            if content.is_empty() {
-                warn!("Input {:?} is empty, skipping", input.kind());
+            if !input.format().is_binary() {
+                input.check_text()?;
+            }
+            #[cfg(feature = "fs")]
+            if let Some(expr) = &self.transform {
+                let document = transform::apply(expr, &document(input)?)?;
+                let mut src = serde_json::from_value::<SourceDocument>(document)
+                    .map_err(|e| {
+                        Error::new(SourceError::SerdeJson(e))
+                            .set_context("Failed to deserialize transformed source")
+                    })?
+                    .sources;
+                debug!("Transformed source deserialized: {:?}", src);
+                self.sources.append(&mut src);
                 continue;
             }
-            */
-
             let mut src: Vec<Source> = match input.format() {
                 InputFormat::Json => {
-                    //debug!("Deserializing as JSON: {}", content);
-                    //serde_json::from_str(content).map_err(|e| {
-                    serde_json::from_reader(input.mut_reader()).map_err(|e| {
-                        Error::new(SourceError::SerdeJson(e))
-                            .set_context("Failed to deserialize source from JSON")
-                            .set_code(CODE_RUNTIME_ERROR)
-                    })?
+                    let text = input.read_text()?;
+                    serde_json::from_str::<SourceDocument>(&text)
+                        .map_err(|e| {
+                            Error::new(SourceError::SerdeJson(e))
+                                .set_context("Failed to deserialize source from JSON")
+                                .locate(&name, &text)
+                        })?
+                        .sources
+                }
+                InputFormat::Json5 => {
+                    let text = input.read_text()?;
+                    json5::from_str::<SourceDocument>(&text)
+                        .map_err(|e| {
+                            Error::new(SourceError::Json5(e))
+                                .set_context("Failed to deserialize source from JSON5")
+                                .locate(&name, &text)
+                        })?
+                        .sources
+                }
+                InputFormat::Hcl => from_hcl(&input.read_text()?)?,
+                #[cfg(feature = "xlsx")]
+                InputFormat::Xlsx => {
+                    let sheet = input.sheet().map(str::to_string);
+                    xlsx::read_sources(input.read_bytes()?, sheet.as_deref())?
                 }
                 InputFormat::Yaml => {
-                    //debug!("Deserializing as YAML: {}", content);
-                    //serde_yaml::from_str(content).map_err(|e| {
-                    serde_yaml::from_reader(input.mut_reader()).map_err(|e| {
-                        Error::new(SourceError::SerdeYaml(e))
-                            .set_context("Failed to deserialize source from YAML")
-                            .set_code(CODE_RUNTIME_ERROR)
-                    })?
+                    let text = input.read_text()?;
+                    yaml::from_str_limited::<SourceDocument>(&text, input.max_yaml_nodes())
+                        .map_err(|e| {
+                            Error::new(SourceError::SerdeYaml(e))
+                                .set_context("Failed to deserialize source from YAML")
+                                .locate(&name, &text)
+                        })?
+                        .sources
                 }
                 _ => {
                     return Err(Error::new(SourceError::UnsupportedInputFormat(
                         input.format().as_str().to_string(),
                     ))
                     .set_context("Unsupported input format for source")
-                    .set_code(CODE_RUNTIME_ERROR));
+                    .set_kind(ErrorKind::Config));
                 }
             };
 
@@ -155,9 +595,29 @@ impl SourceFile {
             self.sources.append(&mut src);
         }
 
+        self.dedupe();
         Ok(())
     }
 
+    /// Merge sources that are exactly the same, such as a source copied into several files, into
+    /// the first of them. They would only add the same targets again. `read_sources` merges the
+    /// sources it reads, and sources added afterwards are merged by calling this again.
+    pub fn dedupe(&mut self) {
+        let mut seen = HashSet::new();
+        let before = self.sources.len();
+        self.sources.retain(|source| seen.insert(source.clone()));
+        let merged = before - self.sources.len();
+        if merged > 0 {
+            debug!("Merged {} identical sources", merged);
+        }
+        self.duplicates += merged;
+    }
+
+    /// The number of sources merged into an identical one by `dedupe`.
+    pub fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+
     pub fn into_targets(
         &self,
         output: &Output,
@@ -167,9 +627,236 @@ impl SourceFile {
         debug!("Converting all sources into target files");
         for source in &self.sources {
             debug!("Converting source: {:?}", source);
+            if self.merge_key == MergeKey::Labels && source.id.is_some() {
+                let mut source = source.clone();
+                source.id = None;
+                source.into_targets(output, format, target_files)?;
+                continue;
+            }
             source.into_targets(output, format, target_files)?;
         }
 
+        // Jobs get a file each in an output directory, which must not be shared.
+        if matches!(output.kind(), OutputKind::Directory(_)) {
+            check_file_names(target_files.jobs())?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_source_builder() {
+        let source = Source::builder()
+            .job("node")
+            .label("env", "prod")
+            .target("host:9100")
+            .build()
+            .unwrap();
+        assert_eq!(source.jobs(), &vec!["node".to_string()]);
+        assert_eq!(source.labels().get("env"), Some(&"prod".to_string()));
+        assert_eq!(source.targets(), &vec!["host:9100".to_string()]);
+    }
+
+    #[test]
+    fn test_source_builder_requires_job() {
+        let res = Source::builder().target("host:9100").build();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_source_builder_invalid_label() {
+        let res = Source::builder()
+            .job("node")
+            .label("bad-label", "value")
+            .build();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_source_merge() {
+        let mut a = Source::builder()
+            .job("node")
+            .label("env", "dev")
+            .target("a")
+            .build()
+            .unwrap();
+        let b = Source::builder()
+            .jobs(["node", "ssh"])
+            .label("env", "prd")
+            .targets(["a", "b"])
+            .build()
+            .unwrap();
+        a.merge(b);
+        assert_eq!(a.jobs(), &vec!["node".to_string(), "ssh".to_string()]);
+        assert_eq!(a.labels().get("env"), Some(&"prd".to_string()));
+        assert_eq!(a.targets(), &vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn test_source_normalize() {
+        let mut source = Source::default();
+        source
+            .jobs_mut()
+            .extend([" node ".to_string(), "node".to_string()]);
+        source.targets_mut().extend([
+            "b".to_string(),
+            "".to_string(),
+            " a".to_string(),
+            "b".to_string(),
+        ]);
+        source.normalize();
+        assert_eq!(source.jobs(), &vec!["node".to_string()]);
+        assert_eq!(source.targets(), &vec!["b".to_string(), "a".to_string()]);
+    }
+
+    #[test]
+    fn test_source_file_from_reader_to_writer() {
+        let yaml = "- jobs: [node]\n  labels:\n    env: dev\n  targets: [host:9100]\n";
+        let input = Input::from_reader(std::io::Cursor::new(yaml), InputFormat::Yaml).unwrap();
+        let mut source_file = SourceFile::new(vec![input]);
+        source_file.read_sources().unwrap();
+        assert_eq!(source_file.sources.len(), 1);
+
+        let buffer = crate::core::io::SharedBuffer::new();
+        let output = Output::from_writer(buffer.clone(), OutputFormat::Json);
+        let mut target_files = TargetFiles::default();
+        source_file
+            .into_targets(&output, output.format(), &mut target_files)
+            .unwrap();
+        target_files.write_all().unwrap();
+        assert_eq!(
+            buffer.to_string_lossy(),
+            r#"[{"labels":{"job":"node","env":"dev"},"targets":["host:9100"]}]"#
+        );
+    }
+
+    #[test]
+    fn test_read_sources_yaml_anchors() {
+        let yaml = "apiVersion: pim/v1\nx-defaults: &defaults\n  jobs: [node]\n  labels: &labels\n    \
+                    env: prod\n    port: 9100\nsources:\n  - <<: *defaults\n    targets: [a]\n  \
+                    - <<: *defaults\n    labels:\n      <<: *labels\n      env: dev\n    targets: [b]\n  \
+                    - jobs: [ssh]\n    labels: *labels\n    targets: [c]\n";
+        let input = Input::from_reader(std::io::Cursor::new(yaml), InputFormat::Yaml).unwrap();
+        let mut source_file = SourceFile::new(vec![input]);
+        source_file.read_sources().unwrap();
+        let labels: Vec<(&str, &str)> = source_file
+            .sources
+            .iter()
+            .map(|s| (s.jobs()[0].as_str(), s.labels()["env"].as_str()))
+            .collect();
+        assert_eq!(labels, [("node", "prod"), ("node", "dev"), ("ssh", "prod")]);
+        assert!(
+            source_file
+                .sources
+                .iter()
+                .all(|s| s.labels()["port"] == "9100")
+        );
+    }
+
+    #[test]
+    fn test_read_sources_json5() {
+        let json5 = "// Web servers\n[\n  {\n    jobs: ['node'], /* the job */\n    \
+                     labels: {env: 'it\\'s \"prod\"',},\n    \"targets\": ['web1', \"web2\",],\n  },\n]\n";
+        let input = Input::from_reader(std::io::Cursor::new(json5), InputFormat::Json5).unwrap();
+        let mut source_file = SourceFile::new(vec![input]);
+        source_file.read_sources().unwrap();
+        let source = &source_file.sources[0];
+        assert_eq!(source.jobs(), &vec!["node".to_string()]);
+        assert_eq!(
+            source.targets(),
+            &vec!["web1".to_string(), "web2".to_string()]
+        );
+        assert_eq!(source.labels()["env"], "it's \"prod\"");
+
+        let input =
+            Input::from_reader(std::io::Cursor::new("[\n  {jobs: }\n]"), InputFormat::Json5)
+                .unwrap();
+        let err = SourceFile::new(vec![input]).read_sources().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        assert!(err.location().starts_with(" --> <reader>:2:"));
+    }
+
+    #[test]
+    fn test_read_sources_parse_error() {
+        let yaml = "- targets: [a]\n  labels: {}\n";
+        let input = Input::from_reader(std::io::Cursor::new(yaml), InputFormat::Yaml).unwrap();
+        let err = SourceFile::new(vec![input]).read_sources().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        assert!(err.location().starts_with(" --> <reader>:1:3"));
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let yaml = "- jobs: [node]\n  targets: [a]\n  labels: {env: dev}\n- jobs: [node]\n  targets: [b]\n  labels: {}\n";
+        let inputs = (0..2)
+            .map(|_| Input::from_reader(std::io::Cursor::new(yaml), InputFormat::Yaml).unwrap())
+            .collect();
+        let mut source = SourceFile::new(inputs);
+        source.read_sources().unwrap();
+        assert_eq!(source.sources.len(), 2);
+        assert_eq!(source.duplicates(), 2);
+
+        source.sources.push(source.sources[1].clone());
+        source.dedupe();
+        assert_eq!(source.sources.len(), 2);
+        assert_eq!(source.duplicates(), 3);
+    }
+
+    #[test]
+    fn test_merge_key() {
+        let yaml = "- jobs: [node]\n  labels: {}\n  targets: [a]\n\
+                    - jobs: [node]\n  labels: {}\n  targets: [b]\n  group_id: ' canary '\n";
+        let input = Input::from_reader(std::io::Cursor::new(yaml), InputFormat::Yaml).unwrap();
+        let mut source = SourceFile::new(vec![input]);
+        source.read_sources().unwrap();
+        assert_eq!(source.sources[1].id(), Some(" canary "));
+
+        let groups = |source: &SourceFile| {
+            let output = Output::from_writer(std::io::sink(), OutputFormat::Json);
+            let mut target_files = TargetFiles::default();
+            source
+                .into_targets(&output, output.format(), &mut target_files)
+                .unwrap();
+            target_files.get("node").unwrap().targets().len()
+        };
+        assert_eq!(groups(&source), 2);
+        source.set_merge_key(MergeKey::Labels);
+        assert_eq!(groups(&source), 1);
+
+        let mut built = Source::builder().job("node").id(" ").build().unwrap();
+        assert_eq!(built.id(), None);
+        built.merge(source.sources[1].clone());
+        assert_eq!(built.id(), Some(" canary "));
+    }
+
+    #[test]
+    fn test_from_hcl() {
+        let hcl = r#"
+source "web" {
+  jobs    = ["node"]
+  labels  = { env = "dev" }
+  targets = ["host:9100"]
+}
+"#;
+        let sources = from_hcl(hcl).unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].jobs(), &vec!["node".to_string()]);
+        assert_eq!(sources[0].labels()["env"], "dev");
+        assert!(from_hcl("").unwrap().is_empty());
+        assert!(from_hcl("target \"web\" {}").is_err());
+    }
+
+    #[test]
+    fn test_is_valid_label_name() {
+        assert!(is_valid_label_name("env"));
+        assert!(is_valid_label_name("_private1"));
+        assert!(!is_valid_label_name("1env"));
+        assert!(!is_valid_label_name("bad-label"));
+        assert!(!is_valid_label_name(""));
+    }
+}
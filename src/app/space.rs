@@ -0,0 +1,130 @@
+use crate::app::target::TargetFiles;
+use crate::core::error::*;
+use crate::core::io::SharedBuffer;
+use crate::core::{Output, OutputKind, Style};
+use log::debug;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Free space is read with statvfs on Unix and GetDiskFreeSpaceExW on Windows, through fs4.
+
+/// Fail if a filesystem the target files are written to doesn't have room for them. The size of
+/// each file is its serialized size, and the files it replaces are kept until the run is
+/// complete, so the whole size must be free.
+pub fn check(target_files: &TargetFiles) -> Result<()> {
+    for (dir, needed) in estimate(target_files)? {
+        let available = available(&dir)?;
+        debug!(
+            "{} needs {} bytes with {} bytes available",
+            dir.display(),
+            needed,
+            available
+        );
+        if needed > available {
+            return Err(Error::new(SourceError::Msg(format!(
+                "The target files need {} but only {} is free on the filesystem of {}",
+                format_size(needed),
+                format_size(available),
+                dir.display()
+            )))
+            .set_context("Not enough free space")
+            .set_kind(ErrorKind::Io));
+        }
+    }
+    Ok(())
+}
+
+/// The serialized size of the target files, in bytes, per directory they are written to.
+pub fn estimate(target_files: &TargetFiles) -> Result<BTreeMap<PathBuf, u64>> {
+    let mut sizes = BTreeMap::new();
+    for (_, target_file) in target_files {
+        let output = target_file.output();
+        let OutputKind::File(path) = output.kind() else {
+            continue;
+        };
+        let buffer = SharedBuffer::new();
+        let mut sized = Output::from_writer(buffer.clone(), output.format().clone());
+        sized.set_style(Style {
+            pretty: Some(output.is_pretty()),
+            ..output.style().clone()
+        });
+        target_file.write_to(&mut sized)?;
+
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        *sizes.entry(dir).or_default() += buffer.contents().len() as u64;
+    }
+    Ok(sizes)
+}
+
+/// The bytes free to unprivileged users on the filesystem holding the directory.
+pub fn available(dir: &Path) -> Result<u64> {
+    fs4::available_space(dir).map_err(|e| {
+        Error::new(SourceError::Io(e))
+            .set_context(&format!("Checking free space of {}", dir.display()))
+            .set_kind(ErrorKind::Io)
+    })
+}
+
+// Format a size in bytes with a binary unit, such as `1.5 MiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_available() {
+        assert!(available(&std::env::temp_dir()).unwrap() > 0);
+        assert!(available(Path::new("/pim-missing-dir")).is_err());
+    }
+
+    #[test]
+    fn test_estimate() {
+        use crate::app::target::{TargetFile, TargetGroup};
+        use crate::core::OutputFormat;
+
+        let dir = std::env::temp_dir().join(format!("pim-space-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = Output::new(&dir, OutputFormat::Yaml).unwrap();
+        let mut file = TargetFile::new("node", &output, &OutputFormat::Yaml).unwrap();
+        let labels = BTreeMap::from([("env".to_string(), "prod".to_string())]);
+        file.add_target(TargetGroup::new(
+            "node",
+            labels,
+            vec!["web1:9100".to_string()],
+        ));
+        let mut target_files = TargetFiles::default();
+        target_files.insert("node".to_string(), file);
+
+        let sizes = estimate(&target_files).unwrap();
+        target_files.write_all().unwrap();
+        let written = std::fs::metadata(dir.join("node_targets.yml"))
+            .unwrap()
+            .len();
+        assert_eq!(sizes, BTreeMap::from([(dir.clone(), written)]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
@@ -0,0 +1,172 @@
+use crate::app::target::TargetFiles;
+use crate::core::table;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Counts of the groups and targets in target files.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub jobs: usize,
+    pub groups: usize,
+    /// Every target of every group, so a target in several groups is counted once per group.
+    pub targets: usize,
+    /// Distinct targets across all jobs.
+    pub unique_targets: usize,
+    pub per_job: BTreeMap<String, JobStats>,
+    /// Targets per value of each label, other than `job`.
+    pub labels: BTreeMap<String, BTreeMap<String, usize>>,
+    /// Sources merged into an identical source, such as one copied into several files.
+    pub duplicate_sources: usize,
+}
+
+/// Counts of the groups and targets of a job.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct JobStats {
+    pub groups: usize,
+    pub targets: usize,
+}
+
+impl Stats {
+    /// Count the groups and targets of the target files. If `labels` is not empty, only those
+    /// labels are broken down by value.
+    pub fn new(target_files: &TargetFiles, labels: &[String]) -> Self {
+        let mut stats = Stats::default();
+        let mut unique = BTreeSet::new();
+        for (job, target_file) in target_files {
+            let job_stats = stats.per_job.entry(job.clone()).or_default();
+            for group in target_file.targets() {
+                job_stats.groups += 1;
+                job_stats.targets += group.targets().len();
+                unique.extend(group.targets());
+                for (name, value) in group.labels() {
+                    if name == "job" || (!labels.is_empty() && !labels.contains(name)) {
+                        continue;
+                    }
+                    *stats
+                        .labels
+                        .entry(name.clone())
+                        .or_default()
+                        .entry(value.clone())
+                        .or_default() += group.targets().len();
+                }
+            }
+        }
+
+        stats.jobs = stats.per_job.len();
+        stats.groups = stats.per_job.values().map(|s| s.groups).sum();
+        stats.targets = stats.per_job.values().map(|s| s.targets).sum();
+        stats.unique_targets = unique.len();
+        stats
+    }
+
+    /// Render the totals, a table of the jobs, and a table of the label values.
+    pub fn table(&self) -> String {
+        let mut out = format!(
+            "{} jobs, {} groups, {} targets, {} unique\n",
+            self.jobs, self.groups, self.targets, self.unique_targets
+        );
+        if self.duplicate_sources > 0 {
+            out.push_str(&format!(
+                "{} duplicate sources merged\n",
+                self.duplicate_sources
+            ));
+        }
+        out.push('\n');
+        let jobs: Vec<Vec<String>> = self
+            .per_job
+            .iter()
+            .map(|(job, s)| vec![job.clone(), s.groups.to_string(), s.targets.to_string()])
+            .collect();
+        out.push_str(&table::render(&["JOB", "GROUPS", "TARGETS"], &jobs));
+
+        if !self.labels.is_empty() {
+            let labels: Vec<Vec<String>> = self
+                .labels
+                .iter()
+                .flat_map(|(name, values)| {
+                    values.iter().map(move |(value, targets)| {
+                        vec![name.clone(), value.clone(), targets.to_string()]
+                    })
+                })
+                .collect();
+            out.push('\n');
+            out.push_str(&table::render(&["LABEL", "VALUE", "TARGETS"], &labels));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::app::target::{TargetFile, TargetGroup};
+    use crate::core::{Output, OutputFormat};
+
+    fn target_files() -> TargetFiles {
+        let output = Output::from_writer(std::io::sink(), OutputFormat::Json);
+        let mut target_files = TargetFiles::default();
+        for (job, groups) in [
+            (
+                "node",
+                vec![
+                    ("prod", vec!["web1:9100", "web2:9100"]),
+                    ("dev", vec!["dev1:9100"]),
+                ],
+            ),
+            ("blackbox", vec![("prod", vec!["web1:9100"])]),
+        ] {
+            let mut file = TargetFile::new(job, &output, &OutputFormat::Json).unwrap();
+            for (env, targets) in groups {
+                let labels = BTreeMap::from([("env".to_string(), env.to_string())]);
+                let targets = targets.into_iter().map(String::from).collect();
+                file.add_target(TargetGroup::new(job, labels, targets));
+            }
+            target_files.insert(job.to_string(), file);
+        }
+        target_files
+    }
+
+    #[test]
+    fn test_stats() {
+        let stats = Stats::new(&target_files(), &[]);
+        assert_eq!(stats.jobs, 2);
+        assert_eq!(stats.groups, 3);
+        assert_eq!(stats.targets, 4);
+        assert_eq!(stats.unique_targets, 3);
+        assert_eq!(
+            stats.per_job["node"],
+            JobStats {
+                groups: 2,
+                targets: 3
+            }
+        );
+        assert_eq!(
+            stats.labels["env"],
+            BTreeMap::from([("dev".to_string(), 1), ("prod".to_string(), 3)])
+        );
+        assert_eq!(
+            stats.table(),
+            "2 jobs, 3 groups, 4 targets, 3 unique\n\n\
+             JOB       GROUPS  TARGETS\n\
+             blackbox  1       1\n\
+             node      2       3\n\n\
+             LABEL  VALUE  TARGETS\n\
+             env    dev    1\n\
+             env    prod   3\n"
+        );
+
+        let stats = Stats::new(&target_files(), &["team".to_string()]);
+        assert!(stats.labels.is_empty());
+
+        let stats = Stats {
+            duplicate_sources: 2,
+            ..Stats::new(&target_files(), &["team".to_string()])
+        };
+        assert!(
+            stats.table().starts_with(
+                "2 jobs, 3 groups, 4 targets, 3 unique\n2 duplicate sources merged\n\n"
+            )
+        );
+    }
+}
@@ -1,8 +1,15 @@
 use crate::core::error::*;
-use crate::core::output::{Output, OutputFormat, OutputKind};
+use crate::core::output::{Output, OutputFormat, OutputKind, Style};
+#[cfg(feature = "fs")]
+use crate::core::{Writer, staging::Staging};
 use log::{debug, info};
-use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, path::PathBuf};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "fs")]
+use std::path::PathBuf;
+use tracing::info_span;
 
 /// A group of targets for Prometheus file-based service discovery.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -12,8 +19,22 @@ pub struct TargetGroup {
     job: String,
     labels: BTreeMap<String, String>,
     targets: Vec<String>,
+    // Set from the source's id. It is written to target files as the `ID_LABEL` label.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
 }
 
+/// Check if a label is a bookkeeping label, such as discovery metadata in `__meta_*` labels or
+/// pim's own `__pim_*` labels, which only relabeling reads. Other `__` labels, such as
+/// `__metrics_path__`, change how targets are scraped and aren't internal.
+pub fn is_internal_label(name: &str) -> bool {
+    name.starts_with("__meta_") || name.starts_with("__pim_")
+}
+
+/// The label a target group's id is written as. Prometheus drops labels starting with `__` after
+/// relabeling, so it doesn't reach the scraped series.
+pub const ID_LABEL: &str = "__pim_id";
+
 /// Helper struct for deserializing TargetGroup.
 #[derive(Debug, Deserialize)]
 struct TargetHelper {
@@ -24,7 +45,11 @@ struct TargetHelper {
 impl From<TargetHelper> for TargetGroup {
     fn from(helper: TargetHelper) -> Self {
         debug!("Converting TargetHelper into TargetGroup");
-        let TargetHelper { labels, targets } = helper;
+        let TargetHelper {
+            mut labels,
+            targets,
+        } = helper;
+        let id = labels.remove(ID_LABEL);
         let job = match labels.get("job") {
             Some(job) => job.clone(),
             None => "unknown".to_string(),
@@ -34,6 +59,7 @@ impl From<TargetHelper> for TargetGroup {
             job,
             labels,
             targets,
+            id,
         }
     }
 }
@@ -41,7 +67,7 @@ impl From<TargetHelper> for TargetGroup {
 impl TargetGroup {
     pub fn new(job: &str, mut labels: BTreeMap<String, String>, targets: Vec<String>) -> Self {
         debug!("Creating new TargetGroup for job '{}'", job);
-        if !labels.contains_key(job) {
+        if !labels.contains_key("job") {
             labels.insert("job".to_string(), job.to_string());
         }
 
@@ -49,9 +75,21 @@ impl TargetGroup {
             job: job.to_string(),
             labels,
             targets,
+            id: None,
         }
     }
 
+    /// Identify the group across runs, and keep it apart from groups with the same job and labels
+    /// but another id.
+    pub fn with_id(mut self, id: Option<String>) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
     pub fn jobs(&self) -> &String {
         &self.job
     }
@@ -76,7 +114,7 @@ impl TargetGroup {
         &mut self.targets
     }
 
-    /// Hash for quick comparison of TargetGroup based on job and labels.
+    /// Hash for quick comparison of TargetGroup based on job, labels, and id.
     pub fn hash(&self) -> u64 {
         use std::hash::{Hash, Hasher};
         debug!("Hashing TargetGroup for job '{}'", self.job);
@@ -87,6 +125,7 @@ impl TargetGroup {
             k.hash(&mut hasher);
             v.hash(&mut hasher);
         }
+        self.id.hash(&mut hasher);
 
         hasher.finish()
     }
@@ -103,27 +142,62 @@ pub struct TargetFile {
 impl TargetFile {
     pub fn new(job: &str, output: &Output, format: &OutputFormat) -> Result<Self> {
         debug!("Creating new TargetFile for job '{}'", job);
-        let output_path = match output.kind() {
-            OutputKind::Stdout => PathBuf::from("<stdout>"),
-            OutputKind::File(path) => path.to_path_buf(),
-            OutputKind::Directory(path) => construct_filebuf(&mut path.to_path_buf(), job, format),
-        };
-        let output = match Output::new(&output_path, format.clone()) {
-            Ok(output) => output,
-            Err(e) => {
-                return Err(e.set_context(&format!(
-                    "Failed to create target output file for job '{}'",
-                    job
-                )));
-            }
-        };
+        if matches!(output.kind(), OutputKind::Writer) {
+            // Writers can't be reopened by path, so all jobs share the same writer.
+            return Ok(TargetFile {
+                job: job.to_string(),
+                output: output.try_clone()?,
+                targets: Vec::new(),
+            });
+        }
 
-        debug!("Created new TargetFile for job '{}'", job);
-        Ok(TargetFile {
-            job: job.to_string(),
-            output,
-            targets: Vec::new(),
-        })
+        #[cfg(feature = "fs")]
+        {
+            let output_path = match output.kind() {
+                OutputKind::Stdout | OutputKind::Writer => PathBuf::from("<stdout>"),
+                OutputKind::File(path) => path.to_path_buf(),
+                OutputKind::Directory(path) => {
+                    construct_filebuf(&mut path.to_path_buf(), job, format)
+                }
+            };
+            let output = match Output::new(&output_path, format.clone()) {
+                Ok(mut file_output) => {
+                    file_output.set_mode(output.mode());
+                    file_output.set_style(output.style().clone());
+                    if !matches!(output.kind(), OutputKind::File(_)) {
+                        // Every job is kept, so define each host once across them.
+                        file_output.share_nagios_hosts(output);
+                    }
+                    file_output
+                }
+                Err(e) => {
+                    return Err(e.set_context(&format!(
+                        "Failed to create target output file for job '{}'",
+                        job
+                    )));
+                }
+            };
+
+            debug!("Created new TargetFile for job '{}'", job);
+            Ok(TargetFile {
+                job: job.to_string(),
+                output,
+                targets: Vec::new(),
+            })
+        }
+
+        #[cfg(not(feature = "fs"))]
+        {
+            let _ = format;
+            Err(Error::new(SourceError::Msg(
+                "Filesystem support is disabled, only writer outputs are supported".to_string(),
+            ))
+            .set_context(&format!(
+                "Failed to create target output file for job '{}'",
+                job
+            ))
+            .set_kind(ErrorKind::Config))
+        }
     }
 
     pub fn job(&self) -> &String {
@@ -152,7 +226,8 @@ impl TargetFile {
 
     pub fn add_target(&mut self, target: TargetGroup) {
         debug!("Adding TargetGroup to TargetFile for job '{}'", self.job);
-        // Check for TargetGroups with the same job and labels. If we find one, merge the targets.
+        // Check for TargetGroups with the same job, labels, and id. If we find one, merge
+        // the targets.
         for tg in &mut self.targets {
             if tg.hash() == target.hash() {
                 for t in &target.targets {
@@ -167,23 +242,194 @@ impl TargetFile {
     }
 
     pub fn write(&mut self) -> Result<()> {
+        let path = self.output.path().display();
+        let _span = info_span!("write target file", job = %self.job, path = %path).entered();
         debug!("Writing TargetFile for job '{}'", self.job);
-        self.output.write(&self.job, &self.targets)
+        let groups = OrderedGroup::list(&self.targets, self.output.style());
+        self.output.write(&self.job, &groups)
+    }
+
+    /// Write the target groups to another output, ordered by that output's style.
+    pub fn write_to(&self, output: &mut Output) -> Result<()> {
+        let groups = OrderedGroup::list(&self.targets, output.style());
+        output.write(&self.job, &groups)
+    }
+
+    // Write a file output to a staged path. The output points back at its own path afterwards,
+    // which also closes the staged file before it is moved into place. Other outputs are written
+    // directly.
+    #[cfg(feature = "fs")]
+    fn write_staged(&mut self, staging: &mut Staging) -> Result<()> {
+        let OutputKind::File(path) = self.output.kind() else {
+            return self.write();
+        };
+        let path = path.clone();
+        let mode = self.output.mode();
+        *self.output.writer() = Writer::Pending(staging.stage(&path)?, mode);
+        let written = self.write();
+        *self.output.writer() = Writer::Pending(path, mode);
+        written
     }
 }
 
+#[cfg(feature = "fs")]
 fn construct_filebuf(path: &mut PathBuf, job: &str, format: &OutputFormat) -> PathBuf {
     debug!(
         "Constructing output file path for job '{}' with format '{:?}'",
         job, format
     );
-    path.push(job.to_owned() + "_targets." + format.extension());
+    path.push(job_file_name(job, format));
     path.to_path_buf()
 }
 
+/// The name of the target file for a job in an output directory, `{job}_targets.{ext}`.
+pub fn job_file_name(job: &str, format: &OutputFormat) -> String {
+    format!("{}_targets.{}", safe_file_name(job), format.extension())
+}
+
+/// A job name with characters that aren't allowed in file names on Linux, macOS, or Windows
+/// replaced with `_`, so the same name is used whichever platform generated the files.
+pub fn safe_file_name(job: &str) -> String {
+    job.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Check that no two jobs have the same file name, as `a/b` and `a:b` both become `a_b`, so the
+/// file of one job would replace the file of the other.
+pub fn check_file_names<'a>(jobs: impl IntoIterator<Item = &'a String>) -> Result<()> {
+    let mut names: HashMap<String, &String> = HashMap::new();
+    for job in jobs {
+        let name = safe_file_name(job);
+        if let Some(other) = names.get(&name) {
+            return Err(Error::new(SourceError::Msg(format!(
+                "Jobs '{}' and '{}' are both written to files named '{}'. Rename one of them",
+                other, job, name
+            )))
+            .set_kind(ErrorKind::Validation));
+        }
+        names.insert(name, job);
+    }
+    Ok(())
+}
+
+// A target group with its labels, and its id as the `ID_LABEL` label, in the order of an output
+// style. Internal labels are left out when the style strips them.
+struct OrderedGroup<'a> {
+    labels: Vec<(&'a str, &'a str)>,
+    targets: &'a Vec<String>,
+}
+
+impl<'a> OrderedGroup<'a> {
+    fn list(groups: &'a [TargetGroup], style: &Style) -> Vec<Self> {
+        groups.iter().map(|g| OrderedGroup::new(g, style)).collect()
+    }
+
+    fn new(group: &'a TargetGroup, style: &Style) -> Self {
+        // Labels are already sorted by name, and the stable sort keeps that after the listed
+        // labels, with the id last.
+        let mut labels: Vec<(&str, &str)> = group
+            .labels
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        if let Some(id) = &group.id {
+            labels.push((ID_LABEL, id));
+        }
+        if style.strip_internal_labels {
+            labels.retain(|(name, _)| !is_internal_label(name));
+        }
+        labels.sort_by_key(|(name, _)| {
+            let position = style.label_order.iter().position(|l| l == *name);
+            position.unwrap_or(usize::MAX)
+        });
+        OrderedGroup {
+            labels,
+            targets: &group.targets,
+        }
+    }
+}
+
+impl Serialize for OrderedGroup<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        struct Labels<'a>(&'a [(&'a str, &'a str)]);
+        impl Serialize for Labels<'_> {
+            fn serialize<S: Serializer>(
+                &self,
+                serializer: S,
+            ) -> std::result::Result<S::Ok, S::Error> {
+                serializer.collect_map(self.0.iter().copied())
+            }
+        }
+
+        let mut group = serializer.serialize_struct("TargetGroup", 2)?;
+        group.serialize_field("labels", &Labels(&self.labels))?;
+        group.serialize_field("targets", self.targets)?;
+        group.end()
+    }
+}
+
+/// How the targets of each group are sorted before they are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum TargetSort {
+    /// Keep the order of the sources.
+    #[default]
+    None,
+    /// Sort with numbers compared by value, so `web2` comes before `web10`.
+    Natural,
+    /// Sort by bytes, so `web10` comes before `web2`.
+    Lexical,
+}
+
+/// What makes target groups of the same job the same group, so their targets are merged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum MergeKey {
+    /// Labels and id, so sources with different ids keep separate groups.
+    #[default]
+    #[cfg_attr(feature = "application", value(alias = "group-id"))]
+    Id,
+    /// Labels only, ignoring ids.
+    Labels,
+}
+
+/// Compare strings with runs of digits compared by value, so `web2` comes before `web10` and
+/// `10.0.0.2` before `10.0.0.10`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let (da, ra) = a.split_at(a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len()));
+            let (db, rb) = b.split_at(b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len()));
+            let (na, nb) = (da.trim_start_matches('0'), db.trim_start_matches('0'));
+            // Fewer leading zeros first when the values are equal, so the order is total.
+            let order = na
+                .len()
+                .cmp(&nb.len())
+                .then_with(|| na.cmp(nb))
+                .then_with(|| da.len().cmp(&db.len()));
+            if order != Ordering::Equal {
+                return order;
+            }
+            (a, b) = (ra, rb);
+        } else if x != y {
+            return x.cmp(&y);
+        } else {
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+        }
+    }
+}
+
 /// A mapping of job names to their corresponding TargetFile.
 #[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
-/// A mapping of job names to their corresponding TargetFile.
 pub struct TargetFiles {
     files: BTreeMap<String, TargetFile>,
 }
@@ -201,16 +447,433 @@ impl TargetFiles {
         self.files.get_mut(job)
     }
 
+    pub fn get(&self, job: &str) -> Option<&TargetFile> {
+        self.files.get(job)
+    }
+
+    pub fn remove(&mut self, job: &str) -> Option<TargetFile> {
+        self.files.remove(job)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Iterate over the job names in sorted order.
+    pub fn jobs(&self) -> impl Iterator<Item = &String> {
+        self.files.keys()
+    }
+
+    /// Iterate over the job names and their TargetFile in sorted job order.
+    pub fn iter(&self) -> std::collections::btree_map::Iter<'_, String, TargetFile> {
+        self.files.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::collections::btree_map::IterMut<'_, String, TargetFile> {
+        self.files.iter_mut()
+    }
+
+    /// Sort the targets of every group.
+    pub fn sort_targets(&mut self, sort: TargetSort) {
+        debug!("Sorting targets: {:?}", sort);
+        for group in self.files.values_mut().flat_map(|f| f.targets.iter_mut()) {
+            match sort {
+                TargetSort::None => {}
+                TargetSort::Natural => group.targets.sort_by(|a, b| natural_cmp(a, b)),
+                TargetSort::Lexical => group.targets.sort(),
+            }
+        }
+    }
+
+    /// Write every target file. File outputs are staged and only moved into place once every
+    /// file is written, so a failure leaves the existing files as they were.
     pub fn write_all(&mut self) -> Result<()> {
         debug!("Writing all TargetFiles");
-        for (_job, target_file) in self.files.iter_mut() {
+        #[cfg(feature = "fs")]
+        {
+            let mut staging = Staging::new();
+            self.write_staged(&mut staging)?;
+            staging.publish()?;
+            staging.commit();
+            Ok(())
+        }
+
+        #[cfg(not(feature = "fs"))]
+        {
+            if self.combines_jobs() {
+                return self.write_combined();
+            }
+            for target_file in self.files.values_mut() {
+                target_file.write()?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Write every target file through the staging, leaving it to the caller to publish them
+    /// and commit or roll back the run.
+    #[cfg(feature = "fs")]
+    pub fn write_staged(&mut self, staging: &mut Staging) -> Result<()> {
+        if self.combines_jobs() {
+            return self.write_combined();
+        }
+
+        for target_file in self.files.values_mut() {
             info!(
                 "Writing TargetFile for job '{}' to path '{:?}'",
                 target_file.job,
                 target_file.output.path()
             );
-            target_file.write()?;
+            target_file.write_staged(staging)?;
         }
         Ok(())
     }
+
+    /// Write every target file through the staging like `write_staged`, but keep going when one
+    /// fails. The files that failed are discarded from the staging, and their jobs are returned
+    /// with the errors.
+    #[cfg(feature = "fs")]
+    pub fn write_staged_each(&mut self, staging: &mut Staging) -> Result<Vec<(String, Error)>> {
+        if self.combines_jobs() {
+            self.write_combined()?;
+            return Ok(Vec::new());
+        }
+
+        let mut failed = Vec::new();
+        for (job, target_file) in self.files.iter_mut() {
+            info!(
+                "Writing TargetFile for job '{}' to path '{:?}'",
+                job,
+                target_file.output.path()
+            );
+            if let Err(e) = target_file.write_staged(staging) {
+                staging.discard(target_file.output.path());
+                failed.push((job.clone(), e));
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Write the groups of every job into one file together with the groups already in it.
+    /// Groups with the same job and labels are merged, so runs appending to a shared target
+    /// file keep each other's groups. The file is written through the staging.
+    #[cfg(feature = "fs")]
+    pub fn write_appended(
+        &mut self,
+        existing: Vec<TargetGroup>,
+        staging: &mut Staging,
+    ) -> Result<()> {
+        let Some(first) = self.files.values().next() else {
+            return Ok(());
+        };
+        let path = first.output.path().display().to_string();
+        let _span = info_span!("append target files", path = %path).entered();
+        info!(
+            "Appending {} jobs to {} existing groups in '{}'",
+            self.files.len(),
+            existing.len(),
+            path
+        );
+        let mut merged = TargetFile {
+            job: first.job.clone(),
+            output: first.output.try_clone()?,
+            targets: Vec::new(),
+        };
+        let groups = self.files.values().flat_map(|f| f.targets.iter().cloned());
+        for group in existing.into_iter().chain(groups) {
+            merged.add_target(group);
+        }
+        merged.write_staged(staging)
+    }
+
+    // Check if the jobs are written as one document. Several jobs written to stdout are, and a
+    // single job is when the style asks for the same shape regardless of the number of jobs.
+    fn combines_jobs(&self) -> bool {
+        let mut outputs = self.files.values().map(|f| &f.output);
+        let force = outputs.clone().any(|o| o.style().combine_jobs);
+        (self.files.len() > 1 || force) && outputs.all(|o| o.combines_jobs())
+    }
+
+    // Write every job as one document mapping each job to its target groups, so several jobs
+    // written to stdout can be parsed as a whole.
+    fn write_combined(&mut self) -> Result<()> {
+        let _span = info_span!("write combined target files").entered();
+        info!("Writing {} jobs as one document", self.files.len());
+        let combined: BTreeMap<&String, Vec<OrderedGroup>> = self
+            .files
+            .iter()
+            .map(|(job, target_file)| {
+                let style = target_file.output.style();
+                (job, OrderedGroup::list(&target_file.targets, style))
+            })
+            .collect();
+        let mut output = match self.files.values().next() {
+            Some(target_file) => target_file.output.try_clone()?,
+            None => return Ok(()),
+        };
+        output.write("", &combined)
+    }
+}
+
+impl IntoIterator for TargetFiles {
+    type Item = (String, TargetFile);
+    type IntoIter = std::collections::btree_map::IntoIter<String, TargetFile>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a TargetFiles {
+    type Item = (&'a String, &'a TargetFile);
+    type IntoIter = std::collections::btree_map::Iter<'a, String, TargetFile>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut TargetFiles {
+    type Item = (&'a String, &'a mut TargetFile);
+    type IntoIter = std::collections::btree_map::IterMut<'a, String, TargetFile>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn buffer_target_file(job: &str) -> TargetFile {
+        let output = Output::from_writer(crate::core::io::SharedBuffer::new(), OutputFormat::Json);
+        TargetFile::new(job, &output, &OutputFormat::Json).unwrap()
+    }
+
+    #[test]
+    fn test_job_file_name() {
+        assert_eq!(
+            job_file_name("node", &OutputFormat::Json),
+            "node_targets.json"
+        );
+        assert_eq!(
+            job_file_name("team/blackbox:icmp", &OutputFormat::Yaml),
+            "team_blackbox_icmp_targets.yml"
+        );
+    }
+
+    #[test]
+    fn test_check_file_names() {
+        let jobs = |jobs: &[&str]| jobs.iter().map(|j| j.to_string()).collect::<Vec<_>>();
+        assert!(check_file_names(&jobs(&["node", "team/node", "team_node2"])).is_ok());
+        let err = check_file_names(&jobs(&["node", "team/node", "team:node"])).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Validation);
+        assert!(err.to_string().contains("'team/node' and 'team:node'"));
+    }
+
+    #[test]
+    fn test_target_files_accessors() {
+        let mut files = TargetFiles::default();
+        assert!(files.is_empty());
+        files.insert("ssh".to_string(), buffer_target_file("ssh"));
+        files.insert("node".to_string(), buffer_target_file("node"));
+        assert_eq!(files.len(), 2);
+        assert!(files.get("node").is_some());
+        let jobs: Vec<&String> = files.jobs().collect();
+        assert_eq!(jobs, vec!["node", "ssh"]);
+
+        let removed = files.remove("ssh").unwrap();
+        assert_eq!(removed.job(), "ssh");
+        assert_eq!(files.len(), 1);
+    }
+
+    #[test]
+    fn test_target_files_into_iter() {
+        let mut files = TargetFiles::default();
+        files.insert("node".to_string(), buffer_target_file("node"));
+        for (job, file) in &files {
+            assert_eq!(job, file.job());
+        }
+
+        let owned: Vec<(String, TargetFile)> = files.into_iter().collect();
+        assert_eq!(owned.len(), 1);
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut targets = vec![
+            "web10",
+            "web2",
+            "10.0.0.10",
+            "10.0.0.2",
+            "web02",
+            "web1",
+            "web",
+        ];
+        targets.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            targets,
+            vec![
+                "10.0.0.2",
+                "10.0.0.10",
+                "web",
+                "web1",
+                "web2",
+                "web02",
+                "web10"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_new_job_label() {
+        let labels = BTreeMap::from([("node".to_string(), "true".to_string())]);
+        let group = TargetGroup::new("node", labels, vec!["web1".to_string()]);
+        assert_eq!(group.labels()["job"], "node");
+        assert_eq!(group.labels()["node"], "true");
+
+        let labels = BTreeMap::from([("job".to_string(), "node-exporter".to_string())]);
+        let group = TargetGroup::new("node", labels, vec!["web1".to_string()]);
+        assert_eq!(group.labels()["job"], "node-exporter");
+    }
+
+    #[test]
+    fn test_ordered_group() {
+        let labels = BTreeMap::from([
+            ("env".to_string(), "prod".to_string()),
+            ("zone".to_string(), "a".to_string()),
+        ]);
+        let group = TargetGroup::new("node", labels, vec!["web1".to_string()]);
+
+        let json = |style: &Style| serde_json::to_string(&OrderedGroup::new(&group, style));
+        assert_eq!(
+            json(&Style::default()).unwrap(),
+            r#"{"labels":{"job":"node","env":"prod","zone":"a"},"targets":["web1"]}"#
+        );
+        let style = Style {
+            label_order: vec!["zone".to_string(), "job".to_string()],
+            ..Style::default()
+        };
+        assert_eq!(
+            json(&style).unwrap(),
+            r#"{"labels":{"zone":"a","job":"node","env":"prod"},"targets":["web1"]}"#
+        );
+
+        let group = group.clone().with_id(Some("web".to_string()));
+        let written = serde_json::to_string(&OrderedGroup::new(&group, &Style::default())).unwrap();
+        assert_eq!(
+            written,
+            r#"{"labels":{"job":"node","env":"prod","zone":"a","__pim_id":"web"},"targets":["web1"]}"#
+        );
+        let read: TargetGroup = serde_json::from_str(&written).unwrap();
+        assert_eq!(read, group);
+
+        let mut group = group;
+        let meta = "__meta_consul_node".to_string();
+        group.mut_labels().insert(meta, "a".to_string());
+        group
+            .mut_labels()
+            .insert("__scheme__".to_string(), "https".to_string());
+        let style = Style {
+            strip_internal_labels: true,
+            ..Style::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&OrderedGroup::new(&group, &style)).unwrap(),
+            r#"{"labels":{"job":"node","__scheme__":"https","env":"prod","zone":"a"},"targets":["web1"]}"#
+        );
+    }
+
+    #[test]
+    fn test_add_target_id() {
+        let mut file = buffer_target_file("node");
+        let group = |target: &str, id: Option<&str>| {
+            TargetGroup::new("node", BTreeMap::new(), vec![target.to_string()])
+                .with_id(id.map(String::from))
+        };
+        file.add_target(group("web1", None));
+        file.add_target(group("web2", Some("canary")));
+        file.add_target(group("web3", Some("canary")));
+        file.add_target(group("web4", None));
+        assert_eq!(file.targets().len(), 2);
+        assert_eq!(file.targets()[0].targets(), &vec!["web1", "web4"]);
+        assert_eq!(file.targets()[1].id(), Some("canary"));
+        assert_eq!(file.targets()[1].targets(), &vec!["web2", "web3"]);
+    }
+
+    #[test]
+    fn test_sort_targets() {
+        let mut file = buffer_target_file("node");
+        let targets = ["web10", "web2", "web1"].map(String::from).to_vec();
+        file.add_target(TargetGroup::new("node", BTreeMap::new(), targets));
+        let mut files = TargetFiles::default();
+        files.insert("node".to_string(), file);
+
+        let sorted = |files: &TargetFiles| files.get("node").unwrap().targets()[0].targets.clone();
+        files.sort_targets(TargetSort::None);
+        assert_eq!(sorted(&files), vec!["web10", "web2", "web1"]);
+        files.sort_targets(TargetSort::Lexical);
+        assert_eq!(sorted(&files), vec!["web1", "web10", "web2"]);
+        files.sort_targets(TargetSort::Natural);
+        assert_eq!(sorted(&files), vec!["web1", "web2", "web10"]);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_write_appended() {
+        let buffer = crate::core::io::SharedBuffer::new();
+        let output = Output::from_writer(buffer.clone(), OutputFormat::Json);
+        let group = |job: &str, env: &str, target: &str| {
+            let labels = BTreeMap::from([("env".to_string(), env.to_string())]);
+            TargetGroup::new(job, labels, vec![target.to_string()])
+        };
+        let mut files = TargetFiles::default();
+        for (job, target) in [("node", "web2"), ("ssh", "web1")] {
+            let mut file = TargetFile::new(job, &output, &OutputFormat::Json).unwrap();
+            file.add_target(group(job, "prod", target));
+            files.insert(job.to_string(), file);
+        }
+
+        let existing = vec![group("node", "prod", "web1"), group("node", "dev", "dev1")];
+        let mut staging = Staging::new();
+        files.write_appended(existing, &mut staging).unwrap();
+        staging.commit();
+        assert_eq!(
+            buffer.to_string_lossy(),
+            r#"[{"labels":{"job":"node","env":"prod"},"targets":["web1","web2"]},"#.to_string()
+                + r#"{"labels":{"job":"node","env":"dev"},"targets":["dev1"]},"#
+                + r#"{"labels":{"job":"ssh","env":"prod"},"targets":["web1"]}]"#
+        );
+    }
+
+    #[test]
+    fn test_write_combined_one_job() {
+        let write = |combine_jobs: bool| {
+            let buffer = crate::core::io::SharedBuffer::new();
+            let mut output = Output::from_writer(buffer.clone(), OutputFormat::Json);
+            output.set_style(Style {
+                combine_jobs,
+                ..Style::default()
+            });
+            let mut file = TargetFile::new("node", &output, &OutputFormat::Json).unwrap();
+            file.add_target(TargetGroup::new(
+                "node",
+                BTreeMap::new(),
+                vec!["web1".to_string()],
+            ));
+            let mut files = TargetFiles::default();
+            files.insert("node".to_string(), file);
+            files.write_all().unwrap();
+            buffer.to_string_lossy()
+        };
+        let group = r#"[{"labels":{"job":"node"},"targets":["web1"]}]"#;
+        assert_eq!(write(false), group);
+        assert_eq!(write(true), format!(r#"{{"node":{}}}"#, group));
+    }
 }
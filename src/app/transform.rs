@@ -0,0 +1,75 @@
+use crate::core::error::*;
+use log::debug;
+use serde_json::Value;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Expressions are run with the jq CLI, so every jq filter works as documented by jq instead of a
+// subset reimplemented here.
+
+/// Apply a jq expression to a parsed source document. Every value the expression outputs is a
+/// source, or a list of sources, so `.hosts[] | {...}` and `map(...)` both give a list.
+pub fn apply(expr: &str, document: &Value) -> Result<Value> {
+    debug!("Applying transform '{}'", expr);
+    let mut child = Command::new("jq")
+        .arg("--compact-output")
+        .arg(expr)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            Error::new(SourceError::Io(e)).set_context("running jq to transform sources")
+        })?;
+    // jq reads the whole document before it writes anything, so stdin can't block on stdout.
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(document.to_string().as_bytes());
+    }
+    let output = child.wait_with_output().map_err(|e| {
+        Error::new(SourceError::Io(e)).set_context("running jq to transform sources")
+    })?;
+    if !output.status.success() {
+        return Err(Error::new(SourceError::Msg(format!(
+            "jq exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+        .set_context(&format!("Failed to transform sources with '{}'", expr))
+        .set_kind(ErrorKind::Io));
+    }
+
+    collect(&String::from_utf8_lossy(&output.stdout))
+}
+
+// Collect the values jq wrote, one per line, into one list of sources.
+fn collect(output: &str) -> Result<Value> {
+    let mut sources = Vec::new();
+    for line in output.lines() {
+        let value: Value = serde_json::from_str(line).map_err(|e| {
+            Error::new(SourceError::SerdeJson(e)).set_context("Failed to read transformed sources")
+        })?;
+        match value {
+            Value::Array(values) => sources.extend(values),
+            value => sources.push(value),
+        }
+    }
+    Ok(Value::Array(sources))
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_collect() {
+        let output = "{\"jobs\":[\"node\"],\"targets\":[\"web1\"]}\n[{\"jobs\":[\"ssh\"]},{}]\n";
+        assert_eq!(
+            collect(output).unwrap(),
+            json!([{"jobs": ["node"], "targets": ["web1"]}, {"jobs": ["ssh"]}, {}])
+        );
+        assert_eq!(collect("").unwrap(), json!([]));
+        assert!(collect("{").is_err());
+    }
+}
@@ -0,0 +1,353 @@
+use crate::core::error::*;
+use crate::core::time::{format_compact, format_rfc3339, unix_now};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Versioned outputs write each run into a new timestamped directory under the output directory
+// and then point the `latest` symlink at it. Prometheus reads target files through `latest`, so it
+// only ever sees complete runs, and older runs stay around to roll back to. Each version has a
+// manifest with the checksums of its files, which are checked before rolling back to it.
+
+/// The name of the symlink to the newest version in a versioned output directory.
+pub const LATEST: &str = "latest";
+
+/// The name of the manifest file in each version directory.
+pub const MANIFEST: &str = "manifest.json";
+
+/// The files written to a version and their SHA-256 checksums, by file name.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub created: String,
+    pub files: BTreeMap<String, String>,
+}
+
+/// The path of the `latest` symlink in a versioned output directory.
+pub fn latest(dir: &Path) -> PathBuf {
+    dir.join(LATEST)
+}
+
+/// Create a new version directory named for the current time, such as `20240131T120000Z`. Runs in
+/// the same second get a `.1`, `.2`, ... suffix.
+pub fn create(dir: &Path) -> Result<PathBuf> {
+    if !dir.is_dir() {
+        return Err(Error::new(SourceError::Msg(format!(
+            "Versioned output requires an existing target directory, {} is not a directory",
+            dir.display()
+        )))
+        .set_kind(ErrorKind::Config)
+        .print_help());
+    }
+
+    let name = format_compact(unix_now());
+    let mut version = dir.join(&name);
+    let mut n = 0;
+    loop {
+        match std::fs::create_dir(&version) {
+            Ok(()) => break,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                n += 1;
+                version = dir.join(format!("{}.{}", name, n));
+            }
+            Err(e) => {
+                return Err(Error::new(SourceError::Io(e))
+                    .set_context(&format!(
+                        "creating version directory: {}",
+                        version.display()
+                    ))
+                    .set_output());
+            }
+        }
+    }
+
+    debug!("Created version directory {}", version.display());
+    Ok(version)
+}
+
+/// Point the `latest` symlink in the output directory at the version. The symlink is relative, so
+/// the output directory can be moved or mounted elsewhere. A new symlink is renamed over the old
+/// one so readers always find a complete version.
+pub fn point_latest(dir: &Path, version: &Path) -> Result<()> {
+    let name = version.file_name().ok_or_else(|| {
+        Error::new(SourceError::Msg(format!(
+            "Invalid version directory: {}",
+            version.display()
+        )))
+        .set_kind(ErrorKind::Io)
+    })?;
+    let link = latest(dir);
+    let tmp = dir.join(format!(".{}.tmp", LATEST));
+    debug!("Pointing {} at {}", link.display(), name.to_string_lossy());
+
+    let _ = std::fs::remove_file(&tmp);
+    symlink(Path::new(name), &tmp)
+        .and_then(|_| replace(&tmp, &link))
+        .map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(&format!("updating symlink: {}", link.display()))
+                .set_output()
+        })
+}
+
+/// The versions in the output directory, oldest first.
+pub fn list(dir: &Path) -> Result<Vec<String>> {
+    let entries = std::fs::read_dir(dir).map_err(|e| {
+        Error::new(SourceError::Io(e)).set_context(&format!("reading directory: {}", dir.display()))
+    })?;
+
+    let mut versions: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name != LATEST && !name.starts_with('.'))
+        .collect();
+    versions.sort_by(|a, b| version_key(a).cmp(&version_key(b)));
+    Ok(versions)
+}
+
+/// The version the `latest` symlink points at, if any.
+pub fn current(dir: &Path) -> Option<String> {
+    std::fs::read_link(latest(dir))
+        .ok()
+        .and_then(|target| target.file_name().map(|n| n.to_string_lossy().to_string()))
+}
+
+/// Write the manifest of the files in a version directory, created at `created` seconds since
+/// the Unix epoch, and return its path.
+pub fn write_manifest(version: &Path, files: &[PathBuf], created: u64) -> Result<PathBuf> {
+    let mut manifest = Manifest {
+        created: format_rfc3339(created),
+        files: BTreeMap::new(),
+    };
+    for file in files {
+        let Some(name) = file.file_name() else {
+            continue;
+        };
+        manifest
+            .files
+            .insert(name.to_string_lossy().to_string(), checksum(file)?);
+    }
+
+    let path = version.join(MANIFEST);
+    debug!("Writing manifest {}", path.display());
+    let json = serde_json::to_string_pretty(&manifest).map_err(|e| {
+        Error::new(SourceError::SerdeJson(e))
+            .set_context("Failed to serialize manifest")
+            .set_kind(ErrorKind::Internal)
+    })?;
+    std::fs::write(&path, json + "\n").map_err(|e| {
+        Error::new(SourceError::Io(e))
+            .set_context(&format!("writing manifest: {}", path.display()))
+            .set_output()
+    })?;
+    Ok(path)
+}
+
+/// Check the files in a version directory against the checksums in its manifest.
+pub fn verify_manifest(version: &Path) -> Result<()> {
+    let path = version.join(MANIFEST);
+    let content = std::fs::read_to_string(&path).map_err(|e| {
+        Error::new(SourceError::Io(e)).set_context(&format!("reading manifest: {}", path.display()))
+    })?;
+    let manifest: Manifest = serde_json::from_str(&content).map_err(|e| {
+        Error::new(SourceError::SerdeJson(e))
+            .set_context(&format!("parsing manifest: {}", path.display()))
+    })?;
+
+    for (name, expected) in &manifest.files {
+        let file = version.join(name);
+        if &checksum(&file)? != expected {
+            return Err(Error::new(SourceError::Msg(format!(
+                "Checksum of {} does not match the manifest",
+                file.display()
+            )))
+            .set_kind(ErrorKind::Validation));
+        }
+    }
+    debug!(
+        "Verified {} files in {}",
+        manifest.files.len(),
+        version.display()
+    );
+    Ok(())
+}
+
+/// Point `latest` back at an earlier version, the one before the current version or the version
+/// named by `id`, and return its name. The version's files are checked against its manifest first.
+pub fn rollback(dir: &Path, id: Option<&str>) -> Result<String> {
+    let versions = list(dir)?;
+    let version = match id {
+        Some(id) => versions.iter().find(|v| v.as_str() == id).ok_or_else(|| {
+            Error::new(SourceError::Msg(format!(
+                "No version {} in {}",
+                id,
+                dir.display()
+            )))
+            .set_kind(ErrorKind::Config)
+        })?,
+        None => {
+            let current = current(dir).ok_or_else(|| {
+                Error::new(SourceError::Msg(format!(
+                    "{} has no latest version to roll back from",
+                    dir.display()
+                )))
+                .set_kind(ErrorKind::Validation)
+            })?;
+            versions
+                .iter()
+                .rfind(|v| version_key(v) < version_key(&current))
+                .ok_or_else(|| {
+                    Error::new(SourceError::Msg(format!(
+                        "No version before {} in {}",
+                        current,
+                        dir.display()
+                    )))
+                    .set_kind(ErrorKind::Validation)
+                })?
+        }
+    };
+
+    let path = dir.join(version);
+    verify_manifest(&path)?;
+    point_latest(dir, &path)?;
+    Ok(version.clone())
+}
+
+// Version names sort by time, then by the `.1`, `.2`, ... suffix of runs in the same second.
+fn version_key(name: &str) -> (&str, u64) {
+    match name.rsplit_once('.') {
+        Some((time, n)) if n.parse::<u64>().is_ok() => (time, n.parse().unwrap_or_default()),
+        _ => (name, 0),
+    }
+}
+
+fn checksum(path: &Path) -> Result<String> {
+    let content = std::fs::read(path).map_err(|e| {
+        Error::new(SourceError::Io(e)).set_context(&format!("reading {}", path.display()))
+    })?;
+    Ok(format!("{:x}", Sha256::digest(&content)))
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(unix)]
+fn replace(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::rename(from, to)
+}
+
+// Windows can't rename over a directory symlink, so the old symlink is removed first and the
+// switch is not atomic.
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_dir(target, link)
+}
+
+#[cfg(windows)]
+fn replace(from: &Path, to: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir(to) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    std::fs::rename(from, to)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "symlinks are not supported on this platform",
+    ))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn replace(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::rename(from, to)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_versions() {
+        let dir = std::env::temp_dir().join(format!("pim-versions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let first = create(&dir).unwrap();
+        let second = create(&dir).unwrap();
+        assert_ne!(first, second);
+        std::fs::write(first.join("node_targets.json"), "[]").unwrap();
+
+        point_latest(&dir, &first).unwrap();
+        assert!(latest(&dir).join("node_targets.json").is_file());
+        point_latest(&dir, &second).unwrap();
+        assert_eq!(
+            std::fs::read_link(latest(&dir)).unwrap(),
+            PathBuf::from(second.file_name().unwrap())
+        );
+        assert!(!latest(&dir).join("node_targets.json").exists());
+
+        assert!(create(&dir.join("missing")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checksum() {
+        let path = std::env::temp_dir().join(format!("pim-checksum-{}", std::process::id()));
+        std::fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            checksum(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(checksum(&path).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rollback() {
+        let dir = std::env::temp_dir().join(format!("pim-rollback-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut created = Vec::new();
+        for content in ["[1]", "[2]", "[3]"] {
+            let version = create(&dir).unwrap();
+            let file = version.join("node_targets.json");
+            std::fs::write(&file, content).unwrap();
+            write_manifest(&version, &[file], 0).unwrap();
+            created.push(version);
+        }
+        let manifest = std::fs::read_to_string(created[0].join(MANIFEST)).unwrap();
+        assert!(manifest.contains(r#""created": "1970-01-01T00:00:00Z""#));
+        point_latest(&dir, &created[2]).unwrap();
+        assert_eq!(list(&dir).unwrap().len(), 3);
+        assert!(version_key("20240131T120000Z.2") < version_key("20240131T120000Z.10"));
+        assert!(version_key("20240131T120000Z") < version_key("20240131T120000Z.1"));
+
+        let second = created[1]
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        assert_eq!(rollback(&dir, None).unwrap(), second);
+        assert_eq!(current(&dir), Some(second));
+
+        std::fs::write(created[0].join("node_targets.json"), "[changed]").unwrap();
+        assert!(rollback(&dir, None).is_err());
+        assert!(rollback(&dir, Some("missing")).is_err());
+
+        let third = created[2]
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        assert_eq!(rollback(&dir, Some(&third)).unwrap(), third);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
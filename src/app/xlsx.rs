@@ -0,0 +1,135 @@
+use crate::app::source::{Source, label_name};
+use crate::core::error::*;
+use calamine::{Data, Range, Reader, Xlsx};
+use log::debug;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+/// Read sources from the rows of an XLSX workbook sheet. The first row is a header naming the
+/// columns.
+///
+/// The `job` or `jobs` column holds the jobs and the `target` or `targets` column holds the
+/// targets, both separated by commas. Other columns become labels, with the header turned into a
+/// valid label name. Blank cells are skipped, as are rows without a job or target. The first sheet
+/// is read when `sheet` is not given.
+pub fn read_sources(bytes: Vec<u8>, sheet: Option<&str>) -> Result<Vec<Source>> {
+    let mut workbook = Xlsx::new(Cursor::new(bytes)).map_err(|e| {
+        Error::new(SourceError::Msg(e.to_string()))
+            .set_context("Failed to open XLSX workbook")
+            .set_kind(ErrorKind::Parse)
+    })?;
+
+    let range = match sheet {
+        Some(name) => workbook.worksheet_range(name).map_err(|e| {
+            Error::new(SourceError::Msg(e.to_string()))
+                .set_context(&format!("Failed to read sheet '{}'", name))
+                .set_kind(ErrorKind::Parse)
+        })?,
+        None => match workbook.worksheet_range_at(0) {
+            Some(range) => range.map_err(|e| {
+                Error::new(SourceError::Msg(e.to_string()))
+                    .set_context("Failed to read the first sheet")
+                    .set_kind(ErrorKind::Parse)
+            })?,
+            None => Range::default(),
+        },
+    };
+
+    let rows: Vec<Vec<String>> = range
+        .rows()
+        .map(|row| row.iter().map(Data::to_string).collect())
+        .collect();
+    rows_to_sources(rows)
+}
+
+fn rows_to_sources(rows: Vec<Vec<String>>) -> Result<Vec<Source>> {
+    let mut rows = rows.into_iter();
+    let Some(header) = rows.next() else {
+        return Ok(Vec::new());
+    };
+    let columns: Vec<String> = header
+        .iter()
+        .map(|name| match name.trim().to_lowercase().as_str() {
+            "" => String::new(),
+            "jobs" => "job".to_string(),
+            "targets" => "target".to_string(),
+            name => label_name(name),
+        })
+        .collect();
+
+    let mut sources = Vec::new();
+    for (number, row) in rows.enumerate() {
+        let mut jobs = Vec::new();
+        let mut targets = Vec::new();
+        let mut labels = BTreeMap::new();
+        for (column, value) in columns.iter().zip(&row) {
+            let value = value.trim();
+            match column.as_str() {
+                _ if value.is_empty() || column.is_empty() => {}
+                "job" => jobs.extend(value.split(',').map(str::to_string)),
+                "target" => targets.extend(value.split(',').map(str::to_string)),
+                label => {
+                    labels.insert(label.to_string(), value.to_string());
+                }
+            }
+        }
+        if jobs.is_empty() || targets.is_empty() {
+            // Rows are numbered as shown in the spreadsheet, after the header row.
+            debug!("Skipping row {} without a job or target", number + 2);
+            continue;
+        }
+
+        sources.push(
+            Source::builder()
+                .jobs(jobs)
+                .labels(labels)
+                .targets(targets)
+                .build()?,
+        );
+    }
+
+    Ok(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.iter().map(|c| c.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_rows_to_sources() {
+        let sources = rows_to_sources(rows(&[
+            &["Jobs", "Target", "Site Code", "", "Rack"],
+            &["node, blackbox", "web1:9100,web2:9100", "NYC1", "note", ""],
+            &["", "web3:9100", "NYC1", "", ""],
+            &["node", "db1:9100", "", "", "R4"],
+        ]))
+        .unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(
+            sources[0].jobs(),
+            &vec!["node".to_string(), "blackbox".to_string()]
+        );
+        assert_eq!(
+            sources[0].targets(),
+            &vec!["web1:9100".to_string(), "web2:9100".to_string()]
+        );
+        assert_eq!(
+            sources[0].labels(),
+            &BTreeMap::from([("site_code".to_string(), "NYC1".to_string())])
+        );
+        assert_eq!(sources[1].labels()["rack"], "R4");
+        assert!(rows_to_sources(Vec::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_sources_invalid_workbook() {
+        assert!(read_sources(b"not a workbook".to_vec(), None).is_err());
+    }
+}
@@ -1,132 +1,1265 @@
-use clap::{CommandFactory, Parser};
-use log::debug;
+use crate::providers::ProviderArgs;
+use clap::builder::StyledStr;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use log::{LevelFilter, debug, warn};
+use pim::app::alloy;
+use pim::app::cardinality::{LabelLimit, parse_label_limit};
+use pim::app::compliance::Compliance;
+use pim::app::count::{LabelSelector, parse_label_selector};
+use pim::app::flavor::Flavor;
+use pim::app::otel;
+use pim::app::params::{UrlParams, parse_url_params};
+use pim::app::probe::{self, Probe};
+use pim::app::target::{MergeKey, TargetSort};
+use pim::app::versions;
+use pim::core::color::Color;
 use pim::core::error::*;
-use pim::core::{Input, InputKind, Output};
-use std::{fs::read_dir, path::PathBuf};
+use pim::core::ignore::IgnoreRules;
+use pim::core::io::{
+    check_output_dir, check_output_writable, parse_file_mode, parse_path_list, parse_size,
+    path_is_stdout, wait_for_stdin,
+};
+use pim::core::time::parse_duration;
+use pim::core::yaml;
+use pim::core::{
+    Input, InputFormat, InputKind, JobHeader, Output, OutputFormat, OutputKind, Style, YamlQuote,
+    YamlStyle,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs::read_dir,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 /// Command line arguments for PIM
 /// Handles arguments parsing and terminal I/O.
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 #[command(
     name = "pim",
     version,
     about = "Convert source format to Prometheus file_sd target data"
 )]
 pub struct Args {
-    /// Input source file path. Can be a file or directory.
-    //source: PathBuf,
-    #[arg(short, long)]
-    source: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Commands>,
+    /// Input source file path. Can be a file or directory. Repeat to read several sources. Use `-`
+    /// to read stdin alongside the other sources.
+    #[arg(short, long, global = true)]
+    source: Vec<PathBuf>,
+    /// Input source files or directories.
+    #[arg(value_name = "PATH")]
+    paths: Vec<PathBuf>,
+    /// Read source paths from this file, one per line. Blank lines and lines starting with `#`
+    /// are skipped.
+    #[arg(long, global = true, value_name = "FILE")]
+    input_list: Option<PathBuf>,
+    /// Order in which sources are read and merged. `path` sorts sources by path with stdin last,
+    /// `given` keeps the command line order with directory contents sorted by path.
+    #[arg(long, value_enum, global = true, default_value_t = InputOrder::Path)]
+    input_order: InputOrder,
+    /// Follow symlinks found while scanning source directories. This is the default. Directories
+    /// reached more than once, such as through a symlink loop, are only scanned once.
+    #[arg(long, global = true, overrides_with = "no_follow_symlinks")]
+    follow_symlinks: bool,
+    /// Skip symlinks found while scanning source directories.
+    #[arg(long, global = true, overrides_with = "follow_symlinks")]
+    no_follow_symlinks: bool,
+    /// Fail if a source directory has subdirectories nested deeper than this.
+    #[arg(long, global = true, value_name = "N")]
+    max_depth: Option<usize>,
+    /// Fail if scanning source directories finds more than this many files.
+    #[arg(long, global = true, value_name = "N")]
+    max_files: Option<usize>,
+    /// Fail if a source is larger than this, such as 64M. Unlimited by default.
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_size)]
+    max_source_size: Option<u64>,
+    /// Fail if a YAML source has more nodes than this with its aliases expanded, so aliases of
+    /// aliases can't expand a small file to more than fits in memory.
+    #[arg(long, global = true, value_name = "N", default_value_t = yaml::DEFAULT_MAX_NODES)]
+    max_yaml_nodes: usize,
     // TODO: Change to output target file argument
     /// Output target file path. Can be a file or directory.
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     target: Option<PathBuf>,
+    /// Input format. Overrides detection by file extension.
+    #[arg(long, value_enum, global = true)]
+    input_format: Option<InputFormat>,
+    /// Apply this jq expression to each parsed source document before it is read as sources,
+    /// such as `.hosts[] | {jobs: [.role], labels: {}, targets: [.name]}`. Requires jq.
+    #[arg(long, global = true, value_name = "EXPR")]
+    transform: Option<String>,
+    /// Read sources of another schema with this mapping file of jq expressions for the `job`,
+    /// `targets`, and `labels.<name>` of each item. Requires jq.
+    #[arg(long, global = true, value_name = "FILE", conflicts_with = "transform")]
+    mapping: Option<PathBuf>,
+    /// Read sources as text even if they appear to be binary.
+    #[arg(long, global = true)]
+    force_text: bool,
+    /// Age identity file to decrypt SOPS and age encrypted sources with. Repeat for several
+    /// identities. Defaults to SOPS_AGE_KEY_FILE.
+    #[arg(long, global = true, value_name = "FILE")]
+    age_identity: Vec<PathBuf>,
+    /// Sheet to read from XLSX sources. Defaults to the first sheet.
+    #[cfg(feature = "xlsx")]
+    #[arg(long, global = true, value_name = "NAME")]
+    sheet: Option<String>,
+    /// Output format for target files.
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::default())]
+    output_format: OutputFormat,
+    /// Scraper the target files are written for. Decides which special labels are kept.
+    #[arg(long, value_enum, global = true, default_value_t = Flavor::default())]
+    flavor: Flavor,
+    /// Check the target files against a format contract before writing them, and fail if they
+    /// don't comply.
+    #[arg(long, value_enum, global = true)]
+    compliance: Option<Compliance>,
+    /// Fail if a label has more distinct values across all target files than this, such as
+    /// `instance_id=100`. Repeat for several labels.
+    #[arg(long, global = true, value_name = "LABEL=N", value_parser = parse_label_limit)]
+    max_label_values: Vec<LabelLimit>,
+    /// Send labels of a job's targets as URL parameters by copying them to `__param_<label>`
+    /// labels, such as `snmp=module,auth`. Repeat for several jobs.
+    #[arg(long, global = true, value_name = "JOB=LABELS", value_parser = parse_url_params)]
+    url_params: Vec<UrlParams>,
+    /// Remove the labels `--url-params` sends as URL parameters, keeping only their
+    /// `__param_<label>` copies.
+    #[arg(long, global = true, requires = "url_params")]
+    drop_param_labels: bool,
+    /// Pretty print target files, including to stdout when it isn't a terminal.
+    #[arg(long, global = true, conflicts_with = "compact")]
+    pretty: bool,
+    /// Write compact target files, including to files and terminals.
+    #[arg(long, global = true)]
+    compact: bool,
+    /// Spaces per indentation level of pretty printed JSON and YAML.
+    #[arg(long, global = true, value_name = "N", default_value_t = Style::default().indent)]
+    indent: usize,
+    /// Layout of YAML target files. Flow style is JSON-like and a single line when compact.
+    #[arg(long, value_enum, global = true, default_value_t = YamlStyle::default())]
+    yaml_style: YamlStyle,
+    /// Quoting of string values in block style YAML target files.
+    #[arg(long, value_enum, global = true, default_value_t = YamlQuote::default())]
+    yaml_quote: YamlQuote,
+    /// Start YAML target files with an explicit `---` document start.
+    #[arg(long, global = true)]
+    yaml_document_start: bool,
+    /// Leave bookkeeping labels, such as `__meta_*` and `__pim_*`, out of the target files. They
+    /// are still seen by `--plugin` and other stages before the files are written.
+    #[arg(long, global = true)]
+    strip_internal_labels: bool,
+    /// Labels written first in each target group, in this order, separated by commas. Other
+    /// labels follow sorted by name.
+    #[arg(
+        long,
+        global = true,
+        value_name = "LABELS",
+        value_delimiter = ',',
+        default_value = "job"
+    )]
+    label_order: Vec<String>,
+    /// Sort the targets of each target group. `natural` compares numbers by value, so `web2`
+    /// comes before `web10`. Targets keep the order of the sources by default.
+    #[arg(long, value_enum, global = true, default_value_t = TargetSort::default())]
+    sort_targets: TargetSort,
+    /// What makes sources of the same job add to the same target group. `id` keeps the groups
+    /// of sources with different `id`s apart even when their labels match, and `labels` merges
+    /// them anyway.
+    #[arg(long, value_enum, global = true, default_value_t = MergeKey::default())]
+    merge_key: MergeKey,
+    /// Pass every target group through the `transform` function of this Lua script before the
+    /// target files are written. It returns the group to keep, changed or not, or nil to drop it.
+    #[cfg(feature = "plugin")]
+    #[arg(long, global = true, value_name = "FILE")]
+    plugin: Option<PathBuf>,
+    /// Mark each job in output to stdout. `text` writes a `<job>:` line before each job, and
+    /// `wrap` wraps each job in a `{"job": ..., "targets": [...]}` object.
+    #[arg(long, value_enum, global = true, value_name = "STYLE", num_args = 0..=1, require_equals = true, default_missing_value = "text")]
+    headers: Option<JobHeader>,
+    /// Write output to stdout as one document mapping each job to its target groups even when
+    /// there is only one job, so it has the same shape for any number of jobs.
+    #[arg(long, global = true, conflicts_with = "headers")]
+    combine_jobs: bool,
+    /// Permission mode for created target files, in octal.
+    #[arg(long, global = true, value_name = "MODE", default_value = "0640", value_parser = parse_file_mode)]
+    output_mode: u32,
+    /// Write each run into a new timestamped directory under the target directory and point the
+    /// `latest` symlink in the target directory at it.
+    #[arg(long, global = true)]
+    versioned_output: bool,
+    /// Record `SOURCE_DATE_EPOCH`, or the Unix epoch, instead of the current time in generated
+    /// metadata, such as the manifest of `--versioned-output`, so the same sources always
+    /// produce the same files.
+    #[arg(long, global = true)]
+    reproducible: bool,
+    /// Merge the groups into the existing target file instead of replacing it. Requires
+    /// --target to be a JSON or YAML file.
+    #[arg(long, global = true, conflicts_with = "versioned_output")]
+    append: bool,
+    /// Fail before writing if the filesystem of the target doesn't have room for the target
+    /// files.
+    #[arg(long, global = true)]
+    check_disk_space: bool,
+    /// Show the changes to existing target files and ask before overwriting them, when stdout
+    /// is a terminal.
+    #[arg(short, long, global = true, conflicts_with = "append")]
+    interactive: bool,
+    /// Write target files into a directory that is world writable or owned by another user.
+    #[arg(long, global = true)]
+    unsafe_output_dir: bool,
+    /// Fail if stdin produces no data within this many seconds.
+    #[arg(long, global = true, value_name = "SECS", value_parser = parse_duration)]
+    stdin_timeout: Option<Duration>,
+    /// Never read from stdin. Fails if no source is given.
+    #[arg(long, global = true)]
+    no_input: bool,
+    /// Append a JSON record of the targets and groups added, removed, or changed by each run to
+    /// this file.
+    #[arg(long, global = true, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+    /// Write a Grafana Alloy config snippet to this file with a `discovery.file` and
+    /// `prometheus.scrape` component for each target file.
+    #[arg(long, global = true, value_name = "FILE")]
+    alloy_config: Option<PathBuf>,
+    /// Alloy receiver the scrape components forward samples to.
+    #[arg(
+        long,
+        global = true,
+        value_name = "RECEIVER",
+        default_value = alloy::DEFAULT_FORWARD_TO,
+        requires = "alloy_config"
+    )]
+    alloy_forward_to: String,
+    /// Write an OpenTelemetry Collector config snippet to this file with a `prometheus` receiver
+    /// scraping each target file.
+    #[arg(long, global = true, value_name = "FILE")]
+    otel_config: Option<PathBuf>,
+    /// Name of the OpenTelemetry Collector receiver.
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        default_value = otel::DEFAULT_RECEIVER,
+        requires = "otel_config"
+    )]
+    otel_receiver: String,
+    /// Write the hosts of the targets, without ports, to this file for parallel-ssh tools. If it
+    /// is a directory, a `{job}.hosts` file is written per job.
+    #[arg(long, global = true, value_name = "PATH")]
+    host_list: Option<PathBuf>,
+    /// Write a minisign detached signature, `<file>.minisig`, next to each target file, signed
+    /// with this secret key. The key must not be password protected.
+    #[arg(long, global = true, value_name = "FILE")]
+    sign_key: Option<PathBuf>,
+    /// Run this command for each target file before any file is moved into place, with the job
+    /// and the path of the new contents as its last two arguments. A command exiting with an
+    /// error fails the run and leaves the existing files as they were.
+    #[arg(long, global = true, value_name = "COMMAND")]
+    pre_write: Option<String>,
+    /// Run this command for each target file written by a successful run, with the job and the
+    /// path of the file as its last two arguments.
+    #[arg(long, global = true, value_name = "COMMAND")]
+    post_write: Option<String>,
+    /// Keep going when the target file of a job can't be written or is rejected by --pre-write,
+    /// and write the target files of the other jobs. A run where some jobs failed exits with code
+    /// 5 and names them.
+    #[arg(long, global = true, conflicts_with_all = ["append", "versioned_output"])]
+    continue_on_error: bool,
+    /// Commit the changed target files in the git work tree holding the target, with a message
+    /// summarizing the changes.
+    #[arg(long, global = true)]
+    git_commit: bool,
+    /// Push the commit to the upstream branch.
+    #[arg(long, global = true, requires = "git_commit")]
+    git_push: bool,
+    /// Push run metrics to a Prometheus Pushgateway at this http:// or https:// URL after
+    /// exporting.
+    #[arg(long, global = true, value_name = "URL")]
+    pushgateway: Option<String>,
+    /// Read option values from a YAML file that maps long option names to values, such as
+    /// `output-format: yaml`. Options given on the command line or in `PIM_<OPTION>`
+    /// environment variables take precedence.
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Increase logging verbosity. Repeat for more detail (-v info, -vv debug, -vvv trace).
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+    /// Disable all logging output.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Write log messages to a file instead of stderr. The file is appended to.
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+    /// Format of log messages.
+    #[arg(long, value_enum, global = true, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Color help, errors, and log messages. `auto` colors terminals unless `NO_COLOR` is set,
+    /// and `CLICOLOR_FORCE` colors other outputs too.
+    #[arg(long, value_enum, global = true, default_value_t = Color::Auto)]
+    color: Color,
+    /// Write a machine-readable summary of the run as its last line, such as
+    /// `{"written":3,"unchanged":5,"pruned":0,"errors":0}`. Written to stderr unless --summary-fd
+    /// is given.
+    #[arg(long, value_enum, global = true, value_name = "FORMAT")]
+    summary: Option<SummaryFormat>,
+    /// File descriptor to write the run summary to, such as 3 for a descriptor opened by a
+    /// wrapper script. Implies `--summary json`.
+    #[arg(long, global = true, value_name = "FD", value_parser = clap::value_parser!(i32).range(1..))]
+    summary_fd: Option<i32>,
+    /// Exit codes to exit with on errors. `sysexits` uses the BSD sysexits(3) codes, such as 65
+    /// for invalid sources and 66 for missing ones, for schedulers that act on them.
+    #[arg(long, value_enum, global = true, value_name = "CODES", default_value_t = ExitCodes::Default)]
+    exit_codes: ExitCodes,
+    #[command(flatten)]
+    providers: ProviderArgs,
+}
+
+/// The order in which sources are read. Groups appear in the output in the order their sources
+/// are read, and targets merged into an existing group are appended to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputOrder {
+    Path,
+    Given,
+}
+
+/// The format of log messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// The exit codes to exit with on errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExitCodes {
+    Default,
+    Sysexits,
+}
+
+/// The format of the run summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SummaryFormat {
+    Json,
 }
 
 impl Args {
-    pub fn new() -> Self {
-        Args::parse()
+    pub fn subcommand(&self) -> Option<&Commands> {
+        self.command.as_ref()
+    }
+
+    /// The log level requested by the verbosity flags. None means use the environment or default.
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        if self.quiet {
+            return Some(LevelFilter::Off);
+        }
+
+        match self.verbose {
+            0 => None,
+            1 => Some(LevelFilter::Info),
+            2 => Some(LevelFilter::Debug),
+            _ => Some(LevelFilter::Trace),
+        }
+    }
+
+    pub fn input_format(&self) -> Option<InputFormat> {
+        self.input_format.clone()
+    }
+
+    pub fn transform(&self) -> Option<&String> {
+        self.transform.as_ref()
+    }
+
+    pub fn mapping(&self) -> Option<&PathBuf> {
+        self.mapping.as_ref()
+    }
+
+    pub fn audit_log(&self) -> Option<&PathBuf> {
+        self.audit_log.as_ref()
+    }
+
+    pub fn output_format(&self) -> &OutputFormat {
+        &self.output_format
+    }
+
+    pub fn flavor(&self) -> Flavor {
+        self.flavor
+    }
+
+    #[cfg(feature = "plugin")]
+    pub fn plugin(&self) -> Option<&PathBuf> {
+        self.plugin.as_ref()
+    }
+
+    pub fn compliance(&self) -> Option<Compliance> {
+        self.compliance
+    }
+
+    /// How target files are formatted.
+    pub fn style(&self) -> Style {
+        let pretty = match (self.pretty, self.compact) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        };
+        Style {
+            pretty,
+            indent: self.indent,
+            yaml: self.yaml_style,
+            yaml_quote: self.yaml_quote,
+            yaml_document_start: self.yaml_document_start,
+            header: self.headers.unwrap_or_default(),
+            label_order: self.label_order.clone(),
+            strip_internal_labels: self.strip_internal_labels,
+            combine_jobs: self.combine_jobs,
+        }
+    }
+
+    pub fn sort_targets(&self) -> TargetSort {
+        self.sort_targets
+    }
+
+    pub fn merge_key(&self) -> MergeKey {
+        self.merge_key
+    }
+
+    pub fn max_label_values(&self) -> &[LabelLimit] {
+        &self.max_label_values
+    }
+
+    pub fn url_params(&self) -> &[UrlParams] {
+        &self.url_params
+    }
+
+    pub fn drop_param_labels(&self) -> bool {
+        self.drop_param_labels
+    }
+
+    pub fn reproducible(&self) -> bool {
+        self.reproducible
+    }
+
+    pub fn versioned_output(&self) -> bool {
+        self.versioned_output
+    }
+
+    pub fn append(&self) -> bool {
+        self.append
+    }
+
+    pub fn interactive(&self) -> bool {
+        self.interactive
+    }
+
+    pub fn check_disk_space(&self) -> bool {
+        self.check_disk_space
+    }
+
+    pub fn git_commit(&self) -> bool {
+        self.git_commit
+    }
+
+    pub fn continue_on_error(&self) -> bool {
+        self.continue_on_error
+    }
+
+    pub fn pre_write(&self) -> Option<&String> {
+        self.pre_write.as_ref()
+    }
+
+    pub fn post_write(&self) -> Option<&String> {
+        self.post_write.as_ref()
+    }
+
+    pub fn git_push(&self) -> bool {
+        self.git_push
+    }
+
+    pub fn alloy_config(&self) -> Option<&PathBuf> {
+        self.alloy_config.as_ref()
+    }
+
+    pub fn alloy_forward_to(&self) -> &str {
+        &self.alloy_forward_to
+    }
+
+    pub fn otel_config(&self) -> Option<&PathBuf> {
+        self.otel_config.as_ref()
+    }
+
+    pub fn otel_receiver(&self) -> &str {
+        &self.otel_receiver
+    }
+
+    pub fn host_list(&self) -> Option<&PathBuf> {
+        self.host_list.as_ref()
+    }
+
+    pub fn sign_key(&self) -> Option<&PathBuf> {
+        self.sign_key.as_ref()
+    }
+
+    pub fn pushgateway(&self) -> Option<&String> {
+        self.pushgateway.as_ref()
+    }
+
+    pub fn log_file(&self) -> Option<&PathBuf> {
+        self.log_file.as_ref()
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    /// The code to exit with for an error.
+    pub fn exit_code(&self, error: &Error) -> i32 {
+        match self.exit_codes {
+            ExitCodes::Default => error.code(),
+            ExitCodes::Sysexits => error.sysexit(),
+        }
+    }
+
+    pub fn summary(&self) -> Option<SummaryFormat> {
+        self.summary
+            .or(self.summary_fd.map(|_| SummaryFormat::Json))
+    }
+
+    pub fn summary_fd(&self) -> i32 {
+        self.summary_fd.unwrap_or(2)
+    }
+
+    pub fn providers(&self) -> &ProviderArgs {
+        &self.providers
+    }
+}
+
+/// Subcommands for PIM. When no subcommand is given, pim exports target files.
+#[derive(Debug, Clone, Subcommand)]
+pub enum Commands {
+    /// Print a roff man page for pim to stdout.
+    Man,
+    /// Regenerate target files on an interval and serve metrics over HTTP.
+    Serve(ServeArgs),
+    /// Verify the minisign signatures of target files.
+    Verify(VerifyArgs),
+    /// Point the `latest` symlink of a versioned target directory back at an earlier version.
+    Rollback(RollbackArgs),
+    /// Probe every generated target and report the unreachable ones.
+    Check(CheckArgs),
+    /// Print a report about the sources.
+    Report(ReportArgs),
+    /// Print the number of distinct targets, such as for assertions in CI.
+    Count(CountArgs),
+    /// List the jobs that would be generated with their target files and counts.
+    Jobs(JobsArgs),
+    /// Show the configuration of pim.
+    Config(ConfigArgs),
+    /// Upgrade source files to the current version of the source format.
+    Migrate(MigrateArgs),
+    /// Format source files in place, keeping the comments and anchors of YAML files.
+    Fmt(FmtArgs),
+}
+
+/// Options for serve mode.
+#[derive(Debug, Clone, clap::Args)]
+pub struct ServeArgs {
+    /// Address to listen on.
+    #[arg(long, default_value = "127.0.0.1:9099")]
+    pub listen: String,
+    /// Time between generation runs, such as 30s or 5m.
+    #[arg(long, default_value = "60s", value_parser = parse_duration)]
+    pub interval: Duration,
+    /// Report not ready on /readyz when the last successful run is older than this.
+    /// Defaults to three times the interval.
+    #[arg(long, value_parser = parse_duration)]
+    pub max_age: Option<Duration>,
+    /// Require `Authorization: Bearer <token>` with the token in this file for every HTTP
+    /// endpoint except /healthz and /readyz.
+    #[arg(long, value_name = "FILE")]
+    pub auth_token_file: Option<PathBuf>,
+    /// Accept source documents on this Unix domain socket. Each connection sends one
+    /// document, which is merged into the next generation run.
+    #[arg(long, value_name = "PATH")]
+    pub listen_socket: Option<PathBuf>,
+    /// Enable `PUT /sources/<name>` and store pushed source documents in this directory. The
+    /// directory is read as a source on every run.
+    #[arg(long, value_name = "DIR", requires = "push_token_file")]
+    pub push_dir: Option<PathBuf>,
+    /// File holding the bearer token required to push sources.
+    #[arg(long, value_name = "FILE", requires = "push_dir")]
+    pub push_token_file: Option<PathBuf>,
+    /// Serve HTTPS with the PEM certificate chain in this file, the server's certificate first.
+    #[cfg(feature = "tls")]
+    #[arg(long, value_name = "FILE", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of the TLS certificate.
+    #[cfg(feature = "tls")]
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Require clients to present a certificate signed by a CA in this PEM file.
+    #[cfg(feature = "tls")]
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+    /// Also serve the gRPC API on this address, with the same tokens and TLS options as HTTP.
+    #[cfg(feature = "grpc")]
+    #[arg(long, value_name = "ADDR")]
+    pub grpc_listen: Option<String>,
+}
+
+/// Options for verify mode.
+#[derive(Debug, Clone, clap::Args)]
+pub struct VerifyArgs {
+    /// Minisign public key to verify signatures with.
+    #[arg(long, value_name = "FILE")]
+    pub public_key: PathBuf,
+    /// Target files or directories to verify. Defaults to the target. Every file in a directory,
+    /// other than signatures and hidden files, must have a valid signature.
+    #[arg(value_name = "FILE")]
+    pub files: Vec<PathBuf>,
+}
+
+/// Options for rollback mode.
+#[derive(Debug, Clone, clap::Args)]
+pub struct RollbackArgs {
+    /// Version to roll back to, such as `20240131T120000Z`. Defaults to the version before the
+    /// one `latest` points at.
+    #[arg(long, value_name = "ID")]
+    pub to: Option<String>,
+}
+
+/// Options for check mode.
+#[derive(Debug, Clone, clap::Args)]
+pub struct CheckArgs {
+    /// How targets are probed.
+    #[arg(long, value_enum, default_value_t = Probe::default())]
+    pub probe: Probe,
+    /// Time to wait for each probe, such as 2s or 500ms.
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    pub timeout: Duration,
+    /// Number of probes run at the same time.
+    #[arg(long, default_value_t = 32)]
+    pub concurrency: usize,
+    /// Scheme of HTTP probes. The `__scheme__` label of a target's group overrides it.
+    #[arg(long, default_value = probe::DEFAULT_SCHEME, value_parser = ["http", "https"])]
+    pub scheme: String,
+    /// Path of HTTP probes. The `__metrics_path__` label of a target's group overrides it.
+    #[arg(long, value_name = "PATH", default_value = probe::DEFAULT_METRICS_PATH)]
+    pub metrics_path: String,
+    /// How results are printed to stdout.
+    #[arg(long, value_enum, default_value_t = Report::Unreachable)]
+    pub report: Report,
+    /// Write the target files with the unreachable targets labeled `pim_unreachable="true"`.
+    #[arg(long, conflicts_with = "prune_dead")]
+    pub annotate: bool,
+    /// Write the target files without the unreachable targets.
+    #[arg(long)]
+    pub prune_dead: bool,
+}
+
+/// How probe results are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Report {
+    /// The unreachable targets as `job: target`.
+    Unreachable,
+    /// Every target with its status and details as a table.
+    Table,
+    /// Every target with its status and details as a JSON list.
+    Json,
+}
+
+/// Options for report mode.
+#[derive(Debug, Clone, clap::Args)]
+pub struct ReportArgs {
+    #[command(subcommand)]
+    pub report: Reports,
+}
+
+/// Reports about the sources.
+#[derive(Debug, Clone, Subcommand)]
+pub enum Reports {
+    /// List every target in more than one job or group with the inputs and providers adding it.
+    Duplicates(DuplicatesArgs),
+    /// Count the groups and targets of each job and the targets per label value.
+    Stats(StatsArgs),
+    /// List the targets by the value of an ownership label and flag the groups without it.
+    Owners(OwnersArgs),
+}
+
+/// Options for count mode.
+#[derive(Debug, Clone, clap::Args)]
+pub struct CountArgs {
+    /// Only count the targets of this job. Repeat for several jobs. Defaults to every job.
+    #[arg(long, value_name = "NAME")]
+    pub job: Vec<String>,
+    /// Only count the targets of groups with this label value, such as `env=prod`. Repeat to
+    /// require several labels.
+    #[arg(long, value_name = "LABEL=VALUE", value_parser = parse_label_selector)]
+    pub select: Vec<LabelSelector>,
+}
+
+/// Options for jobs mode.
+#[derive(Debug, Clone, clap::Args)]
+pub struct JobsArgs {
+    /// How the jobs are printed.
+    #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+    pub format: ReportFormat,
+}
+
+/// Options for migrate mode.
+#[derive(Debug, Clone, clap::Args)]
+pub struct MigrateArgs {
+    /// Source files to migrate in place. The format of each file is taken from its extension
+    /// unless `--input-format` is given.
+    #[arg(value_name = "FILE", required = true)]
+    pub files: Vec<PathBuf>,
+    /// Print the files that need a migration without changing them, and fail if there are any.
+    #[arg(long)]
+    pub check: bool,
+}
+
+/// Options for fmt mode.
+#[derive(Debug, Clone, clap::Args)]
+pub struct FmtArgs {
+    /// YAML source files to format in place.
+    #[arg(value_name = "FILE", required = true)]
+    pub files: Vec<PathBuf>,
+    /// Print the files that need formatting without changing them, and fail if there are any.
+    #[arg(long)]
+    pub check: bool,
+}
+
+/// Options for config mode.
+#[derive(Debug, Clone, clap::Args)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub config: Configs,
+}
+
+/// Configuration commands.
+#[derive(Debug, Clone, Subcommand)]
+pub enum Configs {
+    /// Print the effective value of every option and whether it was given or is the default.
+    Show(ConfigShowArgs),
+}
+
+/// Options for showing the configuration.
+#[derive(Debug, Clone, clap::Args)]
+pub struct ConfigShowArgs {
+    /// How the configuration is printed.
+    #[arg(long, value_enum, default_value_t = ConfigFormat::Yaml)]
+    pub format: ConfigFormat,
+}
+
+/// How the configuration is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConfigFormat {
+    Yaml,
+    Json,
+}
+
+/// Options for the duplicates report.
+#[derive(Debug, Clone, clap::Args)]
+pub struct DuplicatesArgs {
+    /// How the report is printed.
+    #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+    pub format: ReportFormat,
+}
+
+/// Options for the stats report.
+#[derive(Debug, Clone, clap::Args)]
+pub struct StatsArgs {
+    /// How the report is printed.
+    #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+    pub format: ReportFormat,
+    /// Only break down this label by value. Repeat for several labels. Defaults to every label.
+    #[arg(long, value_name = "NAME")]
+    pub label: Vec<String>,
+}
+
+/// Options for the owners report.
+#[derive(Debug, Clone, clap::Args)]
+pub struct OwnersArgs {
+    /// Label naming the owner of a group, such as `team`.
+    #[arg(long, value_name = "NAME")]
+    pub label: String,
+    /// How the report is printed.
+    #[arg(long, value_enum, default_value_t = OwnersFormat::Csv)]
+    pub format: OwnersFormat,
+}
+
+/// How the owners report is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OwnersFormat {
+    /// CSV with the owner, job, and target of each target. Unowned targets have an empty owner.
+    Csv,
+    /// A JSON object with the targets and the number of unowned groups.
+    Json,
+}
+
+/// How a report is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// A table.
+    Table,
+    /// A JSON list.
+    Json,
+}
+
+// The `--color` on the command line, for help and errors printed before it is parsed. An
+// invalid value is left for clap to report.
+fn requested_color() -> Color {
+    let args: Vec<String> = std::env::args().collect();
+    let value = args
+        .iter()
+        .enumerate()
+        .find_map(|(i, a)| match a.strip_prefix("--color") {
+            Some(value) if value.starts_with('=') => Some(value[1..].to_string()),
+            Some("") => args.get(i + 1).cloned(),
+            _ => None,
+        });
+    value
+        .and_then(|v| Color::from_str(&v, false).ok())
+        .unwrap_or_default()
+}
+
+// Print clap's help or error text to stdout or stderr, with its styles when that stream is
+// colored.
+fn print_styled(text: &StyledStr, to_stderr: bool) {
+    let color = requested_color();
+    let _ = match to_stderr {
+        true if color.enabled(std::io::stderr().is_terminal()) => {
+            write!(std::io::stderr(), "{}", text.ansi())
+        }
+        true => write!(std::io::stderr(), "{}", text),
+        false if color.enabled(std::io::stdout().is_terminal()) => {
+            write!(std::io::stdout(), "{}", text.ansi())
+        }
+        false => write!(std::io::stdout(), "{}", text),
+    };
+}
+
+// Whether `--exit-codes sysexits` is on the command line, for errors parsing it.
+fn sysexits_requested() -> bool {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter().any(|a| a == "--exit-codes=sysexits")
+        || args
+            .windows(2)
+            .any(|w| w[0] == "--exit-codes" && w[1] == "sysexits")
+}
+
+// Parse the command line, or print help, the version, or the usage error and exit.
+fn parse(command: clap::Command) -> ArgMatches {
+    command.try_get_matches().unwrap_or_else(|e| exit(e))
+}
+
+// Print help, the version, or a usage error and exit. Help and version go to stdout and usage
+// errors to stderr. Usage errors exit with clap's code 2 unless sysexits were asked for.
+fn exit(e: clap::Error) -> ! {
+    print_styled(&e.render(), e.use_stderr());
+    match e.use_stderr() && sysexits_requested() {
+        true => std::process::exit(EX_USAGE),
+        false => std::process::exit(e.exit_code()),
+    }
+}
+
+/// The prefix of the environment variables that set options, such as `PIM_OUTPUT_FORMAT` for
+/// `--output-format`.
+pub const ENV_PREFIX: &str = "PIM_";
+
+// The command with every option of the top level command also read from its environment
+// variable. The variables are left out of the help, which would otherwise list one per option.
+fn command() -> clap::Command {
+    Args::command().mut_args(|arg| {
+        let name = match arg.get_long() {
+            Some("help" | "version") | None => return arg,
+            Some(long) => format!("{}{}", ENV_PREFIX, long.replace('-', "_").to_uppercase()),
+        };
+        arg.env(name).hide_env(true)
+    })
+}
+
+// Make the values of a config file the defaults of the options of the top level command, so
+// environment variables and the command line still take precedence. Returns the command and the
+// ids of the options the file sets.
+fn with_config(
+    mut command: clap::Command,
+    path: &Path,
+) -> std::result::Result<(clap::Command, BTreeSet<String>), clap::Error> {
+    use clap::error::ErrorKind as ClapErrorKind;
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        let msg = format!("can't read config file '{}': {}", path.display(), e);
+        command.error(ClapErrorKind::Io, msg)
+    })?;
+    let values: BTreeMap<String, serde_yaml::Value> = match text.trim().is_empty() {
+        true => BTreeMap::new(),
+        false => serde_yaml::from_str(&text).map_err(|e| {
+            let msg = format!("invalid config file '{}': {}", path.display(), e);
+            command.error(ClapErrorKind::InvalidValue, msg)
+        })?,
+    };
+
+    let mut ids = BTreeSet::new();
+    for (name, value) in values {
+        let id = command
+            .get_arguments()
+            .find(|a| a.get_long() == Some(name.as_str()) && !a.is_positional())
+            .map(|a| a.get_id().to_string())
+            .filter(|id| !matches!(id.as_str(), "config" | "help" | "version"));
+        let Some(id) = id else {
+            let msg = format!(
+                "unknown option '{}' in config file '{}'",
+                name,
+                path.display()
+            );
+            return Err(command.error(ClapErrorKind::UnknownArgument, msg));
+        };
+        let Some(values) = config_values(&value) else {
+            let msg = format!(
+                "option '{}' in config file '{}' must be a value or a list of values",
+                name,
+                path.display()
+            );
+            return Err(command.error(ClapErrorKind::InvalidValue, msg));
+        };
+        command = command.mut_arg(&id, |arg| arg.default_values(values));
+        ids.insert(id);
     }
+    Ok((command, ids))
 }
 
-#[derive(Debug)]
+// The values of an option in a config file: a scalar, or a list of scalars for options that
+// can be repeated.
+fn config_values(value: &serde_yaml::Value) -> Option<Vec<String>> {
+    let scalar = |value: &serde_yaml::Value| match value {
+        serde_yaml::Value::Bool(b) => Some(b.to_string()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        serde_yaml::Value::String(s) => Some(s.clone()),
+        _ => None,
+    };
+    match value {
+        serde_yaml::Value::Sequence(items) => items.iter().map(scalar).collect(),
+        value => Some(vec![scalar(value)?]),
+    }
+}
+
+// The config file given on the command line or in its environment variable. Global options
+// given after a subcommand are only in the matches of the subcommand.
+fn config_path(matches: &ArgMatches) -> Option<PathBuf> {
+    let mut found = None;
+    let mut matches = Some(matches);
+    while let Some(m) = matches {
+        if let Ok(Some(path)) = m.try_get_one::<PathBuf>("config") {
+            found = Some(path.clone());
+        }
+        matches = m.subcommand().map(|(_, m)| m);
+    }
+    found
+}
+
+#[derive(Debug, Clone)]
 pub struct Cli {
     args: Args,
+    // The parsed command line, which also knows where each value came from.
+    matches: ArgMatches,
+    // The ids of the options set by the config file.
+    from_config: BTreeSet<String>,
 }
 
 impl Cli {
     pub fn new() -> Self {
-        debug!("Initializing CLI");
-        Cli { args: Args::new() }
+        let mut matches = parse(command());
+        let mut from_config = BTreeSet::new();
+        if let Some(path) = config_path(&matches) {
+            let (command, ids) = with_config(command(), &path).unwrap_or_else(|e| exit(e));
+            matches = parse(command);
+            from_config = ids;
+        }
+        let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        Cli {
+            args,
+            matches,
+            from_config,
+        }
     }
 
     pub fn args(&self) -> &Args {
         &self.args
     }
 
-    pub fn source(&self) -> PathBuf {
-        match &self.args.source {
-            Some(p) => p.to_path_buf(),
-            None => PathBuf::from("-"),
+    pub fn matches(&self) -> &ArgMatches {
+        &self.matches
+    }
+
+    /// Check if the value of an option, by id, comes from the config file.
+    pub fn set_by_config(&self, id: &str) -> bool {
+        self.from_config.contains(id)
+    }
+
+    /// Check if any of the sources is stdin.
+    pub fn source_is_stdin(&self) -> Result<bool> {
+        Ok(self
+            .sources()?
+            .iter()
+            .any(|p| InputKind::new(p) == InputKind::Stdin))
+    }
+
+    /// The source paths in the order given: `--source` paths, positional paths, and then the
+    /// paths in the `--input-list` file. Defaults to `-` (stdin) when no source or provider is
+    /// given.
+    pub fn sources(&self) -> Result<Vec<PathBuf>> {
+        let mut sources: Vec<PathBuf> = self
+            .args
+            .source
+            .iter()
+            .chain(self.args.paths.iter())
+            .cloned()
+            .collect();
+
+        match &self.args.input_list {
+            Some(list) => sources.append(&mut read_input_list(list)?),
+            None if sources.is_empty() && self.args.providers.is_empty() => {
+                sources.push(PathBuf::from("-"))
+            }
+            None => {}
         }
+
+        Ok(sources)
+    }
+
+    /// Add a source path, read after the sources given on the command line.
+    pub fn with_source(mut self, path: &Path) -> Self {
+        self.args.source.push(path.to_path_buf());
+        self
     }
 
     pub fn print_help() {
-        let _ = Args::command().print_help();
+        print_styled(&Args::command().render_help(), false);
+    }
+
+    /// The clap command definition for pim.
+    pub fn clap_command() -> clap::Command {
+        command()
     }
 
-    pub fn inputs(&mut self) -> Result<Vec<Input>> {
+    pub fn inputs(&self) -> Result<Vec<Input>> {
         debug!("Getting input sources: {:?}", self.args.source);
-        let inputs = get_sources(&self.source())?;
-        if inputs.is_empty() {
+        let sources = self.sources()?;
+        let stdin_count = sources
+            .iter()
+            .filter(|p| InputKind::new(p) == InputKind::Stdin)
+            .count();
+        if stdin_count > 1 {
+            return Err(Error::new(SourceError::InvalidInputSource(
+                "stdin (-) can only be given once as a source".to_string(),
+            ))
+            .set_kind(ErrorKind::Config)
+            .print_help());
+        }
+
+        if stdin_count == 1 {
+            self.check_stdin()?;
+        }
+
+        let mut scan = DirScan::new(&self.args);
+        let mut inputs = Vec::new();
+        for source in &sources {
+            inputs.append(&mut get_sources(source, &mut scan)?);
+        }
+
+        if self.args.input_order == InputOrder::Path {
+            debug!("Sorting input sources by path");
+            // Stdin has no path and sorts last so ad-hoc sources merge on top of files.
+            inputs.sort_by(|a, b| match (a.kind().path(), b.kind().path()) {
+                (Some(a), Some(b)) => a.cmp(b),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            });
+        }
+
+        if inputs.is_empty() && (self.args.providers.is_empty() || !sources.is_empty()) {
             return Err(Error::new(SourceError::InvalidInputSource(
                 "No valid input sources found".to_string(),
             ))
-            .set_code(CODE_OPTIONS_ERROR));
+            .set_kind(ErrorKind::Config));
         }
 
-        // If inputs is stdin then we have some extra work to do.
-        //
-        // Return an error if the input is a terminal. Interactive terminal input isn't realistic
-        // for this tool.
-        //
-        // If it is stdin and not an interactive terminal, update the source arg to reflect that.
         debug!("Validating input sources");
-        if inputs.len() == 1 && matches!(inputs[0].kind(), InputKind::Stdin) {
-            // Bail on terminal input.
-            if inputs[0].is_terminal() {
-                return Err(Error::new(SourceError::Msg(
-                    "Refusing to run with terminal input/output".to_string(),
-                ))
-                .set_code(CODE_OPTIONS_ERROR)
-                .print_help());
+        if let Some(format) = &self.args.input_format {
+            debug!("Overriding input format with: {}", format);
+            for input in &mut inputs {
+                input.set_format(format.clone());
+            }
+        }
+
+        if self.args.force_text {
+            debug!("Forcing inputs to be read as text");
+            for input in &mut inputs {
+                input.set_force_text(true);
+            }
+        }
+
+        for input in &mut inputs {
+            input.set_max_size(self.args.max_source_size);
+            input.set_max_yaml_nodes(self.args.max_yaml_nodes);
+        }
+
+        if !self.args.age_identity.is_empty() {
+            for input in &mut inputs {
+                input.set_age_identities(self.args.age_identity.clone());
             }
+        }
 
-            debug!("Input is stdin, updating source arg");
-            self.args.source = Some(PathBuf::from("<stdin>"));
+        #[cfg(feature = "xlsx")]
+        if let Some(sheet) = &self.args.sheet {
+            debug!("Reading sheet '{}' from XLSX sources", sheet);
+            for input in &mut inputs {
+                input.set_sheet(sheet);
+            }
         }
 
         debug!("Input sources validated, returning Ok");
         Ok(inputs)
     }
 
+    /// The output target path, `<stdout>` when not given.
+    pub fn target(&self) -> PathBuf {
+        match &self.args.target {
+            Some(p) => p.to_path_buf(),
+            None => PathBuf::from("<stdout>"),
+        }
+    }
+
+    // Check that stdin can be read before reading it so we never hang on a terminal or a pipe
+    // that never produces data.
+    //
+    // Return an error if the input is a terminal. Interactive terminal input isn't realistic for
+    // this tool.
+    fn check_stdin(&self) -> Result<()> {
+        if self.args.no_input {
+            return Err(Error::new(SourceError::InvalidInputSource(
+                "Reading from stdin is disabled by --no-input, provide a source".to_string(),
+            ))
+            .set_kind(ErrorKind::Config)
+            .print_help());
+        }
+
+        // Bail on terminal input.
+        if std::io::stdin().is_terminal() {
+            return Err(Error::new(SourceError::Msg(
+                "Refusing to run with terminal input/output".to_string(),
+            ))
+            .set_kind(ErrorKind::Config)
+            .print_help());
+        }
+
+        if let Some(timeout) = self.args.stdin_timeout {
+            wait_for_stdin(timeout)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the output options and that the output can be written, before any sources are read.
+    pub fn check_output(&self) -> Result<()> {
+        let output_file = self.target();
+        let needs_files = [
+            ("--sign-key", self.args.sign_key.is_some()),
+            ("--git-commit", self.args.git_commit),
+            ("--pre-write", self.args.pre_write.is_some()),
+            ("--post-write", self.args.post_write.is_some()),
+            ("--alloy-config", self.args.alloy_config.is_some()),
+            ("--otel-config", self.args.otel_config.is_some()),
+        ];
+        let needed = needs_files.iter().find(|(_, set)| *set);
+        if let Some((option, _)) = needed.filter(|_| path_is_stdout(&output_file)) {
+            return Err(Error::new(SourceError::Msg(format!(
+                "{} requires a target file or directory",
+                option
+            )))
+            .set_kind(ErrorKind::Config)
+            .print_help());
+        }
+        if !self.args.unsafe_output_dir {
+            check_output_dir(&output_file)?;
+        }
+        check_output_writable(&output_file)?;
+        // Appending reads the existing target file first.
+        if self.args.append && output_file.is_file() {
+            if let Err(e) = std::fs::File::open(&output_file) {
+                return Err(Error::new(SourceError::Msg(format!(
+                    "Target file {} is not readable ({}). --append needs read permission on it",
+                    output_file.display(),
+                    e
+                )))
+                .set_code(CODE_PERMISSION_DENIED));
+            }
+        }
+        Ok(())
+    }
+
     pub fn output(&self) -> Result<Output> {
         debug!("Getting output destination");
-        let output_file = match &self.args.target {
-            Some(p) => p,
-            None => &PathBuf::from("<stdout>"),
-        };
-
+        self.check_output()?;
+        let output_file = &self.target();
         debug!("Output destination obtained: {:?}", output_file);
-        Output::new(output_file, Default::default())
+        let output_file = match self.args.versioned_output {
+            true => versions::create(output_file)?,
+            false => output_file.clone(),
+        };
+        let mut output = Output::new(&output_file, self.args.output_format.clone())?;
+        if self.args.append
+            && (!matches!(output.kind(), OutputKind::File(_))
+                || *output.format() == OutputFormat::Nagios)
+        {
+            return Err(Error::new(SourceError::Msg(
+                "--append requires a JSON or YAML target file".to_string(),
+            ))
+            .set_kind(ErrorKind::Config)
+            .print_help());
+        }
+        output.set_mode(self.args.output_mode);
+        output.set_style(self.args.style());
+        Ok(output)
     }
 }
 
-fn get_sources(path: &PathBuf) -> Result<Vec<Input>> {
+// Read source paths from an input list file.
+fn read_input_list(path: &PathBuf) -> Result<Vec<PathBuf>> {
+    debug!("Reading input list: {}", path.display());
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        Error::new(SourceError::Io(e))
+            .set_context(format!("reading input list: {}", path.display()).as_str())
+            .set_kind(ErrorKind::Config)
+    })?;
+
+    let paths = parse_path_list(&content);
+    debug!("Input list paths: {:?}", paths);
+    Ok(paths)
+}
+
+fn get_sources(path: &PathBuf, scan: &mut DirScan) -> Result<Vec<Input>> {
     debug!("Getting sources from path: {:?}", path);
     let mut inputs = Vec::new();
     let input = Input::new(path)?;
     match &input.kind() {
-        InputKind::Stdin => {
+        InputKind::Stdin | InputKind::Reader => {
             debug!("Input is stdin");
             inputs.push(input);
         }
         InputKind::File(path) => {
             if input.is_dir() {
                 debug!("Input is a directory");
-                let mut dir_inputs = from_dir(path)?;
+                let mut dir_inputs = scan.scan(path, &IgnoreRules::new())?;
                 debug!("Directory inputs obtained: {:?}", dir_inputs);
                 inputs.append(&mut dir_inputs);
                 return Ok(inputs);
             }
 
-            if input.is_binary() {
-                return Err(Error::new(SourceError::InvalidInputSource(
-                    path.display().to_string() + ": " + "Binary input is not supported",
-                ))
-                .set_code(CODE_OPTIONS_ERROR));
-            }
-
             debug!("Input is a file");
             inputs.push(input);
         }
@@ -136,35 +1269,126 @@ fn get_sources(path: &PathBuf) -> Result<Vec<Input>> {
     Ok(inputs)
 }
 
-fn from_dir(path: &PathBuf) -> Result<Vec<Input>> {
-    debug!("Getting inputs from directory: {}", path.display());
-    let mut inputs = Vec::new();
-    let entries = read_dir(path).map_err(|e| {
-        Error::new(SourceError::Io(e))
-            .set_context(format!("reading directory: {}", path.display()).as_str())
-            .set_code(CODE_RUNTIME_ERROR)
-    })?;
+// Options and state for scanning source directories. Paths given on the command line are always
+// followed, `follow_symlinks` only applies to entries found while scanning.
+struct DirScan {
+    follow_symlinks: bool,
+    max_depth: Option<usize>,
+    max_files: Option<usize>,
+    files: usize,
+    // Canonical paths of the directories already scanned, used to detect symlink loops.
+    visited: HashSet<PathBuf>,
+}
+
+impl DirScan {
+    fn new(args: &Args) -> Self {
+        DirScan {
+            follow_symlinks: !args.no_follow_symlinks,
+            max_depth: args.max_depth,
+            max_files: args.max_files,
+            files: 0,
+            visited: HashSet::new(),
+        }
+    }
+
+    // Recursively get inputs from a directory, skipping paths matched by `.pimignore` files in it
+    // or any of its parents up to the scanned directory.
+    fn scan(&mut self, path: &PathBuf, ignore: &IgnoreRules) -> Result<Vec<Input>> {
+        self.scan_depth(path, ignore, 0)
+    }
 
-    for entry in entries {
-        let entry = entry.map_err(|e| {
+    fn scan_depth(
+        &mut self,
+        path: &PathBuf,
+        ignore: &IgnoreRules,
+        depth: usize,
+    ) -> Result<Vec<Input>> {
+        debug!("Getting inputs from directory: {}", path.display());
+        if let Some(max) = self.max_depth.filter(|max| depth > *max) {
+            return Err(Error::new(SourceError::InvalidInputSource(format!(
+                "{}: directory is nested deeper than --max-depth {}",
+                path.display(),
+                max
+            )))
+            .set_kind(ErrorKind::Config));
+        }
+
+        let canonical = path.canonicalize().map_err(|e| {
             Error::new(SourceError::Io(e))
-                .set_context(format!("reading directory entry in: {}", path.display()).as_str())
-                .set_code(CODE_RUNTIME_ERROR)
+                .set_context(format!("resolving directory: {}", path.display()).as_str())
         })?;
-        let file_path = entry.path();
-        debug!("Processing directory entry: {}", file_path.display());
-        if file_path.is_dir() {
-            debug!("Entry is a directory, recursing into it");
-            let mut dir_inputs = from_dir(&file_path)?;
-            inputs.append(&mut dir_inputs);
-            continue;
+        if !self.visited.insert(canonical.clone()) {
+            warn!(
+                "Skipping directory already scanned, possible symlink loop: {} -> {}",
+                path.display(),
+                canonical.display()
+            );
+            return Ok(Vec::new());
         }
 
-        debug!("Entry is a file, creating Input");
-        let input = Input::new(&file_path)?;
-        inputs.push(input);
-    }
+        let mut ignore = ignore.clone();
+        ignore.load(path)?;
+        let mut inputs = Vec::new();
+        let entries = read_dir(path).map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(format!("reading directory: {}", path.display()).as_str())
+        })?;
 
-    debug!("Directory inputs obtained: {:?}", inputs);
-    Ok(inputs)
+        // Directory order is filesystem dependent, so sort entries to read sources in the same
+        // order on every run.
+        let mut paths = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| {
+                Error::new(SourceError::Io(e))
+                    .set_context(format!("reading directory entry in: {}", path.display()).as_str())
+            })?;
+            paths.push(entry.path());
+        }
+        paths.sort();
+
+        for file_path in paths {
+            debug!("Processing directory entry: {}", file_path.display());
+            if file_path.is_symlink() && !self.follow_symlinks {
+                debug!("Entry is a symlink, skipping it");
+                continue;
+            }
+
+            let is_dir = file_path.is_dir();
+            if ignore.is_ignored(&file_path, is_dir) {
+                debug!("Entry is ignored, skipping it");
+                continue;
+            }
+
+            // Named pipes, sockets, and devices in a directory could block or never end. They
+            // can still be read by giving their path directly.
+            if !is_dir && !file_path.is_file() {
+                warn!("Skipping {}: not a regular file", file_path.display());
+                continue;
+            }
+
+            if is_dir {
+                debug!("Entry is a directory, recursing into it");
+                let mut dir_inputs = self.scan_depth(&file_path, &ignore, depth + 1)?;
+                inputs.append(&mut dir_inputs);
+                continue;
+            }
+
+            self.files += 1;
+            if let Some(max) = self.max_files.filter(|max| self.files > *max) {
+                return Err(Error::new(SourceError::InvalidInputSource(format!(
+                    "{}: found more than --max-files {} files in source directories",
+                    file_path.display(),
+                    max
+                )))
+                .set_kind(ErrorKind::Config));
+            }
+
+            debug!("Entry is a file, creating Input");
+            let input = Input::new(&file_path)?;
+            inputs.push(input);
+        }
+
+        debug!("Directory inputs obtained: {:?}", inputs);
+        Ok(inputs)
+    }
 }
@@ -0,0 +1,75 @@
+use crate::cli::{CheckArgs, Cli, Report};
+use crate::commands::export;
+use crate::summary::RunSummary;
+use log::{info, warn};
+use pim::app::probe::{self, ProbeOptions};
+use pim::core::Output;
+use pim::core::error::*;
+use pim::core::io::Writer;
+use std::path::Path;
+
+/// Probe every generated target and print the results to stdout. Fails if any target is
+/// unreachable, unless the target files are written with the unreachable targets annotated or
+/// pruned.
+pub fn run(shell: &Cli, args: &CheckArgs, run_summary: &mut RunSummary) -> Result<()> {
+    let writes = args.annotate || args.prune_dead;
+    let source = export::sources(shell, Vec::new())?;
+    // Only touch the target when writing, since creating the output may create directories.
+    let output = match writes {
+        true => shell.output()?,
+        false => Output::from_writer(std::io::sink(), shell.args().output_format().clone()),
+    };
+    let mut target_files = export::prepare(&source, &output)?;
+    export::finalize(shell, &mut target_files)?;
+
+    let options = ProbeOptions {
+        timeout: args.timeout,
+        concurrency: args.concurrency,
+        scheme: args.scheme.clone(),
+        metrics_path: args.metrics_path.clone(),
+    };
+    let results = args.probe.run(&target_files, &options);
+    let unreachable = probe::unreachable(&results);
+
+    let report = match args.report {
+        Report::Unreachable => unreachable
+            .iter()
+            .map(|(job, target)| format!("{}: {}\n", job, target))
+            .collect(),
+        Report::Table => probe::table(&results),
+        Report::Json => {
+            let json = serde_json::to_string_pretty(&results).map_err(|e| {
+                Error::new(SourceError::SerdeJson(e))
+                    .set_context("Failed to serialize probe results")
+                    .set_kind(ErrorKind::Internal)
+            })?;
+            json + "\n"
+        }
+    };
+    Writer::new(Path::new("<stdout>"))?.write_all(report.as_bytes())?;
+
+    let summary = format!(
+        "{} of {} targets unreachable",
+        unreachable.len(),
+        results.len()
+    );
+    if writes {
+        if !unreachable.is_empty() {
+            warn!("{}", summary);
+        }
+        match args.annotate {
+            true => probe::annotate(&mut target_files, &unreachable),
+            false => {
+                probe::prune(&mut target_files, &unreachable);
+                run_summary.pruned = unreachable.len();
+            }
+        }
+        return export::write(shell, &output, &mut target_files, run_summary);
+    }
+
+    if !unreachable.is_empty() {
+        return Err(Error::new(SourceError::Msg(summary)).set_kind(ErrorKind::Network));
+    }
+    info!("{}", summary);
+    Ok(())
+}
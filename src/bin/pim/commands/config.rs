@@ -0,0 +1,113 @@
+use crate::cli::{Cli, ConfigArgs, ConfigFormat, Configs};
+use clap::ArgAction;
+use clap::parser::ValueSource;
+use pim::core::error::*;
+use pim::core::io::Writer;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The effective value of an option and where it came from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Setting {
+    value: Value,
+    origin: &'static str,
+}
+
+/// Run a configuration command.
+pub fn run(shell: &Cli, args: &ConfigArgs) -> Result<()> {
+    match &args.config {
+        Configs::Show(args) => {
+            let settings = settings(shell);
+            let shown = match args.format {
+                ConfigFormat::Yaml => serde_yaml::to_string(&settings).map_err(|e| {
+                    Error::new(SourceError::SerdeYaml(e))
+                        .set_context("Failed to serialize configuration")
+                        .set_kind(ErrorKind::Internal)
+                })?,
+                ConfigFormat::Json => {
+                    let json = serde_json::to_string_pretty(&settings).map_err(|e| {
+                        Error::new(SourceError::SerdeJson(e))
+                            .set_context("Failed to serialize configuration")
+                            .set_kind(ErrorKind::Internal)
+                    })?;
+                    json + "\n"
+                }
+            };
+            Writer::new(Path::new("<stdout>"))?.write_all(shown.as_bytes())
+        }
+    }
+}
+
+// The options of the top level command by long name, with their values as given on the command
+// line, in the environment, or in the config file, or their defaults. Options without either are left out.
+fn settings(shell: &Cli) -> BTreeMap<String, Setting> {
+    let command = Cli::clap_command();
+    let mut settings = BTreeMap::new();
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        let Some((value, source)) = value(shell, arg) else {
+            continue;
+        };
+        let origin = match source {
+            ValueSource::DefaultValue if shell.set_by_config(id) => "config file",
+            ValueSource::DefaultValue => "default",
+            ValueSource::EnvVariable => "environment",
+            _ => "command line",
+        };
+        let name = arg.get_long().unwrap_or(id).to_string();
+        settings.insert(name, Setting { value, origin });
+    }
+    settings
+}
+
+// The value of an argument and where it came from. Global options given after a subcommand are
+// only in the matches of the subcommand, so the deepest matches with a value win.
+fn value(shell: &Cli, arg: &clap::Arg) -> Option<(Value, ValueSource)> {
+    let id = arg.get_id().as_str();
+    let mut found = None;
+    let mut matches = Some(shell.matches());
+    while let Some(m) = matches {
+        // Subcommands only know the global options.
+        let source = m.try_contains_id(id).ok().and_then(|_| m.value_source(id));
+        if let Some(source) = source {
+            let value = match arg.get_action() {
+                ArgAction::Count => Value::from(m.get_count(id)),
+                ArgAction::SetTrue | ArgAction::SetFalse => Value::from(m.get_flag(id)),
+                action => {
+                    let mut raw: Vec<Value> = m
+                        .get_raw(id)
+                        .into_iter()
+                        .flatten()
+                        .map(|v| Value::from(redact(&v.to_string_lossy())))
+                        .collect();
+                    match action {
+                        ArgAction::Append => Value::Array(raw),
+                        _ if raw.len() == 1 => raw.remove(0),
+                        _ => Value::Array(raw),
+                    }
+                }
+            };
+            found = Some((value, source));
+        }
+        matches = m.subcommand().map(|(_, m)| m);
+    }
+    found
+}
+
+// Hide the password of a URL, such as `postgres://pim:secret@db/inventory`.
+fn redact(value: &str) -> String {
+    let Some((scheme, rest)) = value.split_once("://") else {
+        return value.to_string();
+    };
+    let authority = rest.split('/').next().unwrap_or_default();
+    match authority.rsplit_once('@') {
+        Some((userinfo, host)) if userinfo.contains(':') => {
+            let user = userinfo.split(':').next().unwrap_or_default();
+            let path = &rest[authority.len()..];
+            format!("{}://{}:***@{}{}", scheme, user, host, path)
+        }
+        _ => value.to_string(),
+    }
+}
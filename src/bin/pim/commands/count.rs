@@ -0,0 +1,16 @@
+use crate::cli::{Cli, CountArgs};
+use crate::commands::report;
+use log::info;
+use pim::app::count;
+use pim::core::error::*;
+use pim::core::io::Writer;
+use std::path::Path;
+
+/// Print the number of distinct targets of the selected jobs and groups to stdout, and nothing
+/// else, so it can be compared in a shell.
+pub fn run(shell: &Cli, args: &CountArgs) -> Result<()> {
+    let target_files = report::target_files(shell)?;
+    let count = count::count(&target_files, &args.job, &args.select);
+    info!("{} targets selected", count);
+    Writer::new(Path::new("<stdout>"))?.write_all(format!("{}\n", count).as_bytes())
+}
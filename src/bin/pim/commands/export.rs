@@ -0,0 +1,517 @@
+use crate::cli::Cli;
+use crate::summary::RunSummary;
+use log::{debug, error, info, warn};
+use pim::app::alloy;
+use pim::app::audit::AuditRecord;
+use pim::app::cardinality;
+use pim::app::diff::{self, Diff, JobGroups};
+use pim::app::git;
+use pim::app::hooks;
+use pim::app::hostlist;
+use pim::app::mapping::Mapping;
+use pim::app::metrics::Metrics;
+use pim::app::otel;
+use pim::app::params;
+use pim::app::source::{Source, SourceFile};
+use pim::app::space;
+use pim::app::target::TargetFiles;
+use pim::app::versions;
+use pim::core::io::hostname;
+use pim::core::staging::Staging;
+use pim::core::time::metadata_time;
+use pim::core::{Input, Output, OutputKind, error::*, sign};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+/// Exporter function to read inputs and write outputs for prometheus file_sd target files.
+pub fn run(shell: &mut Cli, summary: &mut RunSummary) -> Result<()> {
+    run_recorded(shell, &Metrics::new(), Vec::new(), summary).map(|_| ())
+}
+
+/// Run an export, recording the outcome in the metrics. The extra sources, such as sources pushed
+/// to a running server, are merged after the sources read from the inputs. Returns the written
+/// target files.
+pub fn run_recorded(
+    shell: &mut Cli,
+    metrics: &Metrics,
+    extra: Vec<Source>,
+    summary: &mut RunSummary,
+) -> Result<TargetFiles> {
+    debug!("Starting export process");
+    metrics.record_run();
+    let start = Instant::now();
+
+    let res = (|| -> Result<TargetFiles> {
+        // Fail on an output that can't be written before doing the work of reading the sources.
+        shell
+            .check_output()
+            .inspect_err(|_| metrics.record_write_failure())?;
+        let source = sources(shell, extra).inspect_err(|_| metrics.record_parse_error())?;
+        debug!("Getting outputs");
+        let output = shell
+            .output()
+            .inspect_err(|_| metrics.record_write_failure())?;
+        debug!("Outputs obtained: {:?}", output);
+        let mut target_files =
+            prepare(&source, &output).inspect_err(|_| metrics.record_parse_error())?;
+        finalize(shell, &mut target_files)?;
+        write(shell, &output, &mut target_files, summary)
+            .inspect_err(|_| metrics.record_write_failure())?;
+        metrics.record_success(source.sources.len(), &target_files);
+        Ok(target_files)
+    })();
+
+    metrics.record_duration(start.elapsed());
+    info!("Export finished in {:?}", start.elapsed());
+    res
+}
+
+/// Run an export and push the run metrics to a Pushgateway. A failed push is logged but does not
+/// fail the run.
+pub fn run_pushed(shell: &mut Cli, url: &str, summary: &mut RunSummary) -> Result<()> {
+    let metrics = Metrics::new();
+    let res = run_recorded(shell, &metrics, Vec::new(), summary).map(|_| ());
+
+    let instance = hostname();
+    let output_dir = shell.target().display().to_string();
+    let grouping = [
+        ("instance", instance.as_str()),
+        ("output_dir", output_dir.as_str()),
+    ];
+    if let Err(e) = metrics.push(url, "pim", &grouping) {
+        warn!("{}", e);
+    }
+
+    res
+}
+
+/// Read the sources from the inputs and providers. The extra sources are merged after the sources
+/// read from the inputs.
+pub fn sources(shell: &Cli, extra: Vec<Source>) -> Result<SourceFile> {
+    debug!("Getting inputs");
+    let inputs = shell.inputs()?;
+    debug!("Inputs obtained: {:?}", inputs);
+    let mut source = read(shell, inputs)?;
+    source.sources.extend(extra);
+    for provider in shell.args().providers().providers()? {
+        info!("Querying {} provider", provider.name());
+        source.sources.extend(provider.sources()?);
+    }
+    source.dedupe();
+    Ok(source)
+}
+
+/// Read the source inputs, transformed by the `--transform` expression or `--mapping` file.
+pub fn read(shell: &Cli, inputs: Vec<Input>) -> Result<SourceFile> {
+    info!("Reading source inputs");
+    let mut source = SourceFile::new(inputs);
+    let transform = match shell.args().mapping() {
+        Some(path) => Some(Mapping::read(path)?.to_jq()),
+        None => shell.args().transform().cloned(),
+    };
+    source.set_transform(transform);
+    source.set_merge_key(shell.args().merge_key());
+    debug!("SourceFile initialized: {:?}", source);
+    source.read_sources()?;
+    debug!("Source inputs read: {:?}\n", source);
+    Ok(source)
+}
+
+/// Convert the sources into target files for the output.
+pub fn prepare(source: &SourceFile, output: &Output) -> Result<TargetFiles> {
+    // Target files holds the generated targets split into the individual files to be written to.
+    info!("Preparing target files for output");
+    let mut target_files = TargetFiles::default();
+    source.into_targets(output, output.format(), &mut target_files)?;
+    debug!("Target files prepared: {:?}", target_files);
+    Ok(target_files)
+}
+
+/// Apply the options that change the prepared target files before they are checked or written.
+pub fn finalize(shell: &Cli, target_files: &mut TargetFiles) -> Result<()> {
+    #[cfg(feature = "plugin")]
+    if let Some(plugin) = shell.args().plugin() {
+        info!(
+            "Transforming target groups with plugin '{}'",
+            plugin.display()
+        );
+        pim::app::plugin::transform(plugin, target_files)?;
+    }
+    let args = shell.args();
+    params::apply(target_files, args.url_params(), args.drop_param_labels());
+    args.flavor().apply(target_files);
+    target_files.sort_targets(args.sort_targets());
+    Ok(())
+}
+
+/// Write all target files. When an audit log is configured, the changes relative to the existing
+/// target files are appended to it after a successful write. With `--git-commit`, the written
+/// files are committed with a message summarizing the changes. With `--continue-on-error`, the
+/// jobs whose target files failed are left out of the run, which then fails with
+/// CODE_PARTIAL_SUCCESS naming them.
+pub fn write(
+    shell: &Cli,
+    output: &Output,
+    target_files: &mut TargetFiles,
+    summary: &mut RunSummary,
+) -> Result<()> {
+    let args = shell.args();
+    let previous = match args.audit_log().is_some() || args.git_commit() {
+        true if args.versioned_output() => Some(previous_version(shell, output)?),
+        true => Some(diff::read_previous(output, target_files)?),
+        false => None,
+    };
+
+    if let Some(compliance) = args.compliance() {
+        compliance.check(target_files)?;
+    }
+    cardinality::check(target_files, args.max_label_values())?;
+    if args.check_disk_space() {
+        space::check(target_files)?;
+    }
+
+    if args.interactive()
+        && std::io::stdout().is_terminal()
+        && !confirm_overwrite(output, target_files)?
+    {
+        return Err(Error::new(SourceError::Msg(
+            "Target files were not overwritten".to_string(),
+        ))
+        .set_context("Aborted")
+        .set_kind(ErrorKind::Internal));
+    }
+
+    // Everything written from here on is rolled back if any of it fails, so scrapers never see
+    // a partly updated run.
+    let mut staging = Staging::new();
+    let (written, published, failed) = match publish(shell, output, target_files, &mut staging) {
+        Ok((written, failed)) => {
+            count_files(&staging, target_files, summary);
+            let published = staging.published().to_vec();
+            staging.commit();
+            (written, published, failed)
+        }
+        Err(e) => {
+            let e = rollback(staging, e);
+            // A failed version is left empty, and is never pointed at.
+            if args.versioned_output() {
+                let _ = std::fs::remove_dir(output.path());
+            }
+            return Err(e);
+        }
+    };
+
+    // The target files of failed jobs were left as they were, so they have no changes.
+    let changes = previous.map(|mut p| {
+        p.retain(|job, _| !failed.contains(job));
+        Diff::between(&p, &diff::current_groups(target_files))
+    });
+    if let (Some(path), Some(changes)) = (args.audit_log(), &changes) {
+        info!("Writing audit record to {}", path.display());
+        AuditRecord::new(changes, &shell.target().display().to_string()).append(path)?;
+    }
+
+    if let (true, Some(changes)) = (args.git_commit(), &changes) {
+        commit(shell, &written, changes)?;
+    }
+
+    if let Some(hook) = args.post_write() {
+        post_write(hook, target_files, &published)?;
+    }
+
+    match failed.is_empty() {
+        true => Ok(()),
+        false => Err(Error::new(SourceError::Msg(format!(
+            "The target files of {} of {} jobs were not written: {}",
+            failed.len(),
+            failed.len() + target_files.len(),
+            failed.into_iter().collect::<Vec<_>>().join(", ")
+        )))
+        .set_kind(ErrorKind::Io)
+        .set_code(CODE_PARTIAL_SUCCESS)),
+    }
+}
+
+// Write the target files and the files made from them through the staging, and return the
+// written files. With `--continue-on-error`, the jobs whose target files failed to be written or
+// were rejected by the pre-write hook are removed from the target files and returned.
+fn publish(
+    shell: &Cli,
+    output: &Output,
+    target_files: &mut TargetFiles,
+    staging: &mut Staging,
+) -> Result<(Vec<PathBuf>, BTreeSet<String>)> {
+    let args = shell.args();
+    let mut failed = BTreeSet::new();
+    match args.append() {
+        true => {
+            let existing = diff::try_read_groups(output.path(), output.format())?;
+            target_files.write_appended(existing, staging)?;
+        }
+        false if args.continue_on_error() => {
+            for (job, e) in target_files.write_staged_each(staging)? {
+                error!(
+                    "{}",
+                    e.set_context(&format!("Failed to write the target file of job '{}'", job))
+                );
+                failed.insert(job);
+            }
+        }
+        false => target_files.write_staged(staging)?,
+    }
+    if let Some(hook) = args.pre_write() {
+        for (path, jobs) in hook_files(target_files) {
+            let Some(staged) = staging.staged(&path) else {
+                continue;
+            };
+            match hooks::run(hook, &jobs, staged) {
+                Err(e) if args.continue_on_error() => {
+                    error!("{}", e);
+                    staging.discard(&path);
+                    failed.extend(
+                        target_files
+                            .iter()
+                            .filter(|(_, file)| file.output().path() == &path)
+                            .map(|(job, _)| job.clone()),
+                    );
+                }
+                res => res?,
+            }
+        }
+    }
+    if !failed.is_empty() && failed.len() == target_files.len() {
+        return Err(Error::new(SourceError::Msg(format!(
+            "The target files of all {} jobs failed",
+            failed.len()
+        )))
+        .set_kind(ErrorKind::Io));
+    }
+    for job in &failed {
+        target_files.remove(job);
+    }
+    staging.publish()?;
+
+    // The files written by this run, which are signed and committed.
+    let mut written: Vec<PathBuf> = target_files
+        .iter()
+        .filter_map(|(_, file)| match file.output().kind() {
+            OutputKind::File(path) => Some(path.clone()),
+            _ => None,
+        })
+        .collect();
+
+    if args.versioned_output() {
+        staging.protect(&output.path().join(versions::MANIFEST))?;
+        let created = metadata_time(args.reproducible());
+        written.push(versions::write_manifest(output.path(), &written, created)?);
+    }
+
+    if let Some(path) = args.alloy_config() {
+        info!("Writing Alloy config to {}", path.display());
+        let config = alloy::config(&config_files(shell, target_files), args.alloy_forward_to());
+        staging.write_file(path, output.mode(), config.as_bytes())?;
+        written.push(path.clone());
+    }
+
+    if let Some(path) = args.otel_config() {
+        info!(
+            "Writing OpenTelemetry Collector config to {}",
+            path.display()
+        );
+        let config = otel::config(&config_files(shell, target_files), args.otel_receiver())?;
+        staging.write_file(path, output.mode(), config.as_bytes())?;
+        written.push(path.clone());
+    }
+
+    if let Some(path) = args.host_list() {
+        for (file, hosts) in hostlist::lists(target_files, path) {
+            info!("Writing host list to {}", file.display());
+            staging.write_file(&file, output.mode(), hosts.as_bytes())?;
+            written.push(file);
+        }
+    }
+
+    if let Some(key) = args.sign_key() {
+        for file in &written {
+            staging.protect(&sign::signature_path(file))?;
+        }
+        let signatures = sign(&written, key)?;
+        written.extend(signatures);
+    }
+
+    if args.versioned_output() {
+        info!("Pointing latest at {}", output.path().display());
+        versions::point_latest(&shell.target(), output.path())?;
+    }
+
+    Ok((written, failed))
+}
+
+// The target files of the run with the jobs written to each, joined with commas when several
+// jobs share a file.
+fn hook_files(target_files: &TargetFiles) -> BTreeMap<PathBuf, String> {
+    let mut files: BTreeMap<PathBuf, Vec<&str>> = BTreeMap::new();
+    for (job, file) in target_files {
+        if let OutputKind::File(path) = file.output().kind() {
+            files.entry(path.clone()).or_default().push(job);
+        }
+    }
+    files
+        .into_iter()
+        .map(|(path, jobs)| (path, jobs.join(",")))
+        .collect()
+}
+
+// Run the post-write hook for every target file the run replaced. The files are already in
+// place, so every hook is run and the first failure is returned.
+fn post_write(hook: &str, target_files: &TargetFiles, published: &[PathBuf]) -> Result<()> {
+    let mut result = Ok(());
+    for (path, jobs) in hook_files(target_files) {
+        if !published.contains(&path) {
+            continue;
+        }
+        match (hooks::run(hook, &jobs, &path), &result) {
+            (Err(e), Ok(())) => result = Err(e),
+            (Err(e), Err(_)) => error!("{}", e),
+            _ => {}
+        }
+    }
+    result
+}
+
+// Count the target files the run replaced and the ones it left alone.
+fn count_files(staging: &Staging, target_files: &TargetFiles, summary: &mut RunSummary) {
+    let paths: BTreeSet<&PathBuf> = target_files
+        .iter()
+        .filter_map(|(_, file)| match file.output().kind() {
+            OutputKind::File(path) => Some(path),
+            _ => None,
+        })
+        .collect();
+    let published = |path: &&&PathBuf| staging.published().contains(path);
+    summary.written = paths.iter().filter(published).count();
+    summary.unchanged = paths
+        .iter()
+        .filter(|path| staging.unchanged().contains(path) && !published(path))
+        .count();
+}
+
+// Restore the files of a failed run to their state before it. The error of a run that was
+// rolled back exits with CODE_ROLLED_BACK.
+fn rollback(staging: Staging, e: Error) -> Error {
+    match staging.rollback() {
+        Ok(()) => {
+            warn!("Restored the target files to their state before the run");
+            e.set_code(CODE_ROLLED_BACK)
+        }
+        Err(rollback_error) => {
+            error!("Could not restore the target files: {}", rollback_error);
+            e
+        }
+    }
+}
+
+// Show the changes to each existing target file and ask whether to overwrite them. New files
+// are written without asking, so nothing is asked when no existing file would change.
+fn confirm_overwrite(output: &Output, target_files: &TargetFiles) -> Result<bool> {
+    let previous = diff::read_previous(output, target_files)?;
+    let changes = Diff::between(&previous, &diff::current_groups(target_files));
+    let mut overwritten = Vec::new();
+    for (job, file) in target_files {
+        let path = file.output().path();
+        if let (OutputKind::File(_), true, Some(job_diff)) =
+            (file.output().kind(), path.exists(), changes.jobs.get(job))
+        {
+            overwritten.push(format!(
+                "{}: {}",
+                path.display(),
+                job_diff.summary().describe()
+            ));
+        }
+    }
+    if overwritten.is_empty() {
+        return Ok(true);
+    }
+
+    eprintln!("Target files to overwrite:");
+    for line in &overwritten {
+        eprintln!("  {}", line);
+    }
+    eprint!("Overwrite them? [y/N] ");
+    let mut answer = String::new();
+    std::io::stderr()
+        .flush()
+        .and_then(|_| std::io::stdin().read_line(&mut answer))
+        .map_err(|e| Error::new(SourceError::Io(e)).set_context("reading confirmation"))?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+// Commit the written files and the `latest` symlink of versioned output. Paths are made absolute
+// since git runs in the target directory.
+fn commit(shell: &Cli, written: &[PathBuf], changes: &Diff) -> Result<()> {
+    let target = shell.target();
+    let dir = match target.is_dir() {
+        true => target,
+        false => match target.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+    };
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+
+    let mut files: Vec<PathBuf> = written.iter().map(|path| absolute(path)).collect();
+    if shell.args().versioned_output() {
+        files.push(absolute(&versions::latest(&dir)));
+    }
+
+    debug!("Committing {} files in {}", files.len(), dir.display());
+    if git::commit(&dir, &files, &git::commit_message(changes))? && shell.args().git_push() {
+        git::push(&dir)?;
+    }
+    Ok(())
+}
+
+// The job and target file pairs scraper configs read. Versioned output is read through the
+// `latest` symlink so configs stay valid for later runs. Paths are absolute since scrapers run
+// elsewhere.
+fn config_files(shell: &Cli, target_files: &TargetFiles) -> Vec<(String, PathBuf)> {
+    let latest = versions::latest(&shell.target());
+    let mut files = Vec::new();
+    for (job, target_file) in target_files {
+        let OutputKind::File(file) = target_file.output().kind() else {
+            continue;
+        };
+        let file = match (shell.args().versioned_output(), file.file_name()) {
+            (true, Some(name)) => latest.join(name),
+            _ => file.clone(),
+        };
+        files.push((job.clone(), std::path::absolute(&file).unwrap_or(file)));
+    }
+    files
+}
+
+// Versioned runs write into a new directory, so the previous target files are the ones in the
+// version `latest` points at.
+fn previous_version(shell: &Cli, output: &Output) -> Result<JobGroups> {
+    let latest = versions::latest(&shell.target());
+    if !latest.is_dir() {
+        return Ok(JobGroups::new());
+    }
+    let latest = Output::new(&latest, output.format().clone())?;
+    diff::read_previous(&latest, &TargetFiles::default())
+}
+
+/// Write a detached signature next to each file and return the signature paths.
+pub fn sign(files: &[PathBuf], key: &Path) -> Result<Vec<PathBuf>> {
+    let mut signatures = Vec::new();
+    for file in files {
+        let signature = sign::sign(file, key)?;
+        info!("Wrote signature {}", signature.display());
+        signatures.push(signature);
+    }
+    Ok(signatures)
+}
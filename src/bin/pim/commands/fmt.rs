@@ -0,0 +1,58 @@
+use crate::cli::{Cli, FmtArgs};
+use crate::commands::migrate::file_mode;
+use log::info;
+use pim::app::fmt;
+use pim::core::error::*;
+use pim::core::input::InputFormat;
+use pim::core::io::Writer;
+use pim::core::staging::Staging;
+use std::path::{Path, PathBuf};
+
+/// Format source files in place. Every formatted file is printed to stdout. With `--check`, the
+/// files needing formatting are printed and nothing is written.
+pub fn run(shell: &Cli, args: &FmtArgs) -> Result<()> {
+    let mut formatted: Vec<(PathBuf, String)> = Vec::new();
+    let mut lines = String::new();
+    for path in &args.files {
+        let format = shell
+            .args()
+            .input_format()
+            .unwrap_or_else(|| InputFormat::from_extension(path));
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(&format!("reading source file: {}", path.display()))
+        })?;
+        let text = fmt::format(&text, &format)
+            .map_err(|e| e.set_context(&format!("Failed to format {}", path.display())))?;
+        match text {
+            Some(text) => {
+                lines.push_str(&format!("formatted {}\n", path.display()));
+                formatted.push((path.clone(), text));
+            }
+            None => info!("{} is already formatted", path.display()),
+        }
+    }
+
+    if args.check {
+        let mut writer = Writer::new(Path::new("<stdout>"))?;
+        for (path, _) in &formatted {
+            writer.write_all(format!("{}\n", path.display()).as_bytes())?;
+        }
+        if !formatted.is_empty() {
+            return Err(Error::new(SourceError::Msg(format!(
+                "{} source files need formatting",
+                formatted.len()
+            )))
+            .set_kind(ErrorKind::Validation));
+        }
+        return Ok(());
+    }
+
+    // Every file is written before any is kept, so a failure leaves all of them as they were.
+    let mut staging = Staging::new();
+    for (path, text) in &formatted {
+        staging.write_file(path, file_mode(path), text.as_bytes())?;
+    }
+    staging.commit();
+    Writer::new(Path::new("<stdout>"))?.write_all(lines.as_bytes())
+}
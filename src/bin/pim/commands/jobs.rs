@@ -0,0 +1,34 @@
+use crate::cli::{Cli, JobsArgs, ReportFormat};
+use crate::commands::{export, report};
+use pim::app::{jobs, versions};
+use pim::core::Output;
+use pim::core::error::*;
+use pim::core::io::Writer;
+use std::path::Path;
+
+/// Print the jobs that would be generated with the target file each is written to, without
+/// writing anything.
+pub fn run(shell: &Cli, args: &JobsArgs) -> Result<()> {
+    let source = export::sources(shell, Vec::new())?;
+    let target = shell.target();
+    // The output is never written, so nothing is created for it.
+    let mut output = Output::new(&target, shell.args().output_format().clone())?;
+    output.set_style(shell.args().style());
+    let mut target_files = export::prepare(&source, &output)?;
+    export::finalize(shell, &mut target_files)?;
+
+    let mut jobs = jobs::list(&target_files);
+    if shell.args().versioned_output() {
+        // Each run writes a new version, which Prometheus reads through `latest`.
+        for job in &mut jobs {
+            if let Some(name) = job.path.file_name() {
+                job.path = versions::latest(&target).join(name);
+            }
+        }
+    }
+    let list = match args.format {
+        ReportFormat::Table => jobs::table(&jobs),
+        ReportFormat::Json => report::json(&jobs, "jobs")?,
+    };
+    Writer::new(Path::new("<stdout>"))?.write_all(list.as_bytes())
+}
@@ -0,0 +1,18 @@
+use clap::Command;
+use log::debug;
+use pim::core::error::*;
+use pim::core::io::Writer;
+use std::path::Path;
+
+/// Render a roff man page for the command and write it to stdout.
+pub fn run(cmd: Command) -> Result<()> {
+    debug!("Rendering man page");
+    let mut page = Vec::new();
+    clap_mangen::Man::new(cmd).render(&mut page).map_err(|e| {
+        Error::new(SourceError::Io(e))
+            .set_context("Failed to render man page")
+            .set_kind(ErrorKind::Internal)
+    })?;
+    let mut writer = Writer::new(Path::new("<stdout>"))?;
+    writer.write_all(&page)
+}
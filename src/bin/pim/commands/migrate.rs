@@ -0,0 +1,74 @@
+use crate::cli::{Cli, MigrateArgs};
+use log::info;
+use pim::app::migrate;
+use pim::core::error::*;
+use pim::core::input::InputFormat;
+use pim::core::io::{DEFAULT_FILE_MODE, Writer};
+use pim::core::staging::Staging;
+use std::path::{Path, PathBuf};
+
+/// Upgrade source files to the current version of the source format in place. Every migrated
+/// file is printed to stdout. With `--check`, the files needing a migration are printed and
+/// nothing is written.
+pub fn run(shell: &Cli, args: &MigrateArgs) -> Result<()> {
+    let mut migrated: Vec<(PathBuf, String)> = Vec::new();
+    let mut lines = String::new();
+    for path in &args.files {
+        let format = shell
+            .args()
+            .input_format()
+            .unwrap_or_else(|| InputFormat::from_extension(path));
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(&format!("reading source file: {}", path.display()))
+        })?;
+        let text = migrate::migrate(&text, &format)
+            .map_err(|e| e.set_context(&format!("Failed to migrate {}", path.display())))?;
+        match text {
+            Some(text) => {
+                lines.push_str(&format!("migrated {}\n", path.display()));
+                migrated.push((path.clone(), text));
+            }
+            None => info!("{} is up to date", path.display()),
+        }
+    }
+
+    if args.check {
+        let mut writer = Writer::new(Path::new("<stdout>"))?;
+        for (path, _) in &migrated {
+            writer.write_all(format!("{}\n", path.display()).as_bytes())?;
+        }
+        if !migrated.is_empty() {
+            return Err(Error::new(SourceError::Msg(format!(
+                "{} source files need a migration",
+                migrated.len()
+            )))
+            .set_kind(ErrorKind::Validation));
+        }
+        return Ok(());
+    }
+
+    // Every file is written before any is kept, so a failure leaves all of them as they were.
+    let mut staging = Staging::new();
+    for (path, text) in &migrated {
+        staging.write_file(path, file_mode(path), text.as_bytes())?;
+    }
+    staging.commit();
+    Writer::new(Path::new("<stdout>"))?.write_all(lines.as_bytes())
+}
+
+/// The permission mode of an existing file, so rewriting it doesn't change who can read it.
+pub fn file_mode(path: &Path) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::metadata(path)
+            .map(|m| m.permissions().mode() & 0o7777)
+            .unwrap_or(DEFAULT_FILE_MODE)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        DEFAULT_FILE_MODE
+    }
+}
@@ -0,0 +1,12 @@
+pub mod check;
+pub mod config;
+pub mod count;
+pub mod export;
+pub mod fmt;
+pub mod jobs;
+pub mod man;
+pub mod migrate;
+pub mod report;
+pub mod rollback;
+pub mod serve;
+pub mod verify;
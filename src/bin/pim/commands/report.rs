@@ -0,0 +1,108 @@
+use crate::cli::{Cli, OwnersArgs, OwnersFormat, ReportArgs, ReportFormat, Reports};
+use crate::commands::export;
+use log::{info, warn};
+use pim::app::TargetFiles;
+use pim::app::duplicates;
+use pim::app::owners::Owners;
+use pim::app::source::SourceFile;
+use pim::app::stats::Stats;
+use pim::app::target::MergeKey;
+use pim::core::Output;
+use pim::core::error::*;
+use pim::core::io::Writer;
+use serde::Serialize;
+use std::path::Path;
+
+/// Print a report about the sources to stdout.
+pub fn run(shell: &Cli, args: &ReportArgs) -> Result<()> {
+    let report = match &args.report {
+        Reports::Duplicates(args) => self::duplicates(shell, &args.format)?,
+        Reports::Stats(args) => self::stats(shell, &args.format, &args.label)?,
+        Reports::Owners(args) => self::owners(shell, args)?,
+    };
+    Writer::new(Path::new("<stdout>"))?.write_all(report.as_bytes())
+}
+
+/// Report the targets in more than one job or group with the inputs and providers adding them.
+fn duplicates(shell: &Cli, format: &ReportFormat) -> Result<String> {
+    // Read each input on its own so every source keeps where it came from.
+    let mut sources = Vec::new();
+    for input in shell.inputs()? {
+        let origin = input.kind().name();
+        for source in export::read(shell, vec![input])?.sources {
+            sources.push((origin.clone(), source));
+        }
+    }
+    for provider in shell.args().providers().providers()? {
+        info!("Querying {} provider", provider.name());
+        let origin = format!("{} provider", provider.name());
+        for source in provider.sources()? {
+            sources.push((origin.clone(), source));
+        }
+    }
+
+    if shell.args().merge_key() == MergeKey::Labels {
+        for (_, source) in &mut sources {
+            source.set_id(None);
+        }
+    }
+
+    let found = duplicates::find(&sources);
+    info!("{} duplicate targets", found.len());
+    match format {
+        ReportFormat::Table => Ok(duplicates::table(&found)),
+        ReportFormat::Json => json(&found, "duplicate targets"),
+    }
+}
+
+/// Report the counts of the generated target files.
+fn stats(shell: &Cli, format: &ReportFormat, labels: &[String]) -> Result<String> {
+    let source = export::sources(shell, Vec::new())?;
+    let target_files = prepare(shell, &source)?;
+    let mut stats = Stats::new(&target_files, labels);
+    stats.duplicate_sources = source.duplicates();
+    match format {
+        ReportFormat::Table => Ok(stats.table()),
+        ReportFormat::Json => json(&stats, "inventory stats"),
+    }
+}
+
+/// Report the targets of the generated target files by owner.
+fn owners(shell: &Cli, args: &OwnersArgs) -> Result<String> {
+    let target_files = target_files(shell)?;
+    let owners = Owners::new(&target_files, &args.label);
+    if owners.unowned_groups > 0 {
+        warn!(
+            "{} groups with {} targets have no {} label",
+            owners.unowned_groups,
+            owners.unowned().count(),
+            args.label
+        );
+    }
+    match args.format {
+        OwnersFormat::Csv => Ok(owners.csv()),
+        OwnersFormat::Json => json(&owners, "owners"),
+    }
+}
+
+/// Generate the target files as they would be written, without writing them.
+pub fn target_files(shell: &Cli) -> Result<TargetFiles> {
+    prepare(shell, &export::sources(shell, Vec::new())?)
+}
+
+// Generate the target files of the sources as they would be written.
+fn prepare(shell: &Cli, source: &SourceFile) -> Result<TargetFiles> {
+    let output = Output::from_writer(std::io::sink(), shell.args().output_format().clone());
+    let mut target_files = export::prepare(source, &output)?;
+    export::finalize(shell, &mut target_files)?;
+    Ok(target_files)
+}
+
+pub fn json<T: Serialize>(report: &T, name: &str) -> Result<String> {
+    let json = serde_json::to_string_pretty(report).map_err(|e| {
+        Error::new(SourceError::SerdeJson(e))
+            .set_context(&format!("Failed to serialize {}", name))
+            .set_kind(ErrorKind::Internal)
+    })?;
+    Ok(json + "\n")
+}
@@ -0,0 +1,25 @@
+use crate::cli::{Cli, RollbackArgs};
+use log::info;
+use pim::app::versions;
+use pim::core::error::*;
+use pim::core::io::Writer;
+use std::path::Path;
+
+/// Roll the versioned target directory back to an earlier version. The version's files are
+/// checked against its manifest before `latest` is pointed at it. The new version is printed to
+/// stdout.
+pub fn run(shell: &Cli, args: &RollbackArgs) -> Result<()> {
+    let dir = shell.target();
+    if !dir.is_dir() {
+        return Err(Error::new(SourceError::Msg(
+            "Rollback requires a versioned target directory".to_string(),
+        ))
+        .set_kind(ErrorKind::Config)
+        .print_help());
+    }
+
+    let version = versions::rollback(&dir, args.to.as_deref())?;
+    info!("Rolled {} back to {}", dir.display(), version);
+    let mut writer = Writer::new(Path::new("<stdout>"))?;
+    writer.write_all(format!("{} -> {}\n", versions::LATEST, version).as_bytes())
+}
@@ -0,0 +1,647 @@
+use crate::cli::{Cli, ServeArgs};
+use crate::commands::export;
+use crate::summary::RunSummary;
+use log::{debug, error, info};
+use pim::app::convert;
+use pim::app::diff::{self, JobGroups};
+use pim::app::metrics::Metrics;
+use pim::app::source::Source;
+use pim::core::InputFormat;
+use pim::core::error::*;
+use pim::core::http::{self, Request, Response};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+/// Sources pushed to the server that are waiting for the next generation run.
+type Pending = Arc<Mutex<Vec<Source>>>;
+
+/// The target groups per job of the last successful generation run.
+type Latest = Arc<Mutex<JobGroups>>;
+
+/// Regenerate target files on an interval and serve metrics and health checks over HTTP.
+/// Readiness fails when the last successful run is older than the max age. Sources can be pushed
+/// on a Unix socket, merged into the next run, or with `PUT /sources/<name>`, stored in the push
+/// directory and generated right away. With `--grpc-listen`, the generated targets, pushes, and
+/// validation are also served over gRPC.
+pub fn run(shell: &Cli, args: &ServeArgs) -> Result<()> {
+    // The push directory is a source, so a registry of pushed sources needs no other source.
+    let mut generator = shell.clone();
+    if let Some(dir) = &args.push_dir {
+        generator = generator.with_source(dir);
+    }
+
+    if shell.args().interactive() {
+        return Err(Error::new(SourceError::Msg(
+            "--interactive can't be used in serve mode".to_string(),
+        ))
+        .set_kind(ErrorKind::Config)
+        .print_help());
+    }
+
+    if generator.source_is_stdin()? {
+        return Err(Error::new(SourceError::InvalidInputSource(
+            "Serve mode requires a source file or directory".to_string(),
+        ))
+        .set_kind(ErrorKind::Config)
+        .print_help());
+    }
+
+    let (trigger, triggered) = mpsc::channel();
+    let lock = Arc::new(Mutex::new(()));
+    let store = match (&args.push_dir, &args.push_token_file) {
+        (Some(dir), Some(token_file)) => Some(Arc::new(PushStore::new(
+            dir,
+            token_file,
+            trigger,
+            Arc::clone(&lock),
+        )?)),
+        _ => None,
+    };
+
+    let auth_token = match &args.auth_token_file {
+        Some(path) => Some(read_token(path)?),
+        None => None,
+    };
+
+    #[cfg(feature = "tls")]
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(pim::core::tls::server_config(
+            cert,
+            key,
+            args.tls_client_ca.as_deref(),
+        )?),
+        _ => None,
+    };
+
+    let metrics = Arc::new(Metrics::new());
+    let listener = http::bind(&args.listen)?;
+    #[cfg(feature = "tls")]
+    let scheme = if tls.is_some() { "https://" } else { "" };
+    #[cfg(not(feature = "tls"))]
+    let scheme = "";
+    info!("Listening on {}{}", scheme, args.listen);
+
+    let latest = Latest::default();
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = &args.grpc_listen {
+        let tls = match (&args.tls_cert, &args.tls_key) {
+            (Some(cert), Some(key)) => Some(pim::core::grpc::tls_config(
+                cert,
+                key,
+                args.tls_client_ca.as_deref(),
+            )?),
+            _ => None,
+        };
+        let listener = http::bind(addr)?;
+        info!("Serving gRPC on {}", addr);
+        let service = grpc::Service {
+            latest: Arc::clone(&latest),
+            store: store.clone(),
+            auth_token: auth_token.clone(),
+            strip_internal_labels: shell.args().style().strip_internal_labels,
+        };
+        thread::spawn(move || {
+            if let Err(e) = pim::core::grpc::serve(listener, service, tls) {
+                error!("{}", e);
+            }
+        });
+    }
+
+    let pending = Pending::default();
+    if let Some(path) = &args.listen_socket {
+        socket::listen(path, shell, Arc::clone(&pending))?;
+    }
+
+    let generator_metrics = Arc::clone(&metrics);
+    let generator_latest = Arc::clone(&latest);
+    let interval = args.interval;
+    thread::spawn(move || {
+        loop {
+            {
+                // Pushed files are not written while a run reads them.
+                let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+                generate(
+                    &mut generator,
+                    &generator_metrics,
+                    &pending,
+                    &generator_latest,
+                );
+            }
+
+            // Wait for the interval or a pushed source, whichever comes first. Pushes made while
+            // running are folded into one extra run.
+            let _ = triggered.recv_timeout(interval);
+            while triggered.try_recv().is_ok() {}
+        }
+    });
+
+    // Allow a couple of missed runs before reporting not ready.
+    let max_age = args.max_age.unwrap_or(interval * 3);
+    let handler = Arc::new(Routes {
+        metrics,
+        max_age,
+        store,
+        auth_token,
+    });
+    #[cfg(feature = "tls")]
+    if let Some(config) = tls {
+        return http::serve_tls(listener, config, handler);
+    }
+    http::serve(listener, handler)
+}
+
+/// Run a single generation with any pending pushed sources, recording the outcome in the metrics
+/// and keeping the generated target groups.
+fn generate(shell: &mut Cli, metrics: &Metrics, pending: &Pending, latest: &Latest) {
+    debug!("Starting generation run");
+    let extra = std::mem::take(&mut *pending.lock().unwrap_or_else(|e| e.into_inner()));
+    if !extra.is_empty() {
+        info!("Merging {} pushed sources into generation run", extra.len());
+    }
+
+    match export::run_recorded(shell, metrics, extra, &mut RunSummary::default()) {
+        Ok(target_files) => {
+            *latest.lock().unwrap_or_else(|e| e.into_inner()) = diff::current_groups(&target_files)
+        }
+        Err(e) => error!("Generation run failed: {}", e),
+    }
+}
+
+/// The HTTP endpoints of serve mode. Pushes are checked against the push token. Everything else
+/// except the health checks, which probes usually can't authenticate, requires the auth token
+/// when one is set. Requests are routed and authorized from their head, and only the bodies of
+/// authorized pushes are read.
+struct Routes {
+    metrics: Arc<Metrics>,
+    max_age: Duration,
+    store: Option<Arc<PushStore>>,
+    auth_token: Option<String>,
+}
+
+impl http::Handler for Routes {
+    fn check(&self, request: &Request) -> Option<Response> {
+        match request.path.strip_prefix("/sources/") {
+            Some(name) => match &self.store {
+                Some(store) => store.check(request, name),
+                None => Some(Response::not_found()),
+            },
+            // Nothing else takes a body, so it is answered without reading one.
+            None => Some(self.route(request)),
+        }
+    }
+
+    fn handle(&self, request: &Request) -> Response {
+        match (&self.store, request.path.strip_prefix("/sources/")) {
+            (Some(store), Some(name)) => store.handle(request, name),
+            _ => self.route(request),
+        }
+    }
+}
+
+impl Routes {
+    fn route(&self, request: &Request) -> Response {
+        let is_health = matches!(request.path.as_str(), "/healthz" | "/readyz");
+        if let Some(token) = &self.auth_token {
+            if !is_health && !is_authorized(request, token) {
+                return unauthorized();
+            }
+        }
+
+        let metrics = &self.metrics;
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/healthz") => health(metrics, None),
+            ("GET", "/readyz") => health(metrics, Some(self.max_age)),
+            (_, "/healthz" | "/readyz") => Response::method_not_allowed(),
+            ("GET", "/metrics") => Response::new(200)
+                .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                .with_body(metrics.render().into_bytes()),
+            (_, "/metrics") => Response::method_not_allowed(),
+            _ => Response::not_found(),
+        }
+    }
+}
+
+// Report generation status. Liveness (no max age) always succeeds while the server is running.
+// Readiness requires a successful run within the max age.
+fn health(metrics: &Metrics, max_age: Option<Duration>) -> Response {
+    let data = metrics.snapshot();
+    let age = data.last_success_age();
+    let ready = match (max_age, age) {
+        (None, _) => true,
+        (Some(max_age), Some(age)) => age <= max_age.as_secs_f64(),
+        (Some(_), None) => false,
+    };
+
+    let body = serde_json::json!({
+        "status": if ready { "ok" } else { "unavailable" },
+        "runs": data.runs_total,
+        "last_run_success": data.last_run_success,
+        "last_success_age_seconds": age,
+    });
+    Response::json(if ready { 200 } else { 503 }, &format!("{}\n", body))
+}
+
+/// Source documents pushed with `PUT /sources/<name>`, stored as `<name>.yaml` or `<name>.json`
+/// in the push directory. Every push or delete triggers a generation run.
+struct PushStore {
+    dir: PathBuf,
+    token: String,
+    trigger: Sender<()>,
+    // Held while files are written and while a generation run reads them.
+    lock: Arc<Mutex<()>>,
+}
+
+impl PushStore {
+    fn new(
+        dir: &Path,
+        token_file: &Path,
+        trigger: Sender<()>,
+        lock: Arc<Mutex<()>>,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(dir).map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(&format!(
+                    "Failed to create push directory: {}",
+                    dir.display()
+                ))
+                .set_kind(ErrorKind::Config)
+        })?;
+
+        let token = read_token(token_file)?;
+
+        info!("Accepting pushed sources into {}", dir.display());
+        Ok(PushStore {
+            dir: dir.to_path_buf(),
+            token,
+            trigger,
+            lock,
+        })
+    }
+
+    // Authorize a push and check its name and method before its body is read.
+    fn check(&self, request: &Request, name: &str) -> Option<Response> {
+        if !is_authorized(request, &self.token) {
+            return Some(unauthorized());
+        }
+
+        if !is_valid_source_name(name) {
+            return Some(Response::text(400, &format!("{}\n", INVALID_SOURCE_NAME)));
+        }
+
+        match request.method.as_str() {
+            "PUT" | "DELETE" => None,
+            _ => Some(Response::method_not_allowed()),
+        }
+    }
+
+    fn handle(&self, request: &Request, name: &str) -> Response {
+        match request.method.as_str() {
+            "PUT" => self.put(name, request),
+            "DELETE" => self.delete(name),
+            _ => Response::method_not_allowed(),
+        }
+    }
+
+    // Validate and store a source document, replacing any previous document with the name.
+    fn put(&self, name: &str, request: &Request) -> Response {
+        let format = match request.header("Content-Type") {
+            Some(ct) if ct.contains("json5") => InputFormat::Json5,
+            Some(ct) if ct.contains("hcl") => InputFormat::Hcl,
+            Some(ct) if ct.contains("json") => InputFormat::Json,
+            _ => InputFormat::Yaml,
+        };
+        let sources = match convert::validate(&request.body, format.clone()) {
+            Ok(sources) => sources,
+            Err(e) => return Response::text(400, &format!("{}\n", e)),
+        };
+
+        match self.save(name, &request.body, &format) {
+            Ok(existed) => {
+                let body = serde_json::json!({"name": name, "sources": sources.len()});
+                Response::json(if existed { 200 } else { 201 }, &format!("{}\n", body))
+            }
+            Err(_) => Response::text(500, "Failed to store source\n"),
+        }
+    }
+
+    // Store a validated source document, replacing any previous document with the name, and
+    // trigger a generation run. Returns whether a document was replaced.
+    fn save(&self, name: &str, content: &[u8], format: &InputFormat) -> std::io::Result<bool> {
+        let path = self.dir.join(format!("{}.{}", name, format.as_str()));
+        let res = {
+            let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+            let existed = self.remove(name);
+            let tmp = self.dir.join(format!(".{}.tmp", name));
+            std::fs::write(&tmp, content)
+                .and_then(|_| std::fs::rename(&tmp, &path))
+                .map(|_| existed)
+        };
+
+        match &res {
+            Ok(_) => {
+                info!("Stored pushed source {}", path.display());
+                let _ = self.trigger.send(());
+            }
+            Err(e) => error!("Failed to store pushed source {}: {}", path.display(), e),
+        }
+        res
+    }
+
+    fn delete(&self, name: &str) -> Response {
+        let removed = {
+            let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+            self.remove(name)
+        };
+        if !removed {
+            return Response::not_found();
+        }
+
+        info!("Deleted pushed source {}", name);
+        let _ = self.trigger.send(());
+        Response::new(204)
+    }
+
+    // Remove the stored document for the name in any format. Returns whether one existed.
+    fn remove(&self, name: &str) -> bool {
+        let mut removed = false;
+        for format in [
+            InputFormat::Json,
+            InputFormat::Json5,
+            InputFormat::Hcl,
+            InputFormat::Yaml,
+        ] {
+            let path = self.dir.join(format!("{}.{}", name, format.as_str()));
+            removed |= std::fs::remove_file(path).is_ok();
+        }
+        removed
+    }
+}
+
+const INVALID_SOURCE_NAME: &str =
+    "Source names may only contain letters, digits, '.', '_', and '-'";
+
+// Names become file names, so only allow a safe set of characters and no leading dot.
+fn is_valid_source_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// Read a bearer token from a file, ignoring surrounding whitespace.
+fn read_token(path: &Path) -> Result<String> {
+    let token = std::fs::read_to_string(path)
+        .map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(&format!("Failed to read token file: {}", path.display()))
+                .set_kind(ErrorKind::Config)
+        })?
+        .trim()
+        .to_string();
+    if token.is_empty() {
+        return Err(Error::new(SourceError::Msg(format!(
+            "Token file is empty: {}",
+            path.display()
+        )))
+        .set_kind(ErrorKind::Config));
+    }
+
+    Ok(token)
+}
+
+// Check the request's `Authorization: Bearer` header against the token.
+fn is_authorized(request: &Request, token: &str) -> bool {
+    request
+        .header("Authorization")
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.trim(), token))
+}
+
+fn unauthorized() -> Response {
+    Response::text(401, "Unauthorized\n").with_header("WWW-Authenticate", "Bearer")
+}
+
+// Compare tokens without returning early so the comparison time doesn't reveal the token.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Accept source documents on a Unix domain socket.
+#[cfg(unix)]
+mod socket {
+    use super::Pending;
+    use crate::cli::Cli;
+    use log::{debug, info, warn};
+    use pim::app::convert;
+    use pim::core::InputFormat;
+    use pim::core::error::*;
+    use pim::core::http::MAX_BODY_SIZE;
+    use std::{
+        io::{Read, Write},
+        os::unix::net::{UnixListener, UnixStream},
+        path::Path,
+        thread,
+    };
+
+    /// Bind the socket and accept documents in the background. A stale socket file left by a
+    /// previous run is removed, but a socket another process is listening on is not.
+    pub fn listen(path: &Path, shell: &Cli, pending: Pending) -> Result<()> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(Error::new(SourceError::Msg(format!(
+                    "Socket is already in use: {}",
+                    path.display()
+                )))
+                .set_kind(ErrorKind::Config));
+            }
+
+            debug!("Removing stale socket: {}", path.display());
+            std::fs::remove_file(path).map_err(|e| {
+                Error::new(SourceError::Io(e)).set_context(&format!(
+                    "Failed to remove stale socket: {}",
+                    path.display()
+                ))
+            })?;
+        }
+
+        let listener = UnixListener::bind(path).map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(&format!("Failed to bind socket: {}", path.display()))
+        })?;
+        info!("Accepting sources on {}", path.display());
+
+        let format = shell.args().input_format().unwrap_or(InputFormat::Yaml);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let pending = pending.clone();
+                        let format = format.clone();
+                        thread::spawn(move || receive(stream, &pending, format));
+                    }
+                    Err(e) => warn!("Failed to accept socket connection: {}", e),
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    // Read one source document until the client closes its side, validate it, and queue its
+    // sources. The client is sent `ok` with the number of sources, or `error` with the reason.
+    fn receive(mut stream: UnixStream, pending: &Pending, format: InputFormat) {
+        let mut content = Vec::new();
+        let res = (&mut stream)
+            .take(MAX_BODY_SIZE as u64 + 1)
+            .read_to_end(&mut content);
+        let reply = match res {
+            Err(e) => format!("error: {}\n", e),
+            Ok(_) if content.len() > MAX_BODY_SIZE => "error: document too large\n".to_string(),
+            Ok(_) => match convert::validate(&content, format) {
+                Ok(sources) => {
+                    info!("Received {} sources on socket", sources.len());
+                    let reply = format!("ok {}\n", sources.len());
+                    pending
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .extend(sources);
+                    reply
+                }
+                Err(e) => {
+                    warn!("Rejected source document on socket: {}", e);
+                    format!("error: {}\n", e.to_string().replace('\n', " "))
+                }
+            },
+        };
+
+        if let Err(e) = stream.write_all(reply.as_bytes()) {
+            debug!("Failed to reply on socket: {}", e);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod socket {
+    use super::Pending;
+    use crate::cli::Cli;
+    use pim::core::error::*;
+    use std::path::Path;
+
+    pub fn listen(_path: &Path, _shell: &Cli, _pending: Pending) -> Result<()> {
+        Err(Error::new(SourceError::Msg(
+            "--listen-socket requires Unix domain sockets, which are not supported on this platform"
+                .to_string(),
+        ))
+        .set_kind(ErrorKind::Config))
+    }
+}
+
+/// The gRPC API. Each call mirrors an HTTP endpoint and is authorized with the same token, sent
+/// as `authorization: Bearer <token>` metadata.
+#[cfg(feature = "grpc")]
+mod grpc {
+    use super::{INVALID_SOURCE_NAME, Latest, PushStore, constant_time_eq, is_valid_source_name};
+    use pim::app::convert;
+    use pim::core::InputFormat;
+    use pim::core::grpc::{pb, target_group};
+    use std::sync::Arc;
+    use tonic::{Request, Response, Status};
+
+    pub struct Service {
+        pub latest: Latest,
+        pub store: Option<Arc<PushStore>>,
+        pub auth_token: Option<String>,
+        pub strip_internal_labels: bool,
+    }
+
+    #[tonic::async_trait]
+    impl pb::pim_server::Pim for Service {
+        async fn list_targets(
+            &self,
+            request: Request<pb::ListTargetsRequest>,
+        ) -> Result<Response<pb::ListTargetsResponse>, Status> {
+            if let Some(token) = &self.auth_token {
+                authorize(&request, token)?;
+            }
+
+            let job = request.into_inner().job;
+            let latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+            let groups = latest
+                .iter()
+                .filter(|(name, _)| job.is_empty() || **name == job)
+                .flat_map(|(_, groups)| groups)
+                .map(|group| target_group(group, self.strip_internal_labels))
+                .collect();
+            Ok(Response::new(pb::ListTargetsResponse { groups }))
+        }
+
+        async fn put_source(
+            &self,
+            request: Request<pb::PutSourceRequest>,
+        ) -> Result<Response<pb::PutSourceResponse>, Status> {
+            let Some(store) = &self.store else {
+                return Err(Status::unimplemented("Pushing sources requires --push-dir"));
+            };
+            authorize(&request, &store.token)?;
+
+            let request = request.into_inner();
+            if !is_valid_source_name(&request.name) {
+                return Err(Status::invalid_argument(INVALID_SOURCE_NAME));
+            }
+
+            let format = InputFormat::from(request.format());
+            let sources = convert::validate(&request.content, format.clone())
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            let replaced = store
+                .save(&request.name, &request.content, &format)
+                .map_err(|_| Status::internal("Failed to store source"))?;
+            Ok(Response::new(pb::PutSourceResponse {
+                name: request.name,
+                sources: sources.len() as u32,
+                replaced,
+            }))
+        }
+
+        async fn validate(
+            &self,
+            request: Request<pb::ValidateRequest>,
+        ) -> Result<Response<pb::ValidateResponse>, Status> {
+            if let Some(token) = &self.auth_token {
+                authorize(&request, token)?;
+            }
+
+            let request = request.into_inner();
+            let sources = convert::validate(&request.content, request.format().into())
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            Ok(Response::new(pb::ValidateResponse {
+                sources: sources.len() as u32,
+            }))
+        }
+    }
+
+    // Check the request's `authorization: Bearer` metadata against the token.
+    fn authorize<T>(request: &Request<T>, token: &str) -> Result<(), Status> {
+        let authorized = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|given| constant_time_eq(given.trim(), token));
+        match authorized {
+            true => Ok(()),
+            false => Err(Status::unauthenticated("Unauthorized")),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::cli::{Cli, VerifyArgs};
+use log::{debug, error};
+use pim::core::error::*;
+use pim::core::io::{Writer, path_is_stdout};
+use pim::core::sign;
+use std::path::{Path, PathBuf};
+
+/// Verify the signatures of the given files, or of the target when none are given. Each verified
+/// file is printed to stdout. Fails if any file is unsigned or has an invalid signature.
+pub fn run(shell: &Cli, args: &VerifyArgs) -> Result<()> {
+    let paths = match args.files.is_empty() {
+        true => vec![shell.target()],
+        false => args.files.clone(),
+    };
+
+    let mut files = Vec::new();
+    for path in &paths {
+        if path_is_stdout(path) {
+            return Err(Error::new(SourceError::Msg(
+                "Verify requires a target file or directory".to_string(),
+            ))
+            .set_kind(ErrorKind::Config)
+            .print_help());
+        }
+        files.extend(signed_files(path)?);
+    }
+
+    let mut writer = Writer::new(Path::new("<stdout>"))?;
+    let mut failed = 0;
+    for file in &files {
+        match sign::verify(file, &args.public_key) {
+            Ok(()) => writer.write_all(format!("{}: OK\n", file.display()).as_bytes())?,
+            Err(e) => {
+                error!("{}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(Error::new(SourceError::Msg(format!(
+            "{} of {} files failed verification",
+            failed,
+            files.len()
+        )))
+        .set_kind(ErrorKind::Validation));
+    }
+    Ok(())
+}
+
+// The files to verify for a path: the path itself, or the files in a directory other than
+// signatures and hidden files, sorted by path.
+fn signed_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    debug!("Finding files to verify in {}", path.display());
+    let entries = std::fs::read_dir(path).map_err(|e| {
+        Error::new(SourceError::Io(e))
+            .set_context(&format!("reading directory: {}", path.display()))
+    })?;
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && !sign::is_signature(p))
+        .filter(|p| {
+            p.file_name()
+                .is_some_and(|name| !name.to_string_lossy().starts_with('.'))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
@@ -1,77 +1,179 @@
-use env_logger::Env;
-use log::{debug, info};
-use pim::app::source::SourceFile;
-use pim::app::target::TargetFiles;
-use pim::core::{Input, Output, error::*};
+use log::debug;
+use pim::core::color::Color;
+use pim::core::error::*;
+use std::{fs::OpenOptions, io::IsTerminal, sync::Mutex};
+use summary::RunSummary;
+use tracing_subscriber::{
+    Layer,
+    filter::{LevelFilter, filter_fn},
+    fmt::{format::FmtSpan, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+};
 
 mod cli;
+mod commands;
+mod providers;
+mod summary;
 
 fn main() {
+    // Get command line arguments or exit. Arguments are parsed first since they configure logging.
+    let mut shell = cli::Cli::new();
+
     // Initialize logger.
-    setup_logger();
+    setup_logger(shell.args()).map_err(|e| exit_handler(shell.args(), &e));
     debug!("Logger initialized");
-
-    // Get command line arguments or exit.
-    let mut shell = cli::Cli::new();
     debug!("Command line arguments parsed: {:?}", shell.args());
 
-    // Run main handler or exit on error.
+    // Run main handler. Runs that write target files end with the run summary, after any error
+    // is logged, so it is always the last line.
     debug!("Running main handler\n");
-    handler(&mut shell).map_err(|e| exit_handler(&e));
+    let mut summary = RunSummary::default();
+    let result = handler(&mut shell, &mut summary);
+    if result
+        .as_ref()
+        .is_err_and(|e| report_error(e) == Handled::Failed)
+    {
+        summary.errors += 1;
+    }
+    if matches!(
+        shell.args().subcommand(),
+        None | Some(cli::Commands::Check(_))
+    ) {
+        summary.emit(shell.args());
+    }
 
-    // Exit successfully.
-    std::process::exit(0);
+    match result {
+        Ok(()) => std::process::exit(0),
+        // The reader of stdout went away, such as with `pim | head`, which isn't a failure.
+        Err(e) if e.is_broken_pipe() => std::process::exit(0),
+        Err(e) => {
+            let code = shell.args().exit_code(&e);
+            debug!("Exiting with code {}", code);
+            std::process::exit(code);
+        }
+    }
 }
 
-fn setup_logger() {
-    let env = Env::default()
-        .filter_or("PIM_LOG_LEVEL", "error")
-        .write_style_or("PIM_LOG_STYLE", "always");
-    env_logger::Builder::from_env(env).init();
+/// Set up the logger, a tracing subscriber that log messages are forwarded to so they carry the
+/// spans active when they were logged. Verbosity flags override PIM_LOG_LEVEL, a level name.
+/// Spans are always recorded, and log their elapsed time when they close at debug level. Text
+/// messages are colored as `--color` decides for stderr, unless PIM_LOG_STYLE is set and
+/// `--color` isn't given.
+fn setup_logger(args: &cli::Args) -> Result<()> {
+    let level = args.log_level().unwrap_or_else(|| {
+        std::env::var("PIM_LOG_LEVEL")
+            .ok()
+            .and_then(|level| level.parse().ok())
+            .unwrap_or(log::LevelFilter::Error)
+    });
+    let level = match level {
+        log::LevelFilter::Off => LevelFilter::OFF,
+        log::LevelFilter::Error => LevelFilter::ERROR,
+        log::LevelFilter::Warn => LevelFilter::WARN,
+        log::LevelFilter::Info => LevelFilter::INFO,
+        log::LevelFilter::Debug => LevelFilter::DEBUG,
+        log::LevelFilter::Trace => LevelFilter::TRACE,
+    };
+    let span_events = match level >= LevelFilter::DEBUG {
+        true => FmtSpan::CLOSE,
+        false => FmtSpan::NONE,
+    };
+
+    // Still log to stderr when the log file can't be opened, so the error can be reported. Log
+    // files are never colored.
+    let stderr = || {
+        let terminal = std::io::stderr().is_terminal();
+        let ansi = match std::env::var("PIM_LOG_STYLE") {
+            Ok(style) if args.color() == Color::Auto => match style.as_str() {
+                "always" => true,
+                "never" => false,
+                _ => terminal,
+            },
+            _ => args.color().enabled(terminal),
+        };
+        (BoxMakeWriter::new(std::io::stderr), ansi)
+    };
+    let ((writer, ansi), result) = match args.log_file() {
+        Some(path) => match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => ((BoxMakeWriter::new(Mutex::new(file)), false), Ok(())),
+            Err(e) => (
+                stderr(),
+                Err(Error::new(SourceError::Io(e))
+                    .set_context(&format!("Failed to open log file: {}", path.display()))
+                    .set_kind(ErrorKind::Config)),
+            ),
+        },
+        None => (stderr(), Ok(())),
+    };
+
+    let layer = tracing_subscriber::fmt::layer()
+        .with_span_events(span_events)
+        .with_writer(writer);
+    let filter = filter_fn(move |meta| meta.is_span() || *meta.level() <= level);
+    match args.log_format() {
+        cli::LogFormat::Json => {
+            let layer = layer
+                .json()
+                .flatten_event(true)
+                .with_span_list(true)
+                .with_ansi(false);
+            tracing_subscriber::registry()
+                .with(layer.with_filter(filter))
+                .init();
+        }
+        cli::LogFormat::Text => {
+            let layer = layer.with_ansi(ansi);
+            tracing_subscriber::registry()
+                .with(layer.with_filter(filter))
+                .init();
+        }
+    }
+    result
 }
 
 /// Handle error and exit program.
-fn exit_handler(error: &Error) -> ! {
-    handle_error(error);
+fn exit_handler(args: &cli::Args, error: &Error) -> ! {
+    report_error(error);
+    let code = args.exit_code(error);
+    debug!("Exiting with code {}", code);
+    std::process::exit(code);
+}
+
+/// Log the error, and print the help if the error asks for it.
+fn report_error(error: &Error) -> Handled {
+    let handled = handle_error(error);
     if error.is_print_help() {
         cli::Cli::print_help();
     }
-    debug!("Exiting with code {:?}", error.code());
-    std::process::exit(error.code().unwrap_or(1));
+    handled
 }
 
-/// Main program handler. Gets inputs and outputs, then run subcommands.
-fn handler(shell: &mut cli::Cli) -> Result<()> {
-    // Get our inputs and outputs.
-    debug!("Getting inputs");
-    let inputs = shell.inputs()?;
-    debug!("Inputs obtained: {:?}", inputs);
-    debug!("Getting outputs");
-    let output = shell.output()?;
-    debug!("Outputs obtained: {:?}", output);
-
-    // TODO: This will later become a match on a subcommand argument as new features are added.
-    // Run exporter.
-    exporter(inputs, output)
-}
-
-/// Exporter function to read inputs and write outputs for prometheus file_sd target files.
-fn exporter(inputs: Vec<Input>, output: Output) -> Result<()> {
-    debug!("Starting export process");
-    // Read input data.
-    info!("Reading source inputs");
-    let mut source = SourceFile::new(inputs);
-    debug!("SourceFile initialized: {:?}", source);
-    source.read_sources()?;
-    debug!("Source inputs read: {:?}\n", source);
+/// Main program handler. Runs the subcommand, or gets inputs and outputs and exports target
+/// files when no subcommand is given.
+fn handler(shell: &mut cli::Cli, summary: &mut RunSummary) -> Result<()> {
+    // Subcommands that don't need inputs or outputs run first so we never block on stdin.
+    if let Some(command) = shell.args().subcommand() {
+        return match command {
+            cli::Commands::Man => commands::man::run(cli::Cli::clap_command()),
+            cli::Commands::Serve(args) => commands::serve::run(shell, args),
+            cli::Commands::Verify(args) => commands::verify::run(shell, args),
+            cli::Commands::Rollback(args) => commands::rollback::run(shell, args),
+            cli::Commands::Check(args) => commands::check::run(shell, args, summary),
+            cli::Commands::Report(args) => commands::report::run(shell, args),
+            cli::Commands::Count(args) => commands::count::run(shell, args),
+            cli::Commands::Jobs(args) => commands::jobs::run(shell, args),
+            cli::Commands::Config(args) => commands::config::run(shell, args),
+            cli::Commands::Migrate(args) => commands::migrate::run(shell, args),
+            cli::Commands::Fmt(args) => commands::fmt::run(shell, args),
+        };
+    }
 
-    // Write output data.
-    // Target files holds the generated targets split into the individual files to be written to.
-    info!("Preparing target files for output");
-    let mut target_files = TargetFiles::default();
-    source.into_targets(&output, output.format(), &mut target_files)?;
-    debug!("Target files prepared: {:?}", target_files);
-    target_files.write_all()?;
+    // One-shot runs reporting to a Pushgateway record metrics around the whole export.
+    if let Some(url) = shell.args().pushgateway().cloned() {
+        return commands::export::run_pushed(shell, &url, summary);
+    }
 
-    Ok(())
+    // Run exporter.
+    commands::export::run(shell, summary)
 }
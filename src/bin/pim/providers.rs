@@ -0,0 +1,635 @@
+#[cfg(feature = "cloud")]
+use pim::app::provider::{Cloud, CloudPlatform};
+use pim::app::provider::{
+    Consul, Docker, Ldap, NetBox, NetBoxKind, Nmap, Provider, PuppetDb, Srv, Terraform, Zone,
+};
+#[cfg(feature = "kubernetes")]
+use pim::app::provider::{Kubernetes, KubernetesRole};
+#[cfg(feature = "sql")]
+use pim::app::provider::{Sql, SqlEngine};
+use pim::core::error::*;
+use std::path::PathBuf;
+
+/// Command line options for the providers that discover sources from external systems.
+#[derive(Debug, Clone, clap::Args)]
+#[command(next_help_heading = "Providers")]
+pub struct ProviderArgs {
+    /// Discover sources from the Consul catalog at this http:// or https:// URL. The ACL token
+    /// is read from CONSUL_HTTP_TOKEN.
+    #[arg(long, global = true, value_name = "URL")]
+    pub consul: Option<String>,
+    /// Consul datacenter to query. Defaults to the agent's datacenter.
+    #[arg(long, global = true, value_name = "DC", requires = "consul")]
+    pub consul_datacenter: Option<String>,
+    /// Consul service to discover. Repeat for several services. Defaults to all services.
+    #[arg(long, global = true, value_name = "NAME", requires = "consul")]
+    pub consul_service: Vec<String>,
+    /// Discover devices and virtual machines with a primary IP from the NetBox API at this
+    /// http:// or https:// URL. The API token is read from NETBOX_TOKEN.
+    #[arg(long, global = true, value_name = "URL")]
+    pub netbox: Option<String>,
+    /// NetBox filter query, such as `status=active&tag=prometheus`.
+    #[arg(long, global = true, value_name = "QUERY", requires = "netbox")]
+    pub netbox_filter: Option<String>,
+    /// NetBox objects to discover.
+    #[arg(long, global = true, value_enum, default_value_t = NetBoxKind::All, requires = "netbox")]
+    pub netbox_kind: NetBoxKind,
+    /// Port added to NetBox primary IPs to build targets.
+    #[arg(
+        long,
+        global = true,
+        value_name = "PORT",
+        default_value_t = 9100,
+        requires = "netbox"
+    )]
+    pub netbox_port: u16,
+    /// Job for NetBox targets.
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        default_value = "node",
+        requires = "netbox"
+    )]
+    pub netbox_job: String,
+    /// Discover nodes with a PQL query from the PuppetDB at this http:// or https:// URL. The
+    /// RBAC token is read from PUPPETDB_TOKEN.
+    #[arg(long, global = true, value_name = "URL")]
+    pub puppetdb: Option<String>,
+    /// PQL query returning nodes with a certname. Defaults to the facts of all active nodes.
+    #[arg(long, global = true, value_name = "PQL", requires = "puppetdb")]
+    pub puppetdb_query: Option<String>,
+    /// Add a fact to PuppetDB targets as a label, such as `os_family=os.family`. Repeat for
+    /// several facts.
+    #[arg(long, global = true, value_name = "LABEL=FACT", requires = "puppetdb")]
+    pub puppetdb_fact: Vec<String>,
+    /// Use this fact as the target host instead of the certname, such as `networking.ip`.
+    #[arg(long, global = true, value_name = "FACT", requires = "puppetdb")]
+    pub puppetdb_address_fact: Option<String>,
+    /// Port added to PuppetDB hosts to build targets.
+    #[arg(
+        long,
+        global = true,
+        value_name = "PORT",
+        default_value_t = 9100,
+        requires = "puppetdb"
+    )]
+    pub puppetdb_port: u16,
+    /// Job for PuppetDB targets.
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        default_value = "node",
+        requires = "puppetdb"
+    )]
+    pub puppetdb_job: String,
+    /// Discover computer objects with a dNSHostName from the LDAP directory at this URI, such as
+    /// ldaps://dc1.corp.example.com. Requires ldapsearch.
+    #[arg(long, global = true, value_name = "URI", requires = "ldap_base")]
+    pub ldap: Option<String>,
+    /// Base DN to search, such as `DC=corp,DC=example,DC=com`.
+    #[arg(long, global = true, value_name = "DN", requires = "ldap")]
+    pub ldap_base: Option<String>,
+    /// LDAP search filter. Defaults to Active Directory computer objects.
+    #[arg(long, global = true, value_name = "FILTER", requires = "ldap")]
+    pub ldap_filter: Option<String>,
+    /// Bind as this DN, with the password read from --ldap-password-file. Searches are anonymous
+    /// otherwise.
+    #[arg(
+        long,
+        global = true,
+        value_name = "DN",
+        requires_all = ["ldap", "ldap_password_file"]
+    )]
+    pub ldap_bind_dn: Option<String>,
+    /// File containing the bind password.
+    #[arg(long, global = true, value_name = "FILE", requires = "ldap_bind_dn")]
+    pub ldap_password_file: Option<PathBuf>,
+    /// Add an attribute to LDAP targets as a label, such as `description=description`. Repeat
+    /// for several attributes.
+    #[arg(long, global = true, value_name = "LABEL=ATTR", requires = "ldap")]
+    pub ldap_attribute: Vec<String>,
+    /// Port added to dNSHostNames to build targets.
+    #[arg(
+        long,
+        global = true,
+        value_name = "PORT",
+        default_value_t = 9182,
+        requires = "ldap"
+    )]
+    pub ldap_port: u16,
+    /// Job for LDAP targets.
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        default_value = "windows",
+        requires = "ldap"
+    )]
+    pub ldap_job: String,
+    /// Import hosts with open ports from an nmap XML scan, such as `nmap -oX scan.xml -O`.
+    #[arg(long, global = true, value_name = "FILE", requires = "nmap_port")]
+    pub nmap: Option<PathBuf>,
+    /// Port that must be open on a scanned host to add it as a target. Repeat for several ports.
+    #[arg(long, global = true, value_name = "PORT", requires = "nmap")]
+    pub nmap_port: Vec<u16>,
+    /// Job for nmap targets.
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        default_value = "node",
+        requires = "nmap"
+    )]
+    pub nmap_job: String,
+    /// Discover instances from a Terraform state file or http:// or https:// backend URL.
+    #[arg(long, global = true, value_name = "STATE")]
+    pub terraform: Option<String>,
+    /// Resource attribute to use as the target host, such as `aws_instance.private_ip`. Repeat
+    /// for several resource types. Defaults to the private IPs of common instance types.
+    #[arg(long, global = true, value_name = "TYPE.ATTR", requires = "terraform")]
+    pub terraform_target: Vec<String>,
+    /// Port added to Terraform hosts to build targets.
+    #[arg(
+        long,
+        global = true,
+        value_name = "PORT",
+        default_value_t = 9100,
+        requires = "terraform"
+    )]
+    pub terraform_port: u16,
+    /// Job for Terraform targets.
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        default_value = "node",
+        requires = "terraform"
+    )]
+    pub terraform_job: String,
+    /// Discover hosts from the A and AAAA records in a BIND zone file. Repeat for several zones.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub zone: Vec<PathBuf>,
+    /// Origin for zone files without an $ORIGIN directive. Defaults to the file name without a
+    /// `db.` prefix or `.zone` or `.db` suffix.
+    #[arg(long, global = true, value_name = "DOMAIN", requires = "zone")]
+    pub zone_origin: Option<String>,
+    /// Only discover names matching this glob, such as `web*.example.com`. Repeat for several
+    /// patterns.
+    #[arg(long, global = true, value_name = "GLOB", requires = "zone")]
+    pub zone_pattern: Vec<String>,
+    /// Target record addresses instead of names.
+    #[arg(long, global = true, requires = "zone")]
+    pub zone_addresses: bool,
+    /// Port added to zone hosts to build targets.
+    #[arg(
+        long,
+        global = true,
+        value_name = "PORT",
+        default_value_t = 9100,
+        requires = "zone"
+    )]
+    pub zone_port: u16,
+    /// Job for zone targets.
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        default_value = "node",
+        requires = "zone"
+    )]
+    pub zone_job: String,
+    /// Discover targets from the SRV records of a DNS name, such as `_node._tcp.example.com`.
+    /// Repeat for several names.
+    #[arg(long, global = true, value_name = "NAME")]
+    pub srv: Vec<String>,
+    /// Add a label to SRV targets, such as `env=prod`. Repeat for several labels.
+    #[arg(long, global = true, value_name = "LABEL=VALUE", requires = "srv")]
+    pub srv_label: Vec<String>,
+    /// Name server for SRV lookups, such as `10.0.0.2:53`. Defaults to the first name server in
+    /// /etc/resolv.conf, and is required without one, such as on Windows.
+    #[arg(long, global = true, value_name = "ADDRESS", requires = "srv")]
+    pub srv_nameserver: Option<std::net::SocketAddr>,
+    /// Job for SRV targets.
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        default_value = "node",
+        requires = "srv"
+    )]
+    pub srv_job: String,
+    /// Discover running containers from the Docker daemon at this unix:// socket or http:// URL,
+    /// such as unix:///var/run/docker.sock.
+    #[arg(
+        long,
+        global = true,
+        value_name = "ADDRESS",
+        conflicts_with = "docker_compose"
+    )]
+    pub docker: Option<String>,
+    /// Discover services from a Compose file instead of a Docker daemon.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub docker_compose: Option<PathBuf>,
+    // The other Docker options need --docker or --docker-compose, which is checked when the
+    // providers are built. Argument groups aren't passed on to subcommands like global arguments,
+    // so they can't be required here.
+    /// Container label holding the metrics port. Defaults to `prometheus.io/port`.
+    #[arg(long, global = true, value_name = "LABEL")]
+    pub docker_label: Option<String>,
+    /// Metrics port for containers that expose it and have no port label.
+    #[arg(long, global = true, value_name = "PORT")]
+    pub docker_port: Option<u16>,
+    /// Target published ports on this host instead of container addresses.
+    #[arg(long, global = true, value_name = "HOST")]
+    pub docker_host: Option<String>,
+    /// Discover Kubernetes endpoints or annotated pods from the API server in the kubeconfig
+    /// context.
+    #[cfg(feature = "kubernetes")]
+    #[arg(long, global = true, value_enum, value_name = "ROLE")]
+    pub kubernetes: Option<KubernetesRole>,
+    /// Kubeconfig file. Defaults to KUBECONFIG or ~/.kube/config.
+    #[cfg(feature = "kubernetes")]
+    #[arg(long, global = true, value_name = "FILE", requires = "kubernetes")]
+    pub kubeconfig: Option<PathBuf>,
+    /// Kubeconfig context to use. Defaults to the current context.
+    #[cfg(feature = "kubernetes")]
+    #[arg(long, global = true, value_name = "NAME", requires = "kubernetes")]
+    pub kube_context: Option<String>,
+    /// Use this API server without credentials instead of the kubeconfig, such as
+    /// http://127.0.0.1:8001 for `kubectl proxy`.
+    #[cfg(feature = "kubernetes")]
+    #[arg(long, global = true, value_name = "URL", requires = "kubernetes")]
+    pub kube_api: Option<String>,
+    /// Only discover targets in this namespace. Defaults to the context's namespace or all
+    /// namespaces.
+    #[cfg(feature = "kubernetes")]
+    #[arg(long, global = true, value_name = "NAME", requires = "kubernetes")]
+    pub kube_namespace: Option<String>,
+    /// Annotation that must be "true" for a pod to be discovered. The port is read from the
+    /// `port` annotation with the same prefix.
+    #[cfg(feature = "kubernetes")]
+    #[arg(long, global = true, value_name = "KEY", requires = "kubernetes")]
+    pub kube_annotation: Option<String>,
+    /// Discover running instances with a private IP from a cloud platform using its CLI, which
+    /// must be installed and logged in.
+    #[cfg(feature = "cloud")]
+    #[arg(long, global = true, value_enum, value_name = "PLATFORM")]
+    pub cloud: Option<CloudPlatform>,
+    /// Only discover instances with this tag, such as `env=prod`. Repeat to require several tags.
+    /// GCE instances are matched by label.
+    #[cfg(feature = "cloud")]
+    #[arg(long, global = true, value_name = "KEY=VALUE", requires = "cloud")]
+    pub cloud_tag: Vec<String>,
+    /// EC2 region, GCE project, or Azure resource group to list. Defaults to the CLI's default.
+    #[cfg(feature = "cloud")]
+    #[arg(long, global = true, value_name = "NAME", requires = "cloud")]
+    pub cloud_scope: Option<String>,
+    /// Port added to instance private IPs to build targets.
+    #[cfg(feature = "cloud")]
+    #[arg(
+        long,
+        global = true,
+        value_name = "PORT",
+        default_value_t = 9100,
+        requires = "cloud"
+    )]
+    pub cloud_port: u16,
+    /// Job for cloud instance targets.
+    #[cfg(feature = "cloud")]
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        default_value = "node",
+        requires = "cloud"
+    )]
+    pub cloud_job: String,
+    /// Discover sources with a SQL query on the database at this URL, such as
+    /// postgres://pim@db/cmdb or mysql://pim@db/cmdb.
+    #[cfg(feature = "sql")]
+    #[arg(long, global = true, value_name = "URL", requires = "sql_query")]
+    pub sql: Option<String>,
+    /// Query returning one row per target. Other columns become labels.
+    #[cfg(feature = "sql")]
+    #[arg(long, global = true, value_name = "QUERY", requires = "sql")]
+    pub sql_query: Option<String>,
+    /// Database engine. Defaults to the URL scheme.
+    #[cfg(feature = "sql")]
+    #[arg(long, global = true, value_enum, requires = "sql")]
+    pub sql_engine: Option<SqlEngine>,
+    /// Column holding the job.
+    #[cfg(feature = "sql")]
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        default_value = "job",
+        requires = "sql"
+    )]
+    pub sql_job_column: String,
+    /// Column holding the target.
+    #[cfg(feature = "sql")]
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        default_value = "target",
+        requires = "sql"
+    )]
+    pub sql_target_column: String,
+}
+
+impl ProviderArgs {
+    /// Build the providers enabled on the command line.
+    pub fn providers(&self) -> Result<Vec<Box<dyn Provider>>> {
+        let mut providers: Vec<Box<dyn Provider>> = Vec::new();
+
+        if let Some(address) = &self.consul {
+            let mut consul = Consul::new(address).services(self.consul_service.clone());
+            if let Some(dc) = &self.consul_datacenter {
+                consul = consul.datacenter(dc);
+            }
+            if let Some(token) = env_token("CONSUL_HTTP_TOKEN") {
+                consul = consul.token(&token);
+            }
+            providers.push(Box::new(consul));
+        }
+
+        if let Some(address) = &self.netbox {
+            let mut netbox =
+                NetBox::new(address, &self.netbox_job, self.netbox_port).kind(self.netbox_kind);
+            if let Some(filter) = &self.netbox_filter {
+                netbox = netbox.filter(filter);
+            }
+            if let Some(token) = env_token("NETBOX_TOKEN") {
+                netbox = netbox.token(&token);
+            }
+            providers.push(Box::new(netbox));
+        }
+
+        if let Some(address) = &self.puppetdb {
+            providers.push(Box::new(self.puppetdb_provider(address)?));
+        }
+
+        if let Some(uri) = &self.ldap {
+            providers.push(Box::new(self.ldap_provider(uri)?));
+        }
+
+        if let Some(location) = &self.terraform {
+            providers.push(Box::new(self.terraform_provider(location)?));
+        }
+
+        if let Some(docker) = self.docker_provider()? {
+            providers.push(Box::new(docker));
+        }
+
+        for path in &self.zone {
+            let mut zone =
+                Zone::new(path, &self.zone_job, self.zone_port).addresses(self.zone_addresses);
+            if let Some(origin) = &self.zone_origin {
+                zone = zone.origin(origin);
+            }
+            for pattern in &self.zone_pattern {
+                zone = zone.pattern(pattern);
+            }
+            providers.push(Box::new(zone));
+        }
+
+        if !self.srv.is_empty() {
+            providers.push(Box::new(self.srv_provider()?));
+        }
+
+        if let Some(path) = &self.nmap {
+            providers.push(Box::new(Nmap::new(
+                path,
+                &self.nmap_job,
+                self.nmap_port.clone(),
+            )));
+        }
+
+        #[cfg(feature = "kubernetes")]
+        if let Some(role) = self.kubernetes {
+            providers.push(Box::new(self.kubernetes_provider(role)?));
+        }
+
+        #[cfg(feature = "cloud")]
+        if let Some(platform) = self.cloud {
+            providers.push(Box::new(self.cloud_provider(platform)?));
+        }
+
+        #[cfg(feature = "sql")]
+        if let Some(url) = &self.sql {
+            providers.push(Box::new(self.sql_provider(url)?));
+        }
+
+        Ok(providers)
+    }
+
+    fn puppetdb_provider(&self, address: &str) -> Result<PuppetDb> {
+        let mut puppetdb = PuppetDb::new(address, &self.puppetdb_job, self.puppetdb_port);
+        if let Some(query) = &self.puppetdb_query {
+            puppetdb = puppetdb.query(query);
+        }
+        if let Some(fact) = &self.puppetdb_address_fact {
+            puppetdb = puppetdb.address_fact(fact);
+        }
+        for mapping in &self.puppetdb_fact {
+            let Some((label, fact)) = mapping.split_once('=') else {
+                return Err(Error::new(SourceError::InvalidInputSource(format!(
+                    "Invalid --puppetdb-fact '{}', expected LABEL=FACT",
+                    mapping
+                )))
+                .set_kind(ErrorKind::Config));
+            };
+            puppetdb = puppetdb.fact(label.trim(), fact.trim());
+        }
+        if let Some(token) = env_token("PUPPETDB_TOKEN") {
+            puppetdb = puppetdb.token(&token);
+        }
+        Ok(puppetdb)
+    }
+
+    fn srv_provider(&self) -> Result<Srv> {
+        let mut srv = Srv::new(self.srv.clone(), &self.srv_job);
+        if let Some(nameserver) = self.srv_nameserver {
+            srv = srv.nameserver(nameserver);
+        }
+        for label in &self.srv_label {
+            let Some((name, value)) = label.split_once('=') else {
+                return Err(Error::new(SourceError::InvalidInputSource(format!(
+                    "Invalid --srv-label '{}', expected LABEL=VALUE",
+                    label
+                )))
+                .set_kind(ErrorKind::Config));
+            };
+            srv = srv.label(name.trim(), value.trim());
+        }
+        Ok(srv)
+    }
+
+    fn ldap_provider(&self, uri: &str) -> Result<Ldap> {
+        let base = self.ldap_base.as_deref().unwrap_or_default();
+        let mut ldap = Ldap::new(uri, base, &self.ldap_job, self.ldap_port);
+        if let Some(filter) = &self.ldap_filter {
+            ldap = ldap.filter(filter);
+        }
+        if let (Some(dn), Some(file)) = (&self.ldap_bind_dn, &self.ldap_password_file) {
+            ldap = ldap.bind(dn, file);
+        }
+        for mapping in &self.ldap_attribute {
+            let Some((label, attribute)) = mapping.split_once('=') else {
+                return Err(Error::new(SourceError::InvalidInputSource(format!(
+                    "Invalid --ldap-attribute '{}', expected LABEL=ATTR",
+                    mapping
+                )))
+                .set_kind(ErrorKind::Config));
+            };
+            ldap = ldap.attribute(label.trim(), attribute.trim());
+        }
+        Ok(ldap)
+    }
+
+    fn docker_provider(&self) -> Result<Option<Docker>> {
+        let mut docker = match (&self.docker, &self.docker_compose) {
+            (Some(address), _) => Docker::daemon(address),
+            (None, Some(path)) => Docker::compose(path),
+            (None, None) => {
+                if self.docker_label.is_some()
+                    || self.docker_port.is_some()
+                    || self.docker_host.is_some()
+                {
+                    return Err(Error::new(SourceError::Msg(
+                        "--docker-label, --docker-port, and --docker-host require --docker or --docker-compose"
+                            .to_string(),
+                    ))
+                    .set_kind(ErrorKind::Config)
+                    .print_help());
+                }
+                return Ok(None);
+            }
+        };
+        if let Some(label) = &self.docker_label {
+            docker = docker.label(label);
+        }
+        if let Some(port) = self.docker_port {
+            docker = docker.port(port);
+        }
+        if let Some(host) = &self.docker_host {
+            docker = docker.host(host);
+        }
+        Ok(Some(docker))
+    }
+
+    fn terraform_provider(&self, location: &str) -> Result<Terraform> {
+        let mut terraform = Terraform::new(location, &self.terraform_job, self.terraform_port);
+        for target in &self.terraform_target {
+            let Some((kind, attribute)) = target.split_once('.') else {
+                return Err(Error::new(SourceError::InvalidInputSource(format!(
+                    "Invalid --terraform-target '{}', expected TYPE.ATTR",
+                    target
+                )))
+                .set_kind(ErrorKind::Config));
+            };
+            terraform = terraform.target(kind, attribute);
+        }
+        Ok(terraform)
+    }
+
+    #[cfg(feature = "kubernetes")]
+    fn kubernetes_provider(&self, role: KubernetesRole) -> Result<Kubernetes> {
+        let mut kubernetes = match &self.kube_api {
+            Some(url) => Kubernetes::new(url, role)?,
+            None => Kubernetes::from_kubeconfig(
+                self.kubeconfig.as_deref(),
+                self.kube_context.as_deref(),
+                role,
+            )?,
+        };
+
+        if let Some(namespace) = &self.kube_namespace {
+            kubernetes = kubernetes.namespace(namespace);
+        }
+        if let Some(annotation) = &self.kube_annotation {
+            kubernetes = kubernetes.annotation(annotation);
+        }
+        Ok(kubernetes)
+    }
+
+    #[cfg(feature = "cloud")]
+    fn cloud_provider(&self, platform: CloudPlatform) -> Result<Cloud> {
+        let mut cloud = Cloud::new(platform, &self.cloud_job, self.cloud_port);
+        if let Some(scope) = &self.cloud_scope {
+            cloud = cloud.scope(scope);
+        }
+        for tag in &self.cloud_tag {
+            let Some((key, value)) = tag.split_once('=') else {
+                return Err(Error::new(SourceError::InvalidInputSource(format!(
+                    "Invalid --cloud-tag '{}', expected KEY=VALUE",
+                    tag
+                )))
+                .set_kind(ErrorKind::Config));
+            };
+            cloud = cloud.tag(key, value);
+        }
+        Ok(cloud)
+    }
+
+    #[cfg(feature = "sql")]
+    fn sql_provider(&self, url: &str) -> Result<Sql> {
+        let engine = match self.sql_engine {
+            Some(engine) => engine,
+            None if url.starts_with("postgres://") || url.starts_with("postgresql://") => {
+                SqlEngine::Postgres
+            }
+            None if url.starts_with("mysql://") || url.starts_with("mariadb://") => {
+                SqlEngine::Mysql
+            }
+            None => {
+                return Err(Error::new(SourceError::InvalidInputSource(format!(
+                    "Unknown database in '{}', use --sql-engine",
+                    url
+                )))
+                .set_kind(ErrorKind::Config));
+            }
+        };
+
+        let query = self.sql_query.as_deref().unwrap_or_default();
+        Ok(Sql::new(engine, url, query)
+            .job_column(&self.sql_job_column)
+            .target_column(&self.sql_target_column))
+    }
+
+    /// Check if any provider is enabled.
+    pub fn is_empty(&self) -> bool {
+        #[cfg(feature = "kubernetes")]
+        if self.kubernetes.is_some() {
+            return false;
+        }
+        #[cfg(feature = "cloud")]
+        if self.cloud.is_some() {
+            return false;
+        }
+        #[cfg(feature = "sql")]
+        if self.sql.is_some() {
+            return false;
+        }
+
+        self.consul.is_none()
+            && self.netbox.is_none()
+            && self.puppetdb.is_none()
+            && self.ldap.is_none()
+            && self.nmap.is_none()
+            && self.terraform.is_none()
+            && self.docker.is_none()
+            && self.docker_compose.is_none()
+            && self.zone.is_empty()
+            && self.srv.is_empty()
+    }
+}
+
+// Read an API token from the environment. An empty variable is the same as an unset one.
+fn env_token(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|token| !token.is_empty())
+}
@@ -0,0 +1,65 @@
+use crate::cli::{Args, SummaryFormat};
+use log::error;
+use pim::core::error::*;
+use serde::Serialize;
+use std::io::Write;
+
+/// What a run did to the target files, written as one line at the end of the run for wrapper
+/// scripts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RunSummary {
+    /// Target files created or replaced.
+    pub written: usize,
+    /// Target files left alone since they already had the generated contents.
+    pub unchanged: usize,
+    /// Unreachable targets left out by `check --prune-dead`.
+    pub pruned: usize,
+    pub errors: usize,
+}
+
+impl RunSummary {
+    /// Write the summary to the file descriptor of the arguments, if a summary was asked for.
+    /// A summary that can't be written is logged and doesn't change the outcome of the run.
+    pub fn emit(&self, args: &Args) {
+        let Some(SummaryFormat::Json) = args.summary() else {
+            return;
+        };
+        let line = match serde_json::to_string(self) {
+            Ok(json) => json + "\n",
+            Err(e) => {
+                error!("Failed to serialize run summary: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = write_fd(args.summary_fd(), line.as_bytes()) {
+            error!("{}", e);
+        }
+    }
+}
+
+// Write to an inherited file descriptor without closing it.
+fn write_fd(fd: i32, buf: &[u8]) -> Result<()> {
+    let written = match fd {
+        1 => std::io::stdout().write_all(buf),
+        2 => std::io::stderr().write_all(buf),
+        #[cfg(unix)]
+        fd => {
+            use std::os::fd::FromRawFd;
+            // The descriptor belongs to the parent process, so it is never closed here.
+            let mut file = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+            file.write_all(buf)
+        }
+        #[cfg(not(unix))]
+        fd => {
+            return Err(Error::new(SourceError::Msg(format!(
+                "--summary-fd {} is not supported, only 1 and 2 are",
+                fd
+            )))
+            .set_kind(ErrorKind::Config));
+        }
+    };
+    written.map_err(|e| {
+        Error::new(SourceError::Io(e))
+            .set_context(&format!("Failed to write run summary to fd {}", fd))
+    })
+}
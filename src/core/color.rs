@@ -0,0 +1,59 @@
+use std::ffi::OsString;
+
+/// When to color output, such as help, log lines, and diagnostics. Every colored output decides
+/// with `Color::enabled` so they all follow the same flag and environment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum Color {
+    /// Color terminals, unless `NO_COLOR` is set. `CLICOLOR_FORCE` colors other outputs too.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    /// Check if output to a stream should be colored, given whether the stream is a terminal.
+    pub fn enabled(&self, is_terminal: bool) -> bool {
+        self.enabled_with(is_terminal, |name| std::env::var_os(name))
+    }
+
+    // Decide with the environment variables given by `var`. A set, non-empty `NO_COLOR` turns
+    // color off, even with `CLICOLOR_FORCE`, and a `CLICOLOR_FORCE` other than `0` turns it on.
+    fn enabled_with(&self, is_terminal: bool, var: impl Fn(&str) -> Option<OsString>) -> bool {
+        match self {
+            Color::Always => true,
+            Color::Never => false,
+            Color::Auto if var("NO_COLOR").is_some_and(|v| !v.is_empty()) => false,
+            Color::Auto if var("CLICOLOR_FORCE").is_some_and(|v| !v.is_empty() && v != "0") => true,
+            Color::Auto => is_terminal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_enabled() {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| OsString::from(v))
+            }
+        };
+        assert!(Color::Auto.enabled_with(true, env(&[])));
+        assert!(!Color::Auto.enabled_with(false, env(&[])));
+        assert!(!Color::Auto.enabled_with(true, env(&[("NO_COLOR", "1")])));
+        assert!(Color::Auto.enabled_with(true, env(&[("NO_COLOR", "")])));
+        assert!(Color::Auto.enabled_with(false, env(&[("CLICOLOR_FORCE", "1")])));
+        assert!(!Color::Auto.enabled_with(false, env(&[("CLICOLOR_FORCE", "0")])));
+        let both = env(&[("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")]);
+        assert!(!Color::Auto.enabled_with(true, both));
+        assert!(Color::Always.enabled_with(false, env(&[("NO_COLOR", "1")])));
+        assert!(!Color::Never.enabled_with(true, env(&[("CLICOLOR_FORCE", "1")])));
+    }
+}
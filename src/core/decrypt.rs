@@ -0,0 +1,157 @@
+use crate::core::error::*;
+use crate::core::input::InputFormat;
+use log::debug;
+use std::{
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+};
+
+// Encrypted sources are decrypted with the sops and age CLIs so pim does not carry its own
+// cryptography. Both must be installed where pim runs to read encrypted sources.
+
+/// The first line of binary age files.
+const AGE_HEADER: &[u8] = b"age-encryption.org/";
+
+/// The first line of ASCII armored age files.
+const AGE_ARMOR_HEADER: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Check if content is encrypted with age, in the binary or ASCII armored format.
+pub fn is_age(content: &[u8]) -> bool {
+    content.starts_with(AGE_HEADER) || content.starts_with(AGE_ARMOR_HEADER)
+}
+
+/// Check if text is a document encrypted with SOPS, which has a `sops` map with a `mac`.
+pub fn is_sops(text: &str, format: &InputFormat) -> bool {
+    if !text.contains("sops") {
+        return false;
+    }
+
+    match format {
+        InputFormat::Json => serde_json::from_str::<serde_json::Value>(text)
+            .is_ok_and(|v| v.get("sops").and_then(|s| s.get("mac")).is_some()),
+        InputFormat::Yaml => serde_yaml::from_str::<serde_yaml::Value>(text)
+            .is_ok_and(|v| v.get("sops").and_then(|s| s.get("mac")).is_some()),
+        _ => false,
+    }
+}
+
+/// The age identity files used when none are given: `SOPS_AGE_KEY_FILE`, or the sops default
+/// `~/.config/sops/age/keys.txt` if it exists.
+pub fn default_identities() -> Vec<PathBuf> {
+    if let Some(path) = std::env::var_os("SOPS_AGE_KEY_FILE").filter(|p| !p.is_empty()) {
+        return vec![PathBuf::from(path)];
+    }
+
+    std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(".config/sops/age/keys.txt"))
+        .filter(|path| path.is_file())
+        .into_iter()
+        .collect()
+}
+
+/// Decrypt age encrypted content with the identity files.
+pub fn age(ciphertext: &[u8], identities: &[PathBuf]) -> Result<Vec<u8>> {
+    let identities = match identities.is_empty() {
+        true => default_identities(),
+        false => identities.to_vec(),
+    };
+    if identities.is_empty() {
+        return Err(Error::new(SourceError::Msg(
+            "No age identity to decrypt the source with, use --age-identity or SOPS_AGE_KEY_FILE"
+                .to_string(),
+        ))
+        .set_kind(ErrorKind::Config));
+    }
+
+    let mut command = Command::new("age");
+    command.arg("--decrypt");
+    for identity in &identities {
+        command.arg("--identity").arg(identity);
+    }
+    run(command, "age", ciphertext)
+}
+
+/// Decrypt a SOPS document and return its `sources` list as JSON. SOPS documents must be maps, so
+/// encrypted sources are kept under a `sources` key. The first identity file, if any, is passed
+/// to sops as `SOPS_AGE_KEY_FILE`. Other keys, such as cloud KMS or PGP keys, are found by sops.
+pub fn sops(text: &str, format: &InputFormat, identities: &[PathBuf]) -> Result<String> {
+    let mut command = Command::new("sops");
+    command.args([
+        "--decrypt",
+        "--input-type",
+        format.as_str(),
+        "--output-type",
+        "json",
+        "--extract",
+        r#"["sources"]"#,
+        "/dev/stdin",
+    ]);
+    if let Some(identity) = identities.first() {
+        command.env("SOPS_AGE_KEY_FILE", identity);
+    }
+    let json = run(command, "sops", text.as_bytes())?;
+    Ok(String::from_utf8_lossy(&json).into_owned())
+}
+
+// Run a command with input on stdin and return its stdout. Stdin is written from a thread so a
+// command writing a lot of output before reading all of its input can not deadlock.
+fn run(mut command: Command, program: &str, input: &[u8]) -> Result<Vec<u8>> {
+    debug!("Running {} to decrypt a source", program);
+    let io_error = |e| Error::new(SourceError::Io(e)).set_context(&format!("running {}", program));
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(io_error)?;
+
+    let writer = child.stdin.take().map(|mut stdin| {
+        let input = input.to_vec();
+        thread::spawn(move || stdin.write_all(&input))
+    });
+    let output = child.wait_with_output().map_err(io_error)?;
+    // A failed write means the command exited early, which its status and stderr explain.
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+
+    if !output.status.success() {
+        return Err(Error::new(SourceError::Msg(format!(
+            "{} exited with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+        .set_context("Failed to decrypt source")
+        .set_kind(ErrorKind::Io));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_is_age() {
+        assert!(is_age(b"age-encryption.org/v1\n-> X25519 abc\n"));
+        assert!(is_age(b"-----BEGIN AGE ENCRYPTED FILE-----\nYWdl\n"));
+        assert!(!is_age(b"- jobs: [node]\n"));
+    }
+
+    #[test]
+    fn test_is_sops() {
+        let yaml = "sources: ENC[AES256_GCM,data:abc]\nsops:\n  mac: ENC[AES256_GCM,data:def]\n  version: 3.9.0\n";
+        assert!(is_sops(yaml, &InputFormat::Yaml));
+        assert!(!is_sops(
+            "- jobs: [sops]\n  targets: [a]\n",
+            &InputFormat::Yaml
+        ));
+        let json = r#"{"sources": "ENC[...]", "sops": {"mac": "ENC[...]"}}"#;
+        assert!(is_sops(json, &InputFormat::Json));
+        assert!(!is_sops(json, &InputFormat::Hcl));
+    }
+}
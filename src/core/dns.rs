@@ -0,0 +1,307 @@
+use crate::core::error::*;
+use log::debug;
+use std::{
+    hash::{BuildHasher, Hasher},
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket},
+    time::Duration,
+};
+
+// A minimal DNS client for SRV lookups. Queries are sent over UDP and retried over TCP when the
+// response is truncated.
+
+/// Timeout for sending a query to and reading the response from a name server.
+pub const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// An SRV record. The target is lowercase without the trailing dot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+/// The first name server in /etc/resolv.conf. It is an error when the file is missing or has no
+/// name server, such as on Windows, rather than guessing one.
+pub fn nameserver() -> Result<SocketAddr> {
+    let conf = std::fs::read_to_string("/etc/resolv.conf").map_err(|e| {
+        Error::new(SourceError::Io(e))
+            .set_context("reading /etc/resolv.conf")
+            .set_context("No name server for SRV lookups. Set one with --srv-nameserver")
+            .set_kind(ErrorKind::Config)
+    })?;
+    parse_nameserver(&conf).ok_or_else(|| {
+        Error::new(SourceError::Msg(
+            "/etc/resolv.conf has no nameserver line".to_string(),
+        ))
+        .set_context("No name server for SRV lookups. Set one with --srv-nameserver")
+        .set_kind(ErrorKind::Config)
+    })
+}
+
+/// Look up the SRV records for `name` with the name server at `server`. Records with the `.`
+/// target, which mark a service as unavailable, are skipped. A name with no SRV records returns an
+/// empty list and a name that does not exist is an error.
+pub fn resolve_srv(name: &str, server: SocketAddr) -> Result<Vec<SrvRecord>> {
+    debug!("Resolving SRV records for {} with {}", name, server);
+    let id = query_id();
+    let query = encode_query(id, name)?;
+    let mut response = send_udp(&query, server).map_err(|e| io_error(e, name, server))?;
+    if response.get(2).is_some_and(|flags| flags & 0x02 != 0) {
+        debug!("SRV response for {} was truncated, retrying over TCP", name);
+        response = send_tcp(&query, server).map_err(|e| io_error(e, name, server))?;
+    }
+
+    parse_response(id, &response).map_err(|msg| {
+        Error::new(SourceError::Msg(format!(
+            "SRV lookup for {} with {} failed: {}",
+            name, server, msg
+        )))
+        .set_kind(ErrorKind::Network)
+    })
+}
+
+fn io_error(e: std::io::Error, name: &str, server: SocketAddr) -> Error {
+    Error::new(SourceError::Io(e))
+        .set_context(&format!("SRV lookup for {} with {}", name, server))
+        .set_kind(ErrorKind::Network)
+}
+
+fn parse_nameserver(conf: &str) -> Option<SocketAddr> {
+    conf.lines()
+        .filter_map(|line| line.strip_prefix("nameserver"))
+        .filter_map(|value| value.split_whitespace().next())
+        // Drop any IPv6 zone, such as `fe80::1%eth0`.
+        .filter_map(|value| value.split('%').next()?.parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .next()
+}
+
+// A random query ID, so spoofed responses can't guess it. `RandomState` is seeded from the
+// random source of the OS, and each one hashes differently.
+fn query_id() -> u16 {
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish() as u16
+}
+
+fn encode_query(id: u16, name: &str) -> Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(
+                Error::new(SourceError::Msg(format!("Invalid DNS name: {}", name)))
+                    .set_kind(ErrorKind::Config),
+            );
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_SRV.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn send_udp(query: &[u8], server: SocketAddr) -> std::io::Result<Vec<u8>> {
+    let bind: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(DNS_TIMEOUT))?;
+    socket.connect(server)?;
+    socket.send(query)?;
+    let mut buf = vec![0; 4096];
+    let len = socket.recv(&mut buf)?;
+    buf.truncate(len);
+    Ok(buf)
+}
+
+// TCP messages are prefixed with their length.
+fn send_tcp(query: &[u8], server: SocketAddr) -> std::io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(&server, DNS_TIMEOUT)?;
+    stream.set_read_timeout(Some(DNS_TIMEOUT))?;
+    stream.set_write_timeout(Some(DNS_TIMEOUT))?;
+    stream.write_all(&(query.len() as u16).to_be_bytes())?;
+    stream.write_all(query)?;
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn parse_response(id: u16, msg: &[u8]) -> std::result::Result<Vec<SrvRecord>, String> {
+    let short = || "response is truncated".to_string();
+    let header = msg.get(..12).ok_or_else(short)?;
+    if u16::from_be_bytes([header[0], header[1]]) != id {
+        return Err("response ID does not match the query".to_string());
+    }
+    match header[3] & 0x0f {
+        0 => {}
+        RCODE_NXDOMAIN => return Err("name does not exist".to_string()),
+        rcode => return Err(format!("server returned error code {}", rcode)),
+    }
+
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(msg, pos).ok_or_else(short)?.1 + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        pos = read_name(msg, pos).ok_or_else(short)?.1;
+        let fixed = msg.get(pos..pos + 10).ok_or_else(short)?;
+        let kind = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let start = pos + 10;
+        pos = start + len;
+        // Answers may include the CNAMEs followed to reach the SRV records.
+        if kind != TYPE_SRV {
+            continue;
+        }
+
+        let data = msg.get(start..pos).ok_or_else(short)?;
+        if data.len() < 7 {
+            return Err(short());
+        }
+        let (target, _) = read_name(msg, start + 6).ok_or_else(short)?;
+        if target.is_empty() {
+            continue;
+        }
+        records.push(SrvRecord {
+            priority: u16::from_be_bytes([data[0], data[1]]),
+            weight: u16::from_be_bytes([data[2], data[3]]),
+            port: u16::from_be_bytes([data[4], data[5]]),
+            target,
+        });
+    }
+
+    Ok(records)
+}
+
+// Read a possibly compressed name at `pos`. Returns the lowercase name without the trailing dot
+// and the position after the name.
+fn read_name(msg: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    // Each pointer must go backwards, so this bounds the loop on malformed messages.
+    let mut limit = pos;
+    loop {
+        let len = *msg.get(pos)? as usize;
+        match len {
+            0 => break,
+            len if len & 0xc0 == 0xc0 => {
+                let target = (len & 0x3f) << 8 | *msg.get(pos + 1)? as usize;
+                if target >= limit {
+                    return None;
+                }
+                end.get_or_insert(pos + 2);
+                limit = target;
+                pos = target;
+            }
+            len => {
+                let label = msg.get(pos + 1..pos + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).to_lowercase());
+                pos += 1 + len;
+            }
+        }
+    }
+
+    Some((labels.join("."), end.unwrap_or(pos + 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    // A response to a query for _node._tcp.example.com with two SRV records, one using a
+    // compressed target, and one unavailable `.` record.
+    fn response(id: u16) -> Vec<u8> {
+        let mut msg = encode_query(id, "_node._tcp.example.com").unwrap();
+        msg[2] = 0x81;
+        msg[3] = 0x80;
+        msg[7] = 3;
+        let answers: [(&[u8], &[u8]); 3] = [
+            (
+                &[0, 10, 0, 5, 0x23, 0x8c],
+                b"\x04web1\x07example\x03com\x00",
+            ),
+            (&[0, 20, 0, 0, 0x23, 0x8d], b"\x04WEB2\xc0\x17"),
+            (&[0, 0, 0, 0, 0, 0], b"\x00"),
+        ];
+        for (fixed, target) in answers {
+            // Owner name pointing at the question, SRV, IN, a TTL of 60.
+            msg.extend_from_slice(&[0xc0, 0x0c, 0, 33, 0, 1, 0, 0, 0, 60]);
+            msg.extend_from_slice(&((fixed.len() + target.len()) as u16).to_be_bytes());
+            msg.extend_from_slice(fixed);
+            msg.extend_from_slice(target);
+        }
+        msg
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let records = parse_response(7, &response(7)).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                SrvRecord {
+                    priority: 10,
+                    weight: 5,
+                    port: 9100,
+                    target: "web1.example.com".to_string()
+                },
+                SrvRecord {
+                    priority: 20,
+                    weight: 0,
+                    port: 9101,
+                    target: "web2.example.com".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_response_errors() {
+        assert!(parse_response(8, &response(7)).is_err());
+        let mut msg = response(7);
+        msg[3] = 0x83;
+        assert_eq!(
+            parse_response(7, &msg).unwrap_err(),
+            "name does not exist".to_string()
+        );
+        let msg = response(7);
+        assert!(parse_response(7, &msg[..msg.len() - 3]).is_err());
+        assert!(encode_query(1, "bad..name").is_err());
+    }
+
+    #[test]
+    fn test_parse_nameserver() {
+        let conf =
+            "# generated\nsearch example.com\nnameserver fe80::1%eth0\nnameserver 10.0.0.2\n";
+        assert_eq!(
+            parse_nameserver(conf),
+            Some("[fe80::1]:53".parse().unwrap())
+        );
+        assert_eq!(parse_nameserver("search example.com\n"), None);
+    }
+
+    #[test]
+    fn test_query_id() {
+        let ids: std::collections::HashSet<u16> = (0..16).map(|_| query_id()).collect();
+        assert!(ids.len() > 1);
+    }
+}
@@ -11,9 +11,71 @@ pub const CODE_RUNTIME_ERROR: i32 = 1;
 /// Exit code for when the command-line options are invalid.
 pub const CODE_OPTIONS_ERROR: i32 = 3;
 
+/// Exit code for when writing the output failed and the files written were restored to their
+/// state before the run.
+pub const CODE_ROLLED_BACK: i32 = 4;
+
+/// Exit code for when `--continue-on-error` wrote the target files of some jobs and others
+/// failed.
+pub const CODE_PARTIAL_SUCCESS: i32 = 5;
+
 /// Exit code for missing file permissions
 pub const CODE_PERMISSION_DENIED: i32 = 13;
 
+// The BSD sysexits(3) codes, for schedulers and service managers that act on them.
+/// The command was used incorrectly, such as with invalid options.
+pub const EX_USAGE: i32 = 64;
+
+/// The input data was incorrect, such as a source that can't be parsed or is invalid.
+pub const EX_DATAERR: i32 = 65;
+
+/// An input file did not exist or was not readable.
+pub const EX_NOINPUT: i32 = 66;
+
+/// A service is unavailable, such as a remote service that can't be reached.
+pub const EX_UNAVAILABLE: i32 = 69;
+
+/// An internal software error was detected.
+pub const EX_SOFTWARE: i32 = 70;
+
+/// An output file can't be created.
+pub const EX_CANTCREAT: i32 = 73;
+
+/// An error occurred while doing I/O on some file.
+pub const EX_IOERR: i32 = 74;
+
+/// What kind of failure an error is. Each kind has an exit code, used unless the error sets one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A source or other input couldn't be read in its format.
+    Parse,
+    /// The input was read but is invalid, such as sources that break a rule or a limit.
+    Validation,
+    /// Reading or writing files or streams, or running an external command, failed.
+    Io,
+    /// The command line options or the configuration are invalid.
+    Config,
+    /// A remote service couldn't be reached or replied with an error.
+    Network,
+    /// Anything else, such as a bug in pim.
+    Internal,
+}
+
+impl ErrorKind {
+    /// The exit code for errors of this kind.
+    pub fn code(self) -> i32 {
+        match self {
+            ErrorKind::Config => CODE_OPTIONS_ERROR,
+            ErrorKind::Parse
+            | ErrorKind::Validation
+            | ErrorKind::Io
+            | ErrorKind::Network
+            | ErrorKind::Internal => CODE_RUNTIME_ERROR,
+        }
+    }
+}
+
 // Barrowed heavily from bat because I'm still learning.
 
 #[derive(Error, Debug)]
@@ -25,6 +87,8 @@ pub enum SourceError {
     SerdeJson(#[from] ::serde_json::Error),
     #[error(transparent)]
     SerdeYaml(#[from] ::serde_yaml::Error),
+    #[error(transparent)]
+    Json5(#[from] ::json5::Error),
     #[error("Unsupported input format: {0}")]
     UnsupportedInputFormat(String),
     #[error("Unsupported output format: {0}")]
@@ -35,6 +99,31 @@ pub enum SourceError {
     Msg(String),
 }
 
+impl SourceError {
+    /// The kind of errors with this source, unless they are given another.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            SourceError::Io(_) => ErrorKind::Io,
+            SourceError::SerdeJson(_) | SourceError::SerdeYaml(_) | SourceError::Json5(_) => {
+                ErrorKind::Parse
+            }
+            _ => ErrorKind::Internal,
+        }
+    }
+
+    /// The line and column, both starting at 1, that a parsing error points at, if it has one.
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            SourceError::SerdeJson(e) if e.line() > 0 => Some((e.line(), e.column().max(1))),
+            SourceError::SerdeYaml(e) => e.location().map(|l| (l.line(), l.column())),
+            SourceError::Json5(::json5::Error::Message {
+                location: Some(l), ..
+            }) => Some((l.line, l.column)),
+            _ => None,
+        }
+    }
+}
+
 impl From<&'static str> for SourceError {
     fn from(s: &'static str) -> Self {
         SourceError::Msg(s.to_owned())
@@ -50,18 +139,35 @@ impl From<String> for SourceError {
 #[derive(Debug)]
 pub struct Error {
     code: Option<i32>,
-    context: String,
+    kind: ErrorKind,
+    // What was being done when the error happened, from the innermost to the outermost.
+    context: Vec<String>,
+    // Where in a source file the error is, with an excerpt of the file, printed after the error.
+    location: String,
     print_help: bool,
+    // The error happened writing output, such as creating target files, rather than reading input.
+    output: bool,
     source: SourceError,
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.context.is_empty() {
-            write!(f, "{}", self.source)
-        } else {
-            write!(f, "{}\n{}", self.context, self.source)
+        // The outermost context comes first, then what caused it down to the source error.
+        let mut chain = self.context.iter().rev();
+        match chain.next() {
+            Some(context) => {
+                write!(f, "{}", context)?;
+                for context in chain {
+                    write!(f, "\ncaused by: {}", context)?;
+                }
+                write!(f, "\ncaused by: {}", self.source)?;
+            }
+            None => write!(f, "{}", self.source)?,
         }
+        if !self.location.is_empty() {
+            write!(f, "\n{}", self.location)?;
+        }
+        Ok(())
     }
 }
 
@@ -75,14 +181,52 @@ impl Error {
     pub fn new(source: SourceError) -> Self {
         Error {
             code: None,
-            context: String::new(),
+            kind: source.kind(),
+            context: Vec::new(),
+            location: String::new(),
             print_help: false,
+            output: false,
             source,
         }
     }
 
-    pub fn code(&self) -> Option<i32> {
-        self.code
+    /// The exit code for the error: the one it was given, or the one for its kind.
+    pub fn code(&self) -> i32 {
+        self.code.unwrap_or_else(|| self.kind.code())
+    }
+
+    /// The BSD sysexits(3) code for the error, from its kind, or from its code for target files
+    /// that couldn't be written. Partly successful runs have no sysexits code and keep theirs.
+    /// Missing or unreadable files are input errors, unless the error happened writing output.
+    pub fn sysexit(&self) -> i32 {
+        use std::io::ErrorKind as Io;
+        match (self.code, self.kind, &self.source) {
+            (Some(CODE_PARTIAL_SUCCESS), _, _) => CODE_PARTIAL_SUCCESS,
+            (Some(CODE_PERMISSION_DENIED | CODE_ROLLED_BACK), _, _) => EX_CANTCREAT,
+            (_, ErrorKind::Config, _) => EX_USAGE,
+            (_, ErrorKind::Parse | ErrorKind::Validation, _) => EX_DATAERR,
+            (_, ErrorKind::Io, SourceError::Io(e))
+                if self.output
+                    && matches!(
+                        e.kind(),
+                        Io::NotFound
+                            | Io::PermissionDenied
+                            | Io::ReadOnlyFilesystem
+                            | Io::NotADirectory
+                            | Io::IsADirectory
+                    ) =>
+            {
+                EX_CANTCREAT
+            }
+            (_, ErrorKind::Io, SourceError::Io(e))
+                if matches!(e.kind(), Io::NotFound | Io::PermissionDenied) =>
+            {
+                EX_NOINPUT
+            }
+            (_, ErrorKind::Io, _) => EX_IOERR,
+            (_, ErrorKind::Network, _) => EX_UNAVAILABLE,
+            (_, ErrorKind::Internal, _) => EX_SOFTWARE,
+        }
     }
 
     pub fn mut_code(&mut self, code: i32) {
@@ -99,25 +243,44 @@ impl Error {
         self
     }
 
-    pub fn context(&self) -> &str {
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn set_kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// The context chain, from the innermost context to the outermost.
+    pub fn context(&self) -> &[String] {
         &self.context
     }
 
+    /// Add an outer context, describing what was being done when the error happened. Empty
+    /// contexts are ignored.
     pub fn mut_context(&mut self, context: &str) {
-        if !self.context.is_empty() {
-            self.context = format!("{}\n{}", context, self.context);
-        } else {
-            self.context = context.to_owned();
+        if !context.is_empty() {
+            self.context.push(context.to_owned());
         }
     }
 
     pub fn set_context(mut self, context: &str) -> Self {
-        if !context.is_empty() {
-            self.context = context.to_owned();
-        } else {
-            self.context = format!("{}\n{}", context, self.context);
-        }
+        self.mut_context(context);
+        self
+    }
 
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+
+    /// Point the error at the line and column of `text`, read from the file `name`, that its
+    /// parsing error reports, with the line before it and a caret under the column. Errors without
+    /// a location are left as they are.
+    pub fn locate(mut self, name: &str, text: &str) -> Self {
+        if let Some((line, column)) = self.source.location() {
+            self.location = excerpt(name, text, line, column);
+        }
         self
     }
 
@@ -135,6 +298,23 @@ impl Error {
         self
     }
 
+    /// Whether the error happened writing output, such as creating target files.
+    pub fn is_output(&self) -> bool {
+        self.output
+    }
+
+    /// Mark the error as happening while writing output, so a missing or unwritable path is
+    /// reported as output that can't be created rather than input that can't be read.
+    pub fn set_output(mut self) -> Self {
+        self.output = true;
+        self
+    }
+
+    /// Whether the error is a write to a pipe whose reader went away.
+    pub fn is_broken_pipe(&self) -> bool {
+        matches!(&self.source, SourceError::Io(e) if e.kind() == std::io::ErrorKind::BrokenPipe)
+    }
+
     pub fn source(&self) -> &SourceError {
         &self.source
     }
@@ -146,17 +326,53 @@ impl Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub fn handle_error(error: &Error) {
+// Render where an error is in a file like compilers do: the path with the line and column, the
+// line before and the line itself, and a caret under the column.
+fn excerpt(name: &str, text: &str, line: usize, column: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let line = line.clamp(1, lines.len().max(1));
+    let width = line.to_string().len();
+    let mut out = format!("{:width$}--> {}:{}:{}\n", "", name, line, column);
+    out.push_str(&format!("{:width$} |", ""));
+    for number in line.saturating_sub(1).max(1)..=line {
+        let text = lines.get(number - 1).copied().unwrap_or_default();
+        out.push_str(&format!("\n{:>width$} | {}", number, text));
+    }
+    // Tabs are kept so the caret lines up however wide they are shown.
+    let text = lines.get(line - 1).copied().unwrap_or_default();
+    let pad: String = text
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { c } else { ' ' })
+        .collect();
+    out.push_str(&format!("\n{:width$} | {}^", "", pad));
+    out
+}
+
+/// How `handle_error` classified an error, for the program to decide how to exit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handled {
+    /// The reader of the output went away, such as `head` in `pim | head`. It has all the output
+    /// it wants, so this isn't a failure.
+    BrokenPipe,
+    /// The error was logged as a failure.
+    Failed,
+}
+
+/// Log an error and classify it. Nothing is exited, which is left to the program.
+pub fn handle_error(error: &Error) -> Handled {
     match &error.source {
-        SourceError::Io(io_err) if io_err.kind() == std::io::ErrorKind::BrokenPipe => {
+        SourceError::Io(io_err) if error.is_broken_pipe() => {
             warn!("Broken pipe encountered: {}", io_err);
-            ::std::process::exit(0);
+            Handled::BrokenPipe
         }
         SourceError::SerdeJson(_) | SourceError::SerdeYaml(_) => {
-            error!("Error while parsing file: {error}")
+            error!("Error while parsing file: {error}");
+            Handled::Failed
         }
         _ => {
-            error!("{error}")
+            error!("{error}");
+            Handled::Failed
         }
     }
 }
@@ -209,7 +425,7 @@ mod tests {
             .set_context("additional context")
             .print_help()
             .set_code(42);
-        assert_eq!(err.context, "additional context");
+        assert_eq!(err.context, vec!["additional context"]);
         assert!(err.print_help);
         assert_eq!(err.code, Some(42));
     }
@@ -223,26 +439,86 @@ mod tests {
         assert!(display_str.contains("source error"));
     }
 
+    #[test]
+    fn test_error_context_chain() {
+        let err = Error::new(SourceError::Msg("source error".to_string()))
+            .set_context("reading file")
+            .set_context("")
+            .set_context("loading sources");
+        assert_eq!(err.context(), ["reading file", "loading sources"]);
+        assert_eq!(
+            err.to_string(),
+            "loading sources\ncaused by: reading file\ncaused by: source error"
+        );
+
+        let mut err = Error::new(SourceError::Msg("source error".to_string()));
+        assert_eq!(err.to_string(), "source error");
+        err.mut_context("reading file");
+        assert_eq!(err.to_string(), "reading file\ncaused by: source error");
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let err = Error::new(SourceError::Io(std::io::Error::other("io error")));
+        assert_eq!(err.kind(), ErrorKind::Io);
+        assert_eq!(err.code(), CODE_RUNTIME_ERROR);
+        let e = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(
+            Error::new(SourceError::SerdeJson(e)).kind(),
+            ErrorKind::Parse
+        );
+
+        let err =
+            Error::new(SourceError::Msg("bad option".to_string())).set_kind(ErrorKind::Config);
+        assert_eq!(err.code(), CODE_OPTIONS_ERROR);
+        let err = err.set_code(CODE_PERMISSION_DENIED);
+        assert_eq!(err.kind(), ErrorKind::Config);
+        assert_eq!(err.code(), CODE_PERMISSION_DENIED);
+        assert_eq!(err.unset_code().code(), CODE_OPTIONS_ERROR);
+    }
+
+    #[test]
+    fn test_error_sysexit() {
+        let not_found = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert_eq!(Error::new(SourceError::Io(not_found)).sysexit(), EX_NOINPUT);
+        let broken = std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+        assert_eq!(Error::new(SourceError::Io(broken)).sysexit(), EX_IOERR);
+        let e = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(Error::new(SourceError::SerdeJson(e)).sysexit(), EX_DATAERR);
+
+        let msg = |kind| Error::new(SourceError::Msg("error".to_string())).set_kind(kind);
+        assert_eq!(msg(ErrorKind::Config).sysexit(), EX_USAGE);
+        assert_eq!(msg(ErrorKind::Validation).sysexit(), EX_DATAERR);
+        assert_eq!(msg(ErrorKind::Network).sysexit(), EX_UNAVAILABLE);
+        assert_eq!(msg(ErrorKind::Internal).sysexit(), EX_SOFTWARE);
+        let rolled_back = msg(ErrorKind::Io).set_code(CODE_ROLLED_BACK);
+        assert_eq!(rolled_back.sysexit(), EX_CANTCREAT);
+
+        let denied = || std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert_eq!(Error::new(SourceError::Io(denied())).sysexit(), EX_NOINPUT);
+        let output = Error::new(SourceError::Io(denied())).set_output();
+        assert_eq!(output.sysexit(), EX_CANTCREAT);
+        let broken = std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+        assert_eq!(
+            Error::new(SourceError::Io(broken)).set_output().sysexit(),
+            EX_IOERR
+        );
+    }
+
     #[test]
     fn test_handle_error_broken_pipe() {
         let io_error = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "broken pipe");
-        let err = Error::new(SourceError::Io(io_error));
-        // This will exit the process, so we can't actually call it in a test.
-        // handle_error(&err);
-        // Instead, we just ensure it matches the broken pipe case.
-        match &err.source {
-            SourceError::Io(io_err) if io_err.kind() == std::io::ErrorKind::BrokenPipe => {}
-            _ => panic!("Expected broken pipe error"),
-        }
+        let err = Error::new(SourceError::Io(io_error)).set_context("Writing to stdout");
+        assert!(err.is_broken_pipe());
+        assert_eq!(handle_error(&err), Handled::BrokenPipe);
     }
 
     #[test]
     fn test_handle_error_other() {
         let source_err = SourceError::Msg("some error".to_string());
         let err = Error::new(source_err);
-        // This will log the error, so we can't easily test the logging output here.
-        // handle_error(&err);
-        // Instead, we just ensure it matches the other case.
+        assert!(!err.is_broken_pipe());
+        assert_eq!(handle_error(&err), Handled::Failed);
         match &err.source {
             SourceError::Msg(msg) if msg == "some error" => {}
             _ => panic!("Expected some error message"),
@@ -257,4 +533,24 @@ mod tests {
         let result = example_function();
         assert_eq!(result.unwrap(), 42);
     }
+
+    #[test]
+    fn test_locate() {
+        let text = "[\n  {\"targets\": [\"a\"],\n   \"labels\": {\"x\" 1}}\n]\n";
+        let e = serde_json::from_str::<serde_json::Value>(text).unwrap_err();
+        let err = Error::new(SourceError::SerdeJson(e)).locate("a.json", text);
+        assert_eq!(
+            err.location(),
+            " --> a.json:3:19\n  |\n2 |   {\"targets\": [\"a\"],\n3 |    \"labels\": {\"x\" 1}}\n  |                   ^"
+        );
+        assert!(err.to_string().ends_with(err.location()));
+
+        let e = serde_yaml::from_str::<serde_yaml::Value>("\tkey: [").unwrap_err();
+        let err = Error::new(SourceError::SerdeYaml(e)).locate("a.yaml", "\tkey: [");
+        assert!(err.location().starts_with(" --> a.yaml:1:"));
+
+        let err = Error::new(SourceError::Msg("no location".to_string())).locate("a", "text");
+        assert_eq!(err.location(), "");
+        assert_eq!(err.to_string(), "no location");
+    }
 }
@@ -0,0 +1,165 @@
+use crate::app::target::{ID_LABEL, TargetGroup, is_internal_label};
+use crate::core::InputFormat;
+use crate::core::error::*;
+use std::net::TcpListener;
+use std::path::Path;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+
+// The gRPC API of serve mode. The messages and service trait are generated from
+// proto/pim.proto, and the service itself is implemented by the pim binary.
+
+/// Messages and the `Pim` service trait generated from `proto/pim.proto`.
+pub mod pb {
+    include!("pim.v1.rs");
+}
+
+/// Load the TLS settings of the gRPC server from the same PEM files as
+/// `tls::server_config`. With `client_ca`, clients must present a certificate signed by one of
+/// its CAs.
+pub fn tls_config(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<ServerTlsConfig> {
+    // Load the files with rustls first, so bad files fail here with the same errors as HTTPS
+    // rather than when the server starts.
+    crate::core::tls::server_config(cert, key, client_ca)?;
+
+    let config = ServerTlsConfig::new().identity(Identity::from_pem(read(cert)?, read(key)?));
+    Ok(match client_ca {
+        Some(path) => config.client_ca_root(Certificate::from_pem(read(path)?)),
+        None => config,
+    })
+}
+
+/// Serve the service on the listener until the server fails. The server runs on its own
+/// runtime, so call this on a thread of its own.
+pub fn serve<S>(listener: TcpListener, service: S, tls: Option<ServerTlsConfig>) -> Result<()>
+where
+    S: pb::pim_server::Pim,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context("Failed to start the gRPC runtime")
+                .set_kind(ErrorKind::Internal)
+        })?;
+
+    runtime.block_on(async {
+        let listener = listener
+            .set_nonblocking(true)
+            .and_then(|_| tokio::net::TcpListener::from_std(listener))
+            .map_err(|e| {
+                Error::new(SourceError::Io(e))
+                    .set_context("Failed to set up the gRPC listener")
+                    .set_kind(ErrorKind::Network)
+            })?;
+
+        let mut server = Server::builder();
+        if let Some(tls) = tls {
+            server = server
+                .tls_config(tls)
+                .map_err(|e| server_error("Invalid TLS settings", e))?;
+        }
+        server
+            .add_service(pb::pim_server::PimServer::new(service))
+            .serve_with_incoming(TcpIncoming::from(listener))
+            .await
+            .map_err(|e| server_error("gRPC server failed", e))
+    })
+}
+
+/// Convert a target group to its message, with its id as the `ID_LABEL` label like in target
+/// files. Internal labels are left out when `strip_internal_labels` is set.
+pub fn target_group(group: &TargetGroup, strip_internal_labels: bool) -> pb::TargetGroup {
+    let mut labels = group.labels().clone();
+    if let Some(id) = group.id() {
+        labels.insert(ID_LABEL.to_string(), id.to_string());
+    }
+    if strip_internal_labels {
+        labels.retain(|name, _| !is_internal_label(name));
+    }
+
+    pb::TargetGroup {
+        job: group.jobs().clone(),
+        labels: labels.into_iter().collect(),
+        targets: group.targets().clone(),
+    }
+}
+
+/// Unspecified formats are YAML, like pushed sources without a JSON `Content-Type`.
+impl From<pb::SourceFormat> for InputFormat {
+    fn from(format: pb::SourceFormat) -> Self {
+        match format {
+            pb::SourceFormat::Json => InputFormat::Json,
+            pb::SourceFormat::Json5 => InputFormat::Json5,
+            pb::SourceFormat::Hcl => InputFormat::Hcl,
+            pb::SourceFormat::Yaml | pb::SourceFormat::Unspecified => InputFormat::Yaml,
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| {
+        Error::new(SourceError::Io(e))
+            .set_context(&format!("Failed to read {}", path.display()))
+            .set_kind(ErrorKind::Config)
+    })
+}
+
+fn server_error(context: &str, e: tonic::transport::Error) -> Error {
+    Error::new(SourceError::Msg(e.to_string()))
+        .set_context(context)
+        .set_kind(ErrorKind::Network)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_target_group() {
+        let labels = BTreeMap::from([
+            ("__meta_zone".to_string(), "a".to_string()),
+            ("env".to_string(), "prod".to_string()),
+        ]);
+        let group = TargetGroup::new("node", labels, vec!["web1:9100".to_string()])
+            .with_id(Some("web".to_string()));
+
+        let message = target_group(&group, false);
+        assert_eq!(message.job, "node");
+        assert_eq!(message.targets, vec!["web1:9100"]);
+        let mut names: Vec<&str> = message.labels.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["__meta_zone", ID_LABEL, "env", "job"]);
+        assert_eq!(message.labels[ID_LABEL], "web");
+
+        let message = target_group(&group, true);
+        let mut names: Vec<&str> = message.labels.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["env", "job"]);
+    }
+
+    #[test]
+    fn test_input_format() {
+        assert_eq!(
+            InputFormat::from(pb::SourceFormat::Unspecified),
+            InputFormat::Yaml
+        );
+        assert_eq!(InputFormat::from(pb::SourceFormat::Json), InputFormat::Json);
+        assert_eq!(InputFormat::from(pb::SourceFormat::Hcl), InputFormat::Hcl);
+    }
+
+    #[test]
+    fn test_tls_config() {
+        let dir = std::env::temp_dir().join(format!("pim-grpc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = dir.join("cert.pem");
+        std::fs::write(&cert, "not a certificate\n").unwrap();
+
+        let e = tls_config(&cert, &dir.join("key.pem"), None).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Config);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
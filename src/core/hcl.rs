@@ -0,0 +1,353 @@
+use crate::core::error::*;
+use serde_json::{Map, Number, Value};
+
+// Only the HCL used for configuration files is supported: attributes, blocks, and literal values.
+// Expressions such as variables, functions, interpolation, and heredocs are errors.
+
+/// Parse an HCL body into a JSON object.
+///
+/// Attributes become keys with their values. Blocks are collected into a list under their type,
+/// so `source { ... }` twice becomes `{"source": [{...}, {...}]}`. Block labels, such as `web` in
+/// `source "web" { ... }`, only name the block and are dropped.
+pub fn to_value(text: &str) -> Result<Value> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    let body = parser.body(false)?;
+    Ok(Value::Object(body))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    // Parse attributes and blocks until the end of the text, or the closing brace of a block.
+    fn body(&mut self, nested: bool) -> Result<Map<String, Value>> {
+        let mut body = Map::new();
+        loop {
+            self.skip();
+            match self.peek() {
+                None if nested => return Err(self.invalid("unclosed block")),
+                None => return Ok(body),
+                Some('}') if nested => {
+                    self.pos += 1;
+                    return Ok(body);
+                }
+                _ => {}
+            }
+
+            let line = self.line;
+            let name = self.identifier()?;
+            self.skip();
+            if self.peek() == Some('=') {
+                self.pos += 1;
+                let value = self.value()?;
+                if body.insert(name.clone(), value).is_some() {
+                    return Err(invalid(line, &format!("duplicate attribute '{}'", name)));
+                }
+                continue;
+            }
+
+            // Block labels before the opening brace.
+            loop {
+                self.skip();
+                match self.peek() {
+                    Some('{') => break,
+                    Some('"') => {
+                        self.string()?;
+                    }
+                    Some(c) if is_identifier(c) => {
+                        self.identifier()?;
+                    }
+                    _ => {
+                        return Err(self.invalid(&format!("expected '=' or '{{' after '{}'", name)));
+                    }
+                }
+            }
+            self.pos += 1;
+            let block = Value::Object(self.body(true)?);
+            match body
+                .entry(name.clone())
+                .or_insert_with(|| Value::Array(Vec::new()))
+            {
+                Value::Array(blocks) => blocks.push(block),
+                _ => {
+                    return Err(invalid(
+                        line,
+                        &format!("'{}' is an attribute and a block", name),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        self.skip();
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.string()?)),
+            Some('[') => {
+                self.pos += 1;
+                let mut list = Vec::new();
+                loop {
+                    self.skip();
+                    match self.peek() {
+                        Some(']') => break,
+                        Some(',') if !list.is_empty() => self.pos += 1,
+                        _ => list.push(self.value()?),
+                    }
+                }
+                self.pos += 1;
+                Ok(Value::Array(list))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut object = Map::new();
+                loop {
+                    self.skip();
+                    match self.peek() {
+                        Some('}') => break,
+                        Some(',') if !object.is_empty() => {
+                            self.pos += 1;
+                            continue;
+                        }
+                        Some('"') => {
+                            let key = self.string()?;
+                            object.insert(key, self.object_value()?);
+                        }
+                        _ => {
+                            let key = self.identifier()?;
+                            object.insert(key, self.object_value()?);
+                        }
+                    }
+                }
+                self.pos += 1;
+                Ok(Value::Object(object))
+            }
+            Some(c) if c.is_ascii_digit() || c == '-' => self.number(),
+            Some(c) if is_identifier(c) => {
+                let word = self.identifier()?;
+                match word.as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    "null" => Ok(Value::Null),
+                    _ => Err(self.invalid(&format!(
+                        "'{}' is not supported, only literal values are",
+                        word
+                    ))),
+                }
+            }
+            Some('<') => Err(self.invalid("heredocs are not supported")),
+            Some(c) => Err(self.invalid(&format!("unexpected '{}'", c))),
+            None => Err(self.invalid("expected a value")),
+        }
+    }
+
+    // Object keys are followed by `=` or `:`.
+    fn object_value(&mut self) -> Result<Value> {
+        self.skip();
+        match self.peek() {
+            Some('=' | ':') => {
+                self.pos += 1;
+                self.value()
+            }
+            _ => Err(self.invalid("expected '=' or ':' after object key")),
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String> {
+        let start = self.pos;
+        while self.peek().is_some_and(is_identifier) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(match self.peek() {
+                Some(c) => self.invalid(&format!("unexpected '{}'", c)),
+                None => self.invalid("unexpected end of file"),
+            });
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let line = self.line;
+        let mut s = String::new();
+        self.pos += 1;
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(invalid(line, "unclosed string"));
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\n' => return Err(invalid(line, "unclosed string")),
+                '$' | '%' if self.peek() == Some('{') => {
+                    return Err(self.invalid("template interpolation is not supported"));
+                }
+                // `$${` and `%%{` are literal `${` and `%{`.
+                '$' | '%'
+                    if self.peek() == Some(c) && self.chars.get(self.pos + 1) == Some(&'{') =>
+                {
+                    s.push(c);
+                    self.pos += 1;
+                }
+                '\\' => {
+                    let e = self
+                        .peek()
+                        .ok_or_else(|| invalid(line, "unclosed string"))?;
+                    self.pos += 1;
+                    match e {
+                        'n' => s.push('\n'),
+                        'r' => s.push('\r'),
+                        't' => s.push('\t'),
+                        '"' | '\\' => s.push(e),
+                        'u' | 'U' => {
+                            let len = if e == 'u' { 4 } else { 8 };
+                            let hex: String = self
+                                .chars
+                                .get(self.pos..self.pos + len)
+                                .unwrap_or_default()
+                                .iter()
+                                .collect();
+                            let c = u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.invalid("invalid unicode escape"))?;
+                            s.push(c);
+                            self.pos += len;
+                        }
+                        e => return Err(self.invalid(&format!("invalid escape '\\{}'", e))),
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value> {
+        let start = self.pos;
+        self.pos += 1;
+        while self.peek().is_some_and(|c| {
+            c.is_ascii_alphanumeric()
+                || c == '.'
+                || (matches!(c, '+' | '-') && matches!(self.chars[self.pos - 1], 'e' | 'E'))
+        }) {
+            self.pos += 1;
+        }
+
+        let word: String = self.chars[start..self.pos].iter().collect();
+        let number = match word.parse::<i64>() {
+            Ok(n) => Some(Number::from(n)),
+            Err(_) => word.parse::<f64>().ok().and_then(Number::from_f64),
+        };
+        number
+            .map(Value::Number)
+            .ok_or_else(|| self.invalid(&format!("invalid number '{}'", word)))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    // Skip whitespace and comments. Newlines are not significant since only literal values are
+    // supported.
+    fn skip(&mut self) {
+        while let Some(c) = self.peek() {
+            let next = self.chars.get(self.pos + 1).copied();
+            if c == '#' || (c == '/' && next == Some('/')) {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+            } else if c == '/' && next == Some('*') {
+                self.pos += 2;
+                while self.pos < self.chars.len()
+                    && !(self.chars[self.pos] == '*' && self.chars.get(self.pos + 1) == Some(&'/'))
+                {
+                    if self.chars[self.pos] == '\n' {
+                        self.line += 1;
+                    }
+                    self.pos += 1;
+                }
+                self.pos += 2;
+            } else if c.is_whitespace() {
+                if c == '\n' {
+                    self.line += 1;
+                }
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn invalid(&self, msg: &str) -> Error {
+        invalid(self.line, msg)
+    }
+}
+
+fn is_identifier(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+fn invalid(line: usize, msg: &str) -> Error {
+    Error::new(SourceError::Msg(format!(
+        "Invalid HCL at line {}: {}",
+        line, msg
+    )))
+    .set_kind(ErrorKind::Parse)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_to_value() {
+        let hcl = r#"# Web servers
+source "web" {
+  jobs = ["node", "blackbox",]
+  labels = {
+    env    = "prod"
+    "team" : "web", tier = "front!"
+  }
+  targets = [
+    "web1:9100", // first
+    "web2:9100"
+  ]
+  /* tuning */
+  weight = -1.5e2
+  enabled = true
+}
+
+source {
+  targets = ["cost: $${price}"]
+}
+"#;
+        let value = to_value(hcl).unwrap();
+        let sources = value["source"].as_array().unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0]["jobs"][1], "blackbox");
+        assert_eq!(sources[0]["labels"]["team"], "web");
+        assert_eq!(sources[0]["labels"]["tier"], "front!");
+        assert_eq!(sources[0]["targets"][1], "web2:9100");
+        assert_eq!(sources[0]["weight"], -150.0);
+        assert_eq!(sources[0]["enabled"], true);
+        assert_eq!(sources[1]["targets"][0], "cost: ${price}");
+    }
+
+    #[test]
+    fn test_to_value_errors() {
+        let err = to_value("source {\n  jobs = [var.job]\n}").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+        assert!(to_value("source {\n  jobs = [\"${job}\"]\n}").is_err());
+        assert!(to_value("source {\n  jobs = []\n").is_err());
+        assert!(to_value("a = 1\na = 2").is_err());
+        assert!(to_value("a = 1\na {}").is_err());
+        assert!(to_value("source [").is_err());
+    }
+}
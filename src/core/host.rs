@@ -0,0 +1,35 @@
+/// Split a target into its host and port. Schemes and paths, as in blackbox targets, are dropped
+/// and IPv6 addresses lose their brackets.
+pub fn split_target(target: &str) -> (&str, Option<&str>) {
+    let target = target.split_once("://").map_or(target, |(_, rest)| rest);
+    let target = target.split(['/', '?']).next().unwrap_or(target);
+    if let Some(rest) = target.strip_prefix('[') {
+        // [IPv6]:port
+        return match rest.split_once(']') {
+            Some((host, port)) => (host, port.strip_prefix(':')),
+            None => (target, None),
+        };
+    }
+    match target.split_once(':') {
+        Some((host, port)) if !port.contains(':') => (host, Some(port)),
+        _ => (target, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_split_target() {
+        assert_eq!(split_target("web1:9100"), ("web1", Some("9100")));
+        assert_eq!(split_target("web1"), ("web1", None));
+        assert_eq!(split_target("[::1]:9100"), ("::1", Some("9100")));
+        assert_eq!(split_target("::1"), ("::1", None));
+        assert_eq!(
+            split_target("https://example.com:8443/health"),
+            ("example.com", Some("8443"))
+        );
+    }
+}
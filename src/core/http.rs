@@ -0,0 +1,838 @@
+use crate::core::error::*;
+use log::{debug, warn};
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::Path};
+
+// A minimal HTTP/1.1 server for serve mode and client for pushing metrics. Each connection handles
+// a single request and is closed after the response is written. The server bounds the open
+// connections, the time and headers of each request, and the body, which is only read once the
+// handler has checked the head.
+
+/// Timeout for connecting to, reading from, and writing to remote servers.
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest request body accepted.
+pub const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+
+/// The largest request line or header line accepted.
+const MAX_LINE_SIZE: usize = 8 * 1024;
+
+/// The most headers accepted in a request.
+const MAX_HEADERS: usize = 100;
+
+/// The most connections handled at once. Connections beyond it are closed right away.
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// Timeout for each read from and write to a client.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The longest a client may take to send its whole request, so clients that trickle bytes
+/// can't hold a connection open.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An HTTP request. Header names are lowercase.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(|v| v.as_str())
+    }
+}
+
+/// An HTTP response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn text(status: u16, body: &str) -> Self {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body.as_bytes().to_vec())
+    }
+
+    pub fn json(status: u16, body: &str) -> Self {
+        Response::new(status)
+            .with_header("Content-Type", "application/json")
+            .with_body(body.as_bytes().to_vec())
+    }
+
+    pub fn not_found() -> Self {
+        Response::text(404, "Not Found\n")
+    }
+
+    pub fn method_not_allowed() -> Self {
+        Response::text(405, "Method Not Allowed\n")
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+}
+
+/// Handles the requests of a server. Closures taking a request handle every request with its
+/// body read.
+pub trait Handler: Send + Sync + 'static {
+    /// Check a request before its body is read. Return a response, such as for an unknown path
+    /// or missing credentials, to answer it without reading the body.
+    fn check(&self, _request: &Request) -> Option<Response> {
+        None
+    }
+
+    /// Answer a request that passed `check`, with its body read.
+    fn handle(&self, request: &Request) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, request: &Request) -> Response {
+        self(request)
+    }
+}
+
+/// Read a single request from the reader.
+pub fn read_request<R: BufRead>(reader: &mut R) -> Result<Request> {
+    let mut request = read_request_head(reader)?;
+    read_request_body(reader, &mut request)?;
+    Ok(request)
+}
+
+/// Read the request line and headers of a request, leaving the body unread.
+pub fn read_request_head<R: BufRead>(reader: &mut R) -> Result<Request> {
+    let request_line = read_line(reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(bad_request("Malformed request line")),
+    };
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (target, String::new()),
+    };
+
+    let mut headers = BTreeMap::new();
+    for count in 0.. {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Err(bad_request("Too many headers"));
+        }
+
+        match line.split_once(':') {
+            Some((name, value)) => {
+                headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+            }
+            None => return Err(bad_request("Malformed header")),
+        }
+    }
+
+    let request = Request {
+        method,
+        path,
+        query,
+        headers,
+        body: Vec::new(),
+    };
+    content_length(&request)?;
+    Ok(request)
+}
+
+/// Read the body of a request whose head was read with `read_request_head`. The body grows as
+/// it arrives, so a large Content-Length alone doesn't allocate memory.
+pub fn read_request_body<R: BufRead>(reader: &mut R, request: &mut Request) -> Result<()> {
+    let length = content_length(request)?;
+    let mut body = Vec::new();
+    reader
+        .take(length as u64)
+        .read_to_end(&mut body)
+        .map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context("Reading request body")
+                .set_kind(ErrorKind::Network)
+        })?;
+    if body.len() < length {
+        return Err(bad_request("Request body ended early"));
+    }
+
+    request.body = body;
+    Ok(())
+}
+
+// The length of a request's body, which must not be over the limit.
+fn content_length(request: &Request) -> Result<usize> {
+    let length = match request.header("Content-Length") {
+        Some(length) => length
+            .parse::<usize>()
+            .map_err(|_| bad_request("Invalid Content-Length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(bad_request("Request body too large"));
+    }
+
+    Ok(length)
+}
+
+/// Write the response to the writer.
+pub fn write_response<W: Write>(writer: &mut W, response: &Response) -> Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        response.status,
+        reason(response.status)
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+    head.push_str("Connection: close\r\n\r\n");
+
+    writer
+        .write_all(head.as_bytes())
+        .and_then(|_| writer.write_all(&response.body))
+        .and_then(|_| writer.flush())
+        .map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context("Writing response")
+                .set_kind(ErrorKind::Network)
+        })
+}
+
+/// Accept connections forever, handling each on its own thread.
+pub fn serve<H: Handler>(listener: TcpListener, handler: Arc<H>) -> Result<()> {
+    accept(listener, handler, handle_connection)
+}
+
+/// Accept TLS connections forever, handling each on its own thread. The handshake happens on the
+/// connection's thread, so a slow or failing client doesn't hold up the others.
+#[cfg(feature = "tls")]
+pub fn serve_tls<H: Handler>(
+    listener: TcpListener,
+    config: Arc<rustls::ServerConfig>,
+    handler: Arc<H>,
+) -> Result<()> {
+    accept(listener, handler, move |stream, handler| {
+        let connection = rustls::ServerConnection::new(Arc::clone(&config)).map_err(|e| {
+            Error::new(SourceError::Msg(e.to_string()))
+                .set_context("Starting TLS connection")
+                .set_kind(ErrorKind::Network)
+        })?;
+        let mut stream = rustls::StreamOwned::new(connection, stream);
+        handle_connection(&mut stream, handler)?;
+        stream.conn.send_close_notify();
+        stream.flush().map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context("Closing TLS connection")
+                .set_kind(ErrorKind::Network)
+        })
+    })
+}
+
+// Hand each accepted connection to `connect` on its own thread, up to MAX_CONNECTIONS at once.
+fn accept<H, C>(listener: TcpListener, handler: Arc<H>, connect: C) -> Result<()>
+where
+    H: Handler,
+    C: Fn(Deadline<TcpStream>, &H) -> Result<()> + Send + Sync + 'static,
+{
+    let connect = Arc::new(connect);
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        if open.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            open.fetch_sub(1, Ordering::SeqCst);
+            warn!(
+                "Closing connection, {} connections are already open",
+                MAX_CONNECTIONS
+            );
+            continue;
+        }
+
+        let handler = Arc::clone(&handler);
+        let connect = Arc::clone(&connect);
+        let open = Arc::clone(&open);
+        thread::spawn(move || {
+            let res = stream
+                .set_read_timeout(Some(SERVER_TIMEOUT))
+                .and_then(|_| stream.set_write_timeout(Some(SERVER_TIMEOUT)))
+                .map_err(|e| {
+                    Error::new(SourceError::Io(e))
+                        .set_context("Setting connection timeouts")
+                        .set_kind(ErrorKind::Network)
+                })
+                .and_then(|_| connect(Deadline::new(stream), handler.as_ref()));
+            if let Err(e) = res {
+                debug!("Connection error: {}", e);
+            }
+            open.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    Ok(())
+}
+
+// A stream whose reads fail once REQUEST_TIMEOUT has passed since the connection was accepted.
+struct Deadline<S> {
+    stream: S,
+    deadline: Instant,
+}
+
+impl<S> Deadline<S> {
+    fn new(stream: S) -> Self {
+        Deadline {
+            stream,
+            deadline: Instant::now() + REQUEST_TIMEOUT,
+        }
+    }
+}
+
+impl<S: Read> Read for Deadline<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if Instant::now() >= self.deadline {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "Request took too long",
+            ));
+        }
+        self.stream.read(buf)
+    }
+}
+
+impl<S: Write> Write for Deadline<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Bind a listener to the address.
+pub fn bind(addr: &str) -> Result<TcpListener> {
+    TcpListener::bind(addr).map_err(|e| {
+        Error::new(SourceError::Io(e))
+            .set_context(&format!("Failed to listen on {}", addr))
+            .set_kind(ErrorKind::Network)
+    })
+}
+
+/// Send a request to an `http://` or `https://` URL and read the response.
+pub fn send(method: &str, url: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<Response> {
+    send_with_timeout(method, url, headers, body, CLIENT_TIMEOUT)
+}
+
+/// Send a request like `send`, with a timeout for connecting, reading, and writing.
+pub fn send_with_timeout(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    timeout: Duration,
+) -> Result<Response> {
+    let (https, host, port, path) = parse_url(url)?;
+    debug!("Sending {} request to {}", method, url);
+    let addr = (host.as_str(), port)
+        .to_socket_addrs()
+        .map_err(|e| client_error(e, url))?
+        .next()
+        .ok_or_else(|| bad_request(&format!("Could not resolve host in URL: {}", url)))?;
+    let mut stream =
+        TcpStream::connect_timeout(&addr, timeout).map_err(|e| client_error(e, url))?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|e| client_error(e, url))?;
+
+    let authority = format!("{}:{}", host, port);
+    if !https {
+        write_request(&mut stream, &authority, method, &path, headers, body)
+            .map_err(|e| client_error(e, url))?;
+        return read_response(&mut BufReader::new(stream));
+    }
+
+    let name = rustls::pki_types::ServerName::try_from(host)
+        .map_err(|_| bad_request(&format!("Invalid host in URL: {}", url)))?;
+    let connection = rustls::ClientConnection::new(crate::core::tls::client_config()?, name)
+        .map_err(|e| {
+            Error::new(SourceError::Msg(e.to_string()))
+                .set_context(&format!("Request to {} failed", url))
+                .set_kind(ErrorKind::Network)
+        })?;
+    let mut stream = rustls::StreamOwned::new(connection, stream);
+    write_request(&mut stream, &authority, method, &path, headers, body)
+        .map_err(|e| client_error(e, url))?;
+    read_response(&mut BufReader::new(stream))
+}
+
+/// Send a request to a server listening on a Unix socket, such as the Docker daemon, and read
+/// the response.
+#[cfg(unix)]
+pub fn send_unix(
+    socket: &Path,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Response> {
+    let url = format!("unix://{}{}", socket.display(), path);
+    debug!("Sending {} request to {}", method, url);
+    let mut stream = UnixStream::connect(socket).map_err(|e| client_error(e, &url))?;
+    stream
+        .set_read_timeout(Some(CLIENT_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(CLIENT_TIMEOUT)))
+        .map_err(|e| client_error(e, &url))?;
+    write_request(&mut stream, "localhost", method, path, headers, body)
+        .map_err(|e| client_error(e, &url))?;
+
+    read_response(&mut BufReader::new(stream))
+}
+
+// Write a request that asks the server to close the connection after the response.
+fn write_request<W: Write>(
+    stream: &mut W,
+    host: &str,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> std::io::Result<()> {
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, host);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    ));
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// Read a response from the reader. The body is read until the connection is closed unless a
+/// Content-Length is given.
+pub fn read_response<R: BufRead>(reader: &mut R) -> Result<Response> {
+    let status_line = read_line(reader)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| bad_request("Malformed status line"))?;
+
+    let mut headers = Vec::new();
+    let mut length = None;
+    let mut chunked = false;
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+            if name.trim().eq_ignore_ascii_case("transfer-encoding")
+                && value.to_lowercase().contains("chunked")
+            {
+                chunked = true;
+            }
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    if chunked {
+        let body = read_chunked(reader)?;
+        return Ok(Response {
+            status,
+            headers,
+            body,
+        });
+    }
+
+    if length.is_some_and(|length| length > MAX_BODY_SIZE) {
+        return Err(bad_request("Response body too large"));
+    }
+
+    // Read one byte past the limit to tell a body at the limit from a larger one.
+    let mut body = Vec::new();
+    reader
+        .take(length.unwrap_or(MAX_BODY_SIZE + 1) as u64)
+        .read_to_end(&mut body)
+        .map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context("Reading response body")
+                .set_kind(ErrorKind::Network)
+        })?;
+    if body.len() > MAX_BODY_SIZE {
+        return Err(bad_request("Response body too large"));
+    }
+    if length.is_some_and(|length| body.len() < length) {
+        return Err(bad_request("Response body ended early"));
+    }
+
+    Ok(Response {
+        status,
+        headers,
+        body,
+    })
+}
+
+// Read a chunked transfer-encoded body. Chunk extensions and trailers are ignored.
+fn read_chunked<R: BufRead>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| bad_request(&format!("Malformed chunk size: {}", line)))?;
+        if size == 0 {
+            break;
+        }
+
+        if body.len() + size > MAX_BODY_SIZE {
+            return Err(bad_request("Response body too large"));
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context("Reading response body")
+                .set_kind(ErrorKind::Network)
+        })?;
+        // Each chunk ends with CRLF.
+        read_line(reader)?;
+    }
+
+    // Skip trailers until the blank line that ends the message. Some servers close the
+    // connection without it.
+    while let Ok(line) = read_line(reader) {
+        if line.is_empty() {
+            break;
+        }
+    }
+    Ok(body)
+}
+
+// Split a URL into whether it uses https, host, port, and path.
+fn parse_url(url: &str) -> Result<(bool, String, u16, String)> {
+    let (https, rest) = match (url.strip_prefix("http://"), url.strip_prefix("https://")) {
+        (Some(rest), _) => (false, rest),
+        (_, Some(rest)) => (true, rest),
+        _ => {
+            return Err(bad_request(&format!(
+                "Unsupported URL '{}', only http:// and https:// URLs are supported",
+                url
+            )));
+        }
+    };
+
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !host.ends_with(']') || authority.starts_with('[') => {
+            let port = port
+                .parse::<u16>()
+                .map_err(|_| bad_request(&format!("Invalid port in URL: {}", url)))?;
+            (host, port)
+        }
+        _ if https => (authority, 443),
+        _ => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(bad_request(&format!("Missing host in URL: {}", url)));
+    }
+
+    Ok((
+        https,
+        host.trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+        port,
+        path.to_string(),
+    ))
+}
+
+fn client_error(e: std::io::Error, url: &str) -> Error {
+    Error::new(SourceError::Io(e))
+        .set_context(&format!("Request to {} failed", url))
+        .set_kind(ErrorKind::Network)
+}
+
+// Answer a single request. The handler checks the head of the request before the body is read.
+fn handle_connection<S, H>(mut stream: S, handler: &H) -> Result<()>
+where
+    S: Read + Write,
+    H: Handler,
+{
+    let response = {
+        let mut reader = BufReader::new(&mut stream);
+        match read_request_head(&mut reader) {
+            Ok(mut request) => {
+                debug!("{} {}", request.method, request.path);
+                match handler.check(&request) {
+                    Some(response) => response,
+                    None => match read_request_body(&mut reader, &mut request) {
+                        Ok(()) => handler.handle(&request),
+                        Err(e) => Response::text(400, &format!("{}\n", e)),
+                    },
+                }
+            }
+            Err(e) => Response::text(400, &format!("{}\n", e)),
+        }
+    };
+
+    write_response(&mut stream, &response)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String> {
+    let mut line = Vec::new();
+    reader
+        .take(MAX_LINE_SIZE as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context("Reading request")
+                .set_kind(ErrorKind::Network)
+        })?;
+    if !line.ends_with(b"\n") {
+        return Err(bad_request("Request line too long or connection closed"));
+    }
+
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+fn bad_request(msg: &str) -> Error {
+    Error::new(SourceError::Msg(msg.to_string())).set_kind(ErrorKind::Network)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let raw =
+            "PUT /sources/a?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello";
+        let request = read_request(&mut raw.as_bytes()).unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/sources/a");
+        assert_eq!(request.query, "x=1");
+        assert_eq!(request.header("Host"), Some("localhost"));
+        assert_eq!(request.body, b"hello");
+
+        let raw = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-A: 1\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert!(read_request(&mut raw.as_bytes()).is_err());
+        let raw = format!(
+            "PUT / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_SIZE + 1
+        );
+        assert!(read_request_head(&mut raw.as_bytes()).is_err());
+        let raw = "PUT / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel";
+        assert!(read_request(&mut raw.as_bytes()).is_err());
+    }
+
+    // A connection that reads from the given input and collects what is written.
+    struct Connection<'a> {
+        input: &'a [u8],
+        output: Vec<u8>,
+    }
+
+    impl Read for Connection<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Connection<'_> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Guarded;
+
+    impl Handler for Guarded {
+        fn check(&self, request: &Request) -> Option<Response> {
+            match request.header("Authorization") {
+                Some(_) => None,
+                None => Some(Response::text(401, "Unauthorized\n")),
+            }
+        }
+
+        fn handle(&self, request: &Request) -> Response {
+            Response::new(200).with_body(request.body.clone())
+        }
+    }
+
+    fn status(input: &str) -> String {
+        let mut connection = Connection {
+            input: input.as_bytes(),
+            output: Vec::new(),
+        };
+        handle_connection(&mut connection, &Guarded).unwrap();
+        let output = String::from_utf8(connection.output).unwrap();
+        output.lines().next().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_handle_connection() {
+        // The body is never read when the check answers the request.
+        assert_eq!(
+            status("PUT / HTTP/1.1\r\nContent-Length: 5\r\n\r\n"),
+            "HTTP/1.1 401 Unauthorized"
+        );
+        assert_eq!(
+            status("PUT / HTTP/1.1\r\nAuthorization: x\r\nContent-Length: 5\r\n\r\n"),
+            "HTTP/1.1 400 Bad Request"
+        );
+        assert_eq!(
+            status("PUT / HTTP/1.1\r\nAuthorization: x\r\nContent-Length: 2\r\n\r\nok"),
+            "HTTP/1.1 200 OK"
+        );
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut stream = Deadline::new("data".as_bytes());
+        let mut buf = [0; 4];
+        assert_eq!(stream.read(&mut buf).unwrap(), 4);
+        stream.deadline = Instant::now();
+        let e = stream.read(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            parse_url("http://localhost:9091/metrics/job/pim").unwrap(),
+            (
+                false,
+                "localhost".to_string(),
+                9091,
+                "/metrics/job/pim".to_string()
+            )
+        );
+        assert_eq!(
+            parse_url("http://example.com").unwrap(),
+            (false, "example.com".to_string(), 80, "/".to_string())
+        );
+        assert_eq!(
+            parse_url("https://netbox.example.com/api/").unwrap(),
+            (
+                true,
+                "netbox.example.com".to_string(),
+                443,
+                "/api/".to_string()
+            )
+        );
+        assert!(parse_url("ftp://example.com").is_err());
+    }
+
+    #[test]
+    fn test_read_response() {
+        let raw = "HTTP/1.1 202 Accepted\r\nContent-Length: 2\r\n\r\nok";
+        let response = read_response(&mut raw.as_bytes()).unwrap();
+        assert_eq!(response.status, 202);
+        assert_eq!(response.body, b"ok");
+
+        let raw = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\n[1, \r\na;x=y\r\n2]\n1234567\r\n0\r\n\r\n";
+        let response = read_response(&mut raw.as_bytes()).unwrap();
+        assert_eq!(response.body, b"[1, 2]\n1234567");
+
+        // Bodies over the limit or shorter than their Content-Length are errors, not truncated.
+        let raw = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\nok",
+            MAX_BODY_SIZE + 1
+        );
+        assert!(read_response(&mut raw.as_bytes()).is_err());
+        let raw = format!("HTTP/1.1 200 OK\r\n\r\n{}", "x".repeat(MAX_BODY_SIZE + 1));
+        assert!(read_response(&mut raw.as_bytes()).is_err());
+        let raw = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nok";
+        assert!(read_response(&mut raw.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut buf = Vec::new();
+        write_response(&mut buf, &Response::text(200, "ok")).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(text.contains("Content-Length: 2\r\n"));
+        assert!(text.ends_with("\r\n\r\nok"));
+    }
+}
@@ -0,0 +1,239 @@
+#[cfg(feature = "fs")]
+use crate::core::error::*;
+use log::debug;
+use std::path::{Component, Path, PathBuf};
+
+/// The name of the file holding ignore patterns for a source directory.
+pub const IGNORE_FILE: &str = ".pimignore";
+
+/// Gitignore-style rules for skipping paths while scanning source directories. Rules are matched
+/// relative to the directory of the ignore file they came from, and the last matching rule wins.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    base: PathBuf,
+    segments: Vec<String>,
+    negate: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl IgnoreRules {
+    pub fn new() -> Self {
+        IgnoreRules::default()
+    }
+
+    /// Add the patterns in `content`, relative to the `base` directory. Blank lines and lines
+    /// starting with `#` are skipped. A leading `!` re-includes a path, a trailing `/` only
+    /// matches directories, and a pattern containing `/` is anchored to `base`.
+    pub fn add(&mut self, base: &Path, content: &str) {
+        for line in content.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negate, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let segments: Vec<String> = line
+                .trim_start_matches('/')
+                .split('/')
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect();
+            if segments.is_empty() {
+                continue;
+            }
+
+            debug!("Adding ignore pattern {:?} for {}", line, base.display());
+            self.rules.push(IgnoreRule {
+                base: base.to_path_buf(),
+                segments,
+                negate,
+                dir_only,
+                anchored,
+            });
+        }
+    }
+
+    /// Add the rules from the ignore file in `dir`, if there is one.
+    #[cfg(feature = "fs")]
+    pub fn load(&mut self, dir: &Path) -> Result<()> {
+        let path = dir.join(IGNORE_FILE);
+        if !path.is_file() {
+            return Ok(());
+        }
+
+        debug!("Loading ignore file: {}", path.display());
+        let content = std::fs::read_to_string(&path).map_err(|e| {
+            Error::new(SourceError::Io(e))
+                .set_context(format!("reading ignore file: {}", path.display()).as_str())
+        })?;
+        self.add(dir, &content);
+        Ok(())
+    }
+
+    /// Check if the path should be skipped. Ignore files themselves are always skipped.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if !is_dir && path.file_name().is_some_and(|n| n == IGNORE_FILE) {
+            return true;
+        }
+
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.matches(path, is_dir) {
+                ignored = !rule.negate;
+            }
+        }
+
+        ignored
+    }
+}
+
+impl IgnoreRule {
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        let parts: Vec<String> = relative
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(s) => Some(s.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        if parts.is_empty() {
+            return false;
+        }
+
+        if self.anchored {
+            return match_segments(&self.segments, &parts);
+        }
+
+        // Unanchored patterns match the name at any depth.
+        match_segments(&self.segments, &parts[parts.len() - 1..])
+    }
+}
+
+// Match pattern segments against path segments. A `**` segment matches zero or more segments.
+fn match_segments(pattern: &[String], parts: &[String]) -> bool {
+    match pattern.split_first() {
+        None => parts.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=parts.len()).any(|skip| match_segments(rest, &parts[skip..]))
+        }
+        Some((first, rest)) => match parts.split_first() {
+            Some((part, parts)) => {
+                glob_match(first.as_bytes(), part.as_bytes()) && match_segments(rest, parts)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Match a single path segment against a glob supporting `*`, `?`, `[abc]`, `[a-z]`, `[!abc]` and
+/// `\` escapes.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| glob_match(rest, &text[skip..])),
+        Some((b'?', rest)) => !text.is_empty() && glob_match(rest, &text[1..]),
+        Some((b'[', rest)) => {
+            let Some((&c, text_rest)) = text.split_first() else {
+                return false;
+            };
+            match match_class(rest, c) {
+                Some((true, pattern_rest)) => glob_match(pattern_rest, text_rest),
+                Some((false, _)) => false,
+                // An unclosed class matches a literal `[`.
+                None => c == b'[' && glob_match(rest, text_rest),
+            }
+        }
+        Some((b'\\', rest)) if !rest.is_empty() => {
+            text.first() == Some(&rest[0]) && glob_match(&rest[1..], &text[1..])
+        }
+        Some((p, rest)) => text.first() == Some(p) && glob_match(rest, &text[1..]),
+    }
+}
+
+// Match a character class after the opening `[`. Returns whether `c` matched and the pattern
+// after the closing `]`, or None if the class is not closed.
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, &[u8])> {
+    let (negate, mut i) = match pattern.first() {
+        Some(b'!') | Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        if pattern[i] == b']' && !first {
+            return Some((matched != negate, &pattern[i + 1..]));
+        }
+
+        first = false;
+        if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']' {
+            matched |= pattern[i] <= c && c <= pattern[i + 2];
+            i += 3;
+        } else {
+            matched |= pattern[i] == c;
+            i += 1;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*.md", b"README.md"));
+        assert!(!glob_match(b"*.md", b"README.yml"));
+        assert!(glob_match(b"file?.yml", b"file1.yml"));
+        assert!(glob_match(b"[a-c]*.yml", b"b.yml"));
+        assert!(!glob_match(b"[!a-c]*.yml", b"b.yml"));
+        assert!(glob_match(b"\\*.yml", b"*.yml"));
+        assert!(!glob_match(b"\\*.yml", b"a.yml"));
+    }
+
+    #[test]
+    fn test_ignore_rules() {
+        let base = Path::new("sources");
+        let mut rules = IgnoreRules::new();
+        rules.add(
+            base,
+            "# docs\n*.md\nfixtures/\n/archive\n!keep.md\nteams/**/old.yml\n",
+        );
+
+        assert!(rules.is_ignored(Path::new("sources/README.md"), false));
+        assert!(rules.is_ignored(Path::new("sources/teams/NOTES.md"), false));
+        assert!(!rules.is_ignored(Path::new("sources/keep.md"), false));
+        assert!(rules.is_ignored(Path::new("sources/fixtures"), true));
+        assert!(!rules.is_ignored(Path::new("sources/fixtures"), false));
+        assert!(rules.is_ignored(Path::new("sources/archive"), true));
+        assert!(!rules.is_ignored(Path::new("sources/teams/archive"), true));
+        assert!(rules.is_ignored(Path::new("sources/teams/old.yml"), false));
+        assert!(rules.is_ignored(Path::new("sources/teams/a/b/old.yml"), false));
+        assert!(!rules.is_ignored(Path::new("sources/web.yml"), false));
+        assert!(rules.is_ignored(Path::new("sources/.pimignore"), false));
+    }
+}
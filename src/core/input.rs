@@ -1,12 +1,22 @@
+#[cfg(feature = "fs")]
+use crate::core::decrypt;
 use crate::core::error::*;
 use crate::core::io::*;
+use crate::core::yaml;
 use content_inspector::ContentType;
 use log::{debug, warn};
+use serde::{Deserialize, Serialize};
 use std::{
-    fmt::Debug,
-    fs::{Metadata, metadata},
-    io::{IsTerminal, stdin},
+    fmt::{Debug, Display},
+    fs::Metadata,
+    io::{BufRead, Read},
     path::{Path, PathBuf},
+    str::FromStr,
+};
+#[cfg(feature = "fs")]
+use std::{
+    fs::metadata,
+    io::{IsTerminal, stdin},
 };
 
 pub const DEFAULT_INPUT_FORMAT: InputFormat = InputFormat::Yaml;
@@ -16,6 +26,8 @@ pub const DEFAULT_INPUT_FORMAT: InputFormat = InputFormat::Yaml;
 pub enum InputKind {
     Stdin,
     File(PathBuf),
+    /// An arbitrary reader provided by a library user.
+    Reader,
 }
 
 impl InputKind {
@@ -27,19 +39,42 @@ impl InputKind {
         }
     }
 
+    /// A display name for the input, such as the file path.
+    pub fn name(&self) -> String {
+        match self {
+            InputKind::Stdin => "<stdin>".to_string(),
+            InputKind::File(p) => p.display().to_string(),
+            InputKind::Reader => "<reader>".to_string(),
+        }
+    }
+
     pub fn path(&self) -> Option<&PathBuf> {
         match self {
-            InputKind::Stdin => None,
+            InputKind::Stdin | InputKind::Reader => None,
             InputKind::File(p) => Some(p),
         }
     }
 }
 
 /// The format of the input data (json, yaml, etc.).
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
 pub enum InputFormat {
     Json,
+    /// JSON5, or JSON with comments and trailing commas (JSONC).
+    #[serde(alias = "jsonc")]
+    #[cfg_attr(feature = "application", value(alias = "jsonc"))]
+    Json5,
+    /// HashiCorp configuration language with a `source` block per entry.
+    Hcl,
+    /// XLSX workbook with a header row. Requires the `xlsx` feature.
+    #[cfg(feature = "xlsx")]
+    Xlsx,
+    #[serde(alias = "yml")]
+    #[cfg_attr(feature = "application", value(alias = "yml"))]
     Yaml,
+    #[cfg_attr(feature = "application", value(skip))]
     Unknown,
 }
 
@@ -51,6 +86,14 @@ impl Default for InputFormat {
 
 impl InputFormat {
     pub fn from_extension(path: &Path) -> Self {
+        // Age encrypted files are named after their decrypted format, such as `hosts.yml.age`.
+        let is_age = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("age"));
+        if let Some(stem) = path.file_stem().filter(|_| is_age) {
+            return InputFormat::from_extension(Path::new(stem));
+        }
+
         let ext = match path.extension() {
             Some(e) => e.to_str().unwrap_or("").to_lowercase(),
             None => "".to_string(),
@@ -58,20 +101,62 @@ impl InputFormat {
 
         match ext.as_str() {
             "json" => InputFormat::Json,
+            "json5" | "jsonc" => InputFormat::Json5,
+            "hcl" => InputFormat::Hcl,
+            #[cfg(feature = "xlsx")]
+            "xlsx" => InputFormat::Xlsx,
             "yaml" | "yml" => InputFormat::Yaml,
             _ => InputFormat::Unknown,
         }
     }
 
+    /// Whether the format is binary, so the input is not checked for text.
+    pub fn is_binary(&self) -> bool {
+        #[cfg(feature = "xlsx")]
+        if *self == InputFormat::Xlsx {
+            return true;
+        }
+        false
+    }
+
     pub fn as_str(&self) -> &str {
         match self {
             InputFormat::Json => "json",
+            InputFormat::Json5 => "json5",
+            InputFormat::Hcl => "hcl",
+            #[cfg(feature = "xlsx")]
+            InputFormat::Xlsx => "xlsx",
             InputFormat::Yaml => "yaml",
             InputFormat::Unknown => "unknown",
         }
     }
 }
 
+impl Display for InputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "json" => Ok(InputFormat::Json),
+            "json5" | "jsonc" => Ok(InputFormat::Json5),
+            "hcl" => Ok(InputFormat::Hcl),
+            #[cfg(feature = "xlsx")]
+            "xlsx" => Ok(InputFormat::Xlsx),
+            "yaml" | "yml" => Ok(InputFormat::Yaml),
+            _ => Err(
+                Error::new(SourceError::UnsupportedInputFormat(s.to_string()))
+                    .set_kind(ErrorKind::Config),
+            ),
+        }
+    }
+}
+
 /// Represents an input sources for the application, holding the reader, kind, format, and other metadata.
 pub struct Input {
     /// The io reader for the input source.
@@ -88,6 +173,18 @@ pub struct Input {
     content: String,
     /// Metadata about the input source, if applicable. Good for getting if directory, size, etc.
     metadata: Option<Metadata>,
+    /// Read the input as text even if it appears to be binary.
+    force_text: bool,
+    /// The sheet to read from a workbook. Defaults to the first sheet.
+    #[cfg(feature = "xlsx")]
+    sheet: Option<String>,
+    /// Age identity files to decrypt encrypted inputs with.
+    #[cfg(feature = "fs")]
+    age_identities: Vec<PathBuf>,
+    /// The most bytes read from the input. Unlimited by default.
+    max_size: Option<u64>,
+    /// The most nodes a YAML input may have with its aliases expanded.
+    max_yaml_nodes: usize,
 }
 
 impl Debug for Input {
@@ -105,8 +202,9 @@ impl Debug for Input {
 }
 
 impl Input {
+    #[cfg(feature = "fs")]
     pub fn new(path: &Path) -> Result<Self> {
-        let mut input;
+        let mut input: Input;
         let reader = Reader::new(path)?;
         match reader {
             Reader::Stdin(_) => {
@@ -115,16 +213,19 @@ impl Input {
             Reader::File(_) => {
                 input = Self::from_file(&path.to_path_buf(), reader)?;
             }
-            Reader::None => {
+            Reader::Custom(_) | Reader::None => {
                 return Err(Error::new(SourceError::InvalidInputSource(
                     path.display().to_string(),
                 ))
                 .set_context("Input source is None")
-                .set_code(CODE_RUNTIME_ERROR));
+                .set_kind(ErrorKind::Internal));
             }
         }
 
-        input.inspect_content()?;
+        // Directories have no content to inspect, their files are read as separate inputs.
+        if !input.is_dir() {
+            input.inspect_content()?;
+        }
         Ok(input)
     }
 
@@ -132,6 +233,11 @@ impl Input {
         &self.format
     }
 
+    /// Override the detected input format.
+    pub fn set_format(&mut self, format: InputFormat) {
+        self.format = format;
+    }
+
     pub fn reader(&self) -> &Reader {
         &self.reader
     }
@@ -159,13 +265,136 @@ impl Input {
         }
     }
 
+    /// Read the rest of the input as text. Byte order marks are handled by `decode_text`. SOPS
+    /// encrypted JSON and YAML are decrypted to their `sources` list as JSON.
+    pub fn read_text(&mut self) -> Result<String> {
+        let text = decode_text(&self.read_bytes()?)?;
+        #[cfg(feature = "fs")]
+        if decrypt::is_sops(&text, &self.format) {
+            debug!("Decrypting SOPS input: {}", self.kind.name());
+            return decrypt::sops(&text, &self.format, &self.age_identities);
+        }
+        Ok(text)
+    }
+
+    /// Read the rest of the input as bytes, for binary formats such as XLSX. Age encrypted
+    /// inputs are decrypted.
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        // One byte more than the limit is read to tell an input at the limit from a larger one.
+        let limit = self.max_size.map_or(u64::MAX, |max| max.saturating_add(1));
+        (&mut self.reader)
+            .take(limit)
+            .read_to_end(&mut bytes)
+            .map_err(|e| {
+                Error::new(SourceError::Io(e))
+                    .set_context(format!("reading input: {}", self.kind.name()).as_str())
+            })?;
+        if let Some(max) = self.max_size.filter(|max| bytes.len() as u64 > *max) {
+            return Err(Error::new(SourceError::Msg(format!(
+                "{} is larger than the limit of {} bytes",
+                self.kind.name(),
+                max
+            )))
+            .set_context("Failed to read input")
+            .set_kind(ErrorKind::Validation));
+        }
+        #[cfg(feature = "fs")]
+        if self.is_age() {
+            debug!("Decrypting age input: {}", self.kind.name());
+            bytes = decrypt::age(&bytes, &self.age_identities)?;
+        }
+        Ok(bytes)
+    }
+
+    /// Check if the input is encrypted with age.
+    #[cfg(feature = "fs")]
+    pub fn is_age(&self) -> bool {
+        decrypt::is_age(self.content.as_bytes())
+    }
+
+    /// Decrypt encrypted inputs with these age identity files instead of the default
+    /// `SOPS_AGE_KEY_FILE`.
+    #[cfg(feature = "fs")]
+    pub fn set_age_identities(&mut self, identities: Vec<PathBuf>) {
+        self.age_identities = identities;
+    }
+
+    /// The sheet to read from a workbook input.
+    #[cfg(feature = "xlsx")]
+    pub fn sheet(&self) -> Option<&str> {
+        self.sheet.as_deref()
+    }
+
+    /// Read this sheet from a workbook input instead of the first sheet.
+    #[cfg(feature = "xlsx")]
+    pub fn set_sheet(&mut self, sheet: &str) {
+        self.sheet = Some(sheet.to_string());
+    }
+
+    /// Fail reading the input once more than this many bytes are read.
+    pub fn set_max_size(&mut self, max_size: Option<u64>) {
+        self.max_size = max_size;
+    }
+
+    /// The most nodes a YAML input may have with its aliases expanded.
+    pub fn max_yaml_nodes(&self) -> usize {
+        self.max_yaml_nodes
+    }
+
+    pub fn set_max_yaml_nodes(&mut self, max_nodes: usize) {
+        self.max_yaml_nodes = max_nodes;
+    }
+
+    /// Read the input as text even if content inspection says it is binary.
+    pub fn set_force_text(&mut self, force_text: bool) {
+        self.force_text = force_text;
+    }
+
+    /// Check that the input looks like text that can be decoded. Binary content is rejected
+    /// unless `force_text` is set. UTF-32 is always rejected.
+    pub fn check_text(&self) -> Result<()> {
+        // The decrypted content is checked when it is decoded.
+        #[cfg(feature = "fs")]
+        if self.is_age() {
+            return Ok(());
+        }
+
+        let Some(content_type) = self.content_type else {
+            return Ok(());
+        };
+
+        let reason = match content_type {
+            ContentType::BINARY if !self.force_text => "Input appears to be binary",
+            ContentType::UTF_32LE | ContentType::UTF_32BE => "Unsupported text encoding",
+            _ => return Ok(()),
+        };
+
+        Err(Error::new(SourceError::InvalidInputSource(format!(
+            "{}: {} (detected content type: {})",
+            self.kind.name(),
+            reason,
+            content_type
+        )))
+        .set_kind(ErrorKind::Validation))
+    }
+
+    /// Check if the input is a named pipe (FIFO).
+    pub fn is_fifo(&self) -> bool {
+        match &self.kind {
+            InputKind::File(_) => is_fifo(&self.metadata),
+            InputKind::Stdin | InputKind::Reader => false,
+        }
+    }
+
     pub fn is_dir(&self) -> bool {
         match &self.kind {
             InputKind::File(_) => is_dir(&self.metadata),
-            InputKind::Stdin => false,
+            InputKind::Stdin | InputKind::Reader => false,
         }
     }
 
+    #[cfg(feature = "fs")]
     pub fn from_stdin(reader: Reader) -> Self {
         debug!("Creating Input from stdin");
         Input {
@@ -176,24 +405,64 @@ impl Input {
             content_type: None,
             content: String::new(),
             metadata: None,
+            force_text: false,
+            #[cfg(feature = "xlsx")]
+            sheet: None,
+            #[cfg(feature = "fs")]
+            age_identities: Vec::new(),
+            max_size: None,
+            max_yaml_nodes: yaml::DEFAULT_MAX_NODES,
         }
     }
 
+    /// Create an Input from any buffered reader, such as an in-memory buffer or network stream.
+    /// The format can not be detected from a reader so it must be provided.
+    pub fn from_reader<R: BufRead + Send + 'static>(
+        reader: R,
+        format: InputFormat,
+    ) -> Result<Self> {
+        debug!("Creating Input from reader");
+        let mut input = Input {
+            reader: Reader::from_reader(reader),
+            kind: InputKind::Reader,
+            format,
+            is_terminal: false,
+            content_type: None,
+            content: String::new(),
+            metadata: None,
+            force_text: false,
+            #[cfg(feature = "xlsx")]
+            sheet: None,
+            #[cfg(feature = "fs")]
+            age_identities: Vec::new(),
+            max_size: None,
+            max_yaml_nodes: yaml::DEFAULT_MAX_NODES,
+        };
+
+        input.inspect_content()?;
+        Ok(input)
+    }
+
+    #[cfg(feature = "fs")]
     pub fn from_file(path: &PathBuf, reader: Reader) -> Result<Self> {
         debug!("Creating Input from file: {}", path.display());
         let metadata = metadata(path).map_err(|e| {
             Error::new(SourceError::Io(e))
                 .set_context(format!("error checking file: {}", path.display()).as_str())
-                .set_code(CODE_RUNTIME_ERROR)
                 .print_help()
         })?;
         debug!("File metadata obtained: {:?}", metadata);
 
-        let format = InputFormat::from_extension(path);
-        debug!(
-            "Determined input format as '{}' from file extension",
-            format.as_str()
-        );
+        // Named pipes are streams like stdin and their paths, such as /dev/fd/63, have no useful
+        // extension.
+        let metadata = Some(metadata);
+        let format = if is_fifo(&metadata) {
+            debug!("Input is a named pipe, using the default input format");
+            DEFAULT_INPUT_FORMAT
+        } else {
+            InputFormat::from_extension(path)
+        };
+        debug!("Determined input format as '{}'", format.as_str());
 
         Ok(Input {
             reader,
@@ -202,22 +471,30 @@ impl Input {
             is_terminal: false,
             content_type: None,
             content: String::new(),
-            metadata: Some(metadata),
+            metadata,
+            force_text: false,
+            #[cfg(feature = "xlsx")]
+            sheet: None,
+            #[cfg(feature = "fs")]
+            age_identities: Vec::new(),
+            max_size: None,
+            max_yaml_nodes: yaml::DEFAULT_MAX_NODES,
         })
     }
 
     pub fn inspect_content(&mut self) -> Result<()> {
         debug!("Inspecting content type for input: {:?}", self.kind);
-        //let content = read_first_line(&mut self.reader)?;
+        // Peek instead of reading so the content is still available to the deserializer.
         let content = match &mut self.reader {
-            Reader::Stdin(r) => read_first_line(r)?,
-            Reader::File(file) => read_first_line(file)?,
+            Reader::Stdin(r) => peek_first_line_bytes(r)?,
+            Reader::File(file) => peek_first_line_bytes(file)?,
+            Reader::Custom(reader) => peek_first_line_bytes(reader)?,
             Reader::None => {
                 return Err(Error::new(SourceError::InvalidInputSource(
                     "No reader available (None), skipping content inspection".to_string(),
                 ))
                 .set_context("No reader available (None) during content inspection")
-                .set_code(CODE_RUNTIME_ERROR));
+                .set_kind(ErrorKind::Internal));
             }
         };
 
@@ -226,9 +503,10 @@ impl Input {
             return Ok(());
         }
 
-        let content_type = content_inspector::inspect(content.as_bytes());
+        // Inspect the raw bytes so byte order marks are seen before any lossy conversion.
+        let content_type = content_inspector::inspect(&content);
         self.content_type = Some(content_type);
-        self.content = content;
+        self.content = String::from_utf8_lossy(&content).into_owned();
         debug!(
             "Content type inspected: {:?} based on content: {}",
             self.content_type, self.content
@@ -236,3 +514,56 @@ impl Input {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_input_format_from_str() {
+        assert_eq!("json".parse::<InputFormat>().unwrap(), InputFormat::Json);
+        assert_eq!("YML".parse::<InputFormat>().unwrap(), InputFormat::Yaml);
+        assert_eq!("jsonc".parse::<InputFormat>().unwrap(), InputFormat::Json5);
+        assert_eq!(
+            InputFormat::from_extension(Path::new("hosts.jsonc")),
+            InputFormat::Json5
+        );
+        assert_eq!(
+            InputFormat::from_extension(Path::new("hosts.HCL")),
+            InputFormat::Hcl
+        );
+        assert_eq!(
+            InputFormat::from_extension(Path::new("hosts.json.age")),
+            InputFormat::Json
+        );
+        assert!("toml".parse::<InputFormat>().is_err());
+        assert_eq!(InputFormat::Yaml.to_string(), "yaml");
+    }
+
+    #[test]
+    fn test_input_check_text() {
+        let text = Input::from_reader(std::io::Cursor::new(b"[]\n".to_vec()), InputFormat::Json);
+        assert!(text.unwrap().check_text().is_ok());
+
+        let mut binary = Input::from_reader(
+            std::io::Cursor::new(vec![0x00, 0xFF, 0x10]),
+            InputFormat::Json,
+        )
+        .unwrap();
+        let err = binary.check_text().unwrap_err();
+        assert!(err.to_string().contains("binary"));
+        binary.set_force_text(true);
+        assert!(binary.check_text().is_ok());
+    }
+
+    #[test]
+    fn test_input_format_serde() {
+        let format: InputFormat = serde_json::from_str("\"yml\"").unwrap();
+        assert_eq!(format, InputFormat::Yaml);
+        assert_eq!(
+            serde_json::to_string(&InputFormat::Json).unwrap(),
+            "\"json\""
+        );
+    }
+}
@@ -1,19 +1,36 @@
 use crate::core::error::*;
 use log::debug;
+#[cfg(feature = "fs")]
+use std::io::stdin;
 use std::{
-    fs::{File, Metadata},
-    io::{BufRead, BufReader, Read, Stdin, Write, stdin},
-    path::Path,
+    fmt::Debug,
+    fs::{File, Metadata, OpenOptions},
+    io::{BufRead, BufReader, Read, Stdin, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
 
-#[derive(Debug)]
 pub enum Reader {
     Stdin(BufReader<Stdin>),
     File(BufReader<File>),
+    /// Any other buffered reader, such as an in-memory buffer or network stream.
+    Custom(Box<dyn BufRead + Send>),
     None,
 }
 
+impl Debug for Reader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reader::Stdin(r) => f.debug_tuple("Stdin").field(r).finish(),
+            Reader::File(r) => f.debug_tuple("File").field(r).finish(),
+            Reader::Custom(_) => f.debug_tuple("Custom").field(&"BufRead {...}").finish(),
+            Reader::None => f.write_str("None"),
+        }
+    }
+}
+
 impl Reader {
+    #[cfg(feature = "fs")]
     pub fn new(path: &Path) -> Result<Self> {
         // Check for stdin first.
         if path_is_stdin(path) {
@@ -27,11 +44,15 @@ impl Reader {
         let file = File::open(path).map_err(|e| {
             Error::new(SourceError::Io(e))
                 .set_context(format!("Failed to open input file: {}", path.display()).as_str())
-                .set_code(CODE_RUNTIME_ERROR)
                 .print_help()
         })?;
         Ok(Reader::File(BufReader::new(file)))
     }
+
+    pub fn from_reader<R: BufRead + Send + 'static>(reader: R) -> Self {
+        debug!("Creating Custom reader");
+        Reader::Custom(Box::new(reader))
+    }
 }
 
 impl Read for Reader {
@@ -39,6 +60,7 @@ impl Read for Reader {
         match self {
             Reader::Stdin(stdin) => stdin.read(buf),
             Reader::File(file) => file.read(buf),
+            Reader::Custom(reader) => reader.read(buf),
             Reader::None => Ok(0),
         }
     }
@@ -49,6 +71,7 @@ impl BufRead for Reader {
         match self {
             Reader::Stdin(stdin) => stdin.fill_buf(),
             Reader::File(file) => file.fill_buf(),
+            Reader::Custom(reader) => reader.fill_buf(),
             Reader::None => Ok(&[]),
         }
     }
@@ -57,22 +80,49 @@ impl BufRead for Reader {
         match self {
             Reader::Stdin(stdin) => stdin.consume(amt),
             Reader::File(file) => file.consume(amt),
+            Reader::Custom(reader) => reader.consume(amt),
             Reader::None => {}
         }
     }
 }
 
-#[derive(Debug)]
+/// The permission mode output files are created with.
+pub const DEFAULT_FILE_MODE: u32 = 0o640;
+
 pub enum Writer {
     Stdout(std::io::Stdout),
     File(std::fs::File),
+    /// A file that is created, truncating any existing file, on the first write. This keeps
+    /// existing files intact until new content is actually written. The file is given the
+    /// permission mode on Unix.
+    Pending(PathBuf, u32),
+    /// Any other writer, such as an in-memory buffer or network stream. The writer is shared so
+    /// every target file written to the same Output ends up in the same stream.
+    Custom(Arc<Mutex<dyn Write + Send>>),
     None,
 }
 
+impl Debug for Writer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Writer::Stdout(w) => f.debug_tuple("Stdout").field(w).finish(),
+            Writer::File(w) => f.debug_tuple("File").field(w).finish(),
+            Writer::Pending(p, mode) => f
+                .debug_tuple("Pending")
+                .field(p)
+                .field(&format_args!("{:o}", mode))
+                .finish(),
+            Writer::Custom(_) => f.debug_tuple("Custom").field(&"Write {...}").finish(),
+            Writer::None => f.write_str("None"),
+        }
+    }
+}
+
 impl Writer {
+    #[cfg(feature = "fs")]
     pub fn new(path: &Path) -> Result<Self> {
         // Check for stdout first.
-        if path.to_str().unwrap_or("<stdout>") == "<stdout>" {
+        if path_is_stdout(path) {
             debug!("Creating Stdout writer");
             return Ok(Writer::Stdout(std::io::stdout()));
         }
@@ -83,29 +133,63 @@ impl Writer {
             return Ok(Writer::None);
         }
 
-        // Otherwise, create a file writer.
+        // Otherwise, create a file writer. The file is created on the first write.
         debug!("Creating File writer for path: {}", path.display());
-        let file = File::create(path).map_err(|e| {
-            Error::new(SourceError::Io(e))
-                .set_context(format!("Failed to create output file: {}", path.display()).as_str())
-                .set_code(CODE_RUNTIME_ERROR)
-        })?;
-        Ok(Writer::File(file))
+        Ok(Writer::Pending(path.to_path_buf(), DEFAULT_FILE_MODE))
     }
 
-    pub fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+    pub fn from_writer<W: Write + Send + 'static>(writer: W) -> Self {
+        debug!("Creating Custom writer");
+        Writer::Custom(Arc::new(Mutex::new(writer)))
+    }
+
+    /// Create a new handle to the same destination. Custom writers share the underlying writer.
+    pub fn try_clone(&self) -> Result<Self> {
         match self {
-            Writer::Stdout(stdout) => stdout.write_all(buf).map_err(|e| {
+            Writer::Stdout(_) => Ok(Writer::Stdout(std::io::stdout())),
+            Writer::File(file) => file
+                .try_clone()
+                .map(Writer::File)
+                .map_err(|e| Error::new(SourceError::Io(e)).set_context("Cloning file writer")),
+            Writer::Pending(path, mode) => Ok(Writer::Pending(path.clone(), *mode)),
+            Writer::Custom(writer) => Ok(Writer::Custom(Arc::clone(writer))),
+            Writer::None => Ok(Writer::None),
+        }
+    }
+
+    pub fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        if let Writer::Pending(path, mode) = self {
+            debug!("Creating output file: {} ({:o})", path.display(), mode);
+            let file = create_file(path, *mode).map_err(|e| {
                 Error::new(SourceError::Io(e))
-                    .set_context("Writing to stdout")
-                    .set_code(CODE_RUNTIME_ERROR)
-            }),
+                    .set_context(
+                        format!("Failed to create output file: {}", path.display()).as_str(),
+                    )
+                    .set_output()
+            })?;
+            *self = Writer::File(file);
+        }
+
+        match self {
+            Writer::Stdout(stdout) => stdout
+                .write_all(buf)
+                .map_err(|e| Error::new(SourceError::Io(e)).set_context("Writing to stdout")),
             Writer::File(file) => file.write_all(buf).map_err(|e| {
                 Error::new(SourceError::Io(e))
                     .set_context("Writing to file")
-                    .set_code(CODE_RUNTIME_ERROR)
+                    .set_output()
             }),
-            Writer::None => {
+            Writer::Custom(writer) => {
+                let mut writer = writer.lock().map_err(|_| {
+                    Error::new(SourceError::Msg("Writer lock poisoned".to_string()))
+                        .set_context("Writing to custom writer")
+                        .set_kind(ErrorKind::Internal)
+                })?;
+                writer.write_all(buf).map_err(|e| {
+                    Error::new(SourceError::Io(e)).set_context("Writing to custom writer")
+                })
+            }
+            Writer::Pending(..) | Writer::None => {
                 debug!("No writer available (None), skipping write");
                 Ok(())
             }
@@ -113,21 +197,353 @@ impl Writer {
     }
 }
 
+// Create or truncate a file with the permission mode. The mode is also set on existing files,
+// which keep their old mode otherwise.
+fn create_file(path: &Path, mode: u32) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        let file = options.mode(mode).open(path)?;
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = mode;
+        options.open(path)
+    }
+}
+
+/// Parse an octal file permission mode, such as `640` or `0640`.
+pub fn parse_file_mode(s: &str) -> std::result::Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("'{}' is not an octal file mode, such as 0640", s)),
+    }
+}
+
+/// Parse a size in bytes, such as `65536`, `64K`, `10MiB`, or `1G`. Units are powers of 1024.
+pub fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let invalid = || format!("'{}' is not a size, such as 65536, 64K, or 10M", s);
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(split);
+    let value: u64 = digits.parse().map_err(|_| invalid())?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let unit = unit
+        .strip_suffix("IB")
+        .or_else(|| unit.strip_suffix('B'))
+        .unwrap_or(&unit);
+    let shift = match unit {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(invalid()),
+    };
+    value.checked_mul(1 << shift).ok_or_else(invalid)
+}
+
+/// Refuse to write outputs into a directory other users can change. The directory is the path
+/// itself if it is a directory, otherwise the parent of the output file. Directories that are
+/// world writable, or owned by a user other than the current user or root, are refused since
+/// other users could replace or read the generated files. Directories that don't exist yet are
+/// left for file creation to report.
+#[cfg(feature = "fs")]
+pub fn check_output_dir(path: &Path) -> Result<()> {
+    if path_is_stdout(path) {
+        return Ok(());
+    }
+
+    let dir = output_dir(path);
+    let Ok(metadata) = std::fs::metadata(dir) else {
+        return Ok(());
+    };
+
+    match unsafe_dir_reason(&metadata) {
+        Some(reason) => Err(Error::new(SourceError::Msg(format!(
+            "Output directory {} is {}, use --unsafe-output-dir to write to it anyway",
+            dir.display(),
+            reason
+        )))
+        .set_kind(ErrorKind::Config)),
+        None => Ok(()),
+    }
+}
+
+/// Check that target files can be created in the directory the output is written to, so an
+/// output that can't be written fails before any sources are read rather than after. Files are
+/// staged and renamed into place, so only the directory needs to be writable.
+#[cfg(feature = "fs")]
+pub fn check_output_writable(path: &Path) -> Result<()> {
+    if path_is_stdout(path) {
+        return Ok(());
+    }
+
+    let dir = output_dir(path);
+    if !dir.is_dir() {
+        return Err(Error::new(SourceError::Msg(format!(
+            "Output directory {} does not exist",
+            dir.display()
+        )))
+        .set_kind(ErrorKind::Config));
+    }
+
+    // Permission bits don't tell the whole story, with ACLs, read-only mounts, and root, so try
+    // creating a file.
+    let probe = dir.join(format!(".pim-write-check-{}", std::process::id()));
+    match OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem
+            ) =>
+        {
+            Err(Error::new(SourceError::Msg(format!(
+                "Output directory {} is not writable ({}). pim needs write and execute \
+                 permission on it to create target files",
+                dir.display(),
+                e
+            )))
+            .set_code(CODE_PERMISSION_DENIED))
+        }
+        Err(e) => Err(Error::new(SourceError::Io(e))
+            .set_context(&format!("checking output directory: {}", dir.display()))),
+    }
+}
+
+// The directory target files are written to: the path itself if it is a directory, otherwise
+// the parent of the output file.
+#[cfg(feature = "fs")]
+fn output_dir(path: &Path) -> &Path {
+    match path.is_dir() {
+        true => path,
+        false => match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        },
+    }
+}
+
+#[cfg(all(feature = "fs", unix))]
+fn unsafe_dir_reason(metadata: &Metadata) -> Option<&'static str> {
+    use std::os::unix::fs::MetadataExt;
+
+    unsafe extern "C" {
+        safe fn geteuid() -> u32;
+    }
+
+    if metadata.mode() & 0o002 != 0 {
+        return Some("world writable");
+    }
+    let uid = metadata.uid();
+    if uid != 0 && uid != geteuid() {
+        return Some("owned by another user");
+    }
+    None
+}
+
+#[cfg(all(feature = "fs", not(unix)))]
+fn unsafe_dir_reason(_metadata: &Metadata) -> Option<&'static str> {
+    None
+}
+
+/// Wait until stdin has data or is closed. Returns an error if nothing arrives within the
+/// timeout. The data is left in stdin's shared buffer so later readers still see it.
+#[cfg(feature = "fs")]
+pub fn wait_for_stdin(timeout: std::time::Duration) -> Result<()> {
+    debug!("Waiting up to {:?} for stdin", timeout);
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let res = stdin().lock().fill_buf().map(|buf| buf.len());
+        let _ = tx.send(res);
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(Ok(len)) => {
+            debug!("Stdin ready with {} bytes buffered", len);
+            Ok(())
+        }
+        Ok(Err(e)) => Err(Error::new(SourceError::Io(e)).set_context("Failed to read stdin")),
+        Err(_) => Err(Error::new(SourceError::InvalidInputSource(format!(
+            "No data received on stdin within {:?}",
+            timeout
+        )))
+        .set_kind(ErrorKind::Config)
+        .print_help()),
+    }
+}
+
+// Paths are compared as a whole, not as strings, so paths that aren't valid UTF-8 are never
+// mistaken for the stdin and stdout sentinels.
+#[cfg(feature = "fs")]
 fn path_is_stdin(path: &Path) -> bool {
-    path == Path::new("-") || path.to_str().unwrap_or("<stdout>") == "<stdin>"
+    path == Path::new("-") || path == Path::new("<stdin>")
+}
+
+/// Check if the path is the stdout sentinel, `<stdout>` or `-`.
+#[cfg(feature = "fs")]
+pub fn path_is_stdout(path: &Path) -> bool {
+    path == Path::new("-") || path == Path::new("<stdout>")
+}
+
+/// A cloneable in-memory buffer implementing Write. Useful with `Output::from_writer` when the
+/// written data needs to be read back, such as in tests or when embedding pim.
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuffer {
+    inner: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SharedBuffer {
+    pub fn new() -> Self {
+        SharedBuffer::default()
+    }
+
+    /// Get a copy of the bytes written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        match self.inner.lock() {
+            Ok(buf) => buf.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Get the bytes written so far as a lossy UTF-8 string.
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.contents()).into_owned()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.inner.lock() {
+            Ok(mut inner) => inner.write(buf),
+            Err(_) => Err(std::io::Error::other("SharedBuffer lock poisoned")),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
 pub fn read_first_line<R: BufRead>(mut reader: R) -> Result<String> {
     let mut content = String::new();
     // read_line returns the number of bytes read, which we do not care about here.
-    let _ = reader.read_line(&mut content).map_err(|e| {
-        Error::new(SourceError::Io(e))
-            .set_context("reading first line")
-            .set_code(CODE_RUNTIME_ERROR)
-    })?;
+    let _ = reader
+        .read_line(&mut content)
+        .map_err(|e| Error::new(SourceError::Io(e)).set_context("reading first line"))?;
     Ok(content)
 }
 
+/// Peek at the first line of the reader without consuming it. Only the currently buffered data
+/// is inspected so very long first lines may be cut short.
+pub fn peek_first_line<R: BufRead>(reader: &mut R) -> Result<String> {
+    Ok(String::from_utf8_lossy(&peek_first_line_bytes(reader)?).into_owned())
+}
+
+/// Peek at the raw bytes of the first line of the reader without consuming them. Use this
+/// instead of `peek_first_line` when the bytes may not be UTF-8.
+pub fn peek_first_line_bytes<R: BufRead>(reader: &mut R) -> Result<Vec<u8>> {
+    let buf = reader
+        .fill_buf()
+        .map_err(|e| Error::new(SourceError::Io(e)).set_context("peeking first line"))?;
+    let end = match buf.iter().position(|b| *b == b'\n') {
+        Some(pos) => pos + 1,
+        None => buf.len(),
+    };
+    Ok(buf[..end].to_vec())
+}
+
+/// Decode text content to a string. A UTF-8 byte order mark is stripped and UTF-16 content with
+/// a byte order mark is converted. Anything else must be valid UTF-8.
+pub fn decode_text(bytes: &[u8]) -> Result<String> {
+    let (encoding, text) = if let Some(rest) = bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        debug!("Stripping UTF-8 byte order mark");
+        ("UTF-8", String::from_utf8(rest.to_vec()).ok())
+    } else if let Some(rest) = bytes.strip_prefix(b"\xFF\xFE") {
+        debug!("Converting UTF-16LE content to UTF-8");
+        ("UTF-16LE", decode_utf16(rest, u16::from_le_bytes))
+    } else if let Some(rest) = bytes.strip_prefix(b"\xFE\xFF") {
+        debug!("Converting UTF-16BE content to UTF-8");
+        ("UTF-16BE", decode_utf16(rest, u16::from_be_bytes))
+    } else {
+        ("UTF-8", String::from_utf8(bytes.to_vec()).ok())
+    };
+
+    text.ok_or_else(|| {
+        Error::new(SourceError::Msg(format!(
+            "Input is not valid {} text",
+            encoding
+        )))
+        .set_context("decoding input")
+        .set_kind(ErrorKind::Parse)
+    })
+}
+
+// Decode UTF-16 code units using the given byte order. Returns None for an odd number of bytes
+// or unpaired surrogates.
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Option<String> {
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| from_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16(&units).ok()
+}
+
+/// The host name of this machine, from `HOSTNAME`, `COMPUTERNAME`, or `/etc/hostname`, or
+/// `unknown`.
+pub fn hostname() -> String {
+    // HOSTNAME is usually set on Unix shells and COMPUTERNAME on Windows.
+    if let Some(name) = ["HOSTNAME", "COMPUTERNAME"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|name| !name.trim().is_empty())
+    {
+        return name.trim().to_string();
+    }
+
+    match std::fs::read_to_string("/etc/hostname") {
+        Ok(name) if !name.trim().is_empty() => name.trim().to_string(),
+        _ => "unknown".to_string(),
+    }
+}
+
+/// The name of the user running pim, from `USER` or `USERNAME`, or `unknown`.
+pub fn username() -> String {
+    ["USER", "USERNAME"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Check if the metadata is for a named pipe (FIFO), such as the `/dev/fd/N` paths created by
+/// shell process substitution. Always false on platforms without FIFOs.
+pub fn is_fifo(metadata: &Option<Metadata>) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        metadata.as_ref().is_some_and(|md| md.file_type().is_fifo())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
+
 pub fn is_dir(metadata: &Option<Metadata>) -> bool {
     match metadata {
         Some(md) => md.is_dir(),
@@ -136,3 +552,106 @@ pub fn is_dir(metadata: &Option<Metadata>) -> bool {
         None => false,
     }
 }
+
+/// Parse a list of paths, one per line. Blank lines and lines starting with `#` are skipped and
+/// surrounding whitespace is trimmed.
+pub fn parse_path_list(content: &str) -> Vec<PathBuf> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_parse_path_list() {
+        let content = "# changed sources\nsources/a.yml\n\n  sources/b.yml  \n#sources/c.yml\n";
+        assert_eq!(
+            parse_path_list(content),
+            vec![
+                PathBuf::from("sources/a.yml"),
+                PathBuf::from("sources/b.yml")
+            ]
+        );
+        assert!(parse_path_list("").is_empty());
+        assert_eq!(
+            parse_path_list("a.yml\r\nsources\\b.yml\r\n"),
+            vec![PathBuf::from("a.yml"), PathBuf::from("sources\\b.yml")]
+        );
+    }
+
+    #[test]
+    fn test_decode_text() {
+        assert_eq!(decode_text(b"jobs: []").unwrap(), "jobs: []");
+        assert_eq!(decode_text(b"\xEF\xBB\xBF[1]").unwrap(), "[1]");
+
+        let mut le = vec![0xFF, 0xFE];
+        let mut be = vec![0xFE, 0xFF];
+        for unit in "[é]".encode_utf16() {
+            le.extend_from_slice(&unit.to_le_bytes());
+            be.extend_from_slice(&unit.to_be_bytes());
+        }
+        assert_eq!(decode_text(&le).unwrap(), "[é]");
+        assert_eq!(decode_text(&be).unwrap(), "[é]");
+
+        assert!(decode_text(b"\xFF\xFE[").is_err());
+        assert!(decode_text(b"\xC3\x28").is_err());
+    }
+
+    #[test]
+    fn test_parse_file_mode() {
+        assert_eq!(parse_file_mode("640"), Ok(0o640));
+        assert_eq!(parse_file_mode("0600"), Ok(0o600));
+        assert_eq!(parse_file_mode("0o644"), Ok(0o644));
+        assert!(parse_file_mode("0689").is_err());
+        assert!(parse_file_mode("17777").is_err());
+        assert!(parse_file_mode("").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("65536"), Ok(65536));
+        assert_eq!(parse_size("64K"), Ok(64 * 1024));
+        assert_eq!(parse_size("10MiB"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("1gb"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[cfg(all(feature = "fs", unix))]
+    #[test]
+    fn test_output_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("pim-output-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+        let path = dir.join("targets.yml");
+        assert!(check_output_dir(&path).is_ok());
+
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o666)).unwrap();
+        let mut writer = Writer::new(&path).unwrap();
+        writer.write_all(b"new").unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, DEFAULT_FILE_MODE);
+
+        assert!(check_output_writable(&path).is_ok());
+        assert!(check_output_writable(&dir).is_ok());
+        let err = check_output_writable(&dir.join("missing/targets.yml")).unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(check_output_dir(&path).is_err());
+        assert!(check_output_dir(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
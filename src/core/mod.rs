@@ -1,9 +1,31 @@
 pub use self::error::*;
 pub use self::input::{Input, InputFormat, InputKind};
 pub use self::io::*;
-pub use self::output::{Output, OutputFormat, OutputKind};
+pub use self::output::{JobHeader, Output, OutputFormat, OutputKind, Style, YamlQuote, YamlStyle};
 
+pub mod color;
+#[cfg(feature = "fs")]
+pub mod decrypt;
+#[cfg(feature = "provider")]
+pub mod dns;
 pub mod error;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hcl;
+pub mod host;
+#[cfg(feature = "server")]
+pub mod http;
+pub mod ignore;
 pub mod input;
 pub mod io;
+pub mod nagios;
 pub mod output;
+#[cfg(feature = "fs")]
+pub mod sign;
+#[cfg(feature = "fs")]
+pub mod staging;
+pub mod table;
+pub mod time;
+#[cfg(feature = "server")]
+pub mod tls;
+pub mod yaml;
@@ -0,0 +1,146 @@
+use crate::core::error::*;
+use crate::core::host::split_target;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Nagios object definitions are also read by Icinga 1.x. Target groups are serialized first so
+// the definitions are built from exactly what the other formats write.
+
+/// The width object directive names are padded to.
+const DIRECTIVE_WIDTH: usize = 20;
+
+struct Host {
+    vars: BTreeMap<String, String>,
+    ports: BTreeSet<String>,
+}
+
+/// Render serialized target groups as Nagios object definitions for a job. The job becomes a
+/// hostgroup, each target host becomes a member with the group labels as custom variables, and
+/// each target port becomes a `check_tcp` service named `<job>:<port>`. Hosts in `defined` are
+/// only added to the hostgroup, and new hosts are added to it, so hosts shared by jobs written to
+/// the same run are defined once. A host keeps the labels of the first group it is in.
+pub fn render<T: serde::Serialize>(
+    content: &T,
+    job: &str,
+    defined: &mut BTreeSet<String>,
+) -> Result<String> {
+    let value = serde_json::to_value(content).map_err(|e| {
+        Error::new(SourceError::SerdeJson(e))
+            .set_context("Failed to serialize to Nagios objects")
+            .set_kind(ErrorKind::Internal)
+    })?;
+
+    let mut hosts: BTreeMap<String, Host> = BTreeMap::new();
+    for group in value.as_array().into_iter().flatten() {
+        let labels: BTreeMap<String, String> = group
+            .get("labels")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .filter(|(name, _)| name.as_str() != "job")
+            .map(|(name, value)| {
+                let value = value.as_str().unwrap_or_default();
+                (format!("_{}", name.to_uppercase()), clean(value))
+            })
+            .collect();
+
+        let targets = group.get("targets").and_then(Value::as_array);
+        for target in targets.into_iter().flatten().filter_map(Value::as_str) {
+            let (name, port) = split_target(target);
+            let host = hosts.entry(name.to_string()).or_insert_with(|| Host {
+                vars: labels.clone(),
+                ports: BTreeSet::new(),
+            });
+            if let Some(port) = port {
+                host.ports.insert(port.to_string());
+            }
+        }
+    }
+
+    let job = clean(job);
+    let mut out = format!(
+        "# Generated by pim for job '{}'. Changes are overwritten.\n",
+        job
+    );
+    let members = hosts.keys().cloned().collect::<Vec<_>>().join(",");
+    out += &object(
+        "hostgroup",
+        &[
+            ("hostgroup_name", &job),
+            ("alias", &job),
+            ("members", &members),
+        ],
+    );
+    for (name, host) in &hosts {
+        if defined.insert(name.clone()) {
+            let mut directives = vec![
+                ("use", "generic-host"),
+                ("host_name", name.as_str()),
+                ("address", name.as_str()),
+            ];
+            directives.extend(host.vars.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+            out += &object("host", &directives);
+        }
+
+        for port in &host.ports {
+            out += &object(
+                "service",
+                &[
+                    ("use", "generic-service"),
+                    ("host_name", name),
+                    ("service_description", &format!("{}:{}", job, port)),
+                    ("check_command", &format!("check_tcp!{}", port)),
+                ],
+            );
+        }
+    }
+    Ok(out)
+}
+
+fn object(kind: &str, directives: &[(&str, &str)]) -> String {
+    let mut out = format!("\ndefine {} {{\n", kind);
+    for (name, value) in directives {
+        out += &format!("    {:width$}{}\n", name, value, width = DIRECTIVE_WIDTH);
+    }
+    out.push_str("}\n");
+    out
+}
+
+// Object directives end at the line, and `;` starts a comment.
+fn clean(value: &str) -> String {
+    value.replace(['\n', '\r'], " ").replace(';', ",")
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render() {
+        let groups = json!([
+            {"labels": {"job": "node", "env": "prod"}, "targets": ["web1:9100", "web2:9100"]},
+            {"labels": {"job": "node", "env": "dev"}, "targets": ["web1:9101"]}
+        ]);
+        let mut defined = BTreeSet::new();
+        let out = render(&groups, "node", &mut defined).unwrap();
+        assert!(out.starts_with("# Generated by pim for job 'node'."));
+        assert!(out.contains("define hostgroup {\n    hostgroup_name      node\n"));
+        assert!(out.contains("    members             web1,web2\n"));
+        assert_eq!(out.matches("define host {").count(), 2);
+        assert_eq!(out.matches("define service {").count(), 3);
+        assert!(out.contains("    _ENV                prod\n"));
+        assert!(!out.contains("dev"));
+        assert!(out.contains("    check_command       check_tcp!9101\n"));
+        assert!(out.contains("    service_description node:9100\n"));
+
+        // Hosts defined for an earlier job are only added to the hostgroup.
+        let groups = json!([{"labels": {"env": "prod"}, "targets": ["web1:22", "db1:22"]}]);
+        let out = render(&groups, "ssh", &mut defined).unwrap();
+        assert!(out.contains("    members             db1,web1\n"));
+        assert_eq!(out.matches("define host {").count(), 1);
+        assert!(out.contains("    host_name           db1\n"));
+        assert_eq!(out.matches("define service {").count(), 2);
+    }
+}
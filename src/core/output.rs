@@ -1,26 +1,35 @@
 use crate::core::error::*;
 use crate::core::io::*;
 use log::debug;
+use serde::{Deserialize, Serialize};
 use std::{
     cmp::Ordering,
-    fmt::Debug,
-    io::IsTerminal,
-    path::{Path, PathBuf},
+    collections::BTreeSet,
+    fmt::{Debug, Display},
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
 };
+#[cfg(feature = "fs")]
+use std::{io::IsTerminal, path::Path};
 
 pub const DEFAULT_OUTPUT_FORMAT: OutputFormat = OutputFormat::Json;
 
-/// The kind of output destination (stdout, file, or directory).
+/// The kind of output destination (stdout, file, directory, or writer).
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum OutputKind {
     Stdout,
     File(PathBuf),
     Directory(PathBuf),
+    /// An arbitrary writer provided by a library user.
+    Writer,
 }
 
 impl OutputKind {
+    #[cfg(feature = "fs")]
     pub fn new(path: &Path) -> Self {
-        if path.to_str().unwrap_or("<stdout>") == "<stdout>" {
+        if path_is_stdout(path) {
             OutputKind::Stdout
         } else if path.is_dir() {
             OutputKind::Directory(path.to_path_buf())
@@ -31,10 +40,19 @@ impl OutputKind {
 }
 
 /// The format of the output data (json, yaml, etc.).
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
 pub enum OutputFormat {
     Json,
+    #[serde(alias = "yml")]
+    #[cfg_attr(feature = "application", value(alias = "yml"))]
     Yaml,
+    /// Nagios host, hostgroup, and service object definitions, also read by Icinga 1.x. Only
+    /// written, never read back.
+    #[serde(alias = "icinga")]
+    #[cfg_attr(feature = "application", value(alias = "icinga"))]
+    Nagios,
 }
 
 impl Default for OutputFormat {
@@ -48,6 +66,7 @@ impl OutputFormat {
         match self {
             OutputFormat::Json => "json",
             OutputFormat::Yaml => "yaml",
+            OutputFormat::Nagios => "nagios",
         }
     }
 
@@ -55,6 +74,109 @@ impl OutputFormat {
         match self {
             OutputFormat::Json => "json",
             OutputFormat::Yaml => "yml",
+            OutputFormat::Nagios => "cfg",
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "json" => Ok(OutputFormat::Json),
+            "yaml" | "yml" => Ok(OutputFormat::Yaml),
+            "nagios" | "icinga" => Ok(OutputFormat::Nagios),
+            _ => Err(
+                Error::new(SourceError::UnsupportedOutputFormat(s.to_string()))
+                    .set_kind(ErrorKind::Config),
+            ),
+        }
+    }
+}
+
+/// How YAML output is laid out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum YamlStyle {
+    /// Block style, with an item per line.
+    #[default]
+    Block,
+    /// Flow style, with JSON-like brackets. Compact output is a single line.
+    Flow,
+    /// Block style with each list of targets in flow style, such as `targets: [a:9100, b:9100]`.
+    FlowTargets,
+}
+
+/// How string values are quoted in block style YAML. Keys are only quoted when they must be.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum YamlQuote {
+    /// Only quote strings that would otherwise be read as another type or break the YAML.
+    #[default]
+    Auto,
+    /// Quote every string with single quotes, or double quotes if it has control characters.
+    Single,
+    /// Quote every string with double quotes.
+    Double,
+}
+
+/// How jobs are marked in output written to stdout, where every job is written in turn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "application", derive(clap::ValueEnum))]
+pub enum JobHeader {
+    /// Jobs are not marked.
+    #[default]
+    None,
+    /// A `<job>:` line before each job.
+    Text,
+    /// Each job is wrapped in an object, `{"job": <job>, "targets": [...]}`. Nagios output is not
+    /// wrapped.
+    Wrap,
+}
+
+/// How output is formatted beyond its format. Outputs created for each job of a run use the
+/// style of the run's output.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Style {
+    /// Pretty print, or not, regardless of the destination. When unset, files and terminals are
+    /// pretty printed and other destinations are compact.
+    pub pretty: Option<bool>,
+    /// Spaces per indentation level of pretty printed JSON and YAML.
+    pub indent: usize,
+    pub yaml: YamlStyle,
+    pub yaml_quote: YamlQuote,
+    /// Start YAML documents with `---`.
+    pub yaml_document_start: bool,
+    pub header: JobHeader,
+    /// Labels written first in each group, in this order. Other labels follow sorted by name.
+    pub label_order: Vec<String>,
+    /// Leave out bookkeeping labels, such as `__meta_*` and `__pim_*`, when writing groups.
+    pub strip_internal_labels: bool,
+    /// Write jobs as one document mapping each job to its target groups even when there is only
+    /// one job, so the output has the same shape for any number of jobs. Also combines jobs
+    /// written to a writer.
+    pub combine_jobs: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            pretty: None,
+            indent: 2,
+            yaml: YamlStyle::default(),
+            yaml_quote: YamlQuote::default(),
+            yaml_document_start: false,
+            header: JobHeader::default(),
+            label_order: vec!["job".to_string()],
+            strip_internal_labels: false,
+            combine_jobs: false,
         }
     }
 }
@@ -65,6 +187,10 @@ pub struct Output {
     kind: OutputKind,
     format: OutputFormat,
     pretty: bool,
+    style: Style,
+    mode: u32,
+    // Hosts already defined in Nagios output, shared by the outputs of a run.
+    nagios_hosts: Arc<Mutex<BTreeSet<String>>>,
 }
 
 impl Debug for Output {
@@ -73,6 +199,7 @@ impl Debug for Output {
             .field("kind", &self.kind)
             .field("format", &self.format)
             .field("pretty", &self.pretty)
+            .field("style", &self.style)
             .finish()
     }
 }
@@ -103,6 +230,7 @@ impl PartialOrd for Output {
 }
 
 impl Output {
+    #[cfg(feature = "fs")]
     pub fn new(path: &PathBuf, format: OutputFormat) -> Result<Self> {
         debug!("Creating new Output for path: {:?}", path);
         // The only time we don't pretty print is when writing to non-terminal stdout.
@@ -114,11 +242,15 @@ impl Output {
                 let is_terminal = std::io::stdout().is_terminal();
                 (OutputKind::Stdout, is_terminal)
             }
-            Writer::File(_) => {
+            Writer::File(_) | Writer::Pending(..) => {
                 debug!("Outputting to file: {:?}", path);
                 // Files should always be written with pretty printing for readability.
                 (OutputKind::File(path.clone()), true)
             }
+            Writer::Custom(_) => {
+                debug!("Outputting to custom writer");
+                (OutputKind::Writer, false)
+            }
             Writer::None => {
                 debug!("Outputting to directory: {:?}", path);
                 (OutputKind::Directory(path.clone()), false)
@@ -135,6 +267,39 @@ impl Output {
             kind,
             format,
             pretty,
+            style: Style::default(),
+            mode: DEFAULT_FILE_MODE,
+            nagios_hosts: Arc::default(),
+        })
+    }
+
+    /// Create an Output from any writer, such as an in-memory buffer or network stream. Output is
+    /// not pretty printed by default; use `set_pretty` to change that.
+    pub fn from_writer<W: Write + Send + 'static>(writer: W, format: OutputFormat) -> Self {
+        debug!("Creating new Output from writer");
+        Output {
+            path: PathBuf::from("<writer>"),
+            writer: Writer::from_writer(writer),
+            kind: OutputKind::Writer,
+            format,
+            pretty: false,
+            style: Style::default(),
+            mode: DEFAULT_FILE_MODE,
+            nagios_hosts: Arc::default(),
+        }
+    }
+
+    /// Create a new Output writing to the same destination.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Output {
+            path: self.path.clone(),
+            writer: self.writer.try_clone()?,
+            kind: self.kind.clone(),
+            format: self.format.clone(),
+            pretty: self.pretty,
+            style: self.style.clone(),
+            mode: self.mode,
+            nagios_hosts: self.nagios_hosts.clone(),
         })
     }
 
@@ -163,75 +328,249 @@ impl Output {
         self.pretty = pretty;
     }
 
+    pub fn style(&self) -> &Style {
+        &self.style
+    }
+
+    /// Set how output is formatted. A set `pretty` overrides the default of the destination.
+    pub fn set_style(&mut self, style: Style) {
+        if let Some(pretty) = style.pretty {
+            self.pretty = pretty;
+        }
+        self.style = style;
+    }
+
+    /// Whether the jobs of a run are written to this output as one document mapping each job to
+    /// its target groups. Jobs written to stdout are, unless they are marked with a job header
+    /// or written as Nagios objects. Jobs written to a writer are only with `combine_jobs`.
+    pub fn combines_jobs(&self) -> bool {
+        let kind = match self.kind {
+            OutputKind::Stdout => self.style.header == JobHeader::None,
+            OutputKind::Writer => self.style.combine_jobs,
+            _ => false,
+        };
+        kind && self.format != OutputFormat::Nagios
+    }
+
+    /// The permission mode output files are created with.
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    /// Set the permission mode output files are created with, `0640` by default. Only used on
+    /// Unix.
+    pub fn set_mode(&mut self, mode: u32) {
+        self.mode = mode;
+        if let Writer::Pending(_, pending) = &mut self.writer {
+            *pending = mode;
+        }
+    }
+
+    /// Share the hosts already defined in Nagios output with another output of the same run, so
+    /// each host is defined once across all target files.
+    #[cfg(feature = "fs")]
+    pub(crate) fn share_nagios_hosts(&mut self, other: &Output) {
+        self.nagios_hosts = other.nagios_hosts.clone();
+    }
+
     pub fn write<T: serde::Serialize>(&mut self, job: &str, content: &T) -> Result<()> {
-        if self.pretty {
-            let is_stdout = matches!(self.kind, OutputKind::Stdout);
-            pretty(&mut self.writer, content, &self.format, job, is_stdout)
-        } else {
-            raw(&mut self.writer, content, &self.format)
+        if self.format == OutputFormat::Nagios {
+            let mut defined = self.nagios_hosts.lock().unwrap_or_else(|e| e.into_inner());
+            let data = crate::core::nagios::render(content, job, &mut defined)?;
+            return self.writer.write_all(data.as_bytes());
         }
+
+        let stdout = matches!(self.kind, OutputKind::Stdout);
+        let header = match stdout {
+            true => self.style.header,
+            false => JobHeader::None,
+        };
+        let mut data = match header {
+            JobHeader::Text => job.to_string() + ":\n",
+            _ => String::new(),
+        };
+        data += &match header {
+            JobHeader::Wrap => {
+                let wrapped = JobTargets {
+                    job,
+                    targets: content,
+                };
+                serialize(&wrapped, &self.format, job, self.pretty, &self.style)?
+            }
+            _ => serialize(content, &self.format, job, self.pretty, &self.style)?,
+        };
+        // Put each job written to stdout on its own lines.
+        if stdout && !data.ends_with('\n') {
+            data.push('\n');
+        }
+
+        debug!("Writing data:\n{}", data);
+        // A reader closing stdout early, such as `head` in `pim | head`, ends the run with a
+        // broken pipe for the program to exit quietly on, rather than a failed write.
+        self.writer
+            .write_all(data.as_bytes())
+            .map_err(|e| match stdout && e.is_broken_pipe() {
+                true => e.set_context("stdout was closed by its reader"),
+                false => e,
+            })
     }
 }
 
-// Write unpretty formatted output to the file or stdout.
-pub fn raw<T: serde::Serialize>(
-    writer: &mut Writer,
-    content: &T,
-    format: &OutputFormat,
-) -> Result<()> {
-    debug!("Writing raw output with format: {:?}", format);
-    let data = match format {
-        OutputFormat::Json => serde_json::to_string(content).map_err(|e| {
-            Error::new(SourceError::SerdeJson(e))
-                .set_context("Failed to serialize to JSON")
-                .set_code(CODE_RUNTIME_ERROR)
-        })?,
-        OutputFormat::Yaml => serde_yaml::to_string(content).map_err(|e| {
-            Error::new(SourceError::SerdeYaml(e))
-                .set_context("Failed to serialize to YAML")
-                .set_code(CODE_RUNTIME_ERROR)
-        })?,
-    };
-
-    debug!("Writing data:\n{}", data);
-    writer.write_all(data.as_bytes())
+// The targets of a job wrapped with the job's name.
+#[derive(Serialize)]
+struct JobTargets<'a, T> {
+    job: &'a str,
+    targets: &'a T,
 }
 
-// Write pretty formatted output to the file or stdout.
-pub fn pretty<T: serde::Serialize>(
-    writer: &mut Writer,
+// Serialize content in the format and style. Flow style YAML is written as JSON, which is valid
+// flow style YAML.
+fn serialize<T: serde::Serialize>(
     content: &T,
     format: &OutputFormat,
     job: &str,
-    is_stdout: bool,
-) -> Result<()> {
-    debug!("Writing pretty output with format: {:?}", format);
-    let mut data = String::new();
-    if is_stdout {
-        // Notify user of output file if pretty printing. The only time we don't pretty
-        // print is when writing to non-terminal stdout. If it's pretty printing, we assume it's
-        // going to another program so we don't want to add extra output.
-        data = job.to_string() + ":\n";
-    }
-
-    let res = match format {
-        OutputFormat::Json => serde_json::to_string_pretty(content).map_err(|e| {
-            Error::new(SourceError::SerdeJson(e))
-                .set_context("Failed to serialize to JSON")
-                .set_code(CODE_RUNTIME_ERROR)
-        })?,
-        OutputFormat::Yaml => serde_yaml::to_string(content).map_err(|e| {
-            Error::new(SourceError::SerdeYaml(e))
-                .set_context("Failed to serialize to YAML")
-                .set_code(CODE_RUNTIME_ERROR)
-        })?,
+    pretty: bool,
+    style: &Style,
+) -> Result<String> {
+    match format {
+        OutputFormat::Json => to_json(content, pretty, style.indent),
+        OutputFormat::Yaml => {
+            let block = Style {
+                pretty: style.pretty,
+                header: style.header,
+                yaml_document_start: style.yaml_document_start,
+                label_order: style.label_order.clone(),
+                ..Style::default()
+            };
+            let yaml = match style.yaml {
+                YamlStyle::Flow => to_json(content, pretty, style.indent)? + "\n",
+                // serde_yaml writes the default style, the emitter any other.
+                _ if *style == block => serde_yaml::to_string(content).map_err(|e| {
+                    Error::new(SourceError::SerdeYaml(e))
+                        .set_context("Failed to serialize to YAML")
+                        .set_kind(ErrorKind::Internal)
+                })?,
+                _ => crate::core::yaml::to_string(content, style)?,
+            };
+            match style.yaml_document_start {
+                true => Ok(format!("---\n{}", yaml)),
+                false => Ok(yaml),
+            }
+        }
+        OutputFormat::Nagios => crate::core::nagios::render(content, job, &mut BTreeSet::new()),
+    }
+}
+
+fn to_json<T: serde::Serialize>(content: &T, pretty: bool, indent: usize) -> Result<String> {
+    let mut buf = Vec::new();
+    let res = match pretty {
+        true => {
+            let indent = " ".repeat(indent);
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+            content.serialize(&mut serde_json::Serializer::with_formatter(
+                &mut buf, formatter,
+            ))
+        }
+        false => content.serialize(&mut serde_json::Serializer::new(&mut buf)),
     };
+    res.map_err(|e| {
+        Error::new(SourceError::SerdeJson(e))
+            .set_context("Failed to serialize to JSON")
+            .set_kind(ErrorKind::Internal)
+    })?;
+    // serde_json only writes valid UTF-8.
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
 
-    data += &res;
-    if is_stdout {
-        data += "\n";
+    #[test]
+    fn test_output_format_from_str() {
+        assert_eq!("Json".parse::<OutputFormat>().unwrap(), OutputFormat::Json);
+        assert_eq!("yml".parse::<OutputFormat>().unwrap(), OutputFormat::Yaml);
+        assert_eq!(
+            "icinga".parse::<OutputFormat>().unwrap(),
+            OutputFormat::Nagios
+        );
+        assert!("xml".parse::<OutputFormat>().is_err());
+        assert_eq!(OutputFormat::Yaml.to_string(), "yaml");
     }
 
-    debug!("Writing data");
-    writer.write_all(data.as_bytes())
+    #[test]
+    fn test_output_format_serde() {
+        let format: OutputFormat = serde_json::from_str("\"yaml\"").unwrap();
+        assert_eq!(format, OutputFormat::Yaml);
+        assert_eq!(
+            serde_json::to_string(&OutputFormat::Json).unwrap(),
+            "\"json\""
+        );
+    }
+
+    #[test]
+    fn test_style() {
+        let mut output = Output::from_writer(std::io::sink(), OutputFormat::Json);
+        assert!(!output.is_pretty());
+        output.set_style(Style {
+            pretty: Some(true),
+            indent: 4,
+            ..Style::default()
+        });
+        assert!(output.is_pretty());
+
+        let content = vec![serde_json::json!({"targets": ["a:1"]})];
+        let json = serialize(&content, &OutputFormat::Json, "node", true, output.style());
+        assert_eq!(
+            json.unwrap(),
+            "[\n    {\n        \"targets\": [\n            \"a:1\"\n        ]\n    }\n]"
+        );
+
+        let flow = Style {
+            yaml: YamlStyle::Flow,
+            ..Style::default()
+        };
+        let yaml = serialize(&content, &OutputFormat::Yaml, "node", false, &flow).unwrap();
+        assert_eq!(yaml, "[{\"targets\":[\"a:1\"]}]\n");
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, serde_yaml::to_value(&content).unwrap());
+    }
+
+    #[test]
+    fn test_job_targets() {
+        let content = vec![serde_json::json!({"targets": ["a:1"]})];
+        let wrapped = JobTargets {
+            job: "node",
+            targets: &content,
+        };
+        let json = serialize(
+            &wrapped,
+            &OutputFormat::Json,
+            "node",
+            false,
+            &Style::default(),
+        );
+        assert_eq!(
+            json.unwrap(),
+            r#"{"job":"node","targets":[{"targets":["a:1"]}]}"#
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_combines_jobs() {
+        let stdout = PathBuf::from("<stdout>");
+        let mut output = Output::new(&stdout, OutputFormat::Json).unwrap();
+        assert!(output.combines_jobs());
+        output.set_style(Style {
+            header: JobHeader::Wrap,
+            ..Style::default()
+        });
+        assert!(!output.combines_jobs());
+        let output = Output::new(&stdout, OutputFormat::Nagios).unwrap();
+        assert!(!output.combines_jobs());
+        let output = Output::from_writer(std::io::sink(), OutputFormat::Json);
+        assert!(!output.combines_jobs());
+    }
 }
@@ -0,0 +1,109 @@
+use crate::core::error::*;
+use log::debug;
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+// Signatures are made and checked with the minisign CLI so pim does not carry its own
+// cryptography. Signing runs unattended, so the secret key must not be password protected, such
+// as a key made with `minisign -G -W`.
+
+/// The extension of minisign signature files.
+pub const SIGNATURE_EXTENSION: &str = "minisig";
+
+/// The detached signature path for a file, the file path with `.minisig` appended.
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(SIGNATURE_EXTENSION);
+    PathBuf::from(name)
+}
+
+/// Check if the path is a minisign signature file.
+pub fn is_signature(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == SIGNATURE_EXTENSION)
+}
+
+/// Write a detached ed25519 signature for the file, signed with the minisign secret key, and
+/// return the signature path.
+pub fn sign(path: &Path, secret_key: &Path) -> Result<PathBuf> {
+    let signature = signature_path(path);
+    debug!("Signing {} to {}", path.display(), signature.display());
+    let mut command = Command::new("minisign");
+    command
+        .arg("-S")
+        .arg("-s")
+        .arg(secret_key)
+        .arg("-m")
+        .arg(path)
+        .arg("-x")
+        .arg(&signature);
+    run(command, &format!("Failed to sign {}", path.display()))?;
+    Ok(signature)
+}
+
+/// Verify the detached signature of the file with the minisign public key.
+pub fn verify(path: &Path, public_key: &Path) -> Result<()> {
+    let signature = signature_path(path);
+    if !signature.is_file() {
+        return Err(Error::new(SourceError::Msg(format!(
+            "No signature found at {}",
+            signature.display()
+        )))
+        .set_kind(ErrorKind::Validation));
+    }
+
+    debug!("Verifying {} with {}", path.display(), signature.display());
+    let mut command = Command::new("minisign");
+    command
+        .arg("-V")
+        .arg("-q")
+        .arg("-p")
+        .arg(public_key)
+        .arg("-m")
+        .arg(path)
+        .arg("-x")
+        .arg(&signature);
+    run(command, &format!("Failed to verify {}", path.display()))
+}
+
+// Run minisign without stdin, so a password protected key fails instead of waiting for a
+// password.
+fn run(mut command: Command, context: &str) -> Result<()> {
+    let output = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| Error::new(SourceError::Io(e)).set_context("running minisign"))?;
+
+    if !output.status.success() {
+        return Err(Error::new(SourceError::Msg(format!(
+            "minisign exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+        .set_context(context)
+        .set_kind(ErrorKind::Io));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_signature_path() {
+        assert_eq!(
+            signature_path(Path::new("out/node_targets.json")),
+            PathBuf::from("out/node_targets.json.minisig")
+        );
+        assert!(is_signature(Path::new("out/node_targets.json.minisig")));
+        assert!(!is_signature(Path::new("out/node_targets.json")));
+    }
+}
@@ -0,0 +1,379 @@
+use crate::core::error::*;
+use crate::core::io::Writer;
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Target files are written into a staging directory next to their destination and only moved
+// into place once every file of the run is written, so a run failing halfway leaves the existing
+// files as they were. The staging directory is on the same filesystem as the destination, so
+// each move is a rename that replaces the old file in one step. The files replaced are kept in
+// the staging directory until the run is committed, so a run failing after its files were moved
+// into place can still be rolled back.
+
+/// The prefix of the staging directories created in output directories. The directories are
+/// hidden so scrapers reading `*.json` from the output directory never see staged files.
+pub const STAGING_PREFIX: &str = ".pim-staging";
+
+/// The files written by a run. Staged files are moved to their destinations by `publish`, and
+/// everything published or protected is restored by `rollback` until the run is committed. A
+/// staging dropped without being committed is rolled back.
+#[derive(Debug, Default)]
+pub struct Staging {
+    // Staging directories by the directory their files are moved to.
+    dirs: BTreeMap<PathBuf, PathBuf>,
+    // Staged files and their destinations, in the order they were staged.
+    files: Vec<(PathBuf, PathBuf)>,
+    // Files changed by the run and the backups of their previous contents. Files that didn't
+    // exist before the run have no backup.
+    changed: Vec<(PathBuf, Option<PathBuf>)>,
+    // Files moved into place, and destinations left alone since they already had the staged
+    // contents.
+    published: Vec<PathBuf>,
+    unchanged: Vec<PathBuf>,
+    // Staged and backup files so far, which keeps their names unique.
+    count: usize,
+    committed: bool,
+}
+
+impl Staging {
+    pub fn new() -> Self {
+        Staging::default()
+    }
+
+    /// The path to write the file meant for `path` to. The staging directory next to it is
+    /// created on first use.
+    pub fn stage(&mut self, path: &Path) -> Result<PathBuf> {
+        // The same destination may be staged more than once, so every staged file gets its own
+        // name and the last one written wins, as without staging.
+        let staged = self.staging_path(path, "")?;
+        debug!("Staging {} as {}", path.display(), staged.display());
+        self.files.push((staged.clone(), path.to_path_buf()));
+        Ok(staged)
+    }
+
+    /// Write a file through the staging and move it into place.
+    pub fn write_file(&mut self, path: &Path, mode: u32, content: &[u8]) -> Result<()> {
+        let staged = self.stage(path)?;
+        Writer::Pending(staged, mode).write_all(content)?;
+        self.publish()
+    }
+
+    /// Keep the current contents of a file that is about to be written in place, so it is
+    /// restored on rollback. A file that doesn't exist yet is removed on rollback.
+    pub fn protect(&mut self, path: &Path) -> Result<()> {
+        let backup = match path.exists() {
+            true => {
+                let backup = self.staging_path(path, ".orig")?;
+                std::fs::copy(path, &backup).map_err(|e| {
+                    Error::new(SourceError::Io(e))
+                        .set_context(&format!("backing up {}", path.display()))
+                        .set_output()
+                })?;
+                Some(backup)
+            }
+            false => None,
+        };
+        self.changed.push((path.to_path_buf(), backup));
+        Ok(())
+    }
+
+    /// Move the files staged since the last publish to their destinations, in the order they
+    /// were staged. Staged paths that were never written are skipped, and destinations that
+    /// already have the staged contents and mode are left alone, so their modification time
+    /// only changes when they do.
+    pub fn publish(&mut self) -> Result<()> {
+        for (staged, path) in std::mem::take(&mut self.files) {
+            if !staged.exists() {
+                continue;
+            }
+            if same_file(&staged, &path) {
+                debug!("{} is unchanged", path.display());
+                let _ = std::fs::remove_file(&staged);
+                self.unchanged.push(path);
+                continue;
+            }
+            let backup = self.backup(&path)?;
+            debug!("Publishing {} to {}", staged.display(), path.display());
+            std::fs::rename(&staged, &path).map_err(|e| {
+                Error::new(SourceError::Io(e))
+                    .set_context(&format!(
+                        "moving staged file into place: {}",
+                        path.display()
+                    ))
+                    .set_output()
+            })?;
+            self.changed.push((path.clone(), backup));
+            self.published.push(path);
+        }
+        Ok(())
+    }
+
+    /// Drop the files staged for a path since the last publish, so publishing leaves the path as
+    /// it is.
+    pub fn discard(&mut self, path: &Path) {
+        self.files.retain(|(staged, dest)| {
+            if dest != path {
+                return true;
+            }
+            debug!(
+                "Discarding {} staged for {}",
+                staged.display(),
+                path.display()
+            );
+            let _ = std::fs::remove_file(staged);
+            false
+        });
+    }
+
+    /// The staged file holding the contents about to be published to the path, if it was
+    /// written. A path staged more than once gives the last staged file.
+    pub fn staged(&self, path: &Path) -> Option<&Path> {
+        self.files
+            .iter()
+            .rev()
+            .find(|(staged, dest)| dest == path && staged.exists())
+            .map(|(staged, _)| staged.as_path())
+    }
+
+    /// The files moved into place so far, in the order they were published.
+    pub fn published(&self) -> &[PathBuf] {
+        &self.published
+    }
+
+    /// The files left alone so far since they already had the staged contents.
+    pub fn unchanged(&self) -> &[PathBuf] {
+        &self.unchanged
+    }
+
+    /// Keep the published files and drop the backups of the files they replaced.
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+
+    /// Restore every file published or protected to its state before the run, newest first.
+    /// Every file is tried, and the first failure is returned.
+    pub fn rollback(mut self) -> Result<()> {
+        self.restore()
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for (path, backup) in std::mem::take(&mut self.changed).into_iter().rev() {
+            debug!("Restoring {}", path.display());
+            let restored = match &backup {
+                Some(backup) => std::fs::rename(backup, &path),
+                None => std::fs::remove_file(&path),
+            };
+            if let Err(e) = restored {
+                if result.is_ok() {
+                    result = Err(Error::new(SourceError::Io(e))
+                        .set_context(&format!("restoring {}", path.display()))
+                        .set_output());
+                }
+            }
+        }
+        result
+    }
+
+    // Link the file about to be replaced into the staging directory. The rename replacing it
+    // leaves the linked file untouched. Filesystems without hard links get a copy.
+    fn backup(&mut self, path: &Path) -> Result<Option<PathBuf>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let backup = self.staging_path(path, ".orig")?;
+        std::fs::hard_link(path, &backup)
+            .or_else(|_| std::fs::copy(path, &backup).map(|_| ()))
+            .map_err(|e| {
+                Error::new(SourceError::Io(e))
+                    .set_context(&format!("backing up {}", path.display()))
+                    .set_output()
+            })?;
+        Ok(Some(backup))
+    }
+
+    // A unique path in the staging directory next to the file.
+    fn staging_path(&mut self, path: &Path, suffix: &str) -> Result<PathBuf> {
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let staging_dir = match self.dirs.get(&dir) {
+            Some(staging_dir) => staging_dir.clone(),
+            None => {
+                let staging_dir = create_dir(&dir)?;
+                self.dirs.insert(dir, staging_dir.clone());
+                staging_dir
+            }
+        };
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        self.count += 1;
+        Ok(staging_dir.join(format!("{}-{}{}", self.count, name, suffix)))
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if !self.committed {
+            if let Err(e) = self.restore() {
+                warn!("Could not roll back the files of the run: {}", e);
+            }
+        }
+        for staging_dir in self.dirs.values() {
+            if let Err(e) = std::fs::remove_dir_all(staging_dir) {
+                warn!(
+                    "Could not remove staging directory {}: {}",
+                    staging_dir.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+// Whether two files have the same contents and, on Unix, the same permission mode.
+fn same_file(a: &Path, b: &Path) -> bool {
+    let (Ok(meta_a), Ok(meta_b)) = (std::fs::metadata(a), std::fs::metadata(b)) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if meta_a.permissions().mode() != meta_b.permissions().mode() {
+            return false;
+        }
+    }
+    if meta_a.len() != meta_b.len() {
+        return false;
+    }
+    matches!((std::fs::read(a), std::fs::read(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+// Create a staging directory in the directory, only accessible to the current user. A directory
+// left by another run with the same process ID gets a `.1`, `.2`, ... suffix.
+fn create_dir(dir: &Path) -> Result<PathBuf> {
+    let name = format!("{}-{}", STAGING_PREFIX, std::process::id());
+    let mut staging_dir = dir.join(&name);
+    let mut n = 0;
+    loop {
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        match builder.create(&staging_dir) {
+            Ok(()) => return Ok(staging_dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                n += 1;
+                staging_dir = dir.join(format!("{}.{}", name, n));
+            }
+            Err(e) => {
+                return Err(Error::new(SourceError::Io(e))
+                    .set_context(&format!(
+                        "creating staging directory: {}",
+                        staging_dir.display()
+                    ))
+                    .set_output());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_staging() {
+        let dir = std::env::temp_dir().join(format!("pim-staging-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let node = dir.join("node_targets.json");
+        let ssh = dir.join("ssh_targets.json");
+        let new = dir.join("new_targets.json");
+        std::fs::write(&node, "old").unwrap();
+        std::fs::write(&ssh, "old").unwrap();
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        let staging_dirs = || {
+            std::fs::read_dir(&dir)
+                .unwrap()
+                .flatten()
+                .filter(|e| e.file_name().to_string_lossy().starts_with(STAGING_PREFIX))
+                .count()
+        };
+
+        // Nothing is moved into place unless the staging is published.
+        let mut staging = Staging::new();
+        std::fs::write(staging.stage(&node).unwrap(), "new").unwrap();
+        drop(staging);
+        assert_eq!(read(&node), "old");
+        assert_eq!(staging_dirs(), 0);
+
+        // Published and protected files are restored unless the staging is committed.
+        let mut staging = Staging::new();
+        std::fs::write(staging.stage(&node).unwrap(), "new").unwrap();
+        staging.write_file(&new, 0o640, b"new").unwrap();
+        staging.protect(&ssh).unwrap();
+        std::fs::write(&ssh, "new").unwrap();
+        assert_eq!(read(&node), "new");
+        staging.rollback().unwrap();
+        assert_eq!(read(&node), "old");
+        assert_eq!(read(&ssh), "old");
+        assert!(!new.exists());
+        assert_eq!(staging_dirs(), 0);
+
+        let mut staging = Staging::new();
+        std::fs::write(staging.stage(&node).unwrap(), "new").unwrap();
+        staging.stage(&ssh).unwrap();
+        assert!(staging.staged(&node).is_some());
+        assert_eq!(staging.staged(&ssh), None);
+        staging.publish().unwrap();
+        staging.commit();
+        assert_eq!(read(&node), "new");
+        assert_eq!(read(&ssh), "old");
+        assert_eq!(staging_dirs(), 0);
+
+        // A file with the staged contents is left alone.
+        let mut staging = Staging::new();
+        std::fs::write(staging.stage(&node).unwrap(), "new").unwrap();
+        std::fs::write(staging.stage(&ssh).unwrap(), "new").unwrap();
+        staging.publish().unwrap();
+        assert_eq!(staging.published(), std::slice::from_ref(&ssh));
+        assert_eq!(staging.unchanged(), std::slice::from_ref(&node));
+        staging.commit();
+        assert_eq!(read(&ssh), "new");
+
+        // A discarded file is not published.
+        let mut staging = Staging::new();
+        std::fs::write(staging.stage(&node).unwrap(), "newer").unwrap();
+        std::fs::write(staging.stage(&ssh).unwrap(), "newer").unwrap();
+        staging.discard(&ssh);
+        assert_eq!(staging.staged(&ssh), None);
+        staging.publish().unwrap();
+        staging.commit();
+        assert_eq!(read(&node), "newer");
+        assert_eq!(read(&ssh), "new");
+        assert_eq!(staging_dirs(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unwritable_output() {
+        let dir = std::env::temp_dir().join(format!("pim-unwritable-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // A file where the output directory should be can't be written into, even by root.
+        let not_dir = dir.join("targets");
+        std::fs::write(&not_dir, "").unwrap();
+        for path in [not_dir.join("node.json"), dir.join("missing/node.json")] {
+            let err = Staging::new().write_file(&path, 0o640, b"[]").unwrap_err();
+            assert!(err.is_output());
+            assert_eq!(err.sysexit(), EX_CANTCREAT);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+/// Render rows as a plain text table with a header row. Columns are padded to their widest cell
+/// and separated by two spaces, and trailing whitespace is trimmed.
+pub fn render(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let header: Vec<String> = header.iter().map(|cell| cell.to_string()).collect();
+    let mut out = String::new();
+    for row in std::iter::once(&header).chain(rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Render rows as CSV with a header row. Fields with commas, quotes, or line breaks are quoted.
+pub fn csv(header: &[&str], rows: &[Vec<String>]) -> String {
+    let header: Vec<String> = header.iter().map(|cell| cell.to_string()).collect();
+    let mut out = String::new();
+    for row in std::iter::once(&header).chain(rows) {
+        let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let rows = vec![
+            vec![
+                "node".to_string(),
+                "web1:9100".to_string(),
+                "up".to_string(),
+            ],
+            vec!["blackbox".to_string(), "a".to_string(), String::new()],
+        ];
+        assert_eq!(
+            render(&["JOB", "TARGET", "STATUS"], &rows),
+            "JOB       TARGET     STATUS\nnode      web1:9100  up\nblackbox  a\n"
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        let rows = vec![
+            vec!["team-a".to_string(), "web1:9100".to_string()],
+            vec![String::new(), "a \"b\", c".to_string()],
+        ];
+        assert_eq!(
+            csv(&["team", "target"], &rows),
+            "team,target\nteam-a,web1:9100\n,\"a \"\"b\"\", c\"\n"
+        );
+    }
+}
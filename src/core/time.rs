@@ -0,0 +1,126 @@
+use crate::core::error::*;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds since the Unix epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The time recorded in generated metadata, such as manifests, in seconds since the Unix epoch.
+/// Reproducible runs record `SOURCE_DATE_EPOCH`, or the epoch itself when it isn't set to a
+/// number of seconds, so the same sources always produce the same files.
+pub fn metadata_time(reproducible: bool) -> u64 {
+    match reproducible {
+        true => source_date_epoch(std::env::var("SOURCE_DATE_EPOCH").ok()).unwrap_or(0),
+        false => unix_now(),
+    }
+}
+
+// Parse a `SOURCE_DATE_EPOCH` value, as defined by reproducible-builds.org.
+fn source_date_epoch(value: Option<String>) -> Option<u64> {
+    value?.trim().parse().ok()
+}
+
+/// Format seconds since the Unix epoch as an RFC 3339 UTC timestamp, such as
+/// `2024-01-31T12:00:00Z`.
+pub fn format_rfc3339(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+/// Format seconds since the Unix epoch as a compact UTC timestamp safe for file names, such as
+/// `20240131T120000Z`.
+pub fn format_compact(secs: u64) -> String {
+    format_rfc3339(secs).replace(['-', ':'], "")
+}
+
+// Convert days since the Unix epoch to a (year, month, day) date. From Howard Hinnant's date
+// algorithms.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Parse a duration such as `500ms`, `30s`, `5m`, or `1h`. A bare number is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value.parse().map_err(|_| invalid_duration(s))?;
+    let seconds = match unit.trim() {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(invalid_duration(s)),
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid_duration(s))
+}
+
+fn invalid_duration(s: &str) -> Error {
+    Error::new(SourceError::Msg(format!(
+        "Invalid duration '{}', expected a number with an optional unit (ms, s, m, h)",
+        s
+    )))
+    .set_kind(ErrorKind::Config)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_format_rfc3339() {
+        assert_eq!(format_rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_rfc3339(1_706_702_400), "2024-01-31T12:00:00Z");
+        assert_eq!(format_compact(1_706_702_400), "20240131T120000Z");
+    }
+
+    #[test]
+    fn test_source_date_epoch() {
+        assert_eq!(
+            source_date_epoch(Some("1706702400".to_string())),
+            Some(1_706_702_400)
+        );
+        assert_eq!(source_date_epoch(Some("yesterday".to_string())), None);
+        assert_eq!(source_date_epoch(None), None);
+        assert!(metadata_time(false) > 0);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("2s").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1.5h").unwrap(), Duration::from_secs(5400));
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("abc").is_err());
+        assert!(parse_duration("").is_err());
+    }
+}
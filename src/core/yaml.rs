@@ -0,0 +1,1044 @@
+use crate::core::error::*;
+use crate::core::output::{Style, YamlQuote, YamlStyle};
+use serde_yaml::{Mapping, Value};
+
+/// Serialize content as block style YAML with the indent width, quoting, and target list style
+/// of the output style. serde_yaml can't change these, so the YAML is emitted by hand. With the
+/// default style the output is the same as serde_yaml's.
+pub fn to_string<T: serde::Serialize>(content: &T, style: &Style) -> Result<String> {
+    let value = serde_yaml::to_value(content).map_err(|e| {
+        Error::new(SourceError::SerdeYaml(e))
+            .set_context("Failed to serialize to YAML")
+            .set_kind(ErrorKind::Internal)
+    })?;
+
+    let emitter = Emitter {
+        indent: style.indent.max(1),
+        quote: style.yaml_quote,
+        flow_targets: style.yaml == YamlStyle::FlowTargets,
+    };
+    let mut out = String::new();
+    match &value {
+        Value::Mapping(map) if !map.is_empty() => emitter.mapping(&mut out, map, 0, false),
+        Value::Sequence(seq) if !seq.is_empty() => emitter.sequence(&mut out, seq, 0),
+        _ => {
+            out += &emitter.scalar(&value, false);
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+struct Emitter {
+    indent: usize,
+    quote: YamlQuote,
+    flow_targets: bool,
+}
+
+impl Emitter {
+    // Write the entries of a mapping at the indent. The first entry follows a `- ` already
+    // written when `inline_first` is set.
+    fn mapping(&self, out: &mut String, map: &Mapping, indent: usize, inline_first: bool) {
+        for (i, (key, value)) in map.iter().enumerate() {
+            if i > 0 || !inline_first {
+                out.push_str(&" ".repeat(indent));
+            }
+            out.push_str(&self.key(key));
+            out.push(':');
+            let flow = self.flow_targets
+                && key.as_str() == Some("targets")
+                && matches!(value, Value::Sequence(seq) if is_flat(seq));
+            match value {
+                Value::Mapping(map) if !map.is_empty() => {
+                    out.push('\n');
+                    self.mapping(out, map, indent + self.indent, false);
+                }
+                // Sequences in mappings aren't indented, like serde_yaml writes them.
+                Value::Sequence(seq) if !seq.is_empty() && !flow => {
+                    out.push('\n');
+                    self.sequence(out, seq, indent);
+                }
+                _ => {
+                    out.push(' ');
+                    out.push_str(&self.scalar(value, false));
+                    out.push('\n');
+                }
+            }
+        }
+    }
+
+    fn sequence(&self, out: &mut String, seq: &[Value], indent: usize) {
+        for value in seq {
+            out.push_str(&" ".repeat(indent));
+            out.push('-');
+            match value {
+                Value::Mapping(map) if !map.is_empty() => {
+                    out.push(' ');
+                    self.mapping(out, map, indent + 2, true);
+                }
+                Value::Sequence(seq) if !seq.is_empty() => {
+                    out.push('\n');
+                    self.sequence(out, seq, indent + 2);
+                }
+                _ => {
+                    out.push(' ');
+                    out.push_str(&self.scalar(value, false));
+                    out.push('\n');
+                }
+            }
+        }
+    }
+
+    // Write a scalar, an empty collection, or a flat sequence in flow style.
+    fn scalar(&self, value: &Value, flow: bool) -> String {
+        match value {
+            Value::Null => "null".to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => self.string(s, flow),
+            Value::Sequence(seq) => {
+                let items: Vec<String> = seq.iter().map(|v| self.scalar(v, true)).collect();
+                format!("[{}]", items.join(", "))
+            }
+            Value::Mapping(_) => "{}".to_string(),
+            Value::Tagged(tagged) => format!("{} {}", tagged.tag, self.scalar(&tagged.value, flow)),
+        }
+    }
+
+    // Keys are only quoted when they must be.
+    fn key(&self, key: &Value) -> String {
+        match key {
+            Value::String(s) if needs_quotes(s, false) => quote_needed(s),
+            Value::String(s) => s.clone(),
+            _ => self.scalar(key, true),
+        }
+    }
+
+    fn string(&self, s: &str, flow: bool) -> String {
+        match self.quote {
+            YamlQuote::Auto if needs_quotes(s, flow) => quote_needed(s),
+            YamlQuote::Auto => s.to_string(),
+            YamlQuote::Single if s.chars().any(char::is_control) => double_quote(s),
+            YamlQuote::Single => single_quote(s),
+            YamlQuote::Double => double_quote(s),
+        }
+    }
+}
+
+// Whether a sequence holds only scalars, so it can be written in flow style.
+fn is_flat(seq: &[Value]) -> bool {
+    seq.iter()
+        .all(|v| !matches!(v, Value::Sequence(_) | Value::Mapping(_) | Value::Tagged(_)))
+}
+
+// Whether a string can't be written as a plain scalar, since it would be read as another type
+// or break the YAML around it.
+fn needs_quotes(s: &str, flow: bool) -> bool {
+    const INDICATORS: &str = "-?:,[]{}#&*!|>'\"%@`";
+    const RESERVED: &[&str] = &[
+        "null", "~", "true", "false", "yes", "no", "on", "off", "y", "n", ".inf", "-.inf", "+.inf",
+        ".nan",
+    ];
+
+    let Some(first) = s.chars().next() else {
+        return true;
+    };
+    let lower = s.to_lowercase();
+    let number = s.replace('_', "");
+    INDICATORS.contains(first)
+        || s.trim() != s
+        || s.ends_with(':')
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.chars().any(char::is_control)
+        || (flow && s.contains([',', '[', ']', '{', '}']))
+        || RESERVED.contains(&lower.as_str())
+        || number.parse::<f64>().is_ok()
+        || lower.starts_with("0x")
+        || lower.starts_with("0o")
+        || is_date(s)
+}
+
+// Whether a string starts like a YAML timestamp, such as 2024-01-31.
+fn is_date(s: &str) -> bool {
+    let b = s.as_bytes();
+    b.len() >= 10
+        && b[..4].iter().all(u8::is_ascii_digit)
+        && b[4] == b'-'
+        && b[5..7].iter().all(u8::is_ascii_digit)
+        && b[7] == b'-'
+        && b[8..10].iter().all(u8::is_ascii_digit)
+}
+
+// Quote a string that can't be plain, with single quotes unless it has control characters.
+fn quote_needed(s: &str) -> String {
+    match s.chars().any(char::is_control) {
+        true => double_quote(s),
+        false => single_quote(s),
+    }
+}
+
+fn single_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn double_quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The most nodes a YAML document may have with its aliases expanded, by default.
+pub const DEFAULT_MAX_NODES: usize = 1_000_000;
+
+/// Deserialize YAML with its merge keys applied, such as `<<: *defaults` copying the fields of
+/// the `&defaults` mapping that aren't set next to it. serde_yaml resolves anchors and aliases,
+/// but leaves merge keys as fields named `<<`. Documents with more than `DEFAULT_MAX_NODES` nodes
+/// are rejected.
+pub fn from_str<T: serde::de::DeserializeOwned>(text: &str) -> serde_yaml::Result<T> {
+    from_str_limited(text, DEFAULT_MAX_NODES)
+}
+
+/// Deserialize YAML like `from_str`, rejecting documents with more than `max_nodes` nodes once
+/// their aliases are expanded. Aliases can repeat a node any number of times, so a small
+/// document, such as one with aliases of aliases, can expand to more than fits in memory. The
+/// nodes are counted without keeping them before the document is deserialized.
+pub fn from_str_limited<T: serde::de::DeserializeOwned>(
+    text: &str,
+    max_nodes: usize,
+) -> serde_yaml::Result<T> {
+    use serde::de::DeserializeSeed;
+    let mut counter = NodeCounter {
+        nodes: 0,
+        max: max_nodes,
+    };
+    for document in serde_yaml::Deserializer::from_str(text) {
+        (&mut counter).deserialize(document)?;
+    }
+
+    if !text.contains("<<") {
+        return serde_yaml::from_str(text);
+    }
+    let mut value: Value = serde_yaml::from_str(text)?;
+    if !has_merge_key(&value) {
+        return serde_yaml::from_str(text);
+    }
+    // `apply_merge` copies the fields of a merged mapping as they are, so a merge key that came
+    // with an alias, such as `<<: *base` where `base` merges `*defaults`, takes another pass.
+    while has_merge_key(&value) {
+        value.apply_merge()?;
+    }
+    T::deserialize(Scalars(value))
+}
+
+// Deserializes a value like serde_yaml deserializes YAML text, where numbers and booleans are
+// read as strings when strings are expected, such as `port: 9100` in labels. `from_value` rejects
+// them. The text of the scalars is gone, so they are read as serde_yaml writes them.
+struct Scalars(Value);
+
+impl<'de> serde::Deserializer<'de> for Scalars {
+    type Error = serde_yaml::Error;
+
+    fn deserialize_any<V: serde::de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> serde_yaml::Result<V::Value> {
+        use serde::de::value::{MapDeserializer, SeqDeserializer};
+        match self.0 {
+            Value::Sequence(values) => {
+                let mut seq = SeqDeserializer::new(values.into_iter().map(Scalars));
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Value::Mapping(mapping) => {
+                let entries = mapping.into_iter().map(|(k, v)| (Scalars(k), Scalars(v)));
+                let mut map = MapDeserializer::new(entries);
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_str<V: serde::de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> serde_yaml::Result<V::Value> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: serde::de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> serde_yaml::Result<V::Value> {
+        match self.0 {
+            Value::Number(number) => visitor.visit_string(number.to_string()),
+            Value::Bool(boolean) => visitor.visit_string(boolean.to_string()),
+            value => value.deserialize_string(visitor),
+        }
+    }
+
+    fn deserialize_option<V: serde::de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> serde_yaml::Result<V::Value> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            value => visitor.visit_some(Scalars(value)),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: serde::de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> serde_yaml::Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: serde::de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> serde_yaml::Result<V::Value> {
+        self.0.deserialize_enum(name, variants, visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl serde::de::IntoDeserializer<'_, serde_yaml::Error> for Scalars {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+// Walks a document without keeping it, counting its nodes with aliases expanded, and fails once
+// there are more than the most allowed.
+struct NodeCounter {
+    nodes: usize,
+    max: usize,
+}
+
+impl NodeCounter {
+    fn count<E: serde::de::Error>(&mut self) -> std::result::Result<(), E> {
+        self.nodes += 1;
+        match self.nodes > self.max {
+            true => Err(E::custom(format!(
+                "the document has more than {} nodes with its aliases expanded",
+                self.max
+            ))),
+            false => Ok(()),
+        }
+    }
+}
+
+impl<'de> serde::de::DeserializeSeed<'de> for &mut NodeCounter {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for &mut NodeCounter {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("any YAML node")
+    }
+
+    fn visit_bool<E: serde::de::Error>(self, _: bool) -> std::result::Result<(), E> {
+        self.count()
+    }
+
+    fn visit_i64<E: serde::de::Error>(self, _: i64) -> std::result::Result<(), E> {
+        self.count()
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, _: u64) -> std::result::Result<(), E> {
+        self.count()
+    }
+
+    fn visit_f64<E: serde::de::Error>(self, _: f64) -> std::result::Result<(), E> {
+        self.count()
+    }
+
+    fn visit_str<E: serde::de::Error>(self, _: &str) -> std::result::Result<(), E> {
+        self.count()
+    }
+
+    fn visit_unit<E: serde::de::Error>(self) -> std::result::Result<(), E> {
+        self.count()
+    }
+
+    fn visit_none<E: serde::de::Error>(self) -> std::result::Result<(), E> {
+        self.count()
+    }
+
+    fn visit_some<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<(), D::Error> {
+        use serde::de::DeserializeSeed;
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<(), A::Error> {
+        self.count()?;
+        while seq.next_element_seed(&mut *self)?.is_some() {}
+        Ok(())
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> std::result::Result<(), A::Error> {
+        self.count()?;
+        while map.next_key_seed(&mut *self)?.is_some() {
+            map.next_value_seed(&mut *self)?;
+        }
+        Ok(())
+    }
+
+    // Tagged nodes, such as `!secret value`, are enums with the tag as the variant.
+    fn visit_enum<A: serde::de::EnumAccess<'de>>(
+        self,
+        data: A,
+    ) -> std::result::Result<(), A::Error> {
+        use serde::de::VariantAccess;
+        let (_, variant) = data.variant::<serde::de::IgnoredAny>()?;
+        variant.newtype_variant_seed(self)
+    }
+}
+
+// Whether a value has a merge key in any of its mappings.
+fn has_merge_key(value: &Value) -> bool {
+    match value {
+        Value::Mapping(mapping) => {
+            mapping.contains_key("<<") || mapping.values().any(has_merge_key)
+        }
+        Value::Sequence(values) => values.iter().any(has_merge_key),
+        Value::Tagged(tagged) => has_merge_key(&tagged.value),
+        _ => false,
+    }
+}
+
+// Source files are written by hand, so their YAML is edited as lines instead of being parsed and
+// serialized again, which would drop comments and anchors and reorder keys. Only block lists of
+// block mappings, the shape of source files, can be edited this way.
+
+/// A YAML list of mappings, such as a source file, kept as its lines so its items can be edited
+/// without losing comments, anchors, or formatting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockList {
+    // Comments, blank lines, and the document start before the first item. In a versioned
+    // document, also the lines up to its `sources` key.
+    head: Vec<String>,
+    items: Vec<Item>,
+    // The lines after the list, such as other keys of a versioned document.
+    tail: Vec<String>,
+}
+
+/// An item of a `BlockList` with the comments and blank lines after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    lines: Vec<String>,
+}
+
+/// A field of an item: the line it starts on, its name, and the column of its name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub line: usize,
+    pub name: String,
+    pub col: usize,
+}
+
+impl BlockList {
+    /// Read the lines of a YAML list. Returns None for other documents, such as lists in flow
+    /// style or several documents.
+    pub fn parse(text: &str) -> Option<Self> {
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        let first = lines.iter().position(|l| {
+            let l = l.trim();
+            is_content(l) && l != "---" && !l.starts_with('%')
+        })?;
+        let col = indentation(&lines[first]);
+        let mut items: Vec<Item> = Vec::new();
+        for line in &lines[first..] {
+            if is_content(line) && indentation(line) <= col {
+                if indentation(line) != col || !is_item(&line[col..]) {
+                    return None;
+                }
+                items.push(Item { lines: Vec::new() });
+            }
+            items.last_mut()?.lines.push(line.clone());
+        }
+        Some(BlockList {
+            head: lines[..first].to_vec(),
+            items,
+            tail: Vec::new(),
+        })
+    }
+
+    /// Read the lines of a YAML source document: a list of sources, or a versioned document
+    /// with a list of sources under its top level `sources` key. Returns None for other
+    /// documents, such as sources in flow style.
+    pub fn parse_sources(text: &str) -> Option<Self> {
+        if let Some(list) = BlockList::parse(text) {
+            return Some(list);
+        }
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        let key = lines.iter().position(|l| {
+            l.strip_prefix("sources:")
+                .is_some_and(|rest| !is_content(rest))
+        })?;
+        // The items of a list under a key can be at the column of the key.
+        let end = lines[key + 1..]
+            .iter()
+            .position(|l| is_content(l) && indentation(l) == 0 && !is_item(l))
+            .map_or(lines.len(), |pos| key + 1 + pos);
+        let mut list = BlockList::parse(&lines[key + 1..end].join("\n"))?;
+        list.head.splice(0..0, lines[..=key].iter().cloned());
+        list.tail = lines[end..].to_vec();
+        Some(list)
+    }
+
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    pub fn items_mut(&mut self) -> &mut [Item] {
+        &mut self.items
+    }
+
+    /// Order the fields of every item that is a block mapping by `Item::sort_fields`. The
+    /// comments of a field moved to the line of an item's `-` are moved above the item.
+    pub fn sort_fields(&mut self, order: &[&str]) {
+        for i in 0..self.items.len() {
+            if self.items[i].is_alias() {
+                continue;
+            }
+            let Some(moved) = self.items[i].sort_fields(order) else {
+                continue;
+            };
+            match i {
+                0 => self.head.extend(moved),
+                _ => self.items[i - 1].lines.extend(moved),
+            }
+        }
+    }
+
+    /// Move every item so its `-` is at the column, keeping the indentation of its lines
+    /// relative to each other, and put the fields of each item two columns after its `-`. Items
+    /// with lines that can't be moved that far left are kept. Comments right above the first
+    /// item are moved to the column too.
+    pub fn indent(&mut self, col: usize) {
+        let above = self
+            .head
+            .iter()
+            .rposition(|l| is_content(l))
+            .map_or(0, |i| i + 1);
+        for line in &mut self.head[above..] {
+            if !line.trim().is_empty() && line.trim_start() != "---" {
+                *line = format!("{}{}", " ".repeat(col), line.trim_start());
+            }
+        }
+        for item in &mut self.items {
+            let dash = item.lines.first().map_or(col, |l| indentation(l));
+            let fits = item
+                .lines
+                .iter()
+                .filter(|l| is_content(l))
+                .all(|l| indentation(l) + col >= dash);
+            if dash != col && fits {
+                shift(&mut item.lines, dash, col);
+            }
+            item.align_fields();
+        }
+    }
+}
+
+impl std::fmt::Display for BlockList {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for line in self
+            .head
+            .iter()
+            .chain(self.items.iter().flat_map(|i| &i.lines))
+            .chain(&self.tail)
+        {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Item {
+    // Put the fields of the item two columns after its `-`, with one space after the `-` for an
+    // inline first field. Items with an anchor or tag before an inline field are kept.
+    fn align_fields(&mut self) {
+        let Some(field) = self.fields().and_then(|f| f.first().cloned()) else {
+            return;
+        };
+        let dash = indentation(&self.lines[0]);
+        let target = dash + 2;
+        let inline = field.line == 0;
+        if field.col == target
+            || (inline && !self.lines[0][dash + 1..field.col].trim().is_empty())
+            || self.lines[1..]
+                .iter()
+                .any(|l| is_content(l) && indentation(l) < field.col)
+        {
+            return;
+        }
+        if inline {
+            self.lines[0] = format!("{}- {}", " ".repeat(dash), &self.lines[0][field.col..]);
+        }
+        shift(&mut self.lines[1..], field.col, target);
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Whether the item is an alias of another node, which changes with it.
+    pub fn is_alias(&self) -> bool {
+        self.lines
+            .first()
+            .is_some_and(|l| l.trim_start()[1..].trim_start().starts_with('*'))
+    }
+
+    /// The fields of the item. Returns None for items that aren't block mappings.
+    pub fn fields(&self) -> Option<Vec<Field>> {
+        let first = self.lines.first()?;
+        let dash = indentation(first);
+        // Anchors and tags of the mapping come before its first field or on the line alone.
+        let mut rest = first[dash + 1..].trim_start();
+        while rest.starts_with(['&', '!']) {
+            rest = rest.find(' ').map_or("", |end| rest[end..].trim_start());
+        }
+        let inline = is_content(rest);
+        let col = match inline {
+            true => first.len() - rest.len(),
+            false => indentation(self.lines.iter().skip(1).find(|l| is_content(l))?),
+        };
+        if col <= dash {
+            return None;
+        }
+
+        let mut fields = Vec::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            let starts = match i {
+                0 => inline,
+                _ => is_content(line) && indentation(line) == col,
+            };
+            if !starts {
+                continue;
+            }
+            let text = &line[col..];
+            // Lists can be written at the indentation of their field.
+            if is_item(text) {
+                continue;
+            }
+            let name: String = text
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '<'))
+                .collect();
+            let after = text[name.len()..].trim_start();
+            if name.is_empty() || !(after == ":" || after.starts_with(": ")) {
+                return None;
+            }
+            fields.push(Field { line: i, name, col });
+        }
+        Some(fields)
+    }
+
+    /// Rename a field, unless the item has a field with the new name. Returns whether the field
+    /// was renamed.
+    pub fn rename(&mut self, old: &str, new: &str) -> Option<bool> {
+        let fields = self.fields()?;
+        if fields.iter().any(|f| f.name == new) {
+            return Some(false);
+        }
+        let Some(field) = fields.into_iter().find(|f| f.name == old) else {
+            return Some(false);
+        };
+        let line = &self.lines[field.line];
+        let renamed = format!(
+            "{}{}{}",
+            &line[..field.col],
+            new,
+            &line[field.col + old.len()..]
+        );
+        self.lines[field.line] = renamed;
+        Some(true)
+    }
+
+    /// Turn a single value of a field into a list of one in flow style, such as `job: node`
+    /// into `job: [node]`. Returns whether the value was changed. Values on the lines after the
+    /// field, anchored or tagged values, and block scalars are left alone.
+    pub fn to_list(&mut self, name: &str) -> Option<bool> {
+        let Some(field) = self.fields()?.into_iter().find(|f| f.name == name) else {
+            return Some(false);
+        };
+        let line = &self.lines[field.line];
+        let colon = field.col + name.len() + line[field.col + name.len()..].find(':')?;
+        let rest = &line[colon + 1..];
+        let (value, comment) = match rest.find(" #") {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, ""),
+        };
+        // The space before a comment is kept.
+        let space = &value[value.trim_end().len()..];
+        let value = value.trim();
+        if value.is_empty() || value.starts_with(['[', '{', '|', '>', '&', '*', '!']) {
+            return Some(false);
+        }
+        let listed = format!("{}: [{}]{}{}", &line[..colon], value, space, comment);
+        self.lines[field.line] = listed;
+        Some(true)
+    }
+
+    /// Add a field with a value in flow style before the first field of the item named in
+    /// `before`, or after its last field. Comments right above that field stay with it.
+    pub fn insert(&mut self, name: &str, value: &str, before: &[&str]) -> Option<()> {
+        let fields = self.fields()?;
+        let col = fields.first()?.col;
+        let added = format!("{}: {}", name, value);
+        match fields.iter().find(|f| before.contains(&f.name.as_str())) {
+            // The first field of an item shares its line with the `-` of the list.
+            Some(field) if field.line == 0 => {
+                let first = &self.lines[0];
+                let moved = format!("{}{}", " ".repeat(col), &first[col..]);
+                self.lines[0] = format!("{}{}", &first[..col], added);
+                self.lines.insert(1, moved);
+            }
+            Some(field) => {
+                let mut at = field.line;
+                while at > 1 && self.lines[at - 1].trim_start().starts_with('#') {
+                    at -= 1;
+                }
+                self.lines
+                    .insert(at, format!("{}{}", " ".repeat(col), added));
+            }
+            None => {
+                let last = self.lines.iter().rposition(|l| is_content(l))?;
+                self.lines
+                    .insert(last + 1, format!("{}{}", " ".repeat(col), added));
+            }
+        }
+        Some(())
+    }
+
+    /// Order the fields of the item by their position in `order`, with fields not in it last in
+    /// the order they are in. Each field moves with the comments right above it and the lines of
+    /// its value. Returns the comments that were above a field moved to the line of the `-`,
+    /// which belong above the item, or None for items that aren't block mappings.
+    pub fn sort_fields(&mut self, order: &[&str]) -> Option<Vec<String>> {
+        let fields = self.fields()?;
+        let rank = |f: &Field| {
+            order
+                .iter()
+                .position(|o| *o == f.name)
+                .unwrap_or(order.len())
+        };
+        if fields.windows(2).all(|w| rank(&w[0]) <= rank(&w[1])) {
+            return Some(Vec::new());
+        }
+
+        let col = fields.first()?.col;
+        let inline = fields.first()?.line == 0;
+        let last = self.lines.iter().rposition(|l| is_content(l))?;
+        let starts: Vec<usize> = fields
+            .iter()
+            .map(|f| {
+                let mut at = f.line;
+                while at > 1 && self.lines[at - 1].trim_start().starts_with('#') {
+                    at -= 1;
+                }
+                at
+            })
+            .collect();
+        let mut blocks: Vec<(usize, Vec<String>)> = starts
+            .iter()
+            .enumerate()
+            .map(|(i, start)| {
+                let end = starts.get(i + 1).copied().unwrap_or(last + 1);
+                (rank(&fields[i]), self.lines[*start..end].to_vec())
+            })
+            .collect();
+        let tail = self.lines.split_off(last + 1);
+
+        // The `-`, and any anchor or tag, of an item whose first field is on its line.
+        let prefix = match inline {
+            true => {
+                let first = &mut blocks[0].1[0];
+                let prefix = first[..col].to_string();
+                *first = format!("{}{}", " ".repeat(col), &first[col..]);
+                prefix
+            }
+            false => String::new(),
+        };
+        blocks.sort_by_key(|(rank, _)| *rank);
+
+        let mut moved = Vec::new();
+        let mut lines: Vec<String> = match inline {
+            true => Vec::new(),
+            false => self.lines[..starts[0]].to_vec(),
+        };
+        for (i, (_, block)) in blocks.into_iter().enumerate() {
+            if i == 0 && inline {
+                let dash = indentation(&prefix);
+                let at = block.iter().position(|l| is_content(l))?;
+                moved.extend(
+                    block[..at]
+                        .iter()
+                        .map(|l| format!("{}{}", " ".repeat(dash), l.trim_start())),
+                );
+                lines.push(format!("{}{}", prefix, &block[at][col..]));
+                lines.extend(block[at + 1..].iter().cloned());
+                continue;
+            }
+            lines.extend(block);
+        }
+        lines.extend(tail);
+        self.lines = lines;
+        Some(moved)
+    }
+
+    /// Replace the item with a block mapping, such as one written by `to_string`. The comments
+    /// and blank lines after the item are kept.
+    pub fn replace(&mut self, mapping: &str) {
+        let dash = self.lines.first().map_or(0, |l| indentation(l));
+        let last = self.lines.iter().rposition(|l| is_content(l)).unwrap_or(0);
+        let after = self.lines.split_off(last + 1);
+        self.lines = mapping
+            .lines()
+            .enumerate()
+            .map(|(i, l)| match (i, l.is_empty()) {
+                (0, _) => format!("{}- {}", " ".repeat(dash), l),
+                (_, true) => String::new(),
+                _ => format!("{}  {}", " ".repeat(dash), l),
+            })
+            .collect();
+        self.lines.extend(after);
+    }
+}
+
+// Whether a line holds more than whitespace or a comment.
+fn is_content(line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty() && !line.starts_with('#')
+}
+
+// Whether text starts a list item.
+fn is_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+// Move lines indented at `from` or more to start at `to` instead. Comments may be less indented
+// than the lines around them, and are moved as far as they can.
+fn shift(lines: &mut [String], from: usize, to: usize) {
+    for line in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let text = line.trim_start_matches(' ');
+        let indent = (indentation(line) + to).saturating_sub(from);
+        *line = format!("{}{}", " ".repeat(indent), text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    fn content() -> serde_json::Value {
+        json!({
+            "node": [
+                {"labels": {"env": "prod", "job": "node", "note": "a: b", "port": "9100"},
+                 "targets": ["web1:9100", "web2:9100"]},
+                {"labels": {}, "targets": []}
+            ],
+            "empty": []
+        })
+    }
+
+    #[test]
+    fn test_default_matches_serde_yaml() {
+        let yaml = to_string(&content(), &Style::default()).unwrap();
+        assert_eq!(yaml, serde_yaml::to_string(&content()).unwrap());
+    }
+
+    #[test]
+    fn test_styles() {
+        let style = Style {
+            indent: 4,
+            yaml: YamlStyle::FlowTargets,
+            yaml_quote: YamlQuote::Double,
+            ..Style::default()
+        };
+        let content = json!([{"labels": {"env": "prod"}, "targets": ["web1:9100", "a,b"]}]);
+        assert_eq!(
+            to_string(&content, &style).unwrap(),
+            "- labels:\n      env: \"prod\"\n  targets: [\"web1:9100\", \"a,b\"]\n"
+        );
+
+        let style = Style {
+            yaml: YamlStyle::FlowTargets,
+            ..Style::default()
+        };
+        assert_eq!(
+            to_string(&content, &style).unwrap(),
+            "- labels:\n    env: prod\n  targets: [web1:9100, 'a,b']\n"
+        );
+        let parsed: serde_yaml::Value =
+            serde_yaml::from_str(&to_string(&content, &style).unwrap()).unwrap();
+        assert_eq!(parsed, serde_yaml::to_value(&content).unwrap());
+    }
+
+    #[test]
+    fn test_quote() {
+        let emitter = Emitter {
+            indent: 2,
+            quote: YamlQuote::Auto,
+            flow_targets: false,
+        };
+        for (s, quoted) in [
+            ("web1:9100", "web1:9100"),
+            ("true", "'true'"),
+            ("9100", "'9100'"),
+            ("2024-01-31", "'2024-01-31'"),
+            ("it's: here", "'it''s: here'"),
+            ("-x", "'-x'"),
+            ("", "''"),
+            ("a\nb", "\"a\\nb\""),
+        ] {
+            assert_eq!(emitter.string(s, false), quoted);
+        }
+        let single = Emitter {
+            quote: YamlQuote::Single,
+            ..emitter
+        };
+        assert_eq!(single.string("prod", false), "'prod'");
+    }
+
+    #[test]
+    fn test_block_list() {
+        let text = "# hosts\n---\n- &web\n  job: node  # exporter\n  # web servers\n  targets:\n  \
+                    - web1\n\n- *web\n- targets: [db1]\n  jobs: [pg]\n# flow\n- {jobs: [a]}\n";
+        let mut list = BlockList::parse(text).unwrap();
+        assert_eq!(list.to_string(), text);
+        assert_eq!(list.items().len(), 4);
+        assert!(list.items()[1].is_alias());
+
+        let item = &mut list.items_mut()[0];
+        assert_eq!(
+            item.fields().unwrap(),
+            vec![
+                Field {
+                    line: 1,
+                    name: "job".to_string(),
+                    col: 2
+                },
+                Field {
+                    line: 3,
+                    name: "targets".to_string(),
+                    col: 2
+                },
+            ]
+        );
+        assert_eq!(item.rename("job", "jobs"), Some(true));
+        assert_eq!(item.to_list("jobs"), Some(true));
+        assert_eq!(item.to_list("targets"), Some(false));
+        item.insert("labels", "{}", &["targets"]).unwrap();
+        list.items_mut()[2]
+            .insert("labels", "{}", &["targets"])
+            .unwrap();
+        assert_eq!(list.items()[3].fields(), None);
+        list.items_mut()[3].replace("jobs:\n- a\nlabels: {}\n");
+        assert_eq!(
+            list.to_string(),
+            "# hosts\n---\n- &web\n  jobs: [node]  # exporter\n  labels: {}\n  # web servers\n  \
+             targets:\n  - web1\n\n- *web\n- labels: {}\n  targets: [db1]\n  jobs: [pg]\n# flow\n\
+             - jobs:\n  - a\n  labels: {}\n"
+        );
+
+        assert_eq!(BlockList::parse("[{jobs: [a]}]\n"), None);
+        assert_eq!(BlockList::parse("- a: 1\n---\n- b: 2\n"), None);
+    }
+
+    #[test]
+    fn test_from_str_merge_keys() {
+        let text = "defaults: &defaults\n  jobs: [node]\n  labels: &labels {env: prod, port: 9100}\n\
+                    base: &base\n  <<: *defaults\n  scheme: https\n\
+                    sources:\n  - <<: *base\n    targets: [a]\n\
+                    \x20 - <<: [{jobs: [ssh]}, *defaults]\n    labels:\n      <<: *labels\n      env: dev\n";
+        let value: serde_json::Value = from_str(text).unwrap();
+        assert_eq!(
+            value["sources"],
+            json!([
+                {"jobs": ["node"], "labels": {"env": "prod", "port": 9100}, "scheme": "https", "targets": ["a"]},
+                {"jobs": ["ssh"], "labels": {"env": "dev", "port": 9100}}
+            ])
+        );
+        // Numbers are still read as strings where strings are expected.
+        let labels: BTreeMap<String, BTreeMap<String, String>> =
+            from_str("base: &base {port: 9100, tls: true}\nlabels:\n  <<: *base\n").unwrap();
+        assert_eq!(labels["labels"]["port"], "9100");
+        assert_eq!(labels["labels"]["tls"], "true");
+        let ports: BTreeMap<String, BTreeMap<String, u16>> =
+            from_str("base: &base {port: 9100}\nports:\n  <<: *base\n").unwrap();
+        assert_eq!(ports["ports"]["port"], 9100);
+
+        assert!(from_str::<serde_json::Value>("a:\n  <<: 1\n").is_err());
+        assert!(from_str::<serde_json::Value>("a:\n  <<: [1]\n").is_err());
+        let text: serde_json::Value = from_str("note: '<<'\n").unwrap();
+        assert_eq!(text["note"], "<<");
+    }
+
+    #[test]
+    fn test_from_str_limited() {
+        let bomb = "a: &a [x, x, x, x, x, x, x, x, x, x]\nb: &b [*a, *a, *a, *a, *a, *a, *a, *a, *a, *a]\n\
+                    c: &c [*b, *b, *b, *b, *b, *b, *b, *b, *b, *b]\nd: [*c, *c, *c, *c, *c, *c, *c, *c, *c, *c]\n";
+        let err = from_str_limited::<serde_json::Value>(bomb, 1000).unwrap_err();
+        assert!(err.to_string().contains("more than 1000 nodes"));
+        // The mapping and its keys are 5 nodes, and the lists 11, 111, 1111, and 11111.
+        assert!(from_str_limited::<serde_json::Value>(bomb, 12_349).is_ok());
+        assert!(from_str_limited::<serde_json::Value>(bomb, 12_348).is_err());
+        assert!(from_str_limited::<Value>("- !tag {a: 1}\n", 4).is_ok());
+        assert!(from_str_limited::<Value>("- !tag {a: 1}\n", 3).is_err());
+    }
+}
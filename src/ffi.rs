@@ -0,0 +1,216 @@
+//! C FFI for the conversion core. Enabled with the `ffi` feature.
+//!
+//! All functions take byte buffers and return owned C strings which must be released with
+//! `pim_string_free`. Functions return `0` on success or a pim exit code on failure, in which case
+//! the error message is written to `error_out` when it is not null.
+use crate::app::convert;
+use crate::core::error::*;
+use crate::core::input::InputFormat;
+use crate::core::output::OutputFormat;
+use std::ffi::{CString, c_char, c_int};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+/// Format code for JSON input or output.
+pub const PIM_FORMAT_JSON: c_int = 0;
+/// Format code for YAML input or output.
+pub const PIM_FORMAT_YAML: c_int = 1;
+/// Format code for JSON5 or JSONC input.
+pub const PIM_FORMAT_JSON5: c_int = 2;
+/// Format code for HCL input.
+pub const PIM_FORMAT_HCL: c_int = 3;
+/// Format code for XLSX input. Requires the `xlsx` feature.
+#[cfg(feature = "xlsx")]
+pub const PIM_FORMAT_XLSX: c_int = 4;
+/// Format code for Nagios object definition output.
+pub const PIM_FORMAT_NAGIOS: c_int = 5;
+
+/// Validate source content.
+///
+/// # Safety
+///
+/// `input` must point to `input_len` readable bytes. `error_out` must be null or point to a
+/// writable `char *`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pim_validate(
+    input: *const u8,
+    input_len: usize,
+    input_format: c_int,
+    error_out: *mut *mut c_char,
+) -> c_int {
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        // SAFETY: The caller guarantees the pointers are valid.
+        let content = unsafe { bytes(input, input_len)? };
+        convert::validate(content, input_format_from(input_format)?).map(|_| ())
+    }));
+
+    // SAFETY: The caller guarantees error_out is null or writable.
+    unsafe { finish(res, error_out) }
+}
+
+/// Convert source content into target file content. On success `output_out` receives a JSON
+/// object mapping each job name to the serialized content of its target file.
+///
+/// # Safety
+///
+/// `input` must point to `input_len` readable bytes. `output_out` and `error_out` must be null or
+/// point to a writable `char *`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pim_convert(
+    input: *const u8,
+    input_len: usize,
+    input_format: c_int,
+    output_format: c_int,
+    pretty: bool,
+    output_out: *mut *mut c_char,
+    error_out: *mut *mut c_char,
+) -> c_int {
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        // SAFETY: The caller guarantees the pointers are valid.
+        let content = unsafe { bytes(input, input_len)? };
+        let converted = convert::convert(
+            content,
+            input_format_from(input_format)?,
+            output_format_from(output_format)?,
+            pretty,
+        )?;
+        let json = serde_json::to_string(&converted).map_err(|e| {
+            Error::new(SourceError::SerdeJson(e))
+                .set_context("Failed to serialize converted targets")
+                .set_kind(ErrorKind::Internal)
+        })?;
+
+        // SAFETY: The caller guarantees output_out is null or writable.
+        unsafe { write_string(output_out, &json) };
+        Ok(())
+    }));
+
+    // SAFETY: The caller guarantees error_out is null or writable.
+    unsafe { finish(res, error_out) }
+}
+
+/// Free a string returned by pim.
+///
+/// # Safety
+///
+/// `s` must be null or a pointer previously returned by pim that has not been freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pim_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        // SAFETY: The caller guarantees the pointer came from CString::into_raw.
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> Result<&'a [u8]> {
+    if ptr.is_null() {
+        if len == 0 {
+            return Ok(&[]);
+        }
+
+        return Err(
+            Error::new(SourceError::Msg("Input pointer is null".to_string()))
+                .set_kind(ErrorKind::Config),
+        );
+    }
+
+    // SAFETY: The caller guarantees ptr points to len readable bytes.
+    Ok(unsafe { std::slice::from_raw_parts(ptr, len) })
+}
+
+fn input_format_from(code: c_int) -> Result<InputFormat> {
+    match code {
+        PIM_FORMAT_JSON => Ok(InputFormat::Json),
+        PIM_FORMAT_YAML => Ok(InputFormat::Yaml),
+        PIM_FORMAT_JSON5 => Ok(InputFormat::Json5),
+        PIM_FORMAT_HCL => Ok(InputFormat::Hcl),
+        #[cfg(feature = "xlsx")]
+        PIM_FORMAT_XLSX => Ok(InputFormat::Xlsx),
+        _ => Err(
+            Error::new(SourceError::UnsupportedInputFormat(code.to_string()))
+                .set_kind(ErrorKind::Config),
+        ),
+    }
+}
+
+fn output_format_from(code: c_int) -> Result<OutputFormat> {
+    match code {
+        PIM_FORMAT_JSON => Ok(OutputFormat::Json),
+        PIM_FORMAT_YAML => Ok(OutputFormat::Yaml),
+        PIM_FORMAT_NAGIOS => Ok(OutputFormat::Nagios),
+        _ => Err(
+            Error::new(SourceError::UnsupportedOutputFormat(code.to_string()))
+                .set_kind(ErrorKind::Config),
+        ),
+    }
+}
+
+unsafe fn write_string(out: *mut *mut c_char, s: &str) {
+    if out.is_null() {
+        return;
+    }
+
+    // Interior nul bytes can't be represented in a C string, so replace them.
+    let s = CString::new(s.replace('\0', "\\0")).unwrap_or_default();
+    // SAFETY: The caller guarantees out is writable.
+    unsafe { *out = s.into_raw() };
+}
+
+unsafe fn finish(res: std::thread::Result<Result<()>>, error_out: *mut *mut c_char) -> c_int {
+    match res {
+        Ok(Ok(())) => CODE_SUCCESS,
+        Ok(Err(e)) => {
+            // SAFETY: The caller guarantees error_out is null or writable.
+            unsafe { write_string(error_out, &e.to_string()) };
+            e.code()
+        }
+        Err(_) => {
+            // SAFETY: The caller guarantees error_out is null or writable.
+            unsafe { write_string(error_out, "pim panicked") };
+            CODE_RUNTIME_ERROR
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_pim_convert() {
+        let source = b"- jobs: [node]\n  labels: {}\n  targets: [host]\n";
+        let mut output: *mut c_char = std::ptr::null_mut();
+        let mut error: *mut c_char = std::ptr::null_mut();
+        let code = unsafe {
+            pim_convert(
+                source.as_ptr(),
+                source.len(),
+                PIM_FORMAT_YAML,
+                PIM_FORMAT_JSON,
+                false,
+                &mut output,
+                &mut error,
+            )
+        };
+        assert_eq!(code, CODE_SUCCESS);
+        assert!(error.is_null());
+        let json = unsafe { CStr::from_ptr(output) }
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(json.contains("\"node\""));
+        unsafe { pim_string_free(output) };
+    }
+
+    #[test]
+    fn test_pim_validate_error() {
+        let source = b"- jobs: []\n  labels: {}\n  targets: [host]\n";
+        let mut error: *mut c_char = std::ptr::null_mut();
+        let code =
+            unsafe { pim_validate(source.as_ptr(), source.len(), PIM_FORMAT_YAML, &mut error) };
+        assert_eq!(code, CODE_RUNTIME_ERROR);
+        assert!(!error.is_null());
+        unsafe { pim_string_free(error) };
+    }
+}
@@ -1,2 +1,4 @@
 pub mod app;
 pub mod core;
+#[cfg(feature = "ffi")]
+pub mod ffi;